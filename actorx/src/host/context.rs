@@ -9,9 +9,13 @@ use tokio::task_local;
 
 use crate::{error::Result, host::Host};
 
+mod gas_report;
 #[cfg(feature = "timeout")]
 pub(crate) mod tracker;
 
+pub(crate) use gas_report::record_gas_usage;
+pub use gas_report::{GasHop, GasReport, WithGasReport};
+
 task_local! {
 	static HOST: Weak<Host>;
 }
@@ -82,3 +86,47 @@ where
 		GAS.scope(Cell::new(0), self).await
 	}
 }
+
+#[allow(async_fn_in_trait)]
+pub trait WithGasLimit: Future {
+	/// Runs the future with `limit` gas and returns its output together with the gas it used.
+	///
+	/// Invocations made by the future fail with `GasFeeExhausted` once the limit is used up,
+	/// in which case the whole limit is reported as used.
+	async fn with_gas_limit(self, limit: u64) -> (Self::Output, u64);
+}
+
+impl<T> WithGasLimit for T
+where
+	T: Future,
+{
+	async fn with_gas_limit(self, limit: u64) -> (Self::Output, u64) {
+		GAS.scope(Cell::new(limit), async move {
+			let output = self.await;
+			(output, limit.saturating_sub(get_gas()))
+		})
+		.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tea_sdk::errorx::Global;
+
+	#[tokio::test]
+	async fn gas_limit_reports_used() {
+		let (result, used) = async { cost(30) }.with_gas_limit(100).await;
+		assert!(result.is_ok());
+		assert_eq!(used, 30);
+
+		let (result, used) = async {
+			cost(60)?;
+			cost(60)
+		}
+		.with_gas_limit(100)
+		.await;
+		assert!(matches!(result, Err(Global::GasFeeExhausted(_))));
+		assert_eq!(used, 100);
+	}
+}
@@ -0,0 +1,222 @@
+use std::{
+	cell::{Cell, RefCell},
+	collections::HashMap,
+	future::Future,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::task_local;
+
+use crate::{calling_stack, core::actor::ActorId, CallingStack};
+
+use super::GAS;
+
+task_local! {
+	static GAS_REPORT: RefCell<GasReport>;
+}
+
+/// Gas consumed by every hop of an invocation, keyed by the calling stack path.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasReport {
+	hops: Vec<GasHop>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasHop {
+	/// Actors from the outermost caller down to the callee of this hop.
+	pub path: Vec<ActorId>,
+	/// How many times this path was invoked.
+	pub calls: u32,
+	/// Gas consumed by this hop, including the nested hops it made.
+	pub total: u64,
+	/// Gas consumed by the callee itself, excluding its nested hops.
+	pub own: u64,
+}
+
+impl GasReport {
+	/// Hops in the order in which they were first completed, so callees come before callers.
+	#[inline(always)]
+	pub fn hops(&self) -> &[GasHop] {
+		&self.hops
+	}
+
+	/// Total gas consumed by the outermost hops of the report.
+	pub fn total(&self) -> u64 {
+		self.hops
+			.iter()
+			.filter(|hop| self.parent(hop).is_none())
+			.map(|hop| hop.total)
+			.sum()
+	}
+
+	/// Gas consumed by each actor itself, summed over every path it appears as the callee.
+	pub fn by_actor(&self) -> HashMap<ActorId, u64> {
+		let mut result = HashMap::new();
+		for hop in &self.hops {
+			if let Some(actor) = hop.path.last() {
+				*result.entry(actor.clone()).or_default() += hop.own;
+			}
+		}
+		result
+	}
+
+	fn parent(&self, hop: &GasHop) -> Option<&GasHop> {
+		let (_, parent) = hop.path.split_last()?;
+		self.hops.iter().find(|x| x.path == parent)
+	}
+
+	fn record(&mut self, stack: &CallingStack, used: u64) {
+		let mut path = stack.into_iter().cloned().collect::<Vec<_>>();
+		path.reverse();
+		if let Some(hop) = self.hops.iter_mut().find(|x| x.path == path) {
+			hop.calls += 1;
+			hop.total = hop.total.saturating_add(used);
+		} else {
+			self.hops.push(GasHop {
+				path,
+				calls: 1,
+				total: used,
+				own: 0,
+			});
+		}
+	}
+
+	fn finish(mut self) -> Self {
+		let children = self
+			.hops
+			.iter()
+			.map(|hop| {
+				self.hops
+					.iter()
+					.filter(|x| x.path.len() == hop.path.len() + 1 && x.path.starts_with(&hop.path))
+					.map(|x| x.total)
+					.sum::<u64>()
+			})
+			.collect::<Vec<_>>();
+		for (hop, children) in self.hops.iter_mut().zip(children) {
+			hop.own = hop.total.saturating_sub(children);
+		}
+		self
+	}
+}
+
+/// Records the gas consumed by the current hop since `before`, if a report is being collected.
+pub(crate) fn record_gas_usage(before: u64) {
+	_ = GAS_REPORT.try_with(|report| {
+		let Some(stack) = calling_stack() else {
+			return;
+		};
+		let after = GAS.try_with(Cell::get).unwrap_or(before);
+		report
+			.borrow_mut()
+			.record(&stack, before.saturating_sub(after));
+	});
+}
+
+#[allow(async_fn_in_trait)]
+pub trait WithGasReport: Future {
+	/// Runs the future and returns its output together with the gas consumed by each actor it invoked.
+	async fn with_gas_report(self) -> (Self::Output, GasReport);
+}
+
+impl<T> WithGasReport for T
+where
+	T: Future,
+{
+	async fn with_gas_report(self) -> (Self::Output, GasReport) {
+		GAS_REPORT
+			.scope(RefCell::default(), async move {
+				let output = self.await;
+				let report = GAS_REPORT.with(RefCell::take);
+				(output, report.finish())
+			})
+			.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{error::Result, sdk::actor::Actor, set_gas, ActorExt, WithActorHost};
+
+	const CALLER: ActorId = ActorId::Static(b"caller");
+	const CALLEE: ActorId = ActorId::Static(b"callee");
+	const REQUEST: &[u8] = b"req";
+
+	struct Consumer {
+		id: ActorId,
+		cost: u64,
+		next: Option<ActorId>,
+	}
+
+	impl Actor for Consumer {
+		async fn invoke(&self, req: &[u8]) -> Result<Vec<u8>> {
+			// lifecycle hooks are free
+			if req != REQUEST {
+				return Ok(Vec::new());
+			}
+			crate::cost(self.cost)?;
+			if let Some(next) = &self.next {
+				next.invoke_raw(req).await?;
+			}
+			Ok(Vec::new())
+		}
+
+		fn id(&self) -> Option<ActorId> {
+			Some(self.id.clone())
+		}
+
+		async fn size(&self) -> Result<u64> {
+			Ok(0)
+		}
+	}
+
+	#[tokio::test]
+	async fn gas_report_splits_hops() -> Result<()> {
+		async {
+			Consumer {
+				id: CALLER,
+				cost: 10,
+				next: Some(CALLEE),
+			}
+			.register()
+			.await?;
+			Consumer {
+				id: CALLEE,
+				cost: 5,
+				next: None,
+			}
+			.register()
+			.await?;
+			set_gas(100);
+
+			let (result, report) = CALLER.invoke_raw(REQUEST).with_gas_report().await;
+			result?;
+
+			assert_eq!(report.total(), 15);
+			assert_eq!(
+				report.hops(),
+				&[
+					GasHop {
+						path: vec![CALLER, CALLEE],
+						calls: 1,
+						total: 5,
+						own: 5,
+					},
+					GasHop {
+						path: vec![CALLER],
+						calls: 1,
+						total: 15,
+						own: 10,
+					},
+				]
+			);
+			let by_actor = report.by_actor();
+			assert_eq!(by_actor[&CALLER], 10);
+			assert_eq!(by_actor[&CALLEE], 5);
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+}
@@ -1,8 +1,14 @@
-use crate::{error::Result, sdk::context::host};
+use crate::{
+	error::Result,
+	sdk::context::{get_gas, host, record_gas_usage},
+};
 
 #[inline(always)]
 pub async fn invoke(req: &[u8]) -> Result<Vec<u8>> {
-	host()?.invoke(req).await
+	let before = get_gas();
+	let result = host()?.invoke(req).await;
+	record_gas_usage(before);
+	result
 }
 
 #[inline(always)]
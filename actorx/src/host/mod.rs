@@ -3,7 +3,12 @@ use std::{
 	future::Future,
 	iter::FusedIterator,
 	mem::ManuallyDrop,
-	sync::Arc,
+	ops::Deref,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
 };
 
 use crate::{core::actor::ActorId, metadata::Metadata};
@@ -11,9 +16,11 @@ use tea_codec::{
 	serde::{get_type_id, ToBytes, TypeId},
 	IntoGlobal,
 };
-use tea_sdk::errorx::{ActorDeactivating, ActorNotExist, Global};
+use tea_sdk::errorx::{
+	ActorDeactivating, ActorIdMismatch, ActorIdMissing, ActorNotExist, Global, RoutineTimeout,
+};
 use tokio::{
-	sync::{RwLock, RwLockReadGuard},
+	sync::{Notify, RwLock, RwLockReadGuard},
 	task::JoinHandle,
 };
 
@@ -21,7 +28,7 @@ use crate::{
 	error::Result,
 	sdk::{
 		actor::{ActorSend, DynActorSend},
-		context::{current, current_ref, host, WithGas, WithHost},
+		context::{current, current_ref, host, WithCallingStack, WithGas, WithHost},
 		hooks::{Activate, Deactivate},
 	},
 };
@@ -33,6 +40,10 @@ pub use wasm::*;
 pub mod context;
 pub mod invoke;
 
+/// How long a replaced actor version waits for its running invocations before giving up on
+/// its deactivate hook.
+const RETIRE_TIMEOUT: Duration = Duration::from_secs(60);
+
 type OutputHandler = Arc<
 	RwLock<
 		Box<
@@ -51,8 +62,10 @@ pub(crate) struct Host {
 }
 
 impl Host {
+	/// The agent of the current actor, with the invocation counted before the map is unlocked,
+	/// so that an upgrade swapping the agent afterwards waits for it before retiring the agent.
 	#[inline(always)]
-	async fn get_actor(&self) -> Result<Arc<ActorAgent>> {
+	async fn get_actor(&self) -> Result<Running> {
 		let actors = self.actors.read().await;
 		let actor = current_ref(|current| {
 			actors
@@ -60,7 +73,7 @@ impl Host {
 				.cloned()
 				.ok_or_else(|| ActorNotExist(current.to_string()))
 		})??;
-		Ok(actor)
+		Ok(Running::new(actor))
 	}
 
 	#[inline(always)]
@@ -78,31 +91,109 @@ impl Host {
 		self.get_actor().await?.deactivate().await
 	}
 
-	#[inline(always)]
-	pub async fn remove(&self, id: &ActorId) -> Result<()> {
+	/// Removes the agent, unless the id has been upgraded to another agent meanwhile.
+	async fn remove(&self, id: &ActorId, agent: &ActorAgent) {
 		let mut actors = self.actors.write().await;
-		actors
-			.remove(id)
-			.expect("Actor runtime internal error: failed to remove actor");
-		Ok(())
+		if let hash_map::Entry::Occupied(entry) = actors.entry(id.clone()) {
+			if std::ptr::eq(Arc::as_ptr(entry.get()), agent) {
+				entry.remove();
+			}
+		}
 	}
 
 	#[inline(always)]
-	pub async fn register(&self, actor: impl ActorSend) {
+	pub async fn register(&self, actor: impl ActorSend) -> Result<()> {
+		let id = actor.id().ok_or(ActorIdMissing)?;
 		let mut actors = self.actors.write().await;
-		actors.insert(
-			actor.id().expect("No id is not defined"),
-			Arc::new(ActorAgent {
-				actor: Box::new(actor),
-				is_active: RwLock::new(Status::Uninit),
-			}),
-		);
+		actors.insert(id, Arc::new(ActorAgent::new(actor)));
+		Ok(())
+	}
+
+	pub async fn upgrade(&self, id: &ActorId, actor: impl ActorSend) -> Result<()> {
+		// fails early without activating the new version, the check is repeated on the swap
+		self.check_upgradable(&*self.actors.read().await, id)?;
+
+		let new_id = match ActorSend::metadata(&actor).await {
+			Ok(metadata) => metadata.id.clone(),
+			Err(Global::NotSupported(_)) => actor.id().ok_or(ActorIdMissing)?,
+			Err(e) => return Err(e),
+		};
+		if &new_id != id {
+			return Err(ActorIdMismatch(id.to_string(), new_id.to_string()).into());
+		}
+
+		let agent = Arc::new(ActorAgent::new(actor));
+
+		// Run the activate hook before the new version becomes visible, so that a failing
+		// version is simply dropped and the old one keeps serving.
+		agent.activate().invoke_target(id.clone()).await?;
+
+		let old = {
+			let mut actors = self.actors.write().await;
+			self.check_upgradable(&actors, id)?;
+			actors.insert(id.clone(), agent)
+		};
+
+		if let Some(old) = old {
+			let id = id.clone();
+			spawn(async move {
+				if let Err(e) = old.retire().invoke_target(id.clone()).await {
+					warn!("failed to retire the old version of actor {id}: {e:?}");
+				}
+			});
+		}
+		Ok(())
+	}
+
+	fn check_upgradable(
+		&self,
+		actors: &HashMap<ActorId, Arc<ActorAgent>>,
+		id: &ActorId,
+	) -> Result<()> {
+		match actors.get(id) {
+			Some(old) if old.deactivating.load(Ordering::Acquire) => {
+				Err(ActorDeactivating(id.to_string()).into())
+			}
+			Some(_) => Ok(()),
+			None => Err(ActorNotExist(id.to_string()).into()),
+		}
 	}
 }
 
 struct ActorAgent {
 	actor: DynActorSend,
 	is_active: RwLock<Status>,
+	/// Set along with `Status::Deactivating`, to be checked while the actor map is locked.
+	deactivating: AtomicBool,
+	/// The number of invocations running on this agent, with `idle` notified when it drops to zero.
+	running: AtomicUsize,
+	idle: Notify,
+}
+
+/// An agent with an invocation counted in `ActorAgent::running` while alive.
+struct Running(Arc<ActorAgent>);
+
+impl Running {
+	fn new(agent: Arc<ActorAgent>) -> Self {
+		agent.running.fetch_add(1, Ordering::AcqRel);
+		Running(agent)
+	}
+}
+
+impl Deref for Running {
+	type Target = ActorAgent;
+
+	fn deref(&self) -> &ActorAgent {
+		&self.0
+	}
+}
+
+impl Drop for Running {
+	fn drop(&mut self) {
+		if self.0.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+			self.0.idle.notify_waiters();
+		}
+	}
 }
 
 enum Status {
@@ -112,6 +203,16 @@ enum Status {
 }
 
 impl ActorAgent {
+	fn new(actor: impl ActorSend) -> Self {
+		ActorAgent {
+			actor: Box::new(actor),
+			is_active: RwLock::new(Status::Uninit),
+			deactivating: AtomicBool::new(false),
+			running: AtomicUsize::new(0),
+			idle: Notify::new(),
+		}
+	}
+
 	#[inline(always)]
 	async fn metadata(&self) -> Result<Arc<Metadata>> {
 		self.actor.metadata().await
@@ -175,14 +276,15 @@ impl ActorAgent {
 		let mut is_active = self.is_active.write().await;
 		match *is_active {
 			Status::Uninit => {
-				*is_active = Status::Deactivating;
+				self.set_deactivating(&mut is_active);
+				drop(is_active);
 				self.remove_self().await?;
 				return Ok(());
 			}
 			Status::Active => (),
 			Status::Deactivating => return Ok(()),
 		}
-		*is_active = Status::Deactivating;
+		self.set_deactivating(&mut is_active);
 		drop(is_active);
 
 		if let Err(e) = self.actor.invoke(&Deactivate.to_bytes()?).await {
@@ -196,9 +298,49 @@ impl ActorAgent {
 		Ok(())
 	}
 
+	fn set_deactivating(&self, status: &mut Status) {
+		*status = Status::Deactivating;
+		self.deactivating.store(true, Ordering::Release);
+	}
+
 	#[inline(always)]
 	async fn remove_self(&self) -> Result<()> {
-		host()?.remove(&current()?).await
+		host()?.remove(&current()?, self).await;
+		Ok(())
+	}
+
+	/// Waits for the invocations running on this agent to finish, then runs the deactivate
+	/// hook without removing the actor id from the host, which now belongs to a newer version.
+	///
+	/// Fails without running the hook if the invocations are still running after
+	/// `RETIRE_TIMEOUT`.
+	async fn retire(self: Arc<Self>) -> Result<()> {
+		let drained = async {
+			loop {
+				let idle = self.idle.notified();
+				if self.running.load(Ordering::Acquire) == 0 {
+					break;
+				}
+				idle.await;
+			}
+		};
+		if tokio::time::timeout(RETIRE_TIMEOUT, drained).await.is_err() {
+			return Err(RoutineTimeout(format!("retiring {}", current()?)).into());
+		}
+
+		let mut is_active = self.is_active.write().await;
+		let was_active = matches!(*is_active, Status::Active);
+		self.set_deactivating(&mut is_active);
+		drop(is_active);
+
+		if was_active {
+			if let Err(e) = self.actor.invoke(&Deactivate.to_bytes()?).await {
+				if !matches!(e, Global::UnexpectedType(_)) {
+					return Err(e);
+				}
+			}
+		}
+		Ok(())
 	}
 }
 
@@ -261,6 +403,13 @@ where
 #[allow(async_fn_in_trait)]
 pub trait ActorExt: ActorSend {
 	async fn register(self) -> Result<()>;
+
+	/// Replaces the registered actor `id` with this one without a window in which calls fail.
+	///
+	/// The new version is activated before it is swapped in; if its activate hook fails the
+	/// old version stays registered and the error is returned. Invocations already running on
+	/// the old version are allowed to finish before its deactivate hook is called.
+	async fn upgrade(self, id: &ActorId) -> Result<()>;
 }
 
 impl<T> ActorExt for T
@@ -269,8 +418,12 @@ where
 {
 	#[inline(always)]
 	async fn register(self) -> Result<()> {
-		host()?.register(self).await;
-		Ok(())
+		host()?.register(self).await
+	}
+
+	#[inline(always)]
+	async fn upgrade(self, id: &ActorId) -> Result<()> {
+		host()?.upgrade(id, self).await
 	}
 }
 
@@ -365,3 +518,227 @@ impl Drop for Iter {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sdk::actor::Actor;
+
+	const TEST_ACTOR: ActorId = ActorId::Static(b"test_actor");
+
+	struct Versioned {
+		id: ActorId,
+		version: &'static [u8],
+		fail_activate: bool,
+		/// The deactivate hook waits for `release` too.
+		slow_deactivate: bool,
+		/// Invocations with `b"slow"` wait for this before they return.
+		release: Arc<Notify>,
+		events: Arc<std::sync::Mutex<Vec<String>>>,
+	}
+
+	impl Versioned {
+		fn new(version: &'static [u8]) -> Self {
+			Self {
+				id: TEST_ACTOR,
+				version,
+				fail_activate: false,
+				slow_deactivate: false,
+				release: Default::default(),
+				events: Default::default(),
+			}
+		}
+
+		fn log(&self, event: &str) {
+			let version = String::from_utf8_lossy(self.version);
+			self.events
+				.lock()
+				.unwrap()
+				.push(format!("{event} {version}"));
+		}
+	}
+
+	impl Actor for Versioned {
+		async fn invoke(&self, req: &[u8]) -> Result<Vec<u8>> {
+			if let Ok(Activate::TYPE_ID) = get_type_id(req) {
+				if self.fail_activate {
+					return Err(Global::Unnamed("activate failed".to_string()));
+				}
+				return Ok(().to_bytes()?);
+			}
+			if let Ok(Deactivate::TYPE_ID) = get_type_id(req) {
+				if self.slow_deactivate {
+					self.release.notified().await;
+				}
+				self.log("deactivate");
+				return Ok(().to_bytes()?);
+			}
+			if req == b"slow" {
+				self.log("start");
+				self.release.notified().await;
+				self.log("slow");
+			}
+			Ok(self.version.to_vec())
+		}
+
+		fn id(&self) -> Option<ActorId> {
+			Some(self.id.clone())
+		}
+
+		async fn size(&self) -> Result<u64> {
+			Ok(0)
+		}
+	}
+
+	#[tokio::test]
+	async fn upgrade_swaps_actor() -> Result<()> {
+		async {
+			Versioned::new(b"v1").register().await?;
+			assert_eq!(TEST_ACTOR.invoke_raw(b"req").await?, b"v1");
+
+			Versioned::new(b"v2").upgrade(&TEST_ACTOR).await?;
+			assert_eq!(TEST_ACTOR.invoke_raw(b"req").await?, b"v2");
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+
+	#[tokio::test]
+	async fn upgrade_retires_old_version_after_running_invocations() -> Result<()> {
+		async {
+			let v1 = Versioned::new(b"v1");
+			let (release, events) = (v1.release.clone(), v1.events.clone());
+			v1.register().await?;
+
+			let slow = spawn(TEST_ACTOR.invoke_raw(b"slow"));
+			while events.lock().unwrap().is_empty() {
+				tokio::task::yield_now().await;
+			}
+			Versioned {
+				events: events.clone(),
+				..Versioned::new(b"v2")
+			}
+			.upgrade(&TEST_ACTOR)
+			.await?;
+			for _ in 0..10 {
+				tokio::task::yield_now().await;
+			}
+			assert_eq!(*events.lock().unwrap(), ["start v1"]);
+
+			release.notify_one();
+			assert_eq!(slow.await.unwrap()?, b"v1");
+			while events.lock().unwrap().len() < 3 {
+				tokio::task::yield_now().await;
+			}
+			assert_eq!(
+				*events.lock().unwrap(),
+				["start v1", "slow v1", "deactivate v1"]
+			);
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+
+	#[tokio::test]
+	async fn upgrade_rolls_back_on_failed_activate() -> Result<()> {
+		async {
+			Versioned::new(b"v1").register().await?;
+
+			let broken = Versioned {
+				fail_activate: true,
+				..Versioned::new(b"v2")
+			};
+			assert!(broken.upgrade(&TEST_ACTOR).await.is_err());
+			assert_eq!(TEST_ACTOR.invoke_raw(b"req").await?, b"v1");
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+
+	#[tokio::test]
+	async fn upgrade_rejects_mismatched_id() -> Result<()> {
+		async {
+			Versioned::new(b"v1").register().await?;
+
+			let other = Versioned {
+				id: ActorId::Static(b"other_actor"),
+				..Versioned::new(b"v2")
+			};
+			let result = other.upgrade(&TEST_ACTOR).await;
+			assert!(matches!(result, Err(Global::ActorIdMismatch(_))));
+
+			let result = Versioned::new(b"v2")
+				.upgrade(&ActorId::Static(b"missing_actor"))
+				.await;
+			assert!(matches!(result, Err(Global::ActorNotExist(_))));
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+
+	async fn wait_for(events: &std::sync::Mutex<Vec<String>>, len: usize) {
+		while events.lock().unwrap().len() < len {
+			tokio::task::yield_now().await;
+		}
+	}
+
+	#[tokio::test]
+	async fn upgrade_waits_for_invocations_counted_before_the_swap() -> Result<()> {
+		async {
+			let v1 = Versioned::new(b"v1");
+			let events = v1.events.clone();
+			v1.register().await?;
+
+			// fetched but not yet invoked, as a call just before the swap would be
+			let running = host()?.get_actor().invoke_target(TEST_ACTOR).await?;
+			Versioned::new(b"v2").upgrade(&TEST_ACTOR).await?;
+			for _ in 0..10 {
+				tokio::task::yield_now().await;
+			}
+			assert!(events.lock().unwrap().is_empty());
+			assert_eq!(running.invoke(b"req").await?, b"v1");
+
+			drop(running);
+			wait_for(&events, 1).await;
+			assert_eq!(*events.lock().unwrap(), ["deactivate v1"]);
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+
+	#[tokio::test]
+	async fn upgrade_rejects_deactivating_actor() -> Result<()> {
+		async {
+			let v1 = Versioned {
+				slow_deactivate: true,
+				..Versioned::new(b"v1")
+			};
+			let (release, events) = (v1.release.clone(), v1.events.clone());
+			v1.register().await?;
+			TEST_ACTOR.invoke_raw(b"req").await?;
+
+			let deactivate = spawn(async { host()?.deactivate().await }.invoke_target(TEST_ACTOR));
+			while !host()?.actors.read().await[&TEST_ACTOR]
+				.deactivating
+				.load(Ordering::Acquire)
+			{
+				tokio::task::yield_now().await;
+			}
+			let result = Versioned::new(b"v2").upgrade(&TEST_ACTOR).await;
+			assert!(matches!(result, Err(Global::ActorDeactivating(_))));
+
+			release.notify_one();
+			deactivate.await.unwrap()?;
+			wait_for(&events, 1).await;
+			assert!(!host()?.actors.read().await.contains_key(&TEST_ACTOR));
+			Ok(())
+		}
+		.with_actor_host()
+		.await
+	}
+}
@@ -1,8 +1,8 @@
+use crate::error::Result;
 #[cfg(target_os = "linux")]
 use procfs::{process::Process, ProcResult};
 use sysinfo::SystemExt;
 use tabled::{Table, Tabled};
-use crate::error::Result;
 
 pub fn dump_sys_usages() -> String {
 	let mut result = String::new();
@@ -8,7 +8,7 @@ use crate::{
 	},
 	IntoActor,
 };
-use tea_codec::serde::{get_type_id, TypeId};
+use tea_codec::serde::{get_type_id, render::render, TypeId};
 use tea_sdk::{errorx::AccessNotPermitted, IntoGlobal, OptionExt};
 use tokio::{
 	sync::{Mutex, MutexGuard},
@@ -206,12 +206,26 @@ impl WasmActor {
 	}
 }
 
+/// The type id of a request, which unlike its rendered payload is safe to log at any level.
+fn type_name(req: &[u8]) -> &str {
+	get_type_id(req)
+		.ok()
+		.filter(|x| !x.is_empty())
+		.unwrap_or("<untyped request>")
+}
+
 impl Actor for WasmActor {
 	async fn invoke(&self, req: &[u8]) -> Result<Vec<u8>> {
 		loop {
 			let worker = self.worker::<true>().await?;
 			let metadata = worker.metadata().clone();
-			let mut channel = match worker.open().await {
+			let mut channel = match worker
+				.open(
+					#[cfg(feature = "track")]
+					req,
+				)
+				.await
+			{
 				Ok(c) => c,
 				Err(e) => {
 					warn!("channel error: {e:?}, resetting worker");
@@ -225,12 +239,28 @@ impl Actor for WasmActor {
 					ctx: bincode::serialize(&ctx).into_g::<Error>()?,
 					req: req.to_vec(),
 				})
-				.await?;
+				.await
+				.map_err(|e| {
+					warn!("{} failed to invoke {}: {e:?}", self.id, type_name(req));
+					debug!("{} failed request: {}", self.id, render(req));
+					e
+				})?;
 			loop {
 				result = match result {
-					Operation::Call { ctx, req } => {
-						Self::inner_call(&mut channel, &metadata, ctx, req).await?
-					}
+					Operation::Call {
+						ctx,
+						req: inner_req,
+					} => Self::inner_call(&mut channel, &metadata, ctx, inner_req)
+						.await
+						.map_err(|e| {
+							warn!(
+								"{} failed in a nested call while handling {}: {e:?}",
+								self.id,
+								type_name(req)
+							);
+							debug!("{} failed request: {}", self.id, render(req));
+							e
+						})?,
 
 					Operation::ReturnOk { resp } => {
 						tokio::spawn(channel.close());
@@ -238,6 +268,7 @@ impl Actor for WasmActor {
 					}
 
 					Operation::ReturnErr { error } => {
+						debug!("{} returned {error:?} for {}", self.id, render(req));
 						tokio::spawn(channel.close());
 						return Err(error);
 					}
@@ -4,6 +4,7 @@ use std::{
 	sync::{Arc, Weak},
 };
 
+use tea_codec::serde::render::render;
 use tokio::sync::{
 	oneshot::{channel, Receiver},
 	RwLock,
@@ -55,13 +56,19 @@ impl WorkerTracker {
 		Arc::new(handle)
 	}
 
-	pub async fn capture(&self) -> HashMap<(ActorId, u64), HashMap<u64, CallingStack>> {
+	pub async fn capture(&self) -> HashMap<(ActorId, u64), HashMap<u64, CapturedChannel>> {
 		let table = self.table.read().await;
 		let mut result = HashMap::with_capacity(table.workers.capacity());
 		for (actor, channels) in &table.workers {
 			let mut c = HashMap::with_capacity(channels.channels.capacity());
-			for (cid, stack) in &channels.channels {
-				c.insert(*cid, stack.read().await.clone());
+			for (cid, channel) in &channels.channels {
+				c.insert(
+					*cid,
+					CapturedChannel {
+						stack: channel.stack.read().await.clone(),
+						request: render(&channel.request).to_string(),
+					},
+				);
 			}
 			result.insert(actor.clone(), c);
 		}
@@ -69,13 +76,26 @@ impl WorkerTracker {
 	}
 }
 
+/// A channel that was open when the tracker was captured.
+#[derive(Clone, Debug)]
+pub struct CapturedChannel {
+	pub stack: CallingStack,
+	/// The request being processed by the channel, rendered for reading.
+	pub request: String,
+}
+
 struct WorkerTable {
 	workers: HashMap<(ActorId, u64), WorkerInfo>,
 	ids: HashMap<ActorId, u64>,
 }
 
 struct WorkerInfo {
-	channels: HashMap<u64, Arc<RwLock<CallingStack>>>,
+	channels: HashMap<u64, ChannelInfo>,
+}
+
+struct ChannelInfo {
+	stack: Arc<RwLock<CallingStack>>,
+	request: Vec<u8>,
 }
 
 pub(crate) struct WorkerHandle {
@@ -107,7 +127,7 @@ impl WorkerHandle {
 		}
 	}
 
-	pub fn create_channel(self: &Arc<Self>, cid: u64) -> ChannelHandle {
+	pub fn create_channel(self: &Arc<Self>, cid: u64, req: &[u8]) -> ChannelHandle {
 		let handle = ChannelHandle {
 			worker: self.clone(),
 			cid,
@@ -116,6 +136,7 @@ impl WorkerHandle {
 			let actor = self.actor.clone();
 			let stack = full_stack().expect("internal error: full_stack not exist");
 			let id = self.clone().id();
+			let request = req.to_vec();
 			tokio::spawn(async move {
 				let mut table = table.write().await;
 				let id = id.await;
@@ -124,7 +145,7 @@ impl WorkerHandle {
 					.get_mut(&(actor, id))
 					.expect("internal error: worker not exist")
 					.channels
-					.insert(cid, stack);
+					.insert(cid, ChannelInfo { stack, request });
 			});
 		}
 		handle
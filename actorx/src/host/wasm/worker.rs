@@ -334,7 +334,7 @@ impl Worker {
 		})
 	}
 
-	pub async fn open(self) -> Result<Channel> {
+	pub async fn open(self, #[cfg(feature = "track")] req: &[u8]) -> Result<Channel> {
 		let (mut tx, rx) = unbounded_channel();
 		let mut channels = self.proc.channels.lock().await;
 		if let Some(e) = &channels.error {
@@ -351,7 +351,7 @@ impl Worker {
 		drop(channels);
 		Ok(Channel {
 			#[cfg(feature = "track")]
-			_tracker: self.proc.tracker.create_channel(id),
+			_tracker: self.proc.tracker.create_channel(id, req),
 			proc: self.proc,
 			rx,
 			id,
@@ -6,7 +6,7 @@ pub mod context;
 #[cfg(any(feature = "host", feature = "wasm"))]
 pub use context::{caller, calling_stack, current};
 #[cfg(feature = "host")]
-pub use context::{cost, get_gas, set_gas};
+pub use context::{cost, get_gas, set_gas, GasReport, WithGasLimit, WithGasReport};
 
 pub mod hooks;
 #[cfg(any(feature = "host", feature = "wasm"))]
@@ -9,7 +9,7 @@ use std::{
 	sync::Arc,
 };
 #[cfg(feature = "verbose_log")]
-use ::{std::time::SystemTime, tea_sdk::serde::get_type_id};
+use ::{std::time::SystemTime, tea_sdk::serde::render::render};
 
 use tea_sdk::IntoGlobal;
 use tokio::{
@@ -155,12 +155,8 @@ impl Worker {
 	#[cfg(feature = "verbose_log")]
 	fn log_operation(op: &Operation, id: ActorId) -> impl FnOnce(&Operation) {
 		let calc_op = |op: &Operation| match op {
-			Operation::Call { req, .. } => {
-				format!("request {}", get_type_id(req).unwrap_or("[untyped]"))
-			}
-			Operation::ReturnOk { resp } => {
-				format!("response {}", get_type_id(resp).unwrap_or("[untyped]"))
-			}
+			Operation::Call { req, .. } => format!("request {}", render(req)),
+			Operation::ReturnOk { resp } => format!("response {}", render(resp)),
 			Operation::ReturnErr { error } => {
 				format!("error {:?}", error)
 			}
@@ -185,10 +181,19 @@ impl Worker {
 	) -> Result<()> {
 		let mut first = true;
 		let mut state_write = state.write().await;
+		#[cfg(feature = "verbose_log")]
+		let mut finish_log = None;
 
 		while let Some((operation, mut gas)) = input.recv().await {
 			let resp = if first {
 				first = false;
+				#[cfg(feature = "verbose_log")]
+				{
+					finish_log = Some(Self::log_operation(
+						&operation,
+						self.host.metadata().id.clone(),
+					));
+				}
 				let op = operation.clone();
 				match state_write.instance().invoke(op, Some(&mut gas)) {
 					Ok(r) => r,
@@ -213,6 +218,12 @@ impl Worker {
 			};
 
 			let is_completed = !matches!(resp, Operation::Call { .. });
+			#[cfg(feature = "verbose_log")]
+			if is_completed {
+				if let Some(finish_log) = finish_log.take() {
+					finish_log(&resp);
+				}
+			}
 			let mut write = self.write.lock().await;
 			resp.write(&mut *write, cid, gas).await?;
 			write.flush().await?;
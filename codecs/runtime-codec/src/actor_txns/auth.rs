@@ -1,12 +1,19 @@
+use self::policy::{decode_policies, is_policies, AuthPolicy};
 use crate::actor_txns::error::{Result, TxnError};
 use crate::tapp::{Account, TokenId};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::str::FromStr;
+use strum::{Display, EnumString};
+use tea_sdk::deserialize;
+
+pub mod policy;
 
 /// These are allow operation that user authorize the tapp to
 /// When user login a tapp, these allow ops will be listed
 /// If user agree, login continue, if not, login cancelled
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Copy, Clone, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
 pub enum AllowedOp {
 	Read,         // Read the account balance
 	Move,         // To move the fund to other account or my deposit account
@@ -26,30 +33,119 @@ pub struct TokenAuthOp {
 	pub withdraw: bool,
 	pub consume: bool,
 	pub bonding_curve: bool,
+	pub cross_move: bool,
 }
 
-impl TokenAuthOp {
-	pub fn new(token_id: TokenId, acct: Account, auth_expr: &str) -> Self {
+/// The layout of [`TokenAuthOp`] before `cross_move`, which auth ops stored earlier still use.
+#[derive(Deserialize)]
+pub(crate) struct TokenAuthOpV0 {
+	token_id: TokenId,
+	acct: Account,
+	read: bool,
+	mov: bool,
+	withdraw: bool,
+	consume: bool,
+	bonding_curve: bool,
+}
+
+impl From<TokenAuthOpV0> for TokenAuthOp {
+	fn from(op: TokenAuthOpV0) -> Self {
 		TokenAuthOp {
+			token_id: op.token_id,
+			acct: op.acct,
+			read: op.read,
+			mov: op.mov,
+			withdraw: op.withdraw,
+			consume: op.consume,
+			bonding_curve: op.bonding_curve,
+			cross_move: false,
+		}
+	}
+}
+
+/// Decodes auth ops encoded with `serialize`, also those encoded before `cross_move` was added,
+/// and the ops granted by policies encoded with [`policy::encode_policies`].
+///
+/// The layouts cannot be mistaken for each other, as every op of the old one is a byte shorter.
+pub fn deserialize_auth_ops(buf: &[u8]) -> tea_sdk::Result<Vec<TokenAuthOp>> {
+	if is_policies(buf) {
+		return Ok(decode_policies(buf)?.iter().map(Into::into).collect());
+	}
+	deserialize(buf).or_else(|e| {
+		let ops: Vec<TokenAuthOpV0> = deserialize(buf).map_err(|_| e)?;
+		Ok(ops.into_iter().map(Into::into).collect())
+	})
+}
+
+/// Decodes auth ops along with their renewed expiration, as queried from the state machine,
+/// also in the layout from before `cross_move`.
+pub fn deserialize_auth_ops_with_expire(buf: &[u8]) -> tea_sdk::Result<(Vec<TokenAuthOp>, u128)> {
+	deserialize(buf).or_else(|e| {
+		let (ops, expire): (Vec<TokenAuthOpV0>, u128) = deserialize(buf).map_err(|_| e)?;
+		Ok((ops.into_iter().map(Into::into).collect(), expire))
+	})
+}
+
+/// Decodes what the state machine answers to a query of auth ops, as
+/// [`deserialize_auth_ops_with_expire`] does. Auth keys that were set with policies are
+/// answered with the policy bytes as they were stored, which are decoded into the policies
+/// along with the ops they grant.
+pub fn deserialize_auth_grants(
+	buf: &[u8],
+) -> tea_sdk::Result<(Vec<TokenAuthOp>, Vec<AuthPolicy>, u128)> {
+	if let Ok((bytes, expire)) = deserialize::<(Vec<u8>, u128), _>(buf) {
+		if is_policies(&bytes) {
+			let policies = decode_policies(&bytes)?;
+			let ops = policies.iter().map(Into::into).collect();
+			return Ok((ops, policies, expire));
+		}
+	}
+	let (ops, expire) = deserialize_auth_ops_with_expire(buf)?;
+	Ok((ops, vec![], expire))
+}
+
+impl TokenAuthOp {
+	/// Grants the ops listed in `auth_expr`.
+	///
+	/// Ops are matched as whole items, so that `cross_move` does not grant `move`. The caps
+	/// and other restrictions of the [`policy::AuthPolicy`] text form are rejected rather
+	/// than granting the bare op, because auth ops cannot enforce them.
+	pub fn new(token_id: TokenId, acct: Account, auth_expr: &str) -> Result<Self> {
+		let mut ops = HashSet::new();
+		for item in auth_items(auth_expr) {
+			if item.contains('=') {
+				return Err(TxnError::InvalidAuthPolicy(format!(
+					"{item}: restrictions are only supported by auth policies"
+				))
+				.into());
+			}
+			ops.extend(parse_op(item));
+		}
+		Ok(TokenAuthOp {
 			token_id,
 			acct,
-			read: auth_expr.contains("read"),
-			mov: auth_expr.contains("move"),
-			withdraw: auth_expr.contains("withdraw"),
-			consume: auth_expr.contains("consume"),
-			bonding_curve: auth_expr.contains("bonding_curve"),
-		}
+			read: ops.contains(&AllowedOp::Read),
+			mov: ops.contains(&AllowedOp::Move),
+			withdraw: ops.contains(&AllowedOp::Withdraw),
+			consume: ops.contains(&AllowedOp::Consume),
+			bonding_curve: ops.contains(&AllowedOp::BondingCurve),
+			cross_move: ops.contains(&AllowedOp::CrossMove),
+		})
 	}
 
 	/// Check authorization method.
+	///
+	/// Besides the token the user logged in, the tappstore token is the hidden token
+	/// allowed by default, so `tappstore_id` is matched as well.
 	pub fn check_auth(
 		&self,
 		ask_acct: Account,
 		ask_token_id: TokenId,
 		ask_op: AllowedOp,
+		tappstore_id: TokenId,
 	) -> Result<()> {
 		let check_failed_err = TxnError::AuthCheckFailed(ask_token_id, ask_acct, ask_op);
-		if !self.is_token_id_match(ask_token_id) || !self.is_account_match(ask_acct) {
+		if !self.is_token_id_match(ask_token_id, tappstore_id) || !self.is_account_match(ask_acct) {
 			return Err(check_failed_err.into());
 		}
 
@@ -61,15 +157,8 @@ impl TokenAuthOp {
 		Err(check_failed_err.into())
 	}
 
-	fn is_token_id_match(&self, ask_token_id: TokenId) -> bool {
-		if self.token_id == ask_token_id {
-			return true;
-		}
-
-		// TODO: add tappstore_id
-		// this is the hidden token id allowed by default
-		// ask_token_id == tappstore_id()?
-		true
+	fn is_token_id_match(&self, ask_token_id: TokenId, tappstore_id: TokenId) -> bool {
+		self.token_id == ask_token_id || ask_token_id == tappstore_id
 	}
 
 	fn is_account_match(&self, ask_acct: Account) -> bool {
@@ -95,6 +184,149 @@ impl From<TokenAuthOp> for HashSet<AllowedOp> {
 		if val.bonding_curve {
 			allowed_set.insert(AllowedOp::BondingCurve);
 		}
+		if val.cross_move {
+			allowed_set.insert(AllowedOp::CrossMove);
+		}
 		allowed_set
 	}
 }
+
+/// The items of an auth expression separated by `,`.
+pub(crate) fn auth_items(expr: &str) -> impl Iterator<Item = &str> {
+	expr.split(',').map(str::trim).filter(|x| !x.is_empty())
+}
+
+pub(crate) fn parse_op(op: &str) -> Option<AllowedOp> {
+	AllowedOp::from_str(op.trim()).ok()
+}
+
+/// Check whether any of the `auth_ops` allows `ask_acct` to do `ask_op` on `ask_token_id`.
+pub fn check_auth_ops(
+	auth_ops: &[TokenAuthOp],
+	ask_acct: Account,
+	ask_token_id: TokenId,
+	ask_op: AllowedOp,
+	tappstore_id: TokenId,
+) -> Result<()> {
+	if auth_ops.iter().any(|x| {
+		x.check_auth(ask_acct, ask_token_id, ask_op, tappstore_id)
+			.is_ok()
+	}) {
+		return Ok(());
+	}
+	Err(TxnError::AuthCheckFailed(ask_token_id, ask_acct, ask_op).into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const USER: Account = Account::repeat_byte(1);
+	const APP: TokenId = TokenId(Account::repeat_byte(2));
+	const OTHER_APP: TokenId = TokenId(Account::repeat_byte(3));
+	const TAPPSTORE: TokenId = TokenId(Account::repeat_byte(4));
+
+	#[test]
+	fn policies_decode_as_auth_grants() {
+		let policies = vec![policy::AuthPolicy::parse(APP, USER, "move<=10/60").unwrap()];
+		let bytes = policy::encode_policies(&policies).unwrap();
+		let ops = deserialize_auth_ops(&bytes).unwrap();
+		assert_eq!(ops, vec![TokenAuthOp::new(APP, USER, "move").unwrap()]);
+
+		let queried = tea_sdk::serialize(&(bytes, 7u128)).unwrap();
+		assert_eq!(
+			deserialize_auth_grants(&queried).unwrap(),
+			(ops.clone(), policies, 7)
+		);
+		let queried = tea_sdk::serialize(&(ops.clone(), 7u128)).unwrap();
+		assert_eq!(deserialize_auth_grants(&queried).unwrap(), (ops, vec![], 7));
+	}
+
+	#[test]
+	fn check_auth_per_account_and_token() {
+		let auth_ops = vec![TokenAuthOp::new(APP, USER, "read,move").unwrap()];
+		assert!(check_auth_ops(&auth_ops, USER, APP, AllowedOp::Move, TAPPSTORE).is_ok());
+		assert!(check_auth_ops(&auth_ops, USER, APP, AllowedOp::Withdraw, TAPPSTORE).is_err());
+		assert!(check_auth_ops(&auth_ops, USER, OTHER_APP, AllowedOp::Move, TAPPSTORE).is_err());
+		assert_eq!(
+			check_auth_ops(
+				&auth_ops,
+				Account::repeat_byte(9),
+				APP,
+				AllowedOp::Read,
+				TAPPSTORE
+			),
+			Err(TxnError::AuthCheckFailed(APP, Account::repeat_byte(9), AllowedOp::Read).into())
+		);
+		// the tappstore token is allowed by default
+		assert!(check_auth_ops(&auth_ops, USER, TAPPSTORE, AllowedOp::Move, TAPPSTORE).is_ok());
+	}
+
+	#[test]
+	fn restrictions_are_rejected() {
+		assert!(TokenAuthOp::new(APP, USER, "read, move<=100/60").is_err());
+		assert!(TokenAuthOp::new(APP, USER, "move, expire=100").is_err());
+		assert!(TokenAuthOp::new(APP, USER, "move, counterpart=0x01").is_err());
+	}
+
+	#[derive(Clone, Serialize)]
+	struct LegacyAuthOp {
+		token_id: TokenId,
+		acct: Account,
+		read: bool,
+		mov: bool,
+		withdraw: bool,
+		consume: bool,
+		bonding_curve: bool,
+	}
+
+	#[test]
+	fn auth_ops_stored_before_cross_move_decode() {
+		let legacy = vec![
+			LegacyAuthOp {
+				token_id: APP,
+				acct: USER,
+				read: true,
+				mov: true,
+				withdraw: false,
+				consume: false,
+				bonding_curve: true,
+			};
+			1
+		];
+		let expected = TokenAuthOp::new(APP, USER, "read, move, bonding_curve").unwrap();
+		let buf = tea_sdk::serialize(&legacy).unwrap();
+		assert_eq!(deserialize_auth_ops(&buf).unwrap(), vec![expected.clone()]);
+		let buf = tea_sdk::serialize(&(&legacy, 7u128)).unwrap();
+		assert_eq!(
+			deserialize_auth_ops_with_expire(&buf).unwrap(),
+			(vec![expected.clone()], 7)
+		);
+
+		let current = vec![TokenAuthOp::new(APP, USER, "read, cross_move").unwrap(); 2];
+		let buf = tea_sdk::serialize(&current).unwrap();
+		assert_eq!(deserialize_auth_ops(&buf).unwrap(), current);
+		assert!(deserialize_auth_ops(&buf[1..]).is_err());
+	}
+
+	#[test]
+	fn cross_move_is_allowed_op() {
+		let auth_op = TokenAuthOp::new(APP, USER, "cross_move").unwrap();
+		let allowed: HashSet<AllowedOp> = auth_op.clone().into();
+		assert!(allowed.contains(&AllowedOp::CrossMove));
+		assert!(auth_op
+			.check_auth(USER, APP, AllowedOp::CrossMove, TAPPSTORE)
+			.is_ok());
+		assert!(TokenAuthOp::new(APP, USER, "move")
+			.unwrap()
+			.check_auth(USER, APP, AllowedOp::CrossMove, TAPPSTORE)
+			.is_err());
+		assert!(auth_op
+			.check_auth(USER, APP, AllowedOp::Move, TAPPSTORE)
+			.is_err());
+		assert!(TokenAuthOp::new(APP, USER, "read, bonding_curve")
+			.unwrap()
+			.check_auth(USER, APP, AllowedOp::Move, TAPPSTORE)
+			.is_err());
+	}
+}
@@ -0,0 +1,426 @@
+//! Typed authorization policies a user signs when logging in a tApp.
+//!
+//! The text form is a list of items separated by `,`:
+//! - `read`, `move`, `withdraw`, `consume`, `bonding_curve` or `cross_move` grants the op.
+//! - `move<=1000/86400` grants the op with a cap of 1000 per 86400 seconds.
+//! - `counterpart=0x..` only allows funds to move to the listed accounts, repeatable.
+//! - `target=0x..` only allows `cross_move` into the listed tokens, repeatable.
+//! - `expire=<ts>` voids the policy from the timestamp in nanoseconds on.
+
+use super::{auth_items as items, parse_op, AllowedOp, TokenAuthOp};
+use crate::actor_txns::context::{concurrent::ConcurrentBalances, AssetContext};
+use crate::actor_txns::error::{Result, TxnError};
+use crate::tapp::{Account, Balance, TokenId, Ts};
+use serde::{Deserialize, Serialize};
+use tea_sdk::serde::{get_type_id, FromBytes, ToBytes, TypeId};
+
+const NANOS_PER_SEC: Ts = 1_000_000_000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthPolicy {
+	pub token_id: TokenId,
+	pub acct: Account,
+	pub grants: Vec<Grant>,
+	/// Accounts that funds are allowed to move to, any account if empty.
+	pub counterparts: Vec<Account>,
+	/// Tokens that `CrossMove` is allowed to move into, any token if empty.
+	pub cross_move_targets: Vec<TokenId>,
+	pub expire_at: Option<Ts>,
+}
+
+/// The auth ops bytes of an auth key granted policies rather than plain auth ops, see
+/// [`encode_policies`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+pub struct AuthPolicies(pub Vec<AuthPolicy>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+	pub op: AllowedOp,
+	pub cap: Option<SpendingCap>,
+}
+
+/// At most `amount` can be spent within every `period_secs` long window since the unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendingCap {
+	pub amount: Balance,
+	pub period_secs: u64,
+}
+
+/// A debit to be checked against a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Debit {
+	pub token_id: TokenId,
+	pub op: AllowedOp,
+	pub amount: Balance,
+	pub counterparts: Vec<Account>,
+	/// The token moved into, for `CrossMove` only.
+	pub target: Option<TokenId>,
+}
+
+/// Amounts already spent under the capped grants of a policy, kept by the state along with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendingUsage {
+	/// The op, the start of its current window and the amount spent in it.
+	entries: Vec<(AllowedOp, Ts, Balance)>,
+}
+
+impl SpendingUsage {
+	pub fn spent(&self, op: AllowedOp, window: Ts) -> Balance {
+		self.entries
+			.iter()
+			.find(|(x, w, _)| *x == op && *w == window)
+			.map(|(_, _, spent)| *spent)
+			.unwrap_or_default()
+	}
+
+	fn set(&mut self, op: AllowedOp, window: Ts, spent: Balance) {
+		match self.entries.iter_mut().find(|(x, _, _)| *x == op) {
+			Some(entry) => *entry = (op, window, spent),
+			None => self.entries.push((op, window, spent)),
+		}
+	}
+}
+
+impl SpendingCap {
+	fn window(&self, now: Ts) -> Ts {
+		let period = (self.period_secs as Ts)
+			.saturating_mul(NANOS_PER_SEC)
+			.max(1);
+		now - now % period
+	}
+}
+
+impl AuthPolicy {
+	pub fn parse(token_id: TokenId, acct: Account, expr: &str) -> Result<Self> {
+		let mut result = AuthPolicy {
+			token_id,
+			acct,
+			..Default::default()
+		};
+		for item in items(expr) {
+			if let Some((key, value)) = item.split_once('=').filter(|_| !item.contains("<=")) {
+				match key.trim() {
+					"counterpart" => result.counterparts.push(
+						value
+							.trim()
+							.parse()
+							.map_err(|_| invalid(item, "bad account"))?,
+					),
+					"target" => result.cross_move_targets.push(
+						TokenId::from_hex(value.trim())
+							.map_err(|_| invalid(item, "bad token id"))?,
+					),
+					"expire" => {
+						result.expire_at = Some(
+							value
+								.trim()
+								.parse()
+								.map_err(|_| invalid(item, "bad timestamp"))?,
+						)
+					}
+					_ => return Err(invalid(item, "unknown key")),
+				}
+				continue;
+			}
+
+			let (op, cap) = match item.split_once("<=") {
+				Some((op, cap)) => (op, Some(parse_cap(item, cap)?)),
+				None => (item, None),
+			};
+			let op = parse_op(op).ok_or_else(|| invalid(item, "unknown op"))?;
+			if result.grant(op).is_some() {
+				return Err(invalid(item, "op granted twice"));
+			}
+			result.grants.push(Grant { op, cap });
+		}
+		Ok(result)
+	}
+
+	pub fn grant(&self, op: AllowedOp) -> Option<&Grant> {
+		self.grants.iter().find(|x| x.op == op)
+	}
+
+	pub fn is_expired(&self, now: Ts) -> bool {
+		self.expire_at.map_or(false, |t| t <= now)
+	}
+
+	/// Check the debit against the policy, and record it into `usage` if allowed.
+	pub fn check(&self, debit: &Debit, now: Ts, usage: &mut SpendingUsage) -> Result<()> {
+		if let Some(expire_at) = self.expire_at.filter(|_| self.is_expired(now)) {
+			return Err(TxnError::AuthPolicyExpired(expire_at).into());
+		}
+		let Some(grant) = self
+			.grant(debit.op)
+			.filter(|_| debit.token_id == self.token_id)
+		else {
+			return Err(TxnError::AuthCheckFailed(debit.token_id, self.acct, debit.op).into());
+		};
+		if debit.op == AllowedOp::CrossMove && !self.cross_move_targets.is_empty() {
+			match debit.target {
+				Some(target) if self.cross_move_targets.contains(&target) => {}
+				target => {
+					return Err(TxnError::AuthTargetNotAllowed(target.unwrap_or_default()).into())
+				}
+			}
+		}
+		if !self.counterparts.is_empty() {
+			if let Some(acct) = debit
+				.counterparts
+				.iter()
+				.find(|x| !self.counterparts.contains(x))
+			{
+				return Err(TxnError::AuthCounterpartNotAllowed(*acct).into());
+			}
+		}
+		if let Some(cap) = &grant.cap {
+			let window = cap.window(now);
+			let spent = usage.spent(debit.op, window).saturating_add(debit.amount);
+			if spent > cap.amount {
+				return Err(TxnError::AuthCapExceeded(debit.op, cap.amount, spent).into());
+			}
+			usage.set(debit.op, window, spent);
+		}
+		Ok(())
+	}
+
+	/// Check the debits of the policy account in `balances`, counting every other credited
+	/// account as a counterpart. `target` is the token moved into by `CrossMove`.
+	#[allow(clippy::too_many_arguments)]
+	pub fn check_balances(
+		&self,
+		token_id: TokenId,
+		op: AllowedOp,
+		target: Option<TokenId>,
+		balances: &ConcurrentBalances,
+		now: Ts,
+		usage: &mut SpendingUsage,
+	) -> Result<()> {
+		let amount = balances
+			.get_token_subtracts()
+			.get(&self.acct)
+			.map(|x| x.iter().fold(Balance::zero(), |a, b| a.saturating_add(*b)))
+			.unwrap_or_default();
+		if amount.is_zero() {
+			return Ok(());
+		}
+		let mut counterparts = balances
+			.get_token_adds()
+			.keys()
+			.filter(|x| **x != self.acct)
+			.copied()
+			.collect::<Vec<_>>();
+		counterparts.sort();
+		self.check(
+			&Debit {
+				token_id,
+				op,
+				amount,
+				counterparts,
+				target,
+			},
+			now,
+			usage,
+		)
+	}
+}
+
+impl From<&AuthPolicy> for TokenAuthOp {
+	fn from(policy: &AuthPolicy) -> Self {
+		let has = |op| policy.grant(op).is_some();
+		TokenAuthOp {
+			token_id: policy.token_id,
+			acct: policy.acct,
+			read: has(AllowedOp::Read),
+			mov: has(AllowedOp::Move),
+			withdraw: has(AllowedOp::Withdraw),
+			consume: has(AllowedOp::Consume),
+			bonding_curve: has(AllowedOp::BondingCurve),
+			cross_move: has(AllowedOp::CrossMove),
+		}
+	}
+}
+
+/// Encodes the policies as the auth ops bytes of `SetAuthOpsBytesRequest`, which are told apart
+/// from plain auth ops by the type id of [`AuthPolicies`] in front.
+pub fn encode_policies(policies: &[AuthPolicy]) -> Result<Vec<u8>> {
+	AuthPolicies(policies.to_vec())
+		.to_bytes()
+		.map_err(|e| TxnError::InvalidAuthPolicy(e.to_string()).into())
+}
+
+pub fn decode_policies(buf: &[u8]) -> Result<Vec<AuthPolicy>> {
+	AuthPolicies::from_bytes(buf)
+		.map(|x| x.0)
+		.map_err(|e| TxnError::InvalidAuthPolicy(e.to_string()).into())
+}
+
+/// Whether the auth ops bytes are policies encoded with [`encode_policies`].
+pub fn is_policies(buf: &[u8]) -> bool {
+	get_type_id(buf).map_or(false, |x| x == AuthPolicies::TYPE_ID)
+}
+
+fn parse_cap(item: &str, cap: &str) -> Result<SpendingCap> {
+	let (amount, period) = cap
+		.split_once('/')
+		.ok_or_else(|| invalid(item, "cap without period"))?;
+	Ok(SpendingCap {
+		amount: Balance::from_dec_str(amount.trim()).map_err(|_| invalid(item, "bad amount"))?,
+		period_secs: period
+			.trim()
+			.parse()
+			.ok()
+			.filter(|x| *x > 0)
+			.ok_or_else(|| invalid(item, "bad period"))?,
+	})
+}
+
+fn invalid(item: &str, reason: &str) -> crate::actor_txns::error::Error {
+	TxnError::InvalidAuthPolicy(format!("{item}: {reason}")).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const USER: Account = Account::repeat_byte(1);
+	const SHOP: Account = Account::repeat_byte(6);
+	const APP: TokenId = TokenId(Account::repeat_byte(2));
+	const OTHER_APP: TokenId = TokenId(Account::repeat_byte(3));
+
+	fn debit(op: AllowedOp, amount: u64) -> Debit {
+		Debit {
+			token_id: APP,
+			op,
+			amount: amount.into(),
+			counterparts: vec![SHOP],
+			target: None,
+		}
+	}
+
+	#[test]
+	fn parse_rejects_substrings() {
+		assert!(AuthPolicy::parse(APP, USER, "remove").is_err());
+		assert!(AuthPolicy::parse(APP, USER, "move<=10").is_err());
+		assert!(AuthPolicy::parse(APP, USER, "read,read").is_err());
+
+		let policy = AuthPolicy::parse(
+			APP,
+			USER,
+			&format!("read, move<=100/60, cross_move, target={OTHER_APP:?}, expire=5000"),
+		)
+		.unwrap();
+		assert_eq!(policy.grants.len(), 3);
+		assert_eq!(
+			policy.grant(AllowedOp::Move).unwrap().cap,
+			Some(SpendingCap {
+				amount: 100.into(),
+				period_secs: 60,
+			})
+		);
+		assert_eq!(policy.cross_move_targets, vec![OTHER_APP]);
+		assert_eq!(policy.expire_at, Some(5000));
+
+		let auth_op = TokenAuthOp::from(&policy);
+		assert!(auth_op.read && auth_op.mov && auth_op.cross_move && !auth_op.withdraw);
+	}
+
+	#[test]
+	fn caps_reset_every_period() {
+		let policy = AuthPolicy::parse(APP, USER, "move<=100/60").unwrap();
+		let mut usage = SpendingUsage::default();
+		policy
+			.check(&debit(AllowedOp::Move, 60), 0, &mut usage)
+			.unwrap();
+		assert_eq!(
+			policy.check(&debit(AllowedOp::Move, 60), 10 * NANOS_PER_SEC, &mut usage),
+			Err(TxnError::AuthCapExceeded(AllowedOp::Move, 100.into(), 120.into()).into())
+		);
+		policy
+			.check(&debit(AllowedOp::Move, 60), 60 * NANOS_PER_SEC, &mut usage)
+			.unwrap();
+		assert!(policy
+			.check(&debit(AllowedOp::Withdraw, 1), 0, &mut usage)
+			.is_err());
+	}
+
+	#[test]
+	fn counterparts_targets_and_expiry() {
+		let policy = AuthPolicy::parse(
+			APP,
+			USER,
+			&format!("move, cross_move, counterpart={USER:?}, target={APP:?}, expire=100"),
+		)
+		.unwrap();
+		let mut usage = SpendingUsage::default();
+		assert_eq!(
+			policy.check(&debit(AllowedOp::Move, 1), 0, &mut usage),
+			Err(TxnError::AuthCounterpartNotAllowed(SHOP).into())
+		);
+		let cross_move = Debit {
+			counterparts: vec![],
+			target: Some(OTHER_APP),
+			..debit(AllowedOp::CrossMove, 1)
+		};
+		assert_eq!(
+			policy.check(&cross_move, 0, &mut usage),
+			Err(TxnError::AuthTargetNotAllowed(OTHER_APP).into())
+		);
+		assert_eq!(
+			policy.check(&debit(AllowedOp::Move, 1), 100, &mut usage),
+			Err(TxnError::AuthPolicyExpired(100).into())
+		);
+	}
+
+	#[test]
+	fn check_concurrent_balances() {
+		let policy = AuthPolicy::parse(APP, USER, "move<=10/60").unwrap();
+		let mut balances = ConcurrentBalances::default();
+		balances.add_token_subtract(USER, 8.into());
+		balances.add_token_add(SHOP, 8.into());
+		let mut usage = SpendingUsage::default();
+		policy
+			.check_balances(APP, AllowedOp::Move, None, &balances, 0, &mut usage)
+			.unwrap();
+		assert!(policy
+			.check_balances(APP, AllowedOp::Move, None, &balances, 0, &mut usage)
+			.is_err());
+
+		let policy =
+			AuthPolicy::parse(APP, USER, &format!("cross_move, target={OTHER_APP:?}")).unwrap();
+		let mut balances = ConcurrentBalances::default();
+		balances.add_token_subtract(USER, 8.into());
+		policy
+			.check_balances(
+				APP,
+				AllowedOp::CrossMove,
+				Some(OTHER_APP),
+				&balances,
+				0,
+				&mut usage,
+			)
+			.unwrap();
+		assert_eq!(
+			policy.check_balances(
+				APP,
+				AllowedOp::CrossMove,
+				Some(APP),
+				&balances,
+				0,
+				&mut usage
+			),
+			Err(TxnError::AuthTargetNotAllowed(APP).into())
+		);
+	}
+
+	#[test]
+	fn encode_roundtrip() {
+		let policies = vec![AuthPolicy::parse(APP, USER, "read, consume<=5/3600").unwrap()];
+		let bytes = encode_policies(&policies).unwrap();
+		assert!(is_policies(&bytes));
+		assert_eq!(decode_policies(&bytes).unwrap(), policies);
+
+		let auth_ops = tea_sdk::serialize(&vec![TokenAuthOp::from(&policies[0])]).unwrap();
+		assert!(!is_policies(&auth_ops));
+		assert!(decode_policies(&auth_ops).is_err());
+	}
+}
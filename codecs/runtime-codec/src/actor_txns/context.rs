@@ -1,18 +1,27 @@
 use self::concurrent::ConcurrentBalances;
+use self::conflict::{BalanceCategory, ConflictReport};
 use self::payment_channel::PaymentChannelContextImpl;
-use crate::actor_txns::auth::AllowedOp;
+use self::storage::StorageMerge;
+use crate::actor_txns::auth::policy::{AuthPolicy, SpendingUsage};
+use crate::actor_txns::auth::{check_auth_ops, deserialize_auth_ops, AllowedOp, TokenAuthOpV0};
 use crate::actor_txns::error::{Result, TxnError};
+use crate::actor_txns::receipt::TxnEvent;
 use crate::actor_txns::{auth::TokenAuthOp, tsid::Tsid};
 use crate::tapp::{Account, AuthKey, Balance, ChannelId, ChannelItem, TokenId};
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::collections::{HashMap, HashSet, VecDeque};
+use tea_sdk::serde::TypeId;
 use tea_sdk::{deserialize, serialize};
 
 use super::error::Error;
 
+pub mod bundle;
 pub mod concurrent;
+pub mod conflict;
 pub mod payment_channel;
+pub mod simulator;
+pub mod storage;
 
 #[doc(hidden)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
@@ -147,8 +156,13 @@ where
 	/// can be done to rebase.
 	fn rebase(&mut self, other: &Self) -> Result<()>;
 
-	// /// Check authorized operation matches or not
-	fn check_auth(&self, ask_acct: Account, ask_ops: Vec<AllowedOp>) -> Result<()>;
+	/// Check authorized operation matches or not
+	fn check_auth(
+		&self,
+		ask_acct: Account,
+		ask_ops: Vec<AllowedOp>,
+		tappstore_id: TokenId,
+	) -> Result<()>;
 
 	fn bonding_context(&self) -> &C;
 
@@ -195,6 +209,10 @@ pub trait TrimContext {
 /// state, until final commit
 /// if commit fail, nothing will change in the state.
 /// if commit succeed, everything will be write into the state.
+///
+/// `storage_merges` and `events` were appended to the layout after `payment_channels`.
+/// Contexts encoded before are still read by [`TokenContext::decode_bytes`], but nodes from
+/// before cannot read the contexts encoded now, so every node has to be upgraded together.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TokenContext {
 	/// Token Id, this context has and has only one tokenid related
@@ -243,12 +261,63 @@ pub struct TokenContext {
 	allowance_tid: Option<TokenId>,
 
 	payment_channels: PaymentChannelContextImpl,
+
+	/// Changes of the storage entries that are not `MergeStrategy::Exclusive`. Unlike
+	/// `storage_changes` they do not conflict with the same kind of changes made by other
+	/// contexts, see `merge_storage`.
+	storage_merges: HashMap<TappStorageType, StorageMerge>,
+
+	/// Typed events emitted by the txn in order, they are not checked for conflicts and end up
+	/// in the `TxnReceipt` of the txn once committed.
+	events: Vec<TxnEvent>,
+}
+
+/// The layout of [`TokenContext`] before `storage_merges` and `events` were added.
+#[derive(Deserialize)]
+struct TokenContextV0 {
+	tid: TokenId,
+	tsid: Tsid,
+	base: Tsid,
+	reads_storage: HashSet<TappStorageType>,
+	storage_changes: HashMap<TappStorageType, VecDeque<Option<Vec<u8>>>>,
+	tea: ConcurrentBalances,
+	deposit: ConcurrentBalances,
+	bonding: ConcurrentBalances,
+	allowance: ConcurrentBalances,
+	credit: ConcurrentBalances,
+	auth_ops: Vec<TokenAuthOpV0>,
+	allowance_tid: Option<TokenId>,
+	payment_channels: PaymentChannelContextImpl,
+}
+
+impl From<TokenContextV0> for TokenContext {
+	fn from(ctx: TokenContextV0) -> Self {
+		TokenContext {
+			tid: ctx.tid,
+			tsid: ctx.tsid,
+			base: ctx.base,
+			reads_storage: ctx.reads_storage,
+			storage_changes: ctx.storage_changes,
+			tea: ctx.tea,
+			deposit: ctx.deposit,
+			bonding: ctx.bonding,
+			allowance: ctx.allowance,
+			credit: ctx.credit,
+			auth_ops: ctx.auth_ops.into_iter().map(Into::into).collect(),
+			allowance_tid: ctx.allowance_tid,
+			payment_channels: ctx.payment_channels,
+			storage_merges: Default::default(),
+			events: Default::default(),
+		}
+	}
 }
 
 impl TrimContext for TokenContext {
 	fn trim_context(&mut self) {
 		self.reads_storage = Default::default();
 		self.storage_changes = Default::default();
+		self.storage_merges = Default::default();
+		self.events = Default::default();
 		self.auth_ops = Default::default();
 
 		if !self.tea.is_balance_related() {
@@ -286,12 +355,10 @@ impl IsBalanceRelated for TokenContext {
 impl CheckConflict for TokenContext {
 	fn check_conflict(&self, other: &Self) -> Result<()> {
 		self.check_base_conflict(other)?;
-		self.check_storage_conflict(other)?;
-		self.tea.check_conflict(&other.tea)?;
-		self.deposit.check_conflict(&other.deposit)?;
-		self.bonding.check_conflict(&other.bonding)?;
-		// self.bonding_reserved
-		// 	.check_conflict(&other.bonding_reserved)?;
+		let report = self.analyze_conflicts(other);
+		if report.is_blocking() {
+			return Err(TxnError::ContextConflict(Box::new(report)).into());
+		}
 		Ok(())
 	}
 }
@@ -331,6 +398,7 @@ impl Context<ConcurrentBalances, PaymentChannelContextImpl> for TokenContext {
 	}
 
 	fn set_storage(&mut self, storage_type: TappStorageType, value: Option<Vec<u8>>) {
+		self.storage_merges.remove(&storage_type);
 		if let Some(changes) = self.storage_changes.get_mut(&storage_type) {
 			changes.push_back(value);
 		} else {
@@ -350,6 +418,8 @@ impl Context<ConcurrentBalances, PaymentChannelContextImpl> for TokenContext {
 	/// You can commit first. After your commit, the state machien tsid will be yours.
 	/// then I commit. Because my base is already rebased yours, there is no
 	/// conflict when I commit. The state machine is happy accepting both of us.
+	/// If we collide, the error is `TxnError::ContextConflict` carrying everything
+	/// that collided, which is why I have to be rerun.
 	fn rebase(&mut self, other: &Self) -> Result<()> {
 		self.check_conflict(other)?;
 
@@ -361,38 +431,22 @@ impl Context<ConcurrentBalances, PaymentChannelContextImpl> for TokenContext {
 		// right now , there is no conflict, I can simple rebase to other tsid
 		// if any of the conditions above not met, we cannot run into this step
 		// and a rerun could be the only solution instead of rebase
+		let merged = self.rebased_storage_merges(other)?;
+		self.set_rebased_storage(merged);
 		self.base = other.get_tsid();
 		Ok(())
 	}
 
-	fn check_auth(&self, _acct: Account, _ask_ops: Vec<AllowedOp>) -> Result<()> {
-		// todo disable check auth for now
+	fn check_auth(
+		&self,
+		ask_acct: Account,
+		ask_ops: Vec<AllowedOp>,
+		tappstore_id: TokenId,
+	) -> Result<()> {
+		for ask_op in ask_ops {
+			check_auth_ops(&self.auth_ops, ask_acct, self.tid, ask_op, tappstore_id)?;
+		}
 		Ok(())
-		// // let token_auth_ops = self.auth_ops;
-		// // checking debit auth ops
-		// debug!(
-		// 	"context check auth ask_ops: {:?}, user_login_auth_ops: {:?}",
-		// 	&ask_ops, &self.auth_ops
-		// );
-		// debug!("tid:{:?} - {:?} ", &self.tid, tappstore_id()?);
-		// debug!("acct:{:?}", acct);
-		// for ask_op in ask_ops {
-		// 	let mut auth_ok = false;
-		// 	for token_auth_op in &self.auth_ops {
-		// 		// if token_auth_op.acct == acct  // this will failed when sell_token.
-		// 		if self.tid == tappstore_id()? || token_auth_op.token_id == tappstore_id()? {
-		// 			debug!("now check token_auth_op.check_auth");
-		// 			// token_auth_op.check_auth(acct, self.tid, ask_op)?;
-		// 			token_auth_op.check_auth(acct, tappstore_id()?, ask_op)?;
-		// 			auth_ok = true;
-		// 		}
-		// 	} //for token auth ops
-		// 	if auth_ok == false {
-		// 		error!("auth check failed");
-		// 		return Err(ContextError::AuthCheckFailed(self.tid, acct, ask_op));
-		// 	}
-		// } // for ask ops
-		// Ok(())
 	}
 
 	fn bonding_context(&self) -> &ConcurrentBalances {
@@ -455,7 +509,7 @@ impl TokenContext {
 			//TODO: We should remove all zero length auth_ops_bytes cases
 			Vec::new()
 		} else {
-			deserialize(auth_ops_bytes)?
+			deserialize_auth_ops(auth_ops_bytes)?
 		};
 
 		Ok(TokenContext {
@@ -467,6 +521,15 @@ impl TokenContext {
 		})
 	}
 
+	/// Decodes a context encoded with `serialize`, also one encoded before `storage_merges`
+	/// and `events` were added.
+	pub fn decode_bytes(buf: &[u8]) -> tea_sdk::Result<Self> {
+		deserialize(buf).or_else(|e| {
+			let ctx: TokenContextV0 = deserialize(buf).map_err(|_| e)?;
+			Ok(ctx.into())
+		})
+	}
+
 	pub fn new_slim(tsid: Tsid, base: Tsid, tid: TokenId) -> Self {
 		TokenContext {
 			tsid,
@@ -486,6 +549,66 @@ impl TokenContext {
 		}
 	}
 
+	/// The operations every debited account has to authorize for the changes of this context.
+	/// Reads and credits need no authorization.
+	pub fn required_auth_ops(&self) -> HashSet<(Account, AllowedOp)> {
+		let mut result = HashSet::new();
+		for (balances, op) in [
+			(&self.tea, AllowedOp::Move),
+			(&self.deposit, AllowedOp::Move),
+			(&self.bonding, AllowedOp::BondingCurve),
+			(&self.credit, AllowedOp::Consume),
+			(&self.allowance, AllowedOp::CrossMove),
+		] {
+			for acct in balances.get_token_subtracts().keys() {
+				result.insert((*acct, op));
+			}
+		}
+		for item in self.payment_channels.get_new_channels().values() {
+			result.insert((item.payer_address, AllowedOp::Consume));
+		}
+		result
+	}
+
+	/// Verify the changes of this context against `auth_ops` at the time of commit.
+	///
+	/// The `auth_ops` should be read again from the auth key instead of the ones
+	/// carried by this context, which the stored procedure actor could have changed.
+	pub fn verify_auth(&self, auth_ops: &[TokenAuthOp], tappstore_id: TokenId) -> Result<()> {
+		let mut required = self.required_auth_ops().into_iter().collect::<Vec<_>>();
+		required.sort_by_key(|(acct, op)| (*acct, *op as u8));
+		for (acct, op) in required {
+			check_auth_ops(auth_ops, acct, self.tid, op, tappstore_id)?;
+		}
+		Ok(())
+	}
+
+	/// Verify the debits of this context against the `policies` of the auth key at the time
+	/// of commit, in addition to [`Self::verify_auth`], spending the capped grants in `usage`.
+	///
+	/// Only the policies of this token are checked. The spending of earlier txns is not
+	/// known at commit, so the caps bound what a single commit can spend.
+	pub fn verify_policies(
+		&self,
+		policies: &[AuthPolicy],
+		usage: &mut SpendingUsage,
+	) -> Result<()> {
+		let now = self.tsid.ts;
+		for policy in policies.iter().filter(|x| x.token_id == self.tid) {
+			for (balances, op) in [
+				(&self.tea, AllowedOp::Move),
+				(&self.deposit, AllowedOp::Move),
+				(&self.bonding, AllowedOp::BondingCurve),
+				(&self.credit, AllowedOp::Consume),
+				(&self.allowance, AllowedOp::CrossMove),
+			] {
+				let target = self.allowance_tid.filter(|_| op == AllowedOp::CrossMove);
+				policy.check_balances(self.tid, op, target, balances, now, usage)?;
+			}
+		}
+		Ok(())
+	}
+
 	fn check_base_conflict(&self, other: &Self) -> Result<()> {
 		if self.tsid == other.get_tsid() {
 			return Err(TxnError::ShouldNotCheckSameTsid.into());
@@ -508,21 +631,140 @@ impl TokenContext {
 		Ok(())
 	}
 
-	fn check_storage_conflict(&self, other: &Self) -> Result<()> {
-		let mut touched_storage = self.reads_storage.clone();
-		for (t, _) in self.storage_changes.iter() {
-			touched_storage.insert(*t);
+	/// Collects every storage item, account and payment channel of mine that collides with
+	/// `other`. Unlike `check_conflict` it does not check the bases, and I can be rebased onto
+	/// `other` unless the report [`is_blocking`](ConflictReport::is_blocking).
+	pub fn analyze_conflicts(&self, other: &Self) -> ConflictReport {
+		let mut report = ConflictReport::new(self.tsid, other.get_tsid());
+		report.storage = self.storage_conflicts(other);
+		report.add_balances(BalanceCategory::Tea, self.tea.conflicts(&other.tea));
+		report.add_balances(
+			BalanceCategory::Deposit,
+			self.deposit.conflicts(&other.deposit),
+		);
+		report.add_balances(
+			BalanceCategory::Bonding,
+			self.bonding.conflicts(&other.bonding),
+		);
+		report.add_balances(
+			BalanceCategory::Allowance,
+			self.allowance.conflicts(&other.allowance),
+		);
+		report.add_balances(
+			BalanceCategory::Credit,
+			self.credit.conflicts(&other.credit),
+		);
+		report.payment_channels = self.payment_channels.conflicts(&other.payment_channels);
+		report.sort();
+		report
+	}
+
+	/// Records a mergeable change of a storage entry, folding it into my earlier changes of
+	/// the same entry. The change must match the `merge_strategy` declared by the entry.
+	///
+	/// The change is also applied to the value of the entry, which is `base` (the value at my
+	/// base) unless I changed it already, so the result is committed as a storage set. It is
+	/// applied again onto the value committed by the context I am rebased onto, see
+	/// `Context::rebase`. An entry I set with `set_storage` stays an exclusive change.
+	pub fn merge_storage(
+		&mut self,
+		storage_type: TappStorageType,
+		base: Option<Vec<u8>>,
+		change: StorageMerge,
+	) -> Result<()> {
+		if storage_type.merge_strategy() != change.strategy() {
+			return Err(TxnError::StorageMergeMismatch(storage_type, change.strategy()).into());
 		}
+		let current = match self.get_storage(storage_type) {
+			Ok(value) => value.clone(),
+			Err(_) => base,
+		};
+		let value = apply_merge(&change, current)?;
+		let exclusive = self.storage_changes.contains_key(&storage_type)
+			&& !self.storage_merges.contains_key(&storage_type);
+		if !exclusive {
+			match self.storage_merges.get_mut(&storage_type) {
+				Some(merged) => merged.merge(storage_type, &change)?,
+				None => {
+					self.storage_merges.insert(storage_type, change);
+				}
+			}
+		}
+		self.storage_changes
+			.entry(storage_type)
+			.or_default()
+			.push_back(value);
+		Ok(())
+	}
 
-		for t in touched_storage {
-			if other.reads_storage.contains(&t) || other.storage_changes.contains_key(&t) {
-				return Err(TxnError::StorageHasBeTouched(t).into());
+	/// The values of my merged storage entries after applying my merges again onto the
+	/// values `other` committed, so that its concurrent merges of the same entries are kept.
+	pub(crate) fn rebased_storage_merges(
+		&self,
+		other: &Self,
+	) -> Result<Vec<(TappStorageType, Option<Vec<u8>>)>> {
+		let mut result = Vec::new();
+		for (t, merge) in self.storage_merges.iter() {
+			if let Ok(committed) = other.get_storage(*t) {
+				result.push((*t, apply_merge(merge, committed.clone())?));
 			}
 		}
+		Ok(result)
+	}
+
+	pub(crate) fn set_rebased_storage(&mut self, values: Vec<(TappStorageType, Option<Vec<u8>>)>) {
+		for (t, value) in values {
+			self.storage_changes.insert(t, VecDeque::from(vec![value]));
+		}
+	}
+
+	pub fn get_storage_merges(&self) -> &HashMap<TappStorageType, StorageMerge> {
+		&self.storage_merges
+	}
 
+	/// Emits a typed event, see `TxnEvent`.
+	pub fn emit_event<E>(&mut self, event: &E) -> tea_sdk::Result<()>
+	where
+		E: TypeId + Serialize,
+	{
+		self.events.push(TxnEvent::new(event)?);
 		Ok(())
 	}
 
+	pub fn get_events(&self) -> &[TxnEvent] {
+		&self.events
+	}
+
+	/// Storage entries I changed with `set_storage` rather than only with `merge_storage`.
+	fn exclusive_storage_changes(&self) -> impl Iterator<Item = &TappStorageType> {
+		self.storage_changes
+			.keys()
+			.filter(|t| !self.storage_merges.contains_key(t))
+	}
+
+	fn storage_conflicts(&self, other: &Self) -> Vec<TappStorageType> {
+		let mut touched_storage = self.reads_storage.clone();
+		touched_storage.extend(self.exclusive_storage_changes());
+
+		// reads and exclusive changes conflict with any change of the other context,
+		// while mergeable changes only conflict with its exclusive changes
+		let mut conflicts = touched_storage
+			.into_iter()
+			.filter(|t| {
+				other.reads_storage.contains(t)
+					|| other.storage_changes.contains_key(t)
+					|| other.storage_merges.contains_key(t)
+			})
+			.collect::<HashSet<_>>();
+		let other_exclusive = other.exclusive_storage_changes().collect::<HashSet<_>>();
+		conflicts.extend(
+			self.storage_merges
+				.keys()
+				.filter(|t| other_exclusive.contains(t)),
+		);
+		conflicts.into_iter().collect()
+	}
+
 	pub fn get_current_tsid(&self) -> Tsid {
 		self.get_tsid()
 	}
@@ -582,6 +824,18 @@ impl TokenContext {
 			hasher.update(serialize(v)?);
 		}
 
+		let mut storage_merges = self.storage_merges.iter().collect::<Vec<_>>();
+		storage_merges.sort_by(|a, b| a.0.cmp(b.0));
+		for (k, v) in storage_merges {
+			hasher.update(serialize(k)?);
+			hasher.update(serialize(v)?);
+		}
+
+		// contexts without events hash as they did before events were added
+		if !self.events.is_empty() {
+			hasher.update(serialize(&self.events)?);
+		}
+
 		self.tea.hash(hasher)?;
 		self.deposit.hash(hasher)?;
 		self.bonding.hash(hasher)?;
@@ -595,3 +849,286 @@ impl TokenContext {
 		Ok(())
 	}
 }
+
+fn apply_merge(merge: &StorageMerge, current: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+	merge
+		.apply(current)
+		.map_err(|e| Error::Unnamed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::conflict::BalanceConflictKind;
+	use super::storage::MergeStrategy;
+	use super::*;
+
+	const USER: Account = Account::repeat_byte(1);
+	const VICTIM: Account = Account::repeat_byte(5);
+	const APP: TokenId = TokenId(Account::repeat_byte(2));
+	const TAPPSTORE: TokenId = TokenId(Account::repeat_byte(4));
+
+	fn context(auth_expr: &str) -> TokenContext {
+		let auth_ops = serialize(&vec![TokenAuthOp::new(APP, USER, auth_expr).unwrap()]).unwrap();
+		TokenContext::new(Tsid::default(), Tsid::default(), APP, &auth_ops).unwrap()
+	}
+
+	#[test]
+	fn check_auth_uses_context_auth_ops() {
+		let ctx = context("read,move");
+		assert!(ctx
+			.check_auth(USER, vec![AllowedOp::Read, AllowedOp::Move], TAPPSTORE)
+			.is_ok());
+		assert!(ctx
+			.check_auth(USER, vec![AllowedOp::Read, AllowedOp::Withdraw], TAPPSTORE)
+			.is_err());
+		assert!(ctx
+			.check_auth(VICTIM, vec![AllowedOp::Read], TAPPSTORE)
+			.is_err());
+	}
+
+	#[test]
+	fn verify_auth_checks_every_debit() {
+		let mut ctx = context("move");
+		ctx.tea_context_mut().add_token_subtract(USER, 10.into());
+		ctx.tea_context_mut().add_token_add(VICTIM, 10.into());
+		let auth_ops = vec![TokenAuthOp::new(APP, USER, "move").unwrap()];
+		assert!(ctx.verify_auth(&auth_ops, TAPPSTORE).is_ok());
+
+		ctx.bonding_context_mut().add_token_subtract(USER, 1.into());
+		assert_eq!(
+			ctx.verify_auth(&auth_ops, TAPPSTORE),
+			Err(TxnError::AuthCheckFailed(APP, USER, AllowedOp::BondingCurve).into())
+		);
+
+		let mut ctx = context("move");
+		ctx.deposit_context_mut()
+			.add_token_subtract(VICTIM, 1.into());
+		assert!(ctx.verify_auth(&auth_ops, TAPPSTORE).is_err());
+	}
+
+	#[test]
+	fn verify_policies_checks_caps_and_targets() {
+		const OTHER_APP: TokenId = TokenId(Account::repeat_byte(3));
+		let policies = vec![AuthPolicy::parse(
+			APP,
+			USER,
+			&format!("move<=10/60, cross_move, target={OTHER_APP:?}"),
+		)
+		.unwrap()];
+
+		let mut ctx =
+			TokenContext::new_cross_move(Tsid::default(), Tsid::default(), APP, OTHER_APP);
+		ctx.allowance_context_mut()
+			.add_token_subtract(USER, 5.into());
+		ctx.tea_context_mut().add_token_subtract(USER, 10.into());
+		assert!(ctx
+			.verify_policies(&policies, &mut SpendingUsage::default())
+			.is_ok());
+
+		ctx.deposit_context_mut().add_token_subtract(USER, 1.into());
+		assert!(ctx
+			.verify_policies(&policies, &mut SpendingUsage::default())
+			.is_err());
+
+		let mut ctx = TokenContext::new_cross_move(Tsid::default(), Tsid::default(), APP, APP);
+		ctx.allowance_context_mut()
+			.add_token_subtract(USER, 5.into());
+		assert_eq!(
+			ctx.verify_policies(&policies, &mut SpendingUsage::default()),
+			Err(TxnError::AuthTargetNotAllowed(APP).into())
+		);
+	}
+
+	#[test]
+	fn contexts_encoded_before_merges_and_events_decode() {
+		let mut ctx = TokenContext::new_slim(tsid_at(3), Tsid::default(), APP);
+		ctx.tea_context_mut().add_token_subtract(USER, 10.into());
+		let mut buf = serialize(&ctx).unwrap();
+		// without auth ops, the old layout only lacks the lengths of the two empty fields
+		buf.truncate(buf.len() - 16);
+		assert!(deserialize::<TokenContext, _>(&buf).is_err());
+
+		let decoded = TokenContext::decode_bytes(&buf).unwrap();
+		assert_eq!(decoded.tsid, ctx.tsid);
+		assert!(decoded.tea.is_balance_related());
+		assert!(decoded.get_events().is_empty());
+	}
+
+	fn tsid_at(ts: u128) -> Tsid {
+		let mut tsid = Tsid::default();
+		tsid.ts = ts;
+		tsid
+	}
+
+	#[test]
+	fn analyze_conflicts_reports_everything() {
+		let mut earlier = TokenContext::new_slim(tsid_at(1), Tsid::default(), APP);
+		let mut later = TokenContext::new_slim(tsid_at(2), Tsid::default(), APP);
+		assert!(later.analyze_conflicts(&earlier).is_empty());
+
+		earlier.set_storage(TappStorageType::Profile, Some(vec![1]));
+		earlier.tea_context_mut().add_token_subtract(USER, 1.into());
+		earlier.tea_context_mut().add_token_add(VICTIM, 1.into());
+		earlier
+			.credit_context_mut()
+			.add_token_subtract(USER, 1.into());
+		earlier
+			.payment_channel_context_mut()
+			.add_payer_terminate(VICTIM);
+
+		later.add_storage_read(TappStorageType::Profile);
+		later.add_storage_read(TappStorageType::AesKey);
+		later
+			.tea_context_mut()
+			.add_token_read(USER, ReadConflictMode::BothConflict);
+		later
+			.tea_context_mut()
+			.add_token_read(VICTIM, ReadConflictMode::BothConflict);
+		later.tea_context_mut().add_token_subtract(USER, 1.into());
+		later
+			.credit_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.payment_channel_context_mut()
+			.add_payee_terminate(VICTIM);
+
+		let report = later.analyze_conflicts(&earlier);
+		assert_eq!(report.tsid, tsid_at(2));
+		assert_eq!(report.other_tsid, tsid_at(1));
+		assert_eq!(report.storage, vec![TappStorageType::Profile]);
+		let tea = report
+			.balances_of(BalanceCategory::Tea)
+			.map(|x| (x.acct, x.kind))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			tea,
+			vec![
+				(USER, BalanceConflictKind::ReadWhileDebit),
+				(USER, BalanceConflictKind::DoubleDebit),
+				(VICTIM, BalanceConflictKind::ReadWhileCredit),
+			]
+		);
+		assert_eq!(report.balances_of(BalanceCategory::Credit).count(), 1);
+		assert_eq!(report.payment_channels, vec![VICTIM]);
+		assert!(report.is_blocking());
+
+		assert_eq!(
+			later.rebase(&earlier),
+			Err(TxnError::ContextConflict(Box::new(report)).into())
+		);
+		assert_eq!(later.get_base(), Tsid::default());
+	}
+
+	#[test]
+	fn rebase_without_conflicts() {
+		let mut earlier = TokenContext::new_slim(tsid_at(1), Tsid::default(), APP);
+		let mut later = TokenContext::new_slim(tsid_at(2), Tsid::default(), APP);
+		earlier.tea_context_mut().add_token_add(USER, 1.into());
+		later
+			.tea_context_mut()
+			.add_token_read(USER, ReadConflictMode::CreditOk);
+		later.tea_context_mut().add_token_subtract(VICTIM, 1.into());
+		assert!(later.analyze_conflicts(&earlier).is_empty());
+		later.rebase(&earlier).unwrap();
+		assert_eq!(later.get_base(), tsid_at(1));
+	}
+
+	#[test]
+	fn informational_conflicts_rebase() {
+		let mut earlier = TokenContext::new_slim(tsid_at(1), Tsid::default(), APP);
+		let mut later = TokenContext::new_slim(tsid_at(2), Tsid::default(), APP);
+		earlier
+			.allowance_context_mut()
+			.add_token_subtract(USER, 1.into());
+		earlier
+			.credit_context_mut()
+			.add_token_subtract(USER, 1.into());
+		earlier
+			.payment_channel_context_mut()
+			.add_payer_terminate(VICTIM);
+		later
+			.allowance_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.credit_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.payment_channel_context_mut()
+			.add_payee_terminate(VICTIM);
+
+		let report = later.analyze_conflicts(&earlier);
+		assert_eq!(report.balances_of(BalanceCategory::Allowance).count(), 1);
+		assert_eq!(report.balances_of(BalanceCategory::Credit).count(), 1);
+		assert_eq!(report.payment_channels, vec![VICTIM]);
+		assert!(!report.is_blocking());
+		later.rebase(&earlier).unwrap();
+		assert_eq!(later.get_base(), tsid_at(1));
+	}
+
+	#[test]
+	fn mergeable_storage_rebases() {
+		let mut earlier = TokenContext::new_slim(tsid_at(1), Tsid::default(), APP);
+		let mut later = TokenContext::new_slim(tsid_at(2), Tsid::default(), APP);
+		let session = TappStorageType::SessionKey(APP, USER);
+		earlier
+			.merge_storage(
+				TappStorageType::FailedPayments,
+				None,
+				StorageMerge::Append(vec![vec![1]]),
+			)
+			.unwrap();
+		earlier
+			.merge_storage(session, None, StorageMerge::Set(Some(vec![1])))
+			.unwrap();
+		later
+			.merge_storage(
+				TappStorageType::FailedPayments,
+				None,
+				StorageMerge::Append(vec![vec![2]]),
+			)
+			.unwrap();
+		later
+			.merge_storage(
+				TappStorageType::FailedPayments,
+				None,
+				StorageMerge::Append(vec![vec![3]]),
+			)
+			.unwrap();
+		later
+			.merge_storage(session, None, StorageMerge::Set(Some(vec![2])))
+			.unwrap();
+		assert_eq!(
+			later.get_storage_merges()[&TappStorageType::FailedPayments],
+			StorageMerge::Append(vec![vec![2], vec![3]])
+		);
+		later.rebase(&earlier).unwrap();
+		assert_eq!(later.get_base(), tsid_at(1));
+		// my merges are applied again onto what the earlier context committed
+		assert_eq!(
+			later.get_storage(TappStorageType::FailedPayments),
+			Ok(&Some(
+				serialize(&vec![vec![1u8], vec![2], vec![3]]).unwrap()
+			))
+		);
+		assert_eq!(later.get_storage(session), Ok(&Some(vec![2])));
+
+		// an exclusive change of a mergeable entry still conflicts
+		earlier.set_storage(TappStorageType::FailedPayments, None);
+		let report = later.analyze_conflicts(&earlier);
+		assert_eq!(report.storage, vec![TappStorageType::FailedPayments]);
+
+		// so does reading an entry that is merged concurrently
+		let mut reader = TokenContext::new_slim(tsid_at(3), Tsid::default(), APP);
+		reader.add_storage_read(session);
+		assert_eq!(reader.analyze_conflicts(&later).storage, vec![session]);
+
+		assert_eq!(
+			later.merge_storage(TappStorageType::Profile, None, StorageMerge::Set(None)),
+			Err(TxnError::StorageMergeMismatch(
+				TappStorageType::Profile,
+				MergeStrategy::LastWriterWins
+			)
+			.into())
+		);
+	}
+}
@@ -0,0 +1,284 @@
+use super::conflict::ConflictReport;
+use super::{CheckConflict, IsBalanceRelated, TokenContext};
+use crate::actor_txns::auth::policy::{AuthPolicy, SpendingUsage};
+use crate::actor_txns::auth::TokenAuthOp;
+use crate::actor_txns::error::{Error, Result, TxnError};
+use crate::actor_txns::receipt::TxnEvent;
+use crate::actor_txns::tsid::Tsid;
+use crate::tapp::TokenId;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::BTreeMap;
+use tea_sdk::serialize;
+
+/// One `TokenContext` per token id, all made by the same txn on the same base.
+///
+/// Conflicts are checked and rebases are done for all contexts together, so the txn either
+/// commits the changes of every token or none of them. This is how swaps and multi-leg
+/// payments touching more than the two tokens of `TokenContext::new_cross_move` commit
+/// atomically.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContextBundle {
+	tsid: Tsid,
+	base: Tsid,
+	contexts: BTreeMap<TokenId, TokenContext>,
+}
+
+impl CheckConflict for ContextBundle {
+	fn check_conflict(&self, other: &Self) -> Result<()> {
+		self.check_base_conflict(other)?;
+		let reports = self.analyze_conflicts(other);
+		if reports.iter().any(ConflictReport::is_blocking) {
+			return Err(TxnError::BundleConflict(reports).into());
+		}
+		Ok(())
+	}
+}
+
+impl IsBalanceRelated for ContextBundle {
+	fn is_balance_related(&self) -> bool {
+		self.contexts.values().any(|x| x.is_balance_related())
+	}
+}
+
+impl ContextBundle {
+	pub fn new(tsid: Tsid, base: Tsid) -> Self {
+		ContextBundle {
+			tsid,
+			base,
+			contexts: Default::default(),
+		}
+	}
+
+	pub fn get_tsid(&self) -> Tsid {
+		self.tsid
+	}
+
+	pub fn get_base(&self) -> Tsid {
+		self.base
+	}
+
+	/// Adds the context of a token, replacing the one already in the bundle.
+	/// The context must have been made by the same txn on the same base as the bundle.
+	pub fn insert(&mut self, ctx: TokenContext) -> Result<Option<TokenContext>> {
+		if ctx.tsid != self.tsid || ctx.base != self.base {
+			return Err(TxnError::BundleContextMismatch(ctx.tid).into());
+		}
+		Ok(self.contexts.insert(ctx.tid, ctx))
+	}
+
+	pub fn get(&self, tid: TokenId) -> Option<&TokenContext> {
+		self.contexts.get(&tid)
+	}
+
+	pub fn get_mut(&mut self, tid: TokenId) -> Option<&mut TokenContext> {
+		self.contexts.get_mut(&tid)
+	}
+
+	/// The context of the token, a new slim one is added if the bundle has none yet.
+	pub fn context_mut(&mut self, tid: TokenId) -> &mut TokenContext {
+		let (tsid, base) = (self.tsid, self.base);
+		self.contexts
+			.entry(tid)
+			.or_insert_with(|| TokenContext::new_slim(tsid, base, tid))
+	}
+
+	pub fn remove(&mut self, tid: TokenId) -> Option<TokenContext> {
+		self.contexts.remove(&tid)
+	}
+
+	/// Contexts in the order of their token ids.
+	pub fn contexts(&self) -> impl Iterator<Item = &TokenContext> {
+		self.contexts.values()
+	}
+
+	pub fn token_ids(&self) -> impl Iterator<Item = TokenId> + '_ {
+		self.contexts.keys().copied()
+	}
+
+	/// Events emitted into every context, in the order of their token ids.
+	pub fn events(&self) -> impl Iterator<Item = &TxnEvent> {
+		self.contexts.values().flat_map(|x| x.get_events())
+	}
+
+	/// The conflict reports of the tokens both bundles touched, skipping those without conflicts.
+	pub fn analyze_conflicts(&self, other: &Self) -> Vec<ConflictReport> {
+		self.contexts
+			.iter()
+			.filter_map(|(tid, ctx)| Some(ctx.analyze_conflicts(other.contexts.get(tid)?)))
+			.filter(|report| !report.is_empty())
+			.collect()
+	}
+
+	/// Rebases every context of mine onto `other`, or none of them if any conflicts.
+	/// See `Context::rebase` of `TokenContext` for the rules of a single token.
+	pub fn rebase(&mut self, other: &Self) -> Result<()> {
+		self.check_conflict(other)?;
+
+		let mut merged = Vec::new();
+		for (tid, ctx) in self.contexts.iter() {
+			if let Some(other) = other.contexts.get(tid) {
+				merged.push((*tid, ctx.rebased_storage_merges(other)?));
+			}
+		}
+		for (tid, values) in merged {
+			if let Some(ctx) = self.contexts.get_mut(&tid) {
+				ctx.set_rebased_storage(values);
+			}
+		}
+
+		self.base = other.get_tsid();
+		for ctx in self.contexts.values_mut() {
+			ctx.base = self.base;
+		}
+		Ok(())
+	}
+
+	/// Verifies every context against the auth ops read again at commit time.
+	pub fn verify_auth(&self, auth_ops: &[TokenAuthOp], tappstore_id: TokenId) -> Result<()> {
+		for ctx in self.contexts.values() {
+			ctx.verify_auth(auth_ops, tappstore_id)?;
+		}
+		Ok(())
+	}
+
+	/// Verifies every context against the policies read again at commit time, spending the
+	/// capped grants of all of them together.
+	pub fn verify_policies(&self, policies: &[AuthPolicy]) -> Result<()> {
+		let mut usage = SpendingUsage::default();
+		for ctx in self.contexts.values() {
+			ctx.verify_policies(policies, &mut usage)?;
+		}
+		Ok(())
+	}
+
+	pub fn encode_bytes(&self) -> Result<Vec<u8>> {
+		serialize(self).map_err(|e| Error::Unnamed(e.to_string()))
+	}
+
+	/// A single hash of all contexts, independent of the order in which they were added.
+	pub fn hash(&self, hasher: &mut sha2::Sha256) -> tea_sdk::Result<()> {
+		hasher.update(serialize(&self.tsid)?);
+		hasher.update(serialize(&self.base)?);
+		for (tid, ctx) in self.contexts.iter() {
+			hasher.update(serialize(tid)?);
+			ctx.hash(hasher)?;
+		}
+		Ok(())
+	}
+
+	fn check_base_conflict(&self, other: &Self) -> Result<()> {
+		if self.tsid == other.get_tsid() {
+			return Err(TxnError::ShouldNotCheckSameTsid.into());
+		}
+		if self.tsid < other.get_tsid() {
+			return Err(TxnError::ShouldNotCheckConflictWithLaterTsid.into());
+		}
+		if other.get_base() != self.base {
+			return Err(TxnError::BaseNotMatchError.into());
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::actor_txns::context::{AssetContext, Context, ReadConflictMode};
+	use crate::tapp::Account;
+
+	const USER: Account = Account::repeat_byte(1);
+	const APP1: TokenId = TokenId(Account::repeat_byte(2));
+	const APP2: TokenId = TokenId(Account::repeat_byte(3));
+	const APP3: TokenId = TokenId(Account::repeat_byte(4));
+
+	fn tsid_at(ts: u128) -> Tsid {
+		let mut tsid = Tsid::default();
+		tsid.ts = ts;
+		tsid
+	}
+
+	fn bundle_hash(bundle: &ContextBundle) -> Vec<u8> {
+		let mut hasher = sha2::Sha256::new();
+		bundle.hash(&mut hasher).unwrap();
+		hasher.finalize().to_vec()
+	}
+
+	#[test]
+	fn insert_checks_tsid_and_base() {
+		let mut bundle = ContextBundle::new(tsid_at(1), Tsid::default());
+		bundle
+			.insert(TokenContext::new_slim(tsid_at(1), Tsid::default(), APP1))
+			.unwrap();
+		assert_eq!(
+			bundle
+				.insert(TokenContext::new_slim(tsid_at(2), Tsid::default(), APP2))
+				.unwrap_err(),
+			TxnError::BundleContextMismatch(APP2).into()
+		);
+		bundle.context_mut(APP3);
+		assert_eq!(bundle.token_ids().collect::<Vec<_>>(), vec![APP1, APP3]);
+	}
+
+	#[test]
+	fn rebase_is_all_or_nothing() {
+		let mut earlier = ContextBundle::new(tsid_at(1), Tsid::default());
+		let mut later = ContextBundle::new(tsid_at(2), Tsid::default());
+		earlier
+			.context_mut(APP1)
+			.tea_context_mut()
+			.add_token_add(USER, 1.into());
+		earlier
+			.context_mut(APP3)
+			.tea_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.context_mut(APP1)
+			.tea_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.context_mut(APP2)
+			.tea_context_mut()
+			.add_token_read(USER, ReadConflictMode::BothConflict);
+		later.rebase(&earlier).unwrap();
+		assert_eq!(later.get_base(), tsid_at(1));
+		assert!(later.contexts().all(|x| x.get_base() == tsid_at(1)));
+
+		let mut later = ContextBundle::new(tsid_at(2), Tsid::default());
+		later
+			.context_mut(APP1)
+			.tea_context_mut()
+			.add_token_subtract(USER, 1.into());
+		later
+			.context_mut(APP3)
+			.tea_context_mut()
+			.add_token_subtract(USER, 1.into());
+		let report = later
+			.get(APP3)
+			.unwrap()
+			.analyze_conflicts(earlier.get(APP3).unwrap());
+		assert_eq!(
+			later.rebase(&earlier),
+			Err(TxnError::BundleConflict(vec![report]).into())
+		);
+		assert!(later.contexts().all(|x| x.get_base() == Tsid::default()));
+	}
+
+	#[test]
+	fn hash_ignores_insertion_order() {
+		let mut a = ContextBundle::new(tsid_at(1), Tsid::default());
+		let mut b = a.clone();
+		a.context_mut(APP1);
+		a.context_mut(APP2)
+			.tea_context_mut()
+			.add_token_add(USER, 1.into());
+		b.context_mut(APP2)
+			.tea_context_mut()
+			.add_token_add(USER, 1.into());
+		b.context_mut(APP1);
+		assert_eq!(bundle_hash(&a), bundle_hash(&b));
+
+		b.context_mut(APP3);
+		assert_ne!(bundle_hash(&a), bundle_hash(&b));
+	}
+}
@@ -1,3 +1,4 @@
+use super::conflict::BalanceConflictKind;
 use super::{AssetContext, CheckConflict, IsBalanceRelated, Merge, ReadConflictMode};
 use crate::actor_txns::error::{ContextError, Error, Result};
 use crate::tapp::{Account, Balance};
@@ -161,27 +162,31 @@ impl AssetContext for ConcurrentBalances {
 
 impl ConcurrentBalances {
 	fn check_partial_conflict(&self, other: &ConcurrentBalances) -> Result<()> {
+		match self.conflicts(other).first() {
+			Some((_, kind)) => Err(ContextError::from(*kind).into()),
+			None => Ok(()),
+		}
+	}
+
+	/// Collects every account colliding with `other` rather than stopping at the first one.
+	pub fn conflicts(&self, other: &ConcurrentBalances) -> Vec<(Account, BalanceConflictKind)> {
+		let mut conflicts = Vec::new();
 		for read in self.read_add.iter() {
 			if other.token_add.contains_key(read) {
-				return Err(ContextError::ReadWhileCredit.into());
+				conflicts.push((*read, BalanceConflictKind::ReadWhileCredit));
 			}
 		}
-
 		for read in self.read_subtract.iter() {
 			if other.token_subtract.contains_key(read) {
-				return Err(ContextError::ReadWhileDebit.into());
+				conflicts.push((*read, BalanceConflictKind::ReadWhileDebit));
 			}
 		}
-		self.check_double_debit(other)
-	}
-
-	fn check_double_debit(&self, other: &ConcurrentBalances) -> Result<()> {
 		for debit_acc in self.token_subtract.keys() {
 			if other.token_subtract.contains_key(debit_acc) {
-				return Err(ContextError::DoubleDebit.into());
+				conflicts.push((*debit_acc, BalanceConflictKind::DoubleDebit));
 			}
 		}
-		Ok(())
+		conflicts
 	}
 
 	pub fn hash(&self, hasher: &mut sha2::Sha256) -> Result<()> {
@@ -490,23 +495,23 @@ mod tests {
 		let mut ctx1 = ConcurrentBalances::default();
 		let mut ctx2 = ConcurrentBalances::default();
 
-		ctx1.check_double_debit(&ctx2).unwrap();
+		ctx1.check_partial_conflict(&ctx2).unwrap();
 
 		// double debit error
 		ctx1.token_subtract.insert(acc1, vec![1.into()]);
 		ctx2.token_subtract.insert(acc1, vec![Balance::from(2)]);
-		ctx1.check_double_debit(&ctx2).unwrap_err();
+		ctx1.check_partial_conflict(&ctx2).unwrap_err();
 
 		// double debit error even if balances is empty
 		ctx1.token_subtract.insert(acc1, vec![]);
 		ctx2.token_subtract.insert(acc1, vec![]);
-		ctx1.check_double_debit(&ctx2).unwrap_err();
+		ctx1.check_partial_conflict(&ctx2).unwrap_err();
 
 		// not error if with different account
 		ctx1 = Default::default();
 		ctx2 = Default::default();
 		ctx1.token_subtract.insert(acc1, vec![1.into()]);
 		ctx2.token_subtract.insert(acc2, vec![Balance::from(2)]);
-		ctx1.check_double_debit(&ctx2).unwrap();
+		ctx1.check_partial_conflict(&ctx2).unwrap();
 	}
 }
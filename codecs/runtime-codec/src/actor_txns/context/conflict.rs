@@ -0,0 +1,152 @@
+use super::TappStorageType;
+use crate::actor_txns::error::ContextError;
+use crate::actor_txns::tsid::Tsid;
+use crate::tapp::{Account, ChannelId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use strum::Display;
+
+/// The balance context in which a conflict was found.
+#[derive(
+	Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Display,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum BalanceCategory {
+	Tea,
+	Deposit,
+	Bonding,
+	Allowance,
+	Credit,
+}
+
+impl BalanceCategory {
+	/// Whether conflicts of the category fail `CheckConflict::check_conflict`. Conflicts of
+	/// the other categories are only reported.
+	pub fn is_blocking(&self) -> bool {
+		matches!(
+			self,
+			BalanceCategory::Tea | BalanceCategory::Deposit | BalanceCategory::Bonding
+		)
+	}
+}
+
+/// Why an account of one context collides with the other context.
+#[derive(
+	Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Display,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum BalanceConflictKind {
+	/// I read the account while the other context credits it.
+	ReadWhileCredit,
+	/// I read the account while the other context debits it.
+	ReadWhileDebit,
+	/// Both contexts debit the account.
+	DoubleDebit,
+}
+
+impl From<BalanceConflictKind> for ContextError {
+	fn from(kind: BalanceConflictKind) -> Self {
+		match kind {
+			BalanceConflictKind::ReadWhileCredit => ContextError::ReadWhileCredit,
+			BalanceConflictKind::ReadWhileDebit => ContextError::ReadWhileDebit,
+			BalanceConflictKind::DoubleDebit => ContextError::DoubleDebit,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceConflict {
+	pub category: BalanceCategory,
+	pub acct: Account,
+	pub kind: BalanceConflictKind,
+}
+
+/// Every collision between a context and an earlier context sharing the same base.
+///
+/// Produced by `TokenContext::analyze_conflicts` and carried by `TxnError::ContextConflict`
+/// so that the reason of a rerun can be logged or dumped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictReport {
+	/// The tsid of the context being checked.
+	pub tsid: Tsid,
+	/// The tsid of the earlier context it was checked against.
+	pub other_tsid: Tsid,
+	/// Storage items I touched that the other context also read or changed.
+	pub storage: Vec<TappStorageType>,
+	pub balances: Vec<BalanceConflict>,
+	/// Payment channels changed by both contexts.
+	pub payment_channels: Vec<ChannelId>,
+}
+
+impl ConflictReport {
+	pub fn new(tsid: Tsid, other_tsid: Tsid) -> Self {
+		ConflictReport {
+			tsid,
+			other_tsid,
+			storage: Default::default(),
+			balances: Default::default(),
+			payment_channels: Default::default(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.storage.is_empty() && self.balances.is_empty() && self.payment_channels.is_empty()
+	}
+
+	/// Whether any conflict fails `CheckConflict::check_conflict`, i.e. a storage conflict or
+	/// a balance conflict of a blocking category. Allowance, credit and payment channel
+	/// conflicts are informational only.
+	pub fn is_blocking(&self) -> bool {
+		!self.storage.is_empty() || self.balances.iter().any(|x| x.category.is_blocking())
+	}
+
+	/// Conflicts of the given balance category.
+	pub fn balances_of(
+		&self,
+		category: BalanceCategory,
+	) -> impl Iterator<Item = &BalanceConflict> + '_ {
+		self.balances.iter().filter(move |x| x.category == category)
+	}
+
+	pub(crate) fn add_balances(
+		&mut self,
+		category: BalanceCategory,
+		conflicts: Vec<(Account, BalanceConflictKind)>,
+	) {
+		self.balances
+			.extend(conflicts.into_iter().map(|(acct, kind)| BalanceConflict {
+				category,
+				acct,
+				kind,
+			}));
+	}
+
+	/// Sorts all entries so that reports of the same contexts compare and print the same.
+	pub(crate) fn sort(&mut self) {
+		self.storage.sort();
+		self.balances
+			.sort_by(|a, b| (a.category, a.acct, a.kind).cmp(&(b.category, b.acct, b.kind)));
+		self.payment_channels.sort();
+	}
+}
+
+impl fmt::Display for ConflictReport {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?} conflicts with {:?}:", self.tsid, self.other_tsid)?;
+		for storage in &self.storage {
+			write!(f, " storage({storage:?})")?;
+		}
+		for BalanceConflict {
+			category,
+			acct,
+			kind,
+		} in &self.balances
+		{
+			write!(f, " {category}({acct:?},{kind})")?;
+		}
+		for channel_id in &self.payment_channels {
+			write!(f, " payment_channel({channel_id:?})")?;
+		}
+		Ok(())
+	}
+}
@@ -80,6 +80,27 @@ impl PaymentChannelContext for PaymentChannelContextImpl {
 }
 
 impl PaymentChannelContextImpl {
+	/// Channels changed by both contexts, which cannot be applied one after another safely.
+	pub fn conflicts(&self, other: &PaymentChannelContextImpl) -> Vec<ChannelId> {
+		let others = other.touched_channels();
+		self.touched_channels()
+			.into_iter()
+			.filter(|x| others.contains(x))
+			.collect()
+	}
+
+	fn touched_channels(&self) -> HashSet<ChannelId> {
+		self.new_channels
+			.keys()
+			.chain(self.update_payments.keys())
+			.chain(self.payer_refills.keys())
+			.chain(self.early_terminate.iter())
+			.chain(self.terminate.iter())
+			.chain(self.payee_terminate.iter())
+			.copied()
+			.collect()
+	}
+
 	pub fn hash(&self, hasher: &mut sha2::Sha256) -> Result<()> {
 		let mut new_channels = self.new_channels.iter().collect::<Vec<_>>();
 		new_channels.sort_by(|a, b| a.0.cmp(&b.0));
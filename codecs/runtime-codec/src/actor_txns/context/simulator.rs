@@ -0,0 +1,680 @@
+//! Runs txns operating on `TokenContext` concurrently against a simulated state, so the
+//! rebase and rerun rules of the contexts can be exercised without a replica cluster.
+//!
+//! Every schedule executes the txns in a random interleaving: a txn snapshots the committed
+//! state when it starts, and commits in tsid order. A context whose base is behind the state
+//! is rebased onto every context committed since, or rerun against the latest state if it
+//! conflicts with any of them, which is what the state machine does. The final state of each
+//! schedule must equal the state of some serial execution of the txns, otherwise the schedule
+//! is reported as a violation of serializability.
+//!
+//! Besides balances and storage, the simulated state keeps the payment channels of the token.
+
+use super::conflict::{BalanceCategory, ConflictReport};
+use super::{concurrent::ConcurrentBalances, AssetContext, Context, ReadConflictMode};
+use super::{storage::StorageMerge, PaymentChannelContext, TappStorageType, TokenContext};
+use crate::actor_txns::error::{ContextError, Error, Result, TxnError};
+use crate::actor_txns::tsid::Tsid;
+use crate::tapp::{Account, Balance, ChannelId, ChannelItem, ChannelItemStatus, TokenId};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Txns with more members are only compared with the serial execution in tsid order,
+/// instead of every permutation of them.
+pub const MAX_PERMUTED_TXNS: usize = 7;
+
+/// A txn is run again whenever it is rerun, so it must not depend on anything but its
+/// snapshot and context.
+pub type SimTxn = Box<dyn Fn(&SimSnapshot, &mut TokenContext) -> Result<()>>;
+
+/// The committed state of the simulated token.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimState {
+	pub balances: BTreeMap<(BalanceCategory, Account), Balance>,
+	/// Accumulated hidden add and subtract of every category.
+	pub hidden: BTreeMap<BalanceCategory, (Balance, Balance)>,
+	pub storage: BTreeMap<TappStorageType, Vec<u8>>,
+	pub channels: BTreeMap<ChannelId, SimChannel>,
+}
+
+/// The part of a payment channel kept by the simulated state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimChannel {
+	pub payer: Account,
+	pub payee: Account,
+	pub fund_remaining: Balance,
+	pub status: ChannelItemStatus,
+}
+
+/// The committed state a txn was started on.
+pub struct SimSnapshot<'a> {
+	state: &'a SimState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimEvent {
+	/// The txn started on the state after `after` txns were committed.
+	Execute {
+		txn: usize,
+		after: usize,
+	},
+	Rebase {
+		txn: usize,
+		onto: usize,
+	},
+	Rerun {
+		txn: usize,
+		report: Box<ConflictReport>,
+	},
+	Commit {
+		txn: usize,
+	},
+	/// The txn failed, nothing of it is committed.
+	Abort {
+		txn: usize,
+		error: Error,
+	},
+}
+
+#[derive(Debug, Clone)]
+pub struct Violation {
+	/// Running the schedule again with this seed replays it.
+	pub seed: u64,
+	pub schedule: Vec<SimEvent>,
+	pub state: SimState,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SimReport {
+	pub schedules: usize,
+	pub reruns: usize,
+	pub violations: Vec<Violation>,
+}
+
+pub struct Simulator {
+	pub tid: TokenId,
+	pub base: SimState,
+	/// Txns in tsid order.
+	pub txns: Vec<SimTxn>,
+}
+
+impl SimState {
+	pub fn with_balance(
+		mut self,
+		category: BalanceCategory,
+		acct: Account,
+		amount: Balance,
+	) -> Self {
+		self.balances.insert((category, acct), amount);
+		self
+	}
+
+	pub fn with_channel(mut self, item: &ChannelItem) -> Self {
+		self.channels.insert(item.channel_id, item.into());
+		self
+	}
+
+	pub fn balance(&self, category: BalanceCategory, acct: Account) -> Balance {
+		self.balances
+			.get(&(category, acct))
+			.copied()
+			.unwrap_or_default()
+	}
+
+	/// Applies the changes of a context, failing without any change if a balance overflows.
+	fn apply(&self, ctx: &TokenContext) -> Result<SimState> {
+		let mut state = self.clone();
+		for category in CATEGORIES {
+			let balances = balances_of(ctx, category);
+			let accts = balances
+				.get_token_adds()
+				.keys()
+				.chain(balances.get_token_subtracts().keys())
+				.collect::<BTreeSet<_>>();
+			for acct in accts {
+				let amount = balances.accumulate_balance(self.balance(category, *acct), *acct)?;
+				if amount.is_zero() {
+					state.balances.remove(&(category, *acct));
+				} else {
+					state.balances.insert((category, *acct), amount);
+				}
+			}
+
+			let (add, subtract) = state.hidden.entry(category).or_default();
+			*add += balances.get_hidden_add();
+			*subtract += balances.get_hidden_subtract();
+		}
+		state
+			.hidden
+			.retain(|_, (add, subtract)| !add.is_zero() || !subtract.is_zero());
+
+		for (t, changes) in ctx.get_storage_sets() {
+			match changes.back() {
+				Some(Some(value)) => {
+					state.storage.insert(*t, value.clone());
+				}
+				Some(None) => {
+					state.storage.remove(t);
+				}
+				None => {}
+			}
+		}
+		state.apply_channels(ctx)?;
+		Ok(state)
+	}
+
+	/// Applies the payment channel changes of a context: channels are created, refilled,
+	/// paid from, early terminated and then terminated, in this order.
+	fn apply_channels(&mut self, ctx: &TokenContext) -> Result<()> {
+		let channels = ctx.payment_channel_context();
+		for (channel_id, item) in channels.get_new_channels() {
+			if self.channels.insert(*channel_id, item.into()).is_some() {
+				return Err(Error::Unnamed(format!(
+					"payment channel {channel_id:?} already exists"
+				)));
+			}
+		}
+		for (channel_id, amount) in channels.get_payer_refills() {
+			let channel = self.channel_mut(channel_id)?;
+			channel.fund_remaining = channel
+				.fund_remaining
+				.checked_add(*amount)
+				.ok_or(ContextError::AddOverflow)?;
+		}
+		for (channel_id, (remaining, close)) in channels.get_update_payments() {
+			self.channel_mut(channel_id)?.fund_remaining = *remaining;
+			if *close {
+				self.channels.remove(channel_id);
+			}
+		}
+		for channel_id in channels.get_payer_early_terminate() {
+			self.channel_mut(channel_id)?.status = ChannelItemStatus::EarlyTerminate(ctx.tsid.ts);
+		}
+		for channel_id in channels
+			.get_payer_terminate()
+			.iter()
+			.chain(channels.get_payee_terminate())
+		{
+			self.channel_mut(channel_id)?;
+			self.channels.remove(channel_id);
+		}
+		Ok(())
+	}
+
+	fn channel_mut(&mut self, channel_id: &ChannelId) -> Result<&mut SimChannel> {
+		self.channels
+			.get_mut(channel_id)
+			.ok_or_else(|| Error::Unnamed(format!("payment channel {channel_id:?} not found")))
+	}
+}
+
+impl From<&ChannelItem> for SimChannel {
+	fn from(item: &ChannelItem) -> Self {
+		SimChannel {
+			payer: item.payer_address,
+			payee: item.payee_address,
+			fund_remaining: item.fund_remaining,
+			status: item.status.clone(),
+		}
+	}
+}
+
+impl<'a> SimSnapshot<'a> {
+	/// Reads a balance as the txn sees it, recording the read in the context.
+	pub fn balance(
+		&self,
+		ctx: &mut TokenContext,
+		category: BalanceCategory,
+		acct: Account,
+		mode: ReadConflictMode,
+	) -> Result<Balance> {
+		balances_of_mut(ctx, category).add_token_read(acct, mode);
+		balances_of(ctx, category).accumulate_balance(self.state.balance(category, acct), acct)
+	}
+
+	/// The channel as committed when the txn started, the changes of the context are not
+	/// included as the context records no reads of channels.
+	pub fn channel(&self, channel_id: ChannelId) -> Option<&SimChannel> {
+		self.state.channels.get(&channel_id)
+	}
+
+	/// Reads a storage entry as the txn sees it, recording the read in the context.
+	pub fn storage(&self, ctx: &mut TokenContext, t: TappStorageType) -> Option<Vec<u8>> {
+		ctx.add_storage_read(t);
+		match ctx.get_storage(t) {
+			Ok(value) => value.clone(),
+			Err(_) => self.state.storage.get(&t).cloned(),
+		}
+	}
+
+	/// Records a mergeable change of a storage entry, which unlike `storage` is no read.
+	pub fn merge_storage(
+		&self,
+		ctx: &mut TokenContext,
+		t: TappStorageType,
+		change: StorageMerge,
+	) -> Result<()> {
+		ctx.merge_storage(t, self.state.storage.get(&t).cloned(), change)
+	}
+}
+
+impl Simulator {
+	pub fn new(tid: TokenId, base: SimState) -> Self {
+		Simulator {
+			tid,
+			base,
+			txns: Default::default(),
+		}
+	}
+
+	pub fn add_txn<F>(&mut self, txn: F)
+	where
+		F: Fn(&SimSnapshot, &mut TokenContext) -> Result<()> + 'static,
+	{
+		self.txns.push(Box::new(txn));
+	}
+
+	/// Runs `schedules` random schedules seeded from `seed` and checks each of them.
+	pub fn run(&self, schedules: usize, seed: u64) -> SimReport {
+		let serial = self.serial_states();
+		let mut rng = SplitMix64(seed);
+		let mut report = SimReport {
+			schedules,
+			..Default::default()
+		};
+		for _ in 0..schedules {
+			let seed = rng.next_u64();
+			let (schedule, state) = self.run_schedule(seed);
+			report.reruns += schedule
+				.iter()
+				.filter(|x| matches!(x, SimEvent::Rerun { .. }))
+				.count();
+			if !serial.contains(&state) {
+				report.violations.push(Violation {
+					seed,
+					schedule,
+					state,
+				});
+			}
+		}
+		report
+	}
+
+	/// Runs a single random schedule, returning its events and final state.
+	pub fn run_schedule(&self, seed: u64) -> (Vec<SimEvent>, SimState) {
+		let mut rng = SplitMix64(seed);
+		let mut schedule = Vec::new();
+		let mut state = self.base.clone();
+		let mut state_tsid = Tsid::default();
+		// committed contexts with the index of their txn, in commit order
+		let mut history: Vec<(usize, TokenContext)> = Vec::new();
+		let mut executed: Vec<Option<Result<TokenContext>>> = vec![None; self.txns.len()];
+		let mut next_commit = 0;
+
+		while next_commit < self.txns.len() {
+			let waiting = (next_commit..self.txns.len())
+				.filter(|i| executed[*i].is_none())
+				.collect::<Vec<_>>();
+			let can_commit = executed[next_commit].is_some();
+			let choice = rng.below((waiting.len() + can_commit as usize) as u64) as usize;
+			if choice < waiting.len() {
+				let txn = waiting[choice];
+				schedule.push(SimEvent::Execute {
+					txn,
+					after: history.len(),
+				});
+				executed[txn] = Some(self.execute(txn, &state, state_tsid));
+				continue;
+			}
+
+			let txn = next_commit;
+			next_commit += 1;
+			let ctx = match executed[txn].take() {
+				Some(Ok(ctx)) => ctx,
+				Some(Err(error)) => {
+					schedule.push(SimEvent::Abort { txn, error });
+					continue;
+				}
+				None => unreachable!(),
+			};
+			let ctx = match self.rebase(txn, ctx, &history, &mut schedule) {
+				Ok(Ok(ctx)) => Ok(ctx),
+				Ok(Err(report)) => {
+					schedule.push(SimEvent::Rerun { txn, report });
+					self.execute(txn, &state, state_tsid)
+				}
+				Err(e) => Err(e),
+			};
+			match ctx.and_then(|ctx| Ok((state.apply(&ctx)?, ctx))) {
+				Ok((new_state, ctx)) => {
+					schedule.push(SimEvent::Commit { txn });
+					state = new_state;
+					state_tsid = ctx.get_tsid();
+					history.push((txn, ctx));
+				}
+				Err(error) => schedule.push(SimEvent::Abort { txn, error }),
+			}
+		}
+		(schedule, state)
+	}
+
+	/// Final states of the serial executions the schedules are compared with.
+	pub fn serial_states(&self) -> Vec<SimState> {
+		let orders = if self.txns.len() <= MAX_PERMUTED_TXNS {
+			permutations(self.txns.len())
+		} else {
+			vec![(0..self.txns.len()).collect()]
+		};
+		let mut states = Vec::new();
+		for order in orders {
+			let mut state = self.base.clone();
+			for txn in order {
+				if let Ok(new_state) = self
+					.execute(txn, &state, Tsid::default())
+					.and_then(|ctx| state.apply(&ctx))
+				{
+					state = new_state;
+				}
+			}
+			if !states.contains(&state) {
+				states.push(state);
+			}
+		}
+		states
+	}
+
+	fn execute(&self, txn: usize, state: &SimState, base: Tsid) -> Result<TokenContext> {
+		let mut ctx = TokenContext::new_slim(txn_tsid(txn), base, self.tid);
+		(self.txns[txn])(&SimSnapshot { state }, &mut ctx)?;
+		Ok(ctx)
+	}
+
+	/// Rebases the context onto every context committed since its base, or returns the
+	/// conflict that makes it rerun. Other errors of the rebase fail the txn.
+	fn rebase(
+		&self,
+		txn: usize,
+		mut ctx: TokenContext,
+		history: &[(usize, TokenContext)],
+		schedule: &mut Vec<SimEvent>,
+	) -> Result<std::result::Result<TokenContext, Box<ConflictReport>>> {
+		let since = history
+			.iter()
+			.position(|(_, x)| x.get_base() == ctx.get_base())
+			.unwrap_or(history.len());
+		for (onto, other) in &history[since..] {
+			match ctx.rebase(other) {
+				Ok(_) => schedule.push(SimEvent::Rebase { txn, onto: *onto }),
+				Err(Error::TxnError(TxnError::ContextConflict(report))) => return Ok(Err(report)),
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(Ok(ctx))
+	}
+}
+
+const CATEGORIES: [BalanceCategory; 5] = [
+	BalanceCategory::Tea,
+	BalanceCategory::Deposit,
+	BalanceCategory::Bonding,
+	BalanceCategory::Allowance,
+	BalanceCategory::Credit,
+];
+
+fn balances_of(ctx: &TokenContext, category: BalanceCategory) -> &ConcurrentBalances {
+	match category {
+		BalanceCategory::Tea => ctx.tea_context(),
+		BalanceCategory::Deposit => ctx.deposit_context(),
+		BalanceCategory::Bonding => ctx.bonding_context(),
+		BalanceCategory::Allowance => ctx.allowance_context(),
+		BalanceCategory::Credit => ctx.credit_context(),
+	}
+}
+
+fn balances_of_mut(ctx: &mut TokenContext, category: BalanceCategory) -> &mut ConcurrentBalances {
+	match category {
+		BalanceCategory::Tea => ctx.tea_context_mut(),
+		BalanceCategory::Deposit => ctx.deposit_context_mut(),
+		BalanceCategory::Bonding => ctx.bonding_context_mut(),
+		BalanceCategory::Allowance => ctx.allowance_context_mut(),
+		BalanceCategory::Credit => ctx.credit_context_mut(),
+	}
+}
+
+fn txn_tsid(txn: usize) -> Tsid {
+	let mut tsid = Tsid::default();
+	tsid.ts = txn as u128 + 1;
+	tsid
+}
+
+fn permutations(len: usize) -> Vec<Vec<usize>> {
+	if len == 0 {
+		return vec![vec![]];
+	}
+	let mut result = Vec::new();
+	for order in permutations(len - 1) {
+		for i in 0..=order.len() {
+			let mut order = order.clone();
+			order.insert(i, len - 1);
+			result.push(order);
+		}
+	}
+	result
+}
+
+/// A small deterministic random number generator, see https://prng.di.unimi.it/splitmix64.c.
+///
+/// Schedules are replayed from their seed with it, so are the other simulations of the repo.
+#[derive(Debug, Clone, Default)]
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// Uniform in `[0, 1)`.
+	pub fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	/// Below `n`, which must not be zero.
+	pub fn below(&mut self, n: u64) -> u64 {
+		self.next_u64() % n
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const APP: TokenId = TokenId(Account::repeat_byte(2));
+	const ALICE: Account = Account::repeat_byte(1);
+	const BOB: Account = Account::repeat_byte(3);
+	const SINK: Account = Account::repeat_byte(9);
+	const TEA: BalanceCategory = BalanceCategory::Tea;
+
+	/// Moves 10 from `from` to the sink if `guard` still holds at least 10.
+	fn guarded_withdraw(
+		from: Account,
+		guard: Account,
+		mode: ReadConflictMode,
+	) -> impl Fn(&SimSnapshot, &mut TokenContext) -> Result<()> {
+		move |snapshot, ctx| {
+			if snapshot.balance(ctx, TEA, guard, mode)? >= 10.into() {
+				ctx.tea_context_mut().add_token_subtract(from, 10.into());
+				ctx.tea_context_mut().add_token_add(SINK, 10.into());
+			}
+			Ok(())
+		}
+	}
+
+	fn simulator(mode: ReadConflictMode) -> Simulator {
+		let base = SimState::default()
+			.with_balance(TEA, ALICE, 10.into())
+			.with_balance(TEA, BOB, 10.into());
+		let mut simulator = Simulator::new(APP, base);
+		simulator.add_txn(guarded_withdraw(ALICE, BOB, mode));
+		simulator.add_txn(guarded_withdraw(BOB, ALICE, mode));
+		simulator
+	}
+
+	#[test]
+	fn conflicting_reads_serialize() {
+		let report = simulator(ReadConflictMode::CreditOk).run(200, 1);
+		assert!(report.violations.is_empty(), "{:?}", report.violations);
+		assert!(report.reruns > 0);
+	}
+
+	#[test]
+	fn write_skew_is_reported() {
+		let report = simulator(ReadConflictMode::BothOk).run(200, 1);
+		let violation = &report.violations[0];
+		assert_eq!(violation.state.balance(TEA, SINK), 20.into());
+
+		let (schedule, state) = simulator(ReadConflictMode::BothOk).run_schedule(violation.seed);
+		assert_eq!(schedule, violation.schedule);
+		assert_eq!(state, violation.state);
+	}
+
+	#[test]
+	fn concurrent_appends_are_merged() {
+		let mut simulator = Simulator::new(APP, SimState::default());
+		for i in 0..3u8 {
+			simulator.add_txn(move |snapshot, ctx| {
+				snapshot.merge_storage(
+					ctx,
+					TappStorageType::FailedPayments,
+					StorageMerge::Append(vec![vec![i]]),
+				)
+			});
+		}
+		let report = simulator.run(50, 3);
+		assert!(report.violations.is_empty(), "{:?}", report.violations);
+		assert_eq!(report.reruns, 0);
+	}
+
+	fn channel(channel_id: ChannelId, fund_remaining: Balance) -> ChannelItem {
+		ChannelItem {
+			channel_id,
+			payer_address: ALICE,
+			payee_address: BOB,
+			fund_remaining,
+			..Default::default()
+		}
+	}
+
+	/// Pays 5 from the channel, as the payee does by updating the remaining fund.
+	fn pay(channel_id: ChannelId) -> impl Fn(&SimSnapshot, &mut TokenContext) -> Result<()> {
+		move |snapshot, ctx| {
+			let remaining = snapshot
+				.channel(channel_id)
+				.map(|x| x.fund_remaining)
+				.unwrap_or_default();
+			ctx.payment_channel_context_mut().add_update_payment(
+				channel_id,
+				remaining.saturating_sub(5.into()),
+				false,
+			);
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn channels_are_created_paid_and_terminated() {
+		const CHANNEL: ChannelId = Account::repeat_byte(7);
+		let mut simulator = Simulator::new(APP, SimState::default());
+		simulator.add_txn(|_, ctx| {
+			ctx.payment_channel_context_mut()
+				.create_channel(channel(CHANNEL, 10.into()));
+			Ok(())
+		});
+		let (_, state) = simulator.run_schedule(1);
+		assert_eq!(state.channels[&CHANNEL].fund_remaining, 10.into());
+
+		let base = state;
+		let mut simulator = Simulator::new(APP, base.clone());
+		simulator.add_txn(|_, ctx| {
+			let channels = ctx.payment_channel_context_mut();
+			channels.add_payer_refill(CHANNEL, 5.into());
+			channels.add_payer_early_terminate(CHANNEL);
+			Ok(())
+		});
+		let (_, state) = simulator.run_schedule(1);
+		assert_eq!(state.channels[&CHANNEL].fund_remaining, 15.into());
+		assert_eq!(
+			state.channels[&CHANNEL].status,
+			ChannelItemStatus::EarlyTerminate(1)
+		);
+
+		let mut simulator = Simulator::new(APP, base);
+		simulator.add_txn(|_, ctx| {
+			ctx.payment_channel_context_mut()
+				.add_payee_terminate(CHANNEL);
+			Ok(())
+		});
+		simulator.add_txn(|_, ctx| {
+			ctx.payment_channel_context_mut()
+				.create_channel(channel(CHANNEL, 1.into()));
+			Ok(())
+		});
+		let (schedule, state) = simulator.run_schedule(1);
+		assert_eq!(state.channels[&CHANNEL].fund_remaining, 1.into());
+		assert!(!schedule.iter().any(|x| matches!(x, SimEvent::Abort { .. })));
+	}
+
+	#[test]
+	fn payments_of_other_channels_serialize() {
+		const FIRST: ChannelId = Account::repeat_byte(7);
+		const SECOND: ChannelId = Account::repeat_byte(8);
+		let base = SimState::default()
+			.with_channel(&channel(FIRST, 10.into()))
+			.with_channel(&channel(SECOND, 10.into()));
+		let mut simulator = Simulator::new(APP, base);
+		simulator.add_txn(pay(FIRST));
+		simulator.add_txn(pay(SECOND));
+		let report = simulator.run(50, 5);
+		assert!(report.violations.is_empty(), "{:?}", report.violations);
+	}
+
+	#[test]
+	fn concurrent_payments_of_a_channel_are_reported() {
+		const CHANNEL: ChannelId = Account::repeat_byte(7);
+		let base = SimState::default().with_channel(&channel(CHANNEL, 10.into()));
+		let mut simulator = Simulator::new(APP, base);
+		simulator.add_txn(pay(CHANNEL));
+		simulator.add_txn(pay(CHANNEL));
+		let report = simulator.run(50, 5);
+		// channel conflicts do not block the rebase, so the payment made on the older
+		// snapshot overwrites the other one
+		let violation = &report.violations[0];
+		assert_eq!(violation.state.channels[&CHANNEL].fund_remaining, 5.into());
+	}
+
+	#[test]
+	fn failed_txns_commit_nothing() {
+		let mut simulator = Simulator::new(APP, SimState::default());
+		simulator.add_txn(|_, ctx| {
+			ctx.tea_context_mut().add_token_subtract(ALICE, 1.into());
+			Ok(())
+		});
+		simulator.add_txn(|_, ctx| {
+			ctx.tea_context_mut().add_token_add(BOB, 1.into());
+			Err(Error::Unnamed("failed".into()))
+		});
+		let report = simulator.run(20, 7);
+		assert!(report.violations.is_empty());
+		let (schedule, state) = simulator.run_schedule(7);
+		assert_eq!(state, SimState::default());
+		assert_eq!(
+			schedule
+				.iter()
+				.filter(|x| matches!(x, SimEvent::Abort { .. }))
+				.count(),
+			2
+		);
+	}
+}
@@ -0,0 +1,129 @@
+use super::TappStorageType;
+use crate::actor_txns::error::{Result, TxnError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tea_sdk::{deserialize, serialize};
+
+/// How concurrent changes of a storage entry are combined.
+///
+/// Entries are `Exclusive` unless declared otherwise by [`TappStorageType::merge_strategy`]:
+/// any two contexts touching the same exclusive entry conflict, and one of them has to rerun.
+/// Changes of the other strategies are recorded as [`StorageMerge`]s instead, which commute with
+/// each other and are applied to the stored value in commit order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MergeStrategy {
+	Exclusive,
+	/// The value of the context committed last is kept.
+	LastWriterWins,
+	/// The stored value is a list, concurrent appends are all kept in commit order.
+	AppendOnly,
+}
+
+/// A mergeable change of a storage entry, one per strategy other than `Exclusive`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageMerge {
+	Set(Option<Vec<u8>>),
+	Append(Vec<Vec<u8>>),
+}
+
+impl TappStorageType {
+	/// The merge strategy declared for this kind of entry.
+	pub fn merge_strategy(&self) -> MergeStrategy {
+		match self {
+			TappStorageType::FailedPayments => MergeStrategy::AppendOnly,
+			TappStorageType::SessionKey(..) => MergeStrategy::LastWriterWins,
+			TappStorageType::AesKey
+			| TappStorageType::Profile
+			| TappStorageType::AuthKey(_)
+			| TappStorageType::TappStoreKey => MergeStrategy::Exclusive,
+		}
+	}
+}
+
+impl StorageMerge {
+	pub fn strategy(&self) -> MergeStrategy {
+		match self {
+			StorageMerge::Set(_) => MergeStrategy::LastWriterWins,
+			StorageMerge::Append(_) => MergeStrategy::AppendOnly,
+		}
+	}
+
+	/// Folds a later change of the same entry into this one, so that applying the result
+	/// equals applying both one after another.
+	pub fn merge(&mut self, storage_type: TappStorageType, later: &Self) -> Result<()> {
+		match (self, later) {
+			(StorageMerge::Set(value), StorageMerge::Set(later)) => *value = later.clone(),
+			(StorageMerge::Append(items), StorageMerge::Append(later)) => {
+				items.extend_from_slice(later)
+			}
+			_ => return Err(TxnError::StorageMergeMismatch(storage_type, later.strategy()).into()),
+		}
+		Ok(())
+	}
+
+	/// Applies the change to the stored value of the entry.
+	pub fn apply(&self, current: Option<Vec<u8>>) -> tea_sdk::Result<Option<Vec<u8>>> {
+		match self {
+			StorageMerge::Set(value) => Ok(value.clone()),
+			StorageMerge::Append(items) => {
+				let mut list: Vec<Vec<u8>> = decode_or_default(current)?;
+				list.extend_from_slice(items);
+				Ok(Some(serialize(&list)?))
+			}
+		}
+	}
+}
+
+fn decode_or_default<T>(value: Option<Vec<u8>>) -> tea_sdk::Result<T>
+where
+	T: DeserializeOwned + Default,
+{
+	match value {
+		Some(buf) => deserialize(buf),
+		None => Ok(T::default()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn merge_then_apply_equals_apply_in_order() {
+		let first = StorageMerge::Append(vec![vec![1], vec![2]]);
+		let second = StorageMerge::Append(vec![vec![3]]);
+		let mut merged = first.clone();
+		merged
+			.merge(TappStorageType::FailedPayments, &second)
+			.unwrap();
+		let serial = second.apply(first.apply(None).unwrap()).unwrap().unwrap();
+		assert_eq!(merged.apply(None).unwrap().unwrap(), serial);
+		let list: Vec<Vec<u8>> = deserialize(serial).unwrap();
+		assert_eq!(list, vec![vec![1], vec![2], vec![3]]);
+	}
+
+	#[test]
+	fn last_writer_wins() {
+		let mut value = StorageMerge::Set(Some(vec![1]));
+		value
+			.merge(
+				TappStorageType::SessionKey(Default::default(), Default::default()),
+				&StorageMerge::Set(None),
+			)
+			.unwrap();
+		assert_eq!(value.apply(Some(vec![2])).unwrap(), None);
+	}
+
+	#[test]
+	fn mismatched_merge_fails() {
+		let mut value = StorageMerge::Append(vec![vec![1]]);
+		assert_eq!(
+			value.merge(TappStorageType::Profile, &StorageMerge::Set(None)),
+			Err(TxnError::StorageMergeMismatch(
+				TappStorageType::Profile,
+				MergeStrategy::LastWriterWins
+			)
+			.into())
+		);
+		assert_eq!(value, StorageMerge::Append(vec![vec![1]]));
+	}
+}
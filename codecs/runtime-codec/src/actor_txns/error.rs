@@ -1,5 +1,8 @@
-use crate::actor_txns::{auth::AllowedOp, context::TappStorageType};
-use crate::tapp::{Account, AuthKey, TokenId};
+use crate::actor_txns::{
+	auth::AllowedOp,
+	context::{conflict::ConflictReport, storage::MergeStrategy, TappStorageType},
+};
+use crate::tapp::{Account, AuthKey, Balance, ReplicaId, TokenId, Ts};
 use serde::{Deserialize, Serialize};
 use tea_sdk::errorx::Global;
 use thiserror::Error;
@@ -56,6 +59,26 @@ pub enum TxnError {
 	BaseNotMatchError,
 	#[error("CX_218__storage_'{0:?}'_has_been_touched_already")]
 	StorageHasBeTouched(TappStorageType),
+	#[error("CX_222__auth_policy_expired_at_{0}")]
+	AuthPolicyExpired(Ts),
+	#[error("CX_223__auth_policy_cap_exceeded__'op:{0:?},cap:{1},spent:{2}'")]
+	AuthCapExceeded(AllowedOp, Balance, Balance),
+	#[error("CX_224__auth_policy_does_not_allow_counterpart_'{0:?}'")]
+	AuthCounterpartNotAllowed(Account),
+	#[error("CX_225__auth_policy_does_not_allow_cross_move_to_'{0:?}'")]
+	AuthTargetNotAllowed(TokenId),
+	#[error("CX_226__invalid_auth_policy__'{0}'")]
+	InvalidAuthPolicy(String),
+	#[error("CX_227__context_conflicts_with_an_earlier_context__'{0}'")]
+	ContextConflict(Box<ConflictReport>),
+	#[error("CX_228__storage_'{0:?}'_cannot_be_merged_as_'{1:?}'")]
+	StorageMergeMismatch(TappStorageType, MergeStrategy),
+	#[error("CX_229__context_bundle_conflicts_with_an_earlier_bundle__'{0:?}'")]
+	BundleConflict(Vec<ConflictReport>),
+	#[error("CX_230__context_of_'{0:?}'_does_not_match_the_tsid_or_base_of_the_bundle")]
+	BundleContextMismatch(TokenId),
+	#[error("CX_231__oracle_record_signature_of_node_'{0:?}'_is_invalid")]
+	OracleSignatureInvalid(ReplicaId),
 }
 
 #[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
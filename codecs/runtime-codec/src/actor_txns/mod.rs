@@ -10,6 +10,7 @@ pub mod context;
 pub mod error;
 mod followup;
 pub mod pre_args;
+pub mod receipt;
 pub mod tsid;
 pub mod txn;
 
@@ -1,9 +1,9 @@
 use crate::actor_txns::error::{Result, TxnError};
-use crate::tapp::{Hash, ReplicaId, TokenId};
+use crate::tapp::{Hash, ReplicaId, TokenId, Ts};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{array::TryFromSliceError, convert::TryInto};
-use tea_sdk::serialize;
+use tea_sdk::{deserialize, serialize};
 
 use super::error::Error;
 
@@ -21,6 +21,7 @@ pub enum Type {
 	CurrentHeight,
 	TopupLogs,
 	BasicString,
+	OracleHttp,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -30,6 +31,31 @@ pub enum Filter {
 	ByStatus(Status),
 	ByString(String),
 	Uncountable,
+	ByHttp(OracleHttpArg),
+}
+
+/// An http oracle fetch, the headers are kept in order so that the arg hashes deterministically.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OracleHttpArg {
+	pub method: String,
+	pub url: String,
+	pub headers: Vec<(String, String)>,
+	pub payload: Option<String>,
+}
+
+/// The result of an `OracleHttp` arg, fetched once by the node that processed the pre-args
+/// before the txn is sequenced. Every replica then executes the txn against this record
+/// instead of fetching again, and it is part of `Tsid.args_hash` through `ArgSlots::hash`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OracleRecord {
+	pub request: OracleHttpArg,
+	pub status: u16,
+	pub body: Vec<u8>,
+	pub fetched_at: Ts,
+	/// The tea id of the fetching node.
+	pub node: ReplicaId,
+	/// Signature of the fetching node over `signed_hash`.
+	pub signature: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -75,12 +101,107 @@ impl ArgSlots {
 	pub fn size(&self) -> usize {
 		self.args.iter().fold(0, |acc, x| acc + x.size())
 	}
+
+	/// All oracle records in the order their args were requested, each verified with
+	/// `verify_signature`, see `OracleRecord::verify`.
+	pub fn oracle_records(
+		&self,
+		verify_signature: impl Fn(&ReplicaId, &Hash, &[u8]) -> bool,
+	) -> Result<Vec<OracleRecord>> {
+		self.args
+			.iter()
+			.filter(|x| x.arg.ty == Type::OracleHttp)
+			.map(|x| x.oracle_record(&verify_signature))
+			.collect()
+	}
+
+	/// The oracle record fetched for `request`, if it was requested as a pre-arg. The record
+	/// is verified with `verify_signature`, see `OracleRecord::verify`.
+	pub fn oracle_record(
+		&self,
+		request: &OracleHttpArg,
+		verify_signature: impl Fn(&ReplicaId, &Hash, &[u8]) -> bool,
+	) -> Result<Option<OracleRecord>> {
+		self.args
+			.iter()
+			.find(|x| {
+				x.arg.ty == Type::OracleHttp && x.arg.filter == Filter::ByHttp(request.clone())
+			})
+			.map(|x| x.oracle_record(&verify_signature))
+			.transpose()
+	}
 }
 
 impl ArgResult {
 	pub fn size(&self) -> usize {
 		self.result.len() + self.arg.size()
 	}
+
+	fn decode_oracle_record(&self) -> Result<OracleRecord> {
+		if self.arg.ty != Type::OracleHttp {
+			return Err(Error::Unnamed(format!(
+				"arg {:?} is not an oracle http arg",
+				self.arg.ty
+			)));
+		}
+		deserialize(&self.result)
+			.map_err(|e| Error::Unnamed(format!("OracleRecord deserialize error: {:?}", e)))
+	}
+
+	/// The oracle record of an oracle http arg, verified with `verify_signature`, see
+	/// `OracleRecord::verify`.
+	pub fn oracle_record(
+		&self,
+		verify_signature: impl Fn(&ReplicaId, &Hash, &[u8]) -> bool,
+	) -> Result<OracleRecord> {
+		let record = self.decode_oracle_record()?;
+		record.verify(verify_signature)?;
+		Ok(record)
+	}
+
+	pub fn from_oracle_record(record: &OracleRecord) -> Result<Self> {
+		Ok(ArgResult {
+			arg: Arg::oracle_http(record.request.clone()),
+			result: serialize(record)
+				.map_err(|e| Error::Unnamed(format!("OracleRecord serialize error: {:?}", e)))?,
+		})
+	}
+}
+
+impl OracleRecord {
+	/// The hash the fetching node signs, covering everything but the signature itself.
+	pub fn signed_hash(&self) -> Result<Hash> {
+		let bytes = serialize(&(
+			&self.request,
+			self.status,
+			&self.body,
+			self.fetched_at,
+			&self.node,
+		))
+		.map_err(|e| Error::Unnamed(format!("OracleRecord hash error: {:?}", e)))?;
+		Sha256::digest(&bytes)
+			.as_slice()
+			.try_into()
+			.map_err(|e: TryFromSliceError| TxnError::PreArgsHashError(e.to_string()).into())
+	}
+
+	/// Checks that the fetching node signed the record. `verify_signature(node, hash, signature)`
+	/// verifies the signature over `signed_hash` with the tea id of the node, which this crate
+	/// cannot do as it has no crypto of its own.
+	pub fn verify(
+		&self,
+		verify_signature: impl Fn(&ReplicaId, &Hash, &[u8]) -> bool,
+	) -> Result<()> {
+		if !verify_signature(&self.node, &self.signed_hash()?, &self.signature) {
+			return Err(TxnError::OracleSignatureInvalid(self.node).into());
+		}
+		Ok(())
+	}
+
+	pub fn body_text(&self) -> Result<&str> {
+		std::str::from_utf8(&self.body)
+			.map_err(|e| Error::Unnamed(format!("oracle body is not utf8: {:?}", e)))
+	}
 }
 
 impl Arg {
@@ -148,6 +269,13 @@ impl Arg {
 		}
 	}
 
+	pub fn oracle_http(request: OracleHttpArg) -> Self {
+		Arg {
+			ty: Type::OracleHttp,
+			filter: Filter::ByHttp(request),
+		}
+	}
+
 	pub fn size(&self) -> usize {
 		self.filter.size() + 1
 	}
@@ -161,10 +289,23 @@ impl Filter {
 			Filter::ByStatus(_) => 1,
 			Filter::Uncountable => 0,
 			Filter::ByString(s) => s.as_bytes().len(),
+			Filter::ByHttp(request) => request.size(),
 		}
 	}
 }
 
+impl OracleHttpArg {
+	pub fn size(&self) -> usize {
+		self.method.len()
+			+ self.url.len()
+			+ self
+				.headers
+				.iter()
+				.fold(0, |acc, (k, v)| acc + k.len() + v.len())
+			+ self.payload.as_ref().map(|x| x.len()).unwrap_or_default()
+	}
+}
+
 impl Indentity {
 	pub fn size(&self) -> usize {
 		1 + match self {
@@ -174,3 +315,90 @@ impl Indentity {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record(body: &[u8]) -> OracleRecord {
+		OracleRecord {
+			request: OracleHttpArg {
+				method: "GET".into(),
+				url: "https://example.com/price".into(),
+				..Default::default()
+			},
+			status: 200,
+			body: body.to_vec(),
+			fetched_at: 100,
+			node: [7; 32],
+			signature: vec![1, 2, 3],
+		}
+	}
+
+	/// Accepts the signature made by `record`.
+	fn signed(node: &ReplicaId, _: &Hash, signature: &[u8]) -> bool {
+		*node == [7; 32] && signature == [1, 2, 3]
+	}
+
+	#[test]
+	fn oracle_record_roundtrip() {
+		let record = record(b"42");
+		let slots = ArgSlots {
+			args: vec![
+				ArgResult {
+					arg: Arg::current_height(),
+					result: vec![1],
+				},
+				ArgResult::from_oracle_record(&record).unwrap(),
+			],
+		};
+		assert_eq!(
+			slots.oracle_record(&record.request, signed).unwrap(),
+			Some(record.clone())
+		);
+		assert_eq!(slots.oracle_records(signed).unwrap(), vec![record.clone()]);
+		assert_eq!(
+			slots
+				.oracle_record(&OracleHttpArg::default(), signed)
+				.unwrap(),
+			None
+		);
+		assert!(slots.args[0].oracle_record(signed).is_err());
+		assert_eq!(record.body_text().unwrap(), "42");
+	}
+
+	#[test]
+	fn forged_oracle_records_are_rejected() {
+		let mut record = record(b"42");
+		record.signature = vec![4, 5, 6];
+		let slots = ArgSlots {
+			args: vec![ArgResult::from_oracle_record(&record).unwrap()],
+		};
+		let err: Error = TxnError::OracleSignatureInvalid([7; 32]).into();
+		assert_eq!(
+			slots.oracle_record(&record.request, signed),
+			Err(err.clone())
+		);
+		assert_eq!(slots.oracle_records(signed), Err(err.clone()));
+		assert_eq!(record.verify(signed), Err(err));
+	}
+
+	#[test]
+	fn oracle_body_changes_hashes() {
+		let slots = |body: &[u8]| ArgSlots {
+			args: vec![ArgResult::from_oracle_record(&record(body)).unwrap()],
+		};
+		assert_ne!(slots(b"42").hash().unwrap(), slots(b"43").hash().unwrap());
+		assert_ne!(
+			record(b"42").signed_hash().unwrap(),
+			record(b"43").signed_hash().unwrap()
+		);
+
+		let mut signed = record(b"42");
+		signed.signature = vec![];
+		assert_eq!(
+			signed.signed_hash().unwrap(),
+			record(b"42").signed_hash().unwrap()
+		);
+	}
+}
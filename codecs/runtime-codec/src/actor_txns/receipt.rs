@@ -0,0 +1,168 @@
+use crate::actor_txns::tsid::Tsid;
+use crate::tapp::{statement::TypedStatement, Hash};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tea_sdk::serde::TypeId;
+use tea_sdk::{deserialize, serialize};
+
+/// A typed event emitted by a tApp actor while executing a txn.
+///
+/// Events are collected in the `TokenContext` together with the balance changes, and end up
+/// in the `TxnReceipt` once the txn commits. The event type is the `TypeId` of the emitted
+/// value, so frontends can filter for e.g. `OrderFilled` without decoding every event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxnEvent {
+	pub event_type: String,
+	pub data: Vec<u8>,
+}
+
+impl TxnEvent {
+	pub fn new<E>(event: &E) -> tea_sdk::Result<Self>
+	where
+		E: TypeId + Serialize,
+	{
+		Ok(TxnEvent {
+			event_type: E::TYPE_ID.to_string(),
+			data: serialize(event)?,
+		})
+	}
+
+	pub fn is<E: TypeId>(&self) -> bool {
+		self.event_type == E::TYPE_ID
+	}
+
+	/// Decodes the event, or returns `None` if it is of another type.
+	pub fn decode<E>(&self) -> Option<tea_sdk::Result<E>>
+	where
+		E: TypeId + DeserializeOwned,
+	{
+		self.is::<E>().then(|| deserialize(&self.data))
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxnStatus {
+	Committed,
+	/// The txn failed with the error message reported by `report_txn_error`, nothing was committed.
+	Failed(String),
+}
+
+/// The outcome of a txn, stored by its hash once the txn has been executed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxnReceipt {
+	pub txn_hash: Hash,
+	pub tsid: Tsid,
+	pub status: TxnStatus,
+	pub statements: Vec<TypedStatement>,
+	pub events: Vec<TxnEvent>,
+}
+
+impl TxnReceipt {
+	/// The receipt of a committed txn.
+	pub fn committed(tsid: Tsid, statements: Vec<TypedStatement>, events: Vec<TxnEvent>) -> Self {
+		TxnReceipt {
+			txn_hash: tsid.hash,
+			tsid,
+			status: TxnStatus::Committed,
+			statements,
+			events,
+		}
+	}
+
+	/// The receipt of a failed txn.
+	pub fn failed(tsid: Tsid, error_msg: String) -> Self {
+		TxnReceipt {
+			txn_hash: tsid.hash,
+			tsid,
+			status: TxnStatus::Failed(error_msg),
+			statements: vec![],
+			events: vec![],
+		}
+	}
+
+	pub fn is_committed(&self) -> bool {
+		self.status == TxnStatus::Committed
+	}
+
+	/// Events of the given types in the order they were emitted.
+	pub fn events_of<'a>(
+		&'a self,
+		event_types: &'a [String],
+	) -> impl Iterator<Item = &'a TxnEvent> + 'a {
+		self.events
+			.iter()
+			.filter(|x| event_types.contains(&x.event_type))
+	}
+
+	/// Decodes every event of type `E` in the order they were emitted.
+	pub fn decode_events<E>(&self) -> tea_sdk::Result<Vec<E>>
+	where
+		E: TypeId + DeserializeOwned,
+	{
+		self.events.iter().filter_map(TxnEvent::decode).collect()
+	}
+
+	/// Keeps only the events of the given types, all events are kept if `event_types` is empty.
+	pub fn retain_events(&mut self, event_types: &[String]) {
+		if !event_types.is_empty() {
+			self.events.retain(|x| event_types.contains(&x.event_type));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+	struct OrderFilled {
+		order_id: u64,
+	}
+
+	#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+	struct OrderCancelled {
+		order_id: u64,
+	}
+
+	#[test]
+	fn events_filter_by_type() {
+		let mut receipt = TxnReceipt {
+			txn_hash: [0; 32],
+			tsid: Tsid::default(),
+			status: TxnStatus::Committed,
+			statements: vec![],
+			events: vec![
+				TxnEvent::new(&OrderFilled { order_id: 1 }).unwrap(),
+				TxnEvent::new(&OrderCancelled { order_id: 2 }).unwrap(),
+				TxnEvent::new(&OrderFilled { order_id: 3 }).unwrap(),
+			],
+		};
+		assert_eq!(
+			receipt.decode_events::<OrderFilled>().unwrap(),
+			vec![OrderFilled { order_id: 1 }, OrderFilled { order_id: 3 }]
+		);
+		assert!(receipt.events[1].decode::<OrderFilled>().is_none());
+
+		let filter = vec![OrderCancelled::TYPE_ID.to_string()];
+		assert_eq!(receipt.events_of(&filter).count(), 1);
+		receipt.retain_events(&[]);
+		assert_eq!(receipt.events.len(), 3);
+		receipt.retain_events(&filter);
+		assert_eq!(
+			receipt.decode_events::<OrderCancelled>().unwrap(),
+			vec![OrderCancelled { order_id: 2 }]
+		);
+	}
+
+	#[test]
+	fn receipts_are_keyed_by_txn_hash() {
+		let mut tsid = Tsid::default();
+		tsid.hash = [7; 32];
+		let receipt = TxnReceipt::committed(tsid, vec![], vec![]);
+		assert_eq!(receipt.txn_hash, [7; 32]);
+		assert!(receipt.is_committed());
+
+		let receipt = TxnReceipt::failed(tsid, "insufficient balance".to_string());
+		assert_eq!(receipt.txn_hash, [7; 32]);
+		assert!(!receipt.is_committed());
+	}
+}
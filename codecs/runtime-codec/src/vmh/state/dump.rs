@@ -1,3 +1,4 @@
+use crate::actor_txns::context::conflict::ConflictReport;
 use crate::actor_txns::tsid::{ChannelList, Tsid};
 use crate::tapp::{Account, Balance, PaymentInfo, TokenId};
 use serde::{Deserialize, Serialize};
@@ -16,6 +17,7 @@ pub struct DumpTxnSeqRequest {
 	pub show_conveyor_executed: bool,
 	pub show_history: bool,
 	pub show_exec_time: bool,
+	pub show_rerun_conflicts: bool,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -24,6 +26,43 @@ pub struct DumpTxnSeqResponse {
 	pub conveyor_mutable: Vec<TsidReadable>,
 	pub conveyor_executed: Vec<TsidReadable>,
 	pub history_txns: Vec<(TsidReadable, Duration)>,
+	/// Conflicts that made txns rerun instead of being rebased.
+	pub rerun_conflicts: Vec<ConflictReadable>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConflictReadable {
+	pub tsid: TsidReadable,
+	pub other_tsid: TsidReadable,
+	pub storage: Vec<String>,
+	/// items fields:
+	/// - balance category
+	/// - account
+	/// - conflict kind
+	pub balances: Vec<(String, String, String)>,
+	pub payment_channels: Vec<String>,
+}
+
+impl From<&ConflictReport> for ConflictReadable {
+	fn from(report: &ConflictReport) -> Self {
+		ConflictReadable {
+			tsid: report.tsid.into(),
+			other_tsid: report.other_tsid.into(),
+			storage: report.storage.iter().map(|x| format!("{x:?}")).collect(),
+			balances: report
+				.balances
+				.iter()
+				.map(|x| {
+					(
+						x.category.to_string(),
+						hex::encode(x.acct),
+						x.kind.to_string(),
+					)
+				})
+				.collect(),
+			payment_channels: report.payment_channels.iter().map(hex::encode).collect(),
+		}
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -0,0 +1,122 @@
+use proc_macro2::Ident;
+use syn::{
+	ext::IdentExt,
+	parse::{Parse, ParseStream},
+	punctuated::Punctuated,
+	spanned::Spanned,
+	Attribute, Data, DeriveInput, Error, Fields, Generics, Lit, LitStr, Meta, NestedMeta, Path,
+	Result, Token, Type,
+};
+
+pub const ATTR_FROM_ROW_IDENT: &str = "from_row";
+
+pub struct Input {
+	pub ident: Ident,
+	pub generics: Generics,
+	/// The module of `FromRow`, set by `#[from_row(path = "...")]`.
+	pub path: Option<Path>,
+	pub fields: Vec<Field>,
+}
+
+pub struct Field {
+	pub ident: Ident,
+	pub ty: Type,
+	/// The column name, which is the field name unless `#[from_row(rename = "...")]` is set.
+	pub column: String,
+	/// Whether `#[from_row(default)]` is set, which fills the field if the column is missing.
+	pub default: bool,
+}
+
+/// The `key = "value"` and `key` arguments of all `#[from_row(...)]` attributes.
+fn args(attrs: &[Attribute]) -> Result<Vec<(Ident, Option<LitStr>)>> {
+	let mut args = Vec::new();
+	for attr in attrs
+		.iter()
+		.filter(|x| x.path.is_ident(ATTR_FROM_ROW_IDENT))
+	{
+		let metas = attr.parse_args_with(Punctuated::<NestedMeta, Token![,]>::parse_terminated)?;
+		for meta in metas {
+			let (path, value) = match meta {
+				NestedMeta::Meta(Meta::Path(path)) => (path, None),
+				NestedMeta::Meta(Meta::NameValue(meta)) => match meta.lit {
+					Lit::Str(value) => (meta.path, Some(value)),
+					lit => return Err(Error::new(lit.span(), "Expected a string literal.")),
+				},
+				meta => return Err(Error::new(meta.span(), "Unknown from_row argument.")),
+			};
+			let ident = path
+				.get_ident()
+				.cloned()
+				.ok_or_else(|| Error::new(path.span(), "Unknown from_row argument."))?;
+			args.push((ident, value));
+		}
+	}
+	Ok(args)
+}
+
+impl Parse for Input {
+	fn parse(input: ParseStream) -> Result<Self> {
+		let DeriveInput {
+			ident,
+			generics,
+			attrs,
+			data,
+			..
+		} = DeriveInput::parse(input)?;
+
+		let mut path = None;
+		for (arg, value) in args(&attrs)? {
+			match (arg.to_string().as_str(), value) {
+				("path", Some(value)) => path = Some(value.parse()?),
+				_ => return Err(Error::new(arg.span(), "Unknown from_row argument.")),
+			}
+		}
+
+		let fields = match data {
+			Data::Struct(data) => match data.fields {
+				Fields::Named(fields) => fields.named,
+				fields => {
+					return Err(Error::new(
+						fields.span(),
+						"FromRow can only be derived for structs with named fields.",
+					))
+				}
+			},
+			_ => {
+				return Err(Error::new(
+					ident.span(),
+					"FromRow can only be derived for structs with named fields.",
+				))
+			}
+		};
+
+		let fields = fields
+			.into_iter()
+			.map(|field| {
+				let ident = field.ident.expect("named fields have idents");
+				let mut column = ident.unraw().to_string();
+				let mut default = false;
+				for (arg, value) in args(&field.attrs)? {
+					match (arg.to_string().as_str(), value) {
+						("rename", Some(value)) => column = value.value(),
+						("default", None) => default = true,
+						_ => return Err(Error::new(arg.span(), "Unknown from_row argument.")),
+					}
+				}
+				Ok(Field {
+					ident,
+					ty: field.ty,
+					column,
+					default,
+				})
+			})
+			.collect::<Result<_>>()?;
+
+		Ok(Self {
+			ident,
+			generics,
+			path,
+			fields,
+		})
+	}
+}
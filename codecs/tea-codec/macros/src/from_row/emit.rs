@@ -0,0 +1,47 @@
+use proc_macro2::{Literal, TokenStream};
+use quote::quote;
+
+use super::ast::{Field, Input};
+
+pub fn emit(
+	Input {
+		ident,
+		generics,
+		path,
+		fields,
+	}: Input,
+) -> TokenStream {
+	let path = match path {
+		Some(path) => quote! { #path },
+		None => quote! { ::tea_sdk::utils::wasm_actor::actors::sql },
+	};
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	let fields = fields.iter().map(
+		|Field {
+		     ident,
+		     ty,
+		     column,
+		     default,
+		 }| {
+			let column = Literal::string(column);
+			if *default {
+				quote! { #ident: reader.get_or_default::<#ty>(#column)? }
+			} else {
+				quote! { #ident: reader.get::<#ty>(#column)? }
+			}
+		},
+	);
+
+	quote! {
+		impl #impl_generics #path::FromRow for #ident #ty_generics #where_clause {
+			fn from_row(
+				labels: &[::std::string::String],
+				row: &#path::__private::Row,
+			) -> #path::__private::Result<Self> {
+				let reader = #path::RowReader::new(labels, row)?;
+				Ok(Self { #(#fields),* })
+			}
+		}
+	}
+}
@@ -0,0 +1,119 @@
+use proc_macro2::Ident;
+use syn::{
+	parse::{Parse, ParseStream},
+	spanned::Spanned,
+	Data, DeriveInput, Error, Fields, Result, Type, Visibility,
+};
+
+pub const ATTR_LAYOUT_IDENT: &str = "layout";
+pub const ARG_BYTES_IDENT: &str = "bytes";
+
+pub struct Input {
+	pub ident: Ident,
+	pub vis: Visibility,
+	/// Whether `#[layout(bytes)]` is set, which makes `ToBytes` and `FromBytes` use the layout.
+	pub bytes: bool,
+	pub body: Body,
+}
+
+pub enum Body {
+	Struct(Shape),
+	Enum(Vec<(Ident, Shape)>),
+}
+
+pub enum Shape {
+	Unit,
+	Tuple(Vec<(Visibility, Type)>),
+	Struct(Vec<(Visibility, Ident, Type)>),
+}
+
+impl Shape {
+	fn from_fields(fields: &Fields) -> Self {
+		match fields {
+			Fields::Unit => Shape::Unit,
+			Fields::Unnamed(fields) => Shape::Tuple(
+				fields
+					.unnamed
+					.iter()
+					.map(|x| (x.vis.clone(), x.ty.clone()))
+					.collect(),
+			),
+			Fields::Named(fields) => Shape::Struct(
+				fields
+					.named
+					.iter()
+					.filter_map(|x| Some((x.vis.clone(), x.ident.clone()?, x.ty.clone())))
+					.collect(),
+			),
+		}
+	}
+
+	pub fn types(&self) -> Vec<&Type> {
+		match self {
+			Shape::Unit => Vec::new(),
+			Shape::Tuple(fields) => fields.iter().map(|(_, ty)| ty).collect(),
+			Shape::Struct(fields) => fields.iter().map(|(_, _, ty)| ty).collect(),
+		}
+	}
+}
+
+impl Body {
+	/// Whether any field exists, otherwise the type is its own read view.
+	pub fn has_fields(&self) -> bool {
+		match self {
+			Body::Struct(shape) => !shape.types().is_empty(),
+			Body::Enum(variants) => variants.iter().any(|(_, x)| !x.types().is_empty()),
+		}
+	}
+}
+
+impl Parse for Input {
+	fn parse(input: ParseStream) -> Result<Self> {
+		let DeriveInput {
+			ident,
+			vis,
+			generics,
+			attrs,
+			data,
+		} = DeriveInput::parse(input)?;
+
+		if !generics.params.is_empty() {
+			return Err(Error::new(
+				generics.span(),
+				"Layout types cannot be generic.",
+			));
+		}
+
+		let mut bytes = false;
+		for attr in attrs.iter().filter(|x| x.path.is_ident(ATTR_LAYOUT_IDENT)) {
+			let arg: Ident = attr.parse_args()?;
+			if arg != ARG_BYTES_IDENT {
+				return Err(Error::new(arg.span(), "Unknown layout argument."));
+			}
+			bytes = true;
+		}
+
+		let body = match data {
+			Data::Struct(data) => Body::Struct(Shape::from_fields(&data.fields)),
+			Data::Enum(data) => Body::Enum(
+				data.variants
+					.iter()
+					.map(|x| (x.ident.clone(), Shape::from_fields(&x.fields)))
+					.collect(),
+			),
+			Data::Union(data) => {
+				return Err(Error::new(
+					data.union_token.span(),
+					"Unions are not supported.",
+				))
+			}
+		};
+
+		Ok(Self {
+			ident,
+			vis,
+			bytes,
+			body,
+		})
+	}
+}
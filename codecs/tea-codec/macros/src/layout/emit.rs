@@ -0,0 +1,278 @@
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::{format_ident, quote};
+use syn::Type;
+
+use super::ast::{Body, Input, Shape};
+
+pub fn emit(
+	Input {
+		ident,
+		vis,
+		bytes,
+		body,
+	}: Input,
+) -> TokenStream {
+	let path = quote! { ::tea_sdk::serde::layout };
+	let type_name = Literal::string(ident.to_string().as_str());
+	let has_fields = body.has_fields();
+	let view = format_ident!("{}View", ident);
+
+	let (view_def, read_type) = if has_fields {
+		let def = match &body {
+			Body::Struct(Shape::Struct(fields)) => {
+				let fields = fields.iter().map(|(vis, name, ty)| {
+					let ty = read_type(ty);
+					quote! { #vis #name: #ty }
+				});
+				quote! { #vis struct #view<'a> { #(#fields),* } }
+			}
+			Body::Struct(Shape::Tuple(fields)) => {
+				let fields = fields.iter().map(|(vis, ty)| {
+					let ty = read_type(ty);
+					quote! { #vis #ty }
+				});
+				quote! { #vis struct #view<'a>(#(#fields),*); }
+			}
+			Body::Struct(Shape::Unit) => unreachable!(),
+			Body::Enum(variants) => {
+				let variants = variants.iter().map(|(name, shape)| match shape {
+					Shape::Unit => quote! { #name },
+					Shape::Tuple(fields) => {
+						let types = fields.iter().map(|(_, ty)| read_type(ty));
+						quote! { #name(#(#types),*) }
+					}
+					Shape::Struct(fields) => {
+						let fields = fields.iter().map(|(_, name, ty)| {
+							let ty = read_type(ty);
+							quote! { #name: #ty }
+						});
+						quote! { #name { #(#fields),* } }
+					}
+				});
+				quote! { #vis enum #view<'a> { #(#variants),* } }
+			}
+		};
+		let doc = format!("The borrowed view of [`{ident}`] read from its layout.");
+		(quote! { #[doc = #doc] #def }, quote! { #view<'a> })
+	} else {
+		(quote! {}, quote! { Self })
+	};
+
+	let (size, write, read, into_owned) = match &body {
+		Body::Struct(shape) => {
+			let value_pattern = pattern(quote! { Self }, shape);
+			let types = shape.types();
+			let bindings = bindings(types.len());
+			let reads = types
+				.iter()
+				.map(|ty| quote! { <#ty as #path::LayoutRead>::read(buf)? });
+			let owned = types
+				.iter()
+				.zip(&bindings)
+				.map(|(ty, x)| quote! { <#ty as #path::IntoLayoutOwned>::into_owned(#x) });
+			let view_path = if has_fields {
+				quote! { #view }
+			} else {
+				quote! { Self }
+			};
+			let view_pattern = pattern(view_path.clone(), shape);
+			let construct_view = construct(view_path, shape, reads);
+			let construct_owned = construct(quote! { Self }, shape, owned);
+			(
+				quote! {
+					let #value_pattern = value;
+					#(size += <#types as #path::LayoutWrite>::size(<#types as #path::AsLayoutWrite>::as_write(#bindings))?;)*
+				},
+				quote! {
+					let #value_pattern = value;
+					#(<#types as #path::LayoutWrite>::write(<#types as #path::AsLayoutWrite>::as_write(#bindings), &mut buf)?;)*
+				},
+				quote! { Ok(#construct_view) },
+				if has_fields {
+					quote! {
+						let #view_pattern = value;
+						#construct_owned
+					}
+				} else {
+					quote! { value }
+				},
+			)
+		}
+		Body::Enum(variants) => {
+			let mut size = Vec::new();
+			let mut write = Vec::new();
+			let mut read = Vec::new();
+			let mut into_owned = Vec::new();
+			for (i, (name, shape)) in variants.iter().enumerate() {
+				let index = Literal::u32_suffixed(i as _);
+				let value_pattern = pattern(quote! { Self::#name }, shape);
+				let types = shape.types();
+				let bindings = bindings(types.len());
+				size.push(quote! {
+					#value_pattern => {
+						#(size += <#types as #path::LayoutWrite>::size(<#types as #path::AsLayoutWrite>::as_write(#bindings))?;)*
+					}
+				});
+				write.push(quote! {
+					#value_pattern => {
+						<u32 as #path::LayoutWrite>::write(#index, &mut buf)?;
+						#(<#types as #path::LayoutWrite>::write(<#types as #path::AsLayoutWrite>::as_write(#bindings), &mut buf)?;)*
+					}
+				});
+				let view_path = if has_fields {
+					quote! { #view::#name }
+				} else {
+					quote! { Self::#name }
+				};
+				let reads = types
+					.iter()
+					.map(|ty| quote! { <#ty as #path::LayoutRead>::read(buf)? });
+				let construct_view = construct(view_path.clone(), shape, reads);
+				read.push(quote! { #index => #construct_view });
+				let view_pattern = pattern(view_path, shape);
+				let owned = types
+					.iter()
+					.zip(&bindings)
+					.map(|(ty, x)| quote! { <#ty as #path::IntoLayoutOwned>::into_owned(#x) });
+				let construct_owned = construct(quote! { Self::#name }, shape, owned);
+				into_owned.push(quote! { #view_pattern => #construct_owned });
+			}
+			(
+				quote! {
+					size += ::std::mem::size_of::<u32>();
+					match value {
+						#(#size)*
+					}
+				},
+				quote! {
+					match value {
+						#(#write)*
+					}
+				},
+				quote! {
+					Ok(match <u32 as #path::LayoutRead>::read(buf)? {
+						#(#read,)*
+						_ => return Err(::tea_sdk::serde::error::InvalidFormat(#type_name.to_string()).into()),
+					})
+				},
+				if has_fields {
+					quote! {
+						match value {
+							#(#into_owned,)*
+						}
+					}
+				} else {
+					quote! { value }
+				},
+			)
+		}
+	};
+
+	let impl_bytes = if bytes {
+		quote! {
+			impl #path::LayoutBytes for #ident {}
+
+			impl ::tea_sdk::serde::ToBytes for #ident {
+				#[inline(always)]
+				fn to_bytes(&self) -> ::tea_sdk::Result<Vec<u8>> {
+					let mut result = Vec::new();
+					#path::LayoutBytes::write_layout_to(self, &mut result)?;
+					Ok(result)
+				}
+
+				#[inline(always)]
+				fn bytes_len(&self) -> ::tea_sdk::Result<usize> {
+					#path::LayoutBytes::layout_bytes_len(self)
+				}
+
+				#[inline(always)]
+				fn write_to(&self, w: impl ::tea_sdk::serde::SerBuf) -> ::tea_sdk::Result<()> {
+					#path::LayoutBytes::write_layout_to(self, w)
+				}
+			}
+
+			impl<'a> ::tea_sdk::serde::FromBytes<'a> for #ident {
+				#[inline(always)]
+				fn from_bytes(buf: &'a [u8]) -> ::tea_sdk::Result<Self> {
+					#path::LayoutBytes::from_layout_bytes(buf)
+				}
+			}
+		}
+	} else {
+		quote! {}
+	};
+
+	quote! {
+		#view_def
+
+		impl #path::LayoutWrite for #ident {
+			type Write<'a> = &'a Self;
+
+			#[allow(unused_mut)]
+			fn size(value: Self::Write<'_>) -> ::tea_sdk::Result<usize> {
+				let mut size = 0;
+				#size
+				Ok(size)
+			}
+
+			#[allow(unused_mut, unused_variables)]
+			fn write(value: Self::Write<'_>, mut buf: impl ::tea_sdk::serde::SerBuf) -> ::tea_sdk::Result<()> {
+				#write
+				Ok(())
+			}
+		}
+
+		impl #path::AsLayoutWrite for #ident {
+			#[inline(always)]
+			fn as_write(&self) -> Self::Write<'_> {
+				self
+			}
+		}
+
+		impl #path::LayoutRead for #ident {
+			type Read<'a> = #read_type;
+
+			#[allow(unused_variables)]
+			fn read<'a>(buf: &mut &'a [u8]) -> ::tea_sdk::Result<Self::Read<'a>> {
+				#read
+			}
+		}
+
+		impl #path::IntoLayoutOwned for #ident {
+			fn into_owned(value: Self::Read<'_>) -> Self {
+				#into_owned
+			}
+		}
+
+		#impl_bytes
+	}
+}
+
+fn read_type(ty: &Type) -> TokenStream {
+	quote! { <#ty as ::tea_sdk::serde::layout::LayoutRead>::Read<'a> }
+}
+
+fn bindings(len: usize) -> Vec<Ident> {
+	(0..len).map(|i| format_ident!("__field{}", i)).collect()
+}
+
+/// Destructures `path` into the bindings of [`bindings`].
+fn pattern(path: TokenStream, shape: &Shape) -> TokenStream {
+	let bindings = bindings(shape.types().len());
+	construct(path, shape, bindings.iter().map(|x| quote! { #x }))
+}
+
+fn construct(
+	path: TokenStream,
+	shape: &Shape,
+	values: impl Iterator<Item = TokenStream>,
+) -> TokenStream {
+	match shape {
+		Shape::Unit => path,
+		Shape::Tuple(_) => quote! { #path(#(#values),*) },
+		Shape::Struct(fields) => {
+			let names = fields.iter().map(|(_, name, _)| name);
+			quote! { #path { #(#names: #values),* } }
+		}
+	}
+}
@@ -1,5 +1,7 @@
 use proc_macro::TokenStream;
+mod from_row;
 mod handle;
+mod layout;
 mod pricing;
 mod serde;
 mod timeout;
@@ -13,6 +15,11 @@ use syn::parse_macro_input;
 ///
 /// If the type name ends with `Request`, then a response attribute is automatically added with the suffix of `Response` by convention.
 ///
+/// Use `#[evolvable]` on a struct with named fields to encode it by field name instead of field order,
+/// so that peers built against other versions of the struct can still decode it.
+/// Fields missing from the payload are filled with `Default::default()` and unknown fields are skipped.
+/// The type id of an evolvable type does not contain the package version.
+///
 /// # Examples
 ///
 /// ```
@@ -25,12 +32,63 @@ use syn::parse_macro_input;
 /// #[derive(TypeId)]
 /// pub struct GetSystemTimeResponse(pub u128);
 /// ```
-#[proc_macro_derive(TypeId, attributes(response))]
+#[proc_macro_derive(TypeId, attributes(response, evolvable))]
 pub fn derive_type_id(input: TokenStream) -> TokenStream {
 	let input: serde::ast::Input = parse_macro_input!(input);
 	serde::emit::emit(input).into()
 }
 
+/// Impls `LayoutWrite` and `LayoutRead` for a struct or an enum, reading into a borrowed `<Type>View<'a>`,
+/// along with `AsLayoutWrite` and `IntoLayoutOwned`, which every field type has to implement.
+///
+/// Fields are laid out in declaration order and enum variants are prefixed with their index as `u32`.
+/// Types without any field are read as themselves.
+///
+/// Use `#[layout(bytes)]` on a type that also derives `TypeId` to encode it with the layout in `ToBytes` and `FromBytes`.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(TypeId, Layout)]
+/// #[layout(bytes)]
+/// pub struct KeyVecGetRequest {
+///     pub key: String,
+/// }
+///
+/// // `KeyVecGetRequestView<'a> { key: &'a str }` is generated for borrowed reads.
+/// ```
+#[proc_macro_derive(Layout, attributes(layout))]
+pub fn derive_layout(input: TokenStream) -> TokenStream {
+	let input: layout::ast::Input = parse_macro_input!(input);
+	layout::emit::emit(input).into()
+}
+
+/// Impls `FromRow` of `tea_wasm_actor_utils::enclave::actors::sql` for a struct with named fields,
+/// reading each field from the column of the same name in a GlueSQL select row.
+///
+/// Use `#[from_row(rename = "...")]` on a field to read it from another column, and
+/// `#[from_row(default)]` to fill it with `Default::default()` if the column is not selected.
+/// Use `#[from_row(path = "...")]` on the struct if the `sql` module is not reachable as
+/// `tea_sdk::utils::wasm_actor::actors::sql`.
+///
+/// # Examples
+///
+/// ```ignore
+/// #[derive(FromRow)]
+/// pub struct GasFee {
+///     #[from_row(rename = "TxnHash")]
+///     pub txn_hash: String,
+///     pub fee: u64,
+///     #[from_row(default)]
+///     pub memo: Option<String>,
+/// }
+/// ```
+#[proc_macro_derive(FromRow, attributes(from_row))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+	let input: from_row::ast::Input = parse_macro_input!(input);
+	from_row::emit::emit(input).into()
+}
+
 #[proc_macro_derive(Priced, attributes(price))]
 pub fn derive_priced(input: TokenStream) -> TokenStream {
 	let input: pricing::ast::Input = parse_macro_input!(input);
@@ -1,17 +1,79 @@
 use proc_macro2::Ident;
 use syn::{
+	ext::IdentExt,
 	parse::{Parse, ParseStream},
+	punctuated::Punctuated,
 	spanned::Spanned,
-	Attribute, DeriveInput, Generics, Result, Type, TypePath,
+	Attribute, Data, DeriveInput, Fields, Generics, Meta, Result, Token, Type, TypePath,
 };
 
+use super::rename::{rename, rename_all, RenameRule};
+
 pub struct Input {
 	pub ident: Ident,
 	pub generics: Generics,
 	pub resp: Option<Type>,
+	pub shape: Shape,
+	/// Every named field and whether serde skips it, if the type is marked with `#[evolvable]`.
+	pub evolvable: Option<Vec<(Ident, bool)>>,
+	/// Whether the type is encoded with `#[derive(Layout)]`, as marked with `#[layout(bytes)]`.
+	pub layout: bool,
+}
+
+/// The fields of the type as seen by bincode, i.e. without those skipped by serde.
+///
+/// Named fields and variants come with the name serde renames them to, if it differs. Named
+/// fields also come with whether serde allows them to be missing, and variants with whether
+/// serde skips them, as they still take up their index.
+pub enum Shape {
+	Unit,
+	Tuple(Vec<Type>),
+	Struct(Vec<(Ident, Option<String>, Type, bool)>),
+	Enum(Vec<(Ident, Option<String>, Shape, bool)>),
+}
+
+impl Shape {
+	/// `default` tells whether the container is marked with `#[serde(default)]`.
+	fn from_fields(fields: &Fields, rule: Option<RenameRule>, default: bool) -> Result<Self> {
+		Ok(match fields {
+			Fields::Unit => Shape::Unit,
+			Fields::Unnamed(fields) => Shape::Tuple(
+				fields
+					.unnamed
+					.iter()
+					.filter(|x| !x.attrs.iter().any(is_serde_skip))
+					.map(|x| x.ty.clone())
+					.collect(),
+			),
+			Fields::Named(fields) => {
+				let mut named = Vec::new();
+				for field in fields
+					.named
+					.iter()
+					.filter(|x| !x.attrs.iter().any(is_serde_skip))
+				{
+					let Some(ident) = field.ident.clone() else {
+						continue;
+					};
+					let name = ident.unraw().to_string();
+					let renamed = match rename(&field.attrs)? {
+						Some(renamed) => renamed,
+						None => {
+							rule.map_or_else(|| name.clone(), |rule| rule.apply_to_field(&name))
+						}
+					};
+					let renamed = (ident != renamed).then_some(renamed);
+					let optional = default || field.attrs.iter().any(is_serde_optional);
+					named.push((ident, renamed, field.ty.clone(), optional));
+				}
+				Shape::Struct(named)
+			}
+		})
+	}
 }
 
 const ATTR_RESPONSE_IDENT: &str = "response";
+const ATTR_EVOLVABLE_IDENT: &str = "evolvable";
 
 impl Parse for Input {
 	fn parse(input: ParseStream) -> Result<Self> {
@@ -38,10 +100,55 @@ impl Parse for Input {
 				}))
 			}
 		}
+		let rule = rename_all(&body.attrs)?;
+		let shape = match &body.data {
+			Data::Struct(data) => Shape::from_fields(
+				&data.fields,
+				rule,
+				body.attrs.iter().any(|x| has_serde_flag(x, "default")),
+			)?,
+			Data::Enum(data) => Shape::Enum(
+				data.variants
+					.iter()
+					.map(|x| {
+						let name = x.ident.to_string();
+						let renamed = match rename(&x.attrs)? {
+							Some(renamed) => renamed,
+							None => rule
+								.map_or_else(|| name.clone(), |rule| rule.apply_to_variant(&name)),
+						};
+						Ok((
+							x.ident.clone(),
+							(renamed != name).then_some(renamed),
+							Shape::from_fields(&x.fields, rename_all(&x.attrs)?, false)?,
+							x.attrs.iter().any(is_serde_skip),
+						))
+					})
+					.collect::<Result<_>>()?,
+			),
+			Data::Union(data) => {
+				return Err(syn::Error::new(
+					data.union_token.span(),
+					"Unions are not supported.",
+				))
+			}
+		};
+		let evolvable = match body
+			.attrs
+			.iter()
+			.find(|x| is_single_ident(ATTR_EVOLVABLE_IDENT)(x))
+		{
+			Some(attr) => Some(evolvable_fields(attr, &body)?),
+			None => None,
+		};
+		let layout = body.attrs.iter().any(|x| has_flag(x, "layout", "bytes"));
 		Ok(Self {
 			resp,
 			ident: body.ident,
 			generics: body.generics,
+			shape,
+			evolvable,
+			layout,
 		})
 	}
 }
@@ -55,3 +162,51 @@ const fn is_single_ident(cmp: &str) -> impl Fn(&Attribute) -> bool + '_ {
 		}
 	}
 }
+
+fn evolvable_fields(attr: &Attribute, body: &DeriveInput) -> Result<Vec<(Ident, bool)>> {
+	if body.generics.params.iter().next().is_some() {
+		return Err(syn::Error::new(
+			attr.span(),
+			"Evolvable types cannot be generic.",
+		));
+	}
+	match &body.data {
+		Data::Struct(data) => match &data.fields {
+			Fields::Named(fields) => Ok(fields
+				.named
+				.iter()
+				.filter_map(|x| Some((x.ident.clone()?, x.attrs.iter().any(is_serde_skip))))
+				.collect()),
+			_ => Err(syn::Error::new(
+				attr.span(),
+				"Evolvable types must have named fields.",
+			)),
+		},
+		_ => Err(syn::Error::new(
+			attr.span(),
+			"Evolvable types must be structs.",
+		)),
+	}
+}
+
+fn is_serde_skip(attr: &Attribute) -> bool {
+	has_serde_flag(attr, "skip")
+}
+
+/// Whether the field may be missing in self-describing formats.
+fn is_serde_optional(attr: &Attribute) -> bool {
+	has_serde_flag(attr, "default") || has_serde_flag(attr, "skip_serializing_if")
+}
+
+/// Whether the attribute is `#[serde(..)]` with `name` among its arguments, with or without value.
+fn has_serde_flag(attr: &Attribute, name: &str) -> bool {
+	has_flag(attr, "serde", name)
+}
+
+fn has_flag(attr: &Attribute, container: &str, name: &str) -> bool {
+	is_single_ident(container)(attr)
+		&& attr
+			.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+			.map(|metas| metas.iter().any(|x| x.path().is_ident(name)))
+			.unwrap_or(false)
+}
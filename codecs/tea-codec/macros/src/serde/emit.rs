@@ -1,17 +1,20 @@
 use proc_macro2::{Ident, Literal, TokenStream};
-use quote::quote;
+use quote::{quote, ToTokens};
 use syn::{
-	punctuated::Punctuated, Path, PredicateType, TraitBound, TraitBoundModifier, Type,
-	TypeParamBound, TypePath, WherePredicate,
+	punctuated::Punctuated, GenericArgument, Path, PathArguments, PredicateType, TraitBound,
+	TraitBoundModifier, Type, TypeParamBound, TypePath, WherePredicate,
 };
 
-use super::ast::Input;
+use super::ast::{Input, Shape};
 
 pub fn emit(
 	Input {
 		ident,
 		mut generics,
 		resp,
+		shape,
+		evolvable,
+		layout,
 	}: Input,
 ) -> TokenStream {
 	let type_name = Literal::string(ident.to_string().as_str());
@@ -50,7 +53,12 @@ pub fn emit(
 		}));
 	}
 
-	let mut value = quote! { ::tea_sdk::const_concat::ConstStr::empty().append_str(concat!(module_path!(), "::", #type_name, "@", env!("CARGO_PKG_VERSION"))) };
+	// evolvable types stay compatible across versions of the defining crate
+	let mut value = if evolvable.is_some() {
+		quote! { ::tea_sdk::const_concat::ConstStr::empty().append_str(concat!(module_path!(), "::", #type_name)) }
+	} else {
+		quote! { ::tea_sdk::const_concat::ConstStr::empty().append_str(concat!(module_path!(), "::", #type_name, "@", env!("CARGO_PKG_VERSION"))) }
+	};
 	if !targs.is_empty() {
 		value = quote! { #value.append_str("<") };
 		for (i, t) in targs.iter().enumerate() {
@@ -77,6 +85,50 @@ pub fn emit(
 		}
 	};
 
+	let schema = emit_schema(&shape);
+	let mut nested = Vec::new();
+	nested_types(&shape, &mut nested);
+	let is_evolvable = evolvable.is_some();
+	let resp_type_id = if let Some(resp) = &resp {
+		quote! { <#resp as ::tea_sdk::serde::registry::MaybeTypeId>::maybe_type_id().map(::std::borrow::Cow::Borrowed) }
+	} else {
+		quote! { None }
+	};
+	let impl_describe = quote! {
+		impl<#(#targs),*> ::tea_sdk::serde::registry::Describe for #ident<#(#targs),*>
+			#wc
+		{
+			fn descriptor() -> ::tea_sdk::serde::registry::TypeDescriptor {
+				::tea_sdk::serde::registry::TypeDescriptor {
+					type_id: ::std::borrow::Cow::Borrowed(<Self as ::tea_sdk::serde::TypeId>::TYPE_ID),
+					name: ::std::borrow::Cow::Borrowed(#type_name),
+					response_type_id: #resp_type_id,
+					schema: #schema,
+					evolvable: #is_evolvable,
+					layout: #layout,
+				}
+			}
+
+			fn describe_nested(registry: &mut ::tea_sdk::serde::registry::TypeRegistry) {
+				#(<#nested as ::tea_sdk::serde::registry::MaybeDescribe>::describe_into(registry);)*
+			}
+		}
+	};
+
+	// generic types have no single descriptor to collect
+	let submit = if targs.is_empty() {
+		quote! {
+			::tea_sdk::serde::registry::inventory::submit! {
+				::tea_sdk::serde::registry::Derived {
+					type_id: <#ident as ::tea_sdk::serde::TypeId>::TYPE_ID,
+					register: <#ident as ::tea_sdk::serde::registry::Register>::register_into,
+				}
+			}
+		}
+	} else {
+		quote! {}
+	};
+
 	let resp = if let Some(resp) = resp {
 		quote! {
 			impl<#(#targs),*> ::tea_sdk::serde::handle::Request for #ident<#(#targs),*>
@@ -88,8 +140,183 @@ pub fn emit(
 		quote! {}
 	};
 
+	let impl_evolvable = if let Some(fields) = evolvable {
+		emit_evolvable(&ident, &fields)
+	} else {
+		quote! {}
+	};
+
 	quote! {
 		#impl_type_id
+		#impl_describe
+		#submit
+		#impl_evolvable
 		#resp
 	}
 }
+
+fn emit_evolvable(ident: &Ident, fields: &[(Ident, bool)]) -> TokenStream {
+	let path = quote! { ::tea_sdk::serde::evolvable };
+	let writes = fields.iter().filter(|(_, skip)| !skip).map(|(name, _)| {
+		let tag = Literal::string(name.to_string().as_str());
+		quote! { fields.write(#tag, &self.#name)?; }
+	});
+	let reads = fields.iter().map(|(name, skip)| {
+		if *skip {
+			quote! { #name: ::std::default::Default::default() }
+		} else {
+			let tag = Literal::string(name.to_string().as_str());
+			quote! { #name: fields.read(#tag)? }
+		}
+	});
+	quote! {
+		impl #path::Evolvable for #ident {
+			fn write_fields(&self, fields: &mut #path::FieldWriter) -> ::tea_sdk::Result<()> {
+				#(#writes)*
+				Ok(())
+			}
+
+			fn read_fields(fields: &#path::FieldReader) -> ::tea_sdk::Result<Self> {
+				Ok(Self {
+					#(#reads),*
+				})
+			}
+		}
+	}
+}
+
+fn emit_schema(shape: &Shape) -> TokenStream {
+	let path = quote! { ::tea_sdk::serde::registry };
+	match shape {
+		Shape::Unit => quote! { #path::Schema::Unit },
+		Shape::Tuple(types) => {
+			let fields = types
+				.iter()
+				.enumerate()
+				.map(|(i, ty)| emit_field(&i.to_string(), &None, ty, false));
+			quote! { #path::Schema::Tuple(vec![#(#fields),*]) }
+		}
+		Shape::Struct(fields) => {
+			let fields = fields.iter().map(|(name, json_name, ty, optional)| {
+				emit_field(&name.to_string(), json_name, ty, *optional)
+			});
+			quote! { #path::Schema::Struct(vec![#(#fields),*]) }
+		}
+		Shape::Enum(variants) => {
+			let variants = variants.iter().map(|(name, json_name, shape, skipped)| {
+				let name = Literal::string(name.to_string().as_str());
+				let json_name = emit_json_name(json_name);
+				let schema = emit_schema(shape);
+				quote! {
+					#path::Variant {
+						name: ::std::borrow::Cow::Borrowed(#name),
+						json_name: #json_name,
+						schema: #schema,
+						skipped: #skipped,
+					}
+				}
+			});
+			quote! { #path::Schema::Enum(vec![#(#variants),*]) }
+		}
+	}
+}
+
+fn emit_field(name: &str, json_name: &Option<String>, ty: &Type, optional: bool) -> TokenStream {
+	let path = quote! { ::tea_sdk::serde::registry };
+	let name = Literal::string(name);
+	let json_name = emit_json_name(json_name);
+	let mut nested = Vec::new();
+	named_types(ty, &mut nested);
+	let refs = if nested.is_empty() {
+		quote! { ::std::vec::Vec::new() }
+	} else {
+		let refs = nested.iter().map(|x| {
+			let name = type_name(x);
+			quote! { <#x as #path::MaybeDescribe>::reference(#name) }
+		});
+		quote! { [#(#refs),*].into_iter().flatten().collect() }
+	};
+	let ty = type_name(ty);
+	quote! {
+		#path::Field {
+			name: ::std::borrow::Cow::Borrowed(#name),
+			json_name: #json_name,
+			ty: ::std::borrow::Cow::Borrowed(#ty),
+			optional: #optional,
+			refs: #refs,
+		}
+	}
+}
+
+/// Collects the types named in the fields of the shape that may be described.
+fn nested_types(shape: &Shape, out: &mut Vec<Type>) {
+	match shape {
+		Shape::Unit => {}
+		Shape::Tuple(types) => types.iter().for_each(|ty| named_types(ty, out)),
+		Shape::Struct(fields) => fields.iter().for_each(|(_, _, ty, _)| named_types(ty, out)),
+		Shape::Enum(variants) => variants
+			.iter()
+			.for_each(|(_, _, shape, _)| nested_types(shape, out)),
+	}
+}
+
+/// Collects the type and the types in its generic arguments, e.g. `Option<Account>` and
+/// `Account`, leaving out primitives.
+fn named_types(ty: &Type, out: &mut Vec<Type>) {
+	match ty {
+		Type::Path(TypePath { qself: None, path }) => {
+			const PRIMITIVES: &[&str] = &[
+				"bool", "char", "str", "String", "u8", "u16", "u32", "u64", "u128", "usize", "i8",
+				"i16", "i32", "i64", "i128", "isize", "f32", "f64",
+			];
+			let name = ty.to_token_stream().to_string();
+			if !PRIMITIVES.iter().any(|x| path.is_ident(x))
+				&& !out.iter().any(|x| x.to_token_stream().to_string() == name)
+			{
+				out.push(ty.clone());
+			}
+			for segment in &path.segments {
+				if let PathArguments::AngleBracketed(args) = &segment.arguments {
+					for arg in &args.args {
+						if let GenericArgument::Type(ty) = arg {
+							named_types(ty, out);
+						}
+					}
+				}
+			}
+		}
+		Type::Reference(x) => named_types(&x.elem, out),
+		Type::Slice(x) => named_types(&x.elem, out),
+		Type::Array(x) => named_types(&x.elem, out),
+		Type::Paren(x) => named_types(&x.elem, out),
+		Type::Group(x) => named_types(&x.elem, out),
+		Type::Tuple(x) => x.elems.iter().for_each(|ty| named_types(ty, out)),
+		_ => {}
+	}
+}
+
+fn emit_json_name(json_name: &Option<String>) -> TokenStream {
+	match json_name {
+		Some(json_name) => {
+			let json_name = Literal::string(json_name);
+			quote! { Some(::std::borrow::Cow::Borrowed(#json_name)) }
+		}
+		None => quote! { None },
+	}
+}
+
+/// Renders the type as written in the source, e.g. `Option<Vec<u8>>`.
+fn type_name(ty: &Type) -> Literal {
+	let is_word = |c: char| c.is_alphanumeric() || c == '_';
+	let mut name = String::new();
+	for token in ty.to_token_stream().to_string().split_whitespace() {
+		if name.ends_with(is_word) && token.starts_with(is_word) {
+			name.push(' ');
+		}
+		name.push_str(token);
+		if token == "," || token == ";" {
+			name.push(' ');
+		}
+	}
+	Literal::string(name.as_str())
+}
@@ -0,0 +1,127 @@
+use syn::{punctuated::Punctuated, Attribute, Error, Lit, Meta, Result, Token};
+
+/// A `#[serde(rename_all = "...")]` rule, applied the same way serde does.
+#[derive(Clone, Copy)]
+pub enum RenameRule {
+	Lower,
+	Upper,
+	Pascal,
+	Camel,
+	Snake,
+	ScreamingSnake,
+	Kebab,
+	ScreamingKebab,
+}
+
+impl RenameRule {
+	fn parse(rule: &str) -> Option<Self> {
+		Some(match rule {
+			"lowercase" => RenameRule::Lower,
+			"UPPERCASE" => RenameRule::Upper,
+			"PascalCase" => RenameRule::Pascal,
+			"camelCase" => RenameRule::Camel,
+			"snake_case" => RenameRule::Snake,
+			"SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+			"kebab-case" => RenameRule::Kebab,
+			"SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+			_ => return None,
+		})
+	}
+
+	/// Renames a field declared in snake_case.
+	pub fn apply_to_field(self, field: &str) -> String {
+		match self {
+			RenameRule::Lower | RenameRule::Snake => field.to_string(),
+			RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+			RenameRule::Pascal => field
+				.split('_')
+				.map(|word| {
+					let mut chars = word.chars();
+					chars
+						.next()
+						.map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+						.unwrap_or_default()
+				})
+				.collect(),
+			RenameRule::Camel => {
+				let pascal = RenameRule::Pascal.apply_to_field(field);
+				let mut chars = pascal.chars();
+				chars
+					.next()
+					.map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+					.unwrap_or_default()
+			}
+			RenameRule::Kebab => field.replace('_', "-"),
+			RenameRule::ScreamingKebab => field.replace('_', "-").to_ascii_uppercase(),
+		}
+	}
+
+	/// Renames a variant declared in PascalCase.
+	pub fn apply_to_variant(self, variant: &str) -> String {
+		match self {
+			RenameRule::Pascal => variant.to_string(),
+			RenameRule::Lower => variant.to_ascii_lowercase(),
+			RenameRule::Upper => variant.to_ascii_uppercase(),
+			RenameRule::Camel => {
+				let mut chars = variant.chars();
+				chars
+					.next()
+					.map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+					.unwrap_or_default()
+			}
+			RenameRule::Snake => {
+				let mut snake = String::new();
+				for (i, c) in variant.char_indices() {
+					if i > 0 && c.is_uppercase() {
+						snake.push('_');
+					}
+					snake.push(c.to_ascii_lowercase());
+				}
+				snake
+			}
+			RenameRule::ScreamingSnake => RenameRule::Snake
+				.apply_to_variant(variant)
+				.to_ascii_uppercase(),
+			RenameRule::Kebab => RenameRule::Snake
+				.apply_to_variant(variant)
+				.replace('_', "-"),
+			RenameRule::ScreamingKebab => RenameRule::ScreamingSnake
+				.apply_to_variant(variant)
+				.replace('_', "-"),
+		}
+	}
+}
+
+/// The value of `#[serde(<key> = "...")]` among the attributes, if any.
+fn serde_str(attrs: &[Attribute], key: &str) -> Result<Option<(String, proc_macro2::Span)>> {
+	for attr in attrs.iter().filter(|x| x.path.is_ident("serde")) {
+		let Ok(metas) = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+		else {
+			continue;
+		};
+		for meta in metas {
+			if let Meta::NameValue(meta) = meta {
+				if meta.path.is_ident(key) {
+					let Lit::Str(value) = &meta.lit else {
+						return Err(Error::new(meta.lit.span(), "Expected a string literal."));
+					};
+					return Ok(Some((value.value(), value.span())));
+				}
+			}
+		}
+	}
+	Ok(None)
+}
+
+pub fn rename_all(attrs: &[Attribute]) -> Result<Option<RenameRule>> {
+	match serde_str(attrs, "rename_all")? {
+		Some((rule, span)) => RenameRule::parse(&rule)
+			.map(Some)
+			.ok_or_else(|| Error::new(span, "Unknown rename_all rule.")),
+		None => Ok(None),
+	}
+}
+
+pub fn rename(attrs: &[Attribute]) -> Result<Option<String>> {
+	Ok(serde_str(attrs, "rename")?.map(|(name, _)| name))
+}
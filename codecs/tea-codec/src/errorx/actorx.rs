@@ -29,6 +29,14 @@ pub struct ActorHostDropped;
 #[error("Actor {0} is deactivating")]
 pub struct ActorDeactivating(pub String);
 
+#[derive(Debug, Clone, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[error("Cannot upgrade actor {0} with a new version whose id is {1}")]
+pub struct ActorIdMismatch(pub String, pub String);
+
+#[derive(Debug, Clone, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[error("Cannot register or upgrade an actor without id")]
+pub struct ActorIdMissing;
+
 #[derive(Debug, Clone, Error, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(
 	any(feature = "host", feature = "wasm"),
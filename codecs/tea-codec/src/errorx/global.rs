@@ -152,6 +152,12 @@ pub enum Global {
 
 	#[error("Channel send error: {0}")]
 	ChannelSend(String),
+
+	#[error(transparent)]
+	ActorIdMismatch(#[from] ActorIdMismatch),
+
+	#[error(transparent)]
+	ActorIdMissing(#[from] ActorIdMissing),
 }
 
 impl From<serde_json::Error> for Global {
@@ -5,7 +5,7 @@ use std::{io::Write, mem::size_of};
 use bincode::Options;
 use prost::bytes::BufMut;
 
-use self::error::TypeIdMismatch;
+use self::{error::TypeIdMismatch, evolvable::Evolvable};
 use crate::Result;
 use crate::{
 	bincode_options, const_concat::ConstStr, errorx::BadBinaryFormat, IntoGlobal, ResultExt,
@@ -13,8 +13,11 @@ use crate::{
 pub use tea_codec_macros::TypeId;
 
 pub mod error;
+pub mod evolvable;
 pub mod handle;
 pub mod layout;
+pub mod registry;
+pub mod render;
 
 #[rustc_specialization_trait]
 trait Serialize = serde::Serialize;
@@ -95,17 +98,17 @@ impl<T> ToBytesUsingSerialize for T
 where
 	T: TypeId + Serialize,
 {
-	fn to_bytes(&self) -> Result<Vec<u8>> {
+	default fn to_bytes(&self) -> Result<Vec<u8>> {
 		let mut result = Vec::new();
 		ToBytesUsingSerialize::write_to(self, &mut result)?;
 		Ok(result)
 	}
 
-	fn bytes_len(&self) -> Result<usize> {
+	default fn bytes_len(&self) -> Result<usize> {
 		Ok(size_of::<u32>() + T::TYPE_ID.len() + bincode_options().serialized_size(self)? as usize)
 	}
 
-	fn write_to(&self, mut w: impl SerBuf) -> Result<()> {
+	default fn write_to(&self, mut w: impl SerBuf) -> Result<()> {
 		w.reserve(
 			size_of::<u32>() + T::TYPE_ID.len() + bincode_options().serialized_size(self)? as usize,
 		);
@@ -123,30 +126,71 @@ where
 impl<'a, T> FromBytesUsingSerialize<'a> for T
 where
 	T: TypeId + Deserialize<'a>,
+{
+	default fn from_bytes(buf: &'a [u8]) -> Result<Self> {
+		bincode_options()
+			.deserialize(split_payload::<T>(buf)?)
+			.into_g()
+	}
+}
+
+impl<T> ToBytesUsingSerialize for T
+where
+	T: TypeId + Serialize + Evolvable,
+{
+	fn to_bytes(&self) -> Result<Vec<u8>> {
+		let mut result = Vec::new();
+		ToBytesUsingSerialize::write_to(self, &mut result)?;
+		Ok(result)
+	}
+
+	fn bytes_len(&self) -> Result<usize> {
+		Ok(size_of::<u32>() + T::TYPE_ID.len() + self.to_body()?.len())
+	}
+
+	fn write_to(&self, mut w: impl SerBuf) -> Result<()> {
+		let body = self.to_body()?;
+		w.reserve(size_of::<u32>() + T::TYPE_ID.len() + body.len());
+		w.write_all(&(T::TYPE_ID.len() as u32).to_le_bytes())?;
+		w.write_all(T::TYPE_ID.as_bytes())?;
+		w.write_all(&body)?;
+		Ok(())
+	}
+}
+
+impl<'a, T> FromBytesUsingSerialize<'a> for T
+where
+	T: TypeId + Deserialize<'a> + Evolvable,
 {
 	fn from_bytes(buf: &'a [u8]) -> Result<Self> {
-		if buf.len() < size_of::<u32>() {
-			return Err(BadBinaryFormat.into());
-		}
-		let (type_id_len, buf) = buf.split_at(size_of::<u32>());
-		let type_id_len =
-			u32::from_le_bytes(unsafe { type_id_len.try_into().unwrap_unchecked() }) as _;
-		if buf.len() < type_id_len {
-			return Err(BadBinaryFormat.into());
-		}
-		let (type_id, payload) = buf.split_at(type_id_len);
-		if type_id != T::TYPE_ID.as_bytes() {
-			return Err(TypeIdMismatch(
-				T::TYPE_ID.to_string(),
-				String::from_utf8_lossy(type_id).into_owned(),
-			)
-			.into());
-		}
-
-		bincode_options().deserialize(payload).into_g()
+		T::from_body(split_payload::<T>(buf)?)
 	}
 }
 
+/// Checks the type id header of `buf` against `T` and returns the body after it.
+fn split_payload<T>(buf: &[u8]) -> Result<&[u8]>
+where
+	T: TypeId,
+{
+	if buf.len() < size_of::<u32>() {
+		return Err(BadBinaryFormat.into());
+	}
+	let (type_id_len, buf) = buf.split_at(size_of::<u32>());
+	let type_id_len = u32::from_le_bytes(unsafe { type_id_len.try_into().unwrap_unchecked() }) as _;
+	if buf.len() < type_id_len {
+		return Err(BadBinaryFormat.into());
+	}
+	let (type_id, payload) = buf.split_at(type_id_len);
+	if type_id != T::TYPE_ID.as_bytes() {
+		return Err(TypeIdMismatch(
+			T::TYPE_ID.to_string(),
+			String::from_utf8_lossy(type_id).into_owned(),
+		)
+		.into());
+	}
+	Ok(payload)
+}
+
 pub fn get_type_id(buf: &[u8]) -> Result<&str> {
 	if buf.len() < size_of::<u32>() {
 		return Err(BadBinaryFormat.into());
@@ -338,17 +382,17 @@ where
 	T: IsToBytes,
 {
 	#[inline(always)]
-	fn to_bytes(&self) -> Result<Vec<u8>> {
+	default fn to_bytes(&self) -> Result<Vec<u8>> {
 		ToBytesUsingSerializeOrProto::to_bytes(self)
 	}
 
 	#[inline(always)]
-	fn bytes_len(&self) -> Result<usize> {
+	default fn bytes_len(&self) -> Result<usize> {
 		ToBytesUsingSerializeOrProto::bytes_len(self)
 	}
 
 	#[inline(always)]
-	fn write_to(&self, w: impl SerBuf) -> Result<()> {
+	default fn write_to(&self, w: impl SerBuf) -> Result<()> {
 		ToBytesUsingSerializeOrProto::write_to(self, w)
 	}
 }
@@ -0,0 +1,166 @@
+//! The tagged encoding of the types marked with `#[derive(TypeId)]` and `#[evolvable]`.
+//!
+//! The body of an evolvable type is a list of `(tag, bincode bytes)` pairs where the tag is the
+//! field name. Decoding looks fields up by tag, so fields can be added, removed or reordered
+//! between versions: missing fields fall back to `Default::default()` and unknown ones are skipped.
+//! Evolvable types also leave the crate version out of their type id, so actors built against
+//! different versions of the defining crate can still talk to each other.
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_bytes::Bytes;
+
+use crate::{bincode_options, Result};
+
+/// Implemented by `#[derive(TypeId)]` for types marked with `#[evolvable]`.
+#[rustc_specialization_trait]
+pub trait Evolvable: Sized {
+	fn write_fields(&self, fields: &mut FieldWriter) -> Result<()>;
+	fn read_fields(fields: &FieldReader) -> Result<Self>;
+
+	fn to_body(&self) -> Result<Vec<u8>> {
+		let mut fields = FieldWriter::default();
+		self.write_fields(&mut fields)?;
+		fields.finish()
+	}
+
+	fn from_body(buf: &[u8]) -> Result<Self> {
+		Self::read_fields(&FieldReader::parse(buf)?)
+	}
+}
+
+#[derive(Default)]
+pub struct FieldWriter {
+	fields: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl FieldWriter {
+	pub fn write<T>(&mut self, tag: &'static str, value: &T) -> Result<()>
+	where
+		T: Serialize,
+	{
+		self.fields.push((tag, bincode_options().serialize(value)?));
+		Ok(())
+	}
+
+	pub fn finish(self) -> Result<Vec<u8>> {
+		let fields = self
+			.fields
+			.iter()
+			.map(|(tag, value)| (*tag, Bytes::new(value)))
+			.collect::<Vec<_>>();
+		Ok(bincode_options().serialize(&fields)?)
+	}
+}
+
+pub struct FieldReader<'a> {
+	fields: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> FieldReader<'a> {
+	pub fn parse(buf: &'a [u8]) -> Result<Self> {
+		let fields: Vec<(&'a str, &'a Bytes)> = bincode_options().deserialize(buf)?;
+		Ok(Self {
+			fields: fields
+				.into_iter()
+				.map(|(tag, value)| (tag, &**value))
+				.collect(),
+		})
+	}
+
+	/// Reads the field of the tag, or its default value if the encoder did not know about it.
+	pub fn read<T>(&self, tag: &str) -> Result<T>
+	where
+		T: DeserializeOwned + Default,
+	{
+		match self.fields.iter().find(|(x, _)| *x == tag) {
+			Some((_, value)) => Ok(bincode_options().deserialize(value)?),
+			None => Ok(T::default()),
+		}
+	}
+
+	/// Tags in the order in which they were encoded.
+	pub fn tags(&self) -> impl Iterator<Item = &'a str> + '_ {
+		self.fields.iter().map(|(tag, _)| *tag)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use super::Evolvable;
+	use crate::serde::{FromBytes, ToBytes, TypeId};
+
+	mod v1 {
+		use super::*;
+
+		#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+		#[evolvable]
+		pub struct ChannelMessage {
+			pub channel_id: String,
+			pub tbu_1: Option<String>,
+			pub fund: u64,
+		}
+	}
+
+	mod v2 {
+		use super::*;
+
+		#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+		#[evolvable]
+		pub struct ChannelMessage {
+			pub fund: u64,
+			pub channel_id: String,
+			pub grace_period: Option<u64>,
+		}
+	}
+
+	#[test]
+	fn newer_reads_older() {
+		let old = v1::ChannelMessage {
+			channel_id: "channel".into(),
+			tbu_1: Some("unused".into()),
+			fund: 10,
+		};
+		let new = v2::ChannelMessage::from_body(&old.to_body().unwrap()).unwrap();
+		assert_eq!(
+			new,
+			v2::ChannelMessage {
+				fund: 10,
+				channel_id: "channel".into(),
+				grace_period: None,
+			}
+		);
+	}
+
+	#[test]
+	fn older_reads_newer() {
+		let new = v2::ChannelMessage {
+			fund: 3,
+			channel_id: "channel".into(),
+			grace_period: Some(100),
+		};
+		let old = v1::ChannelMessage::from_body(&new.to_body().unwrap()).unwrap();
+		assert_eq!(
+			old,
+			v1::ChannelMessage {
+				channel_id: "channel".into(),
+				tbu_1: None,
+				fund: 3,
+			}
+		);
+	}
+
+	#[test]
+	fn to_bytes_roundtrip() {
+		let value = v1::ChannelMessage {
+			channel_id: "channel".into(),
+			tbu_1: None,
+			fund: 1,
+		};
+		let bytes = value.to_bytes().unwrap();
+		assert_eq!(v1::ChannelMessage::from_bytes(&bytes).unwrap(), value);
+		assert!(!v1::ChannelMessage::TYPE_ID.contains('@'));
+	}
+}
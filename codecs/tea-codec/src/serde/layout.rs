@@ -4,7 +4,8 @@ use impl_trait_for_tuples::impl_for_tuples;
 
 use crate::{IntoGlobal, Result, ResultExt};
 
-use super::{error::InvalidFormat, FromBytes, SerBuf, ToBytes};
+use super::{error::InvalidFormat, FromBytes, SerBuf, ToBytes, TypeId};
+pub use tea_codec_macros::Layout;
 
 pub trait LayoutWrite: Sized {
 	type Write<'a>: Copy
@@ -19,6 +20,40 @@ pub trait LayoutRead: Sized {
 	fn read<'a>(buf: &mut &'a [u8]) -> Result<Self::Read<'a>>;
 }
 
+/// Borrows the value to write, as `#[derive(Layout)]` requires of every field.
+pub trait AsLayoutWrite: LayoutWrite {
+	fn as_write(&self) -> Self::Write<'_>;
+}
+
+/// Owns the value read, as `#[derive(Layout)]` requires of every field.
+pub trait IntoLayoutOwned: LayoutRead {
+	fn into_owned(value: Self::Read<'_>) -> Self;
+}
+
+/// Encodes the type with its layout in `ToBytes` and `FromBytes`, after the usual type id header.
+///
+/// Implemented by `#[derive(Layout)]` with `#[layout(bytes)]`, which also impls `ToBytes` and
+/// `FromBytes` of the type with the methods of this trait.
+#[rustc_specialization_trait]
+pub trait LayoutBytes: TypeId + AsLayoutWrite + IntoLayoutOwned {
+	fn layout_bytes_len(&self) -> Result<usize> {
+		Ok(size_of::<u32>() + Self::TYPE_ID.len() + Self::size(self.as_write())?)
+	}
+
+	fn write_layout_to(&self, mut w: impl SerBuf) -> Result<()> {
+		let value = self.as_write();
+		w.reserve(size_of::<u32>() + Self::TYPE_ID.len() + Self::size(value)?);
+		u32::write(Self::TYPE_ID.len() as _, &mut w)?;
+		w.write_all(Self::TYPE_ID.as_bytes())?;
+		Self::write(value, w)
+	}
+
+	fn from_layout_bytes(buf: &[u8]) -> Result<Self> {
+		let mut payload = super::split_payload::<Self>(buf)?;
+		Ok(Self::into_owned(Self::read(&mut payload)?))
+	}
+}
+
 macro_rules! impl_nums {
 	($($t:ty),*) => {$(
 		impl LayoutWrite for $t {
@@ -35,6 +70,13 @@ macro_rules! impl_nums {
 			}
 		}
 
+		impl AsLayoutWrite for $t {
+			#[inline(always)]
+			fn as_write(&self) -> Self::Write<'_> {
+				*self
+			}
+		}
+
 		impl LayoutRead for $t {
             type Read<'a> = Self;
 
@@ -49,6 +91,13 @@ macro_rules! impl_nums {
 				Ok(result)
 			}
 		}
+
+		impl IntoLayoutOwned for $t {
+			#[inline(always)]
+			fn into_owned(value: Self::Read<'_>) -> Self {
+				value
+			}
+		}
 	)*};
 }
 
@@ -69,6 +118,13 @@ impl LayoutWrite for String {
 	}
 }
 
+impl AsLayoutWrite for String {
+	#[inline(always)]
+	fn as_write(&self) -> Self::Write<'_> {
+		self
+	}
+}
+
 impl LayoutRead for String {
 	type Read<'a> = &'a str;
 
@@ -85,6 +141,13 @@ impl LayoutRead for String {
 	}
 }
 
+impl IntoLayoutOwned for String {
+	#[inline(always)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		value.to_owned()
+	}
+}
+
 impl LayoutWrite for Vec<u8> {
 	type Write<'a> = &'a [u8];
 
@@ -100,6 +163,13 @@ impl LayoutWrite for Vec<u8> {
 	}
 }
 
+impl AsLayoutWrite for Vec<u8> {
+	#[inline(always)]
+	fn as_write(&self) -> Self::Write<'_> {
+		self
+	}
+}
+
 impl LayoutRead for Vec<u8> {
 	type Read<'a> = &'a [u8];
 
@@ -115,6 +185,13 @@ impl LayoutRead for Vec<u8> {
 	}
 }
 
+impl IntoLayoutOwned for Vec<u8> {
+	#[inline(always)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		value.to_vec()
+	}
+}
+
 impl<T> LayoutWrite for Option<T>
 where
 	T: LayoutWrite,
@@ -144,6 +221,16 @@ where
 	}
 }
 
+impl<T> AsLayoutWrite for Option<T>
+where
+	T: AsLayoutWrite,
+{
+	#[inline(always)]
+	fn as_write(&self) -> Self::Write<'_> {
+		self.as_ref().map(|value| value.as_write())
+	}
+}
+
 impl<T> LayoutRead for Option<T>
 where
 	T: LayoutRead,
@@ -160,6 +247,16 @@ where
 	}
 }
 
+impl<T> IntoLayoutOwned for Option<T>
+where
+	T: IntoLayoutOwned,
+{
+	#[inline(always)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		value.map(T::into_owned)
+	}
+}
+
 pub struct UseFromToBytes<T>(T);
 
 impl<T> LayoutWrite for UseFromToBytes<T>
@@ -182,6 +279,16 @@ where
 	}
 }
 
+impl<T> AsLayoutWrite for UseFromToBytes<T>
+where
+	T: ToBytes,
+{
+	#[inline(always)]
+	fn as_write(&self) -> Self::Write<'_> {
+		&self.0
+	}
+}
+
 impl<T> LayoutRead for UseFromToBytes<T>
 where
 	T: for<'a> FromBytes<'a>,
@@ -201,6 +308,16 @@ where
 	}
 }
 
+impl<T> IntoLayoutOwned for UseFromToBytes<T>
+where
+	T: for<'a> FromBytes<'a>,
+{
+	#[inline(always)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		Self(value)
+	}
+}
+
 pub struct WithSize<T>(T);
 
 impl<T> LayoutWrite for WithSize<T>
@@ -225,6 +342,16 @@ where
 	}
 }
 
+impl<T> AsLayoutWrite for WithSize<T>
+where
+	T: AsLayoutWrite,
+{
+	#[inline(always)]
+	fn as_write(&self) -> Self::Write<'_> {
+		self.0.as_write()
+	}
+}
+
 impl<T> LayoutRead for WithSize<T>
 where
 	T: LayoutRead,
@@ -241,6 +368,16 @@ where
 	}
 }
 
+impl<T> IntoLayoutOwned for WithSize<T>
+where
+	T: IntoLayoutOwned,
+{
+	#[inline(always)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		Self(T::into_owned(value))
+	}
+}
+
 #[impl_for_tuples(64)]
 impl LayoutWrite for Item {
 	type Write<'a> = for_tuples!((#(Item::Write::<'a>),*))
@@ -257,12 +394,21 @@ impl LayoutWrite for Item {
 	#[inline(always)]
 	fn write(value: Self::Write<'_>, mut buf: impl SerBuf) -> Result<()> {
 		for_tuples!(#(
-            Item::write(value.Item, &mut buf);
+            Item::write(value.Item, &mut buf)?;
         )*);
 		Ok(())
 	}
 }
 
+#[impl_for_tuples(64)]
+impl AsLayoutWrite for Item {
+	#[inline(always)]
+	#[allow(clippy::unused_unit)]
+	fn as_write(&self) -> Self::Write<'_> {
+		for_tuples!((#(self.Item.as_write()),*))
+	}
+}
+
 #[impl_for_tuples(64)]
 impl LayoutRead for Item {
 	type Read<'a> = for_tuples!((#(Item::Read::<'a>),*));
@@ -272,3 +418,93 @@ impl LayoutRead for Item {
 		Ok((for_tuples!(#(Item::read(buf)?),*)))
 	}
 }
+
+#[impl_for_tuples(64)]
+impl IntoLayoutOwned for Item {
+	#[inline(always)]
+	#[allow(clippy::unused_unit)]
+	fn into_owned(value: Self::Read<'_>) -> Self {
+		for_tuples!((#(Item::into_owned(value.Item)),*))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::serde::{get_type_id, FromBytes};
+
+	#[derive(Debug, PartialEq, Eq, TypeId, Layout)]
+	#[layout(bytes)]
+	struct PutMessage {
+		key: String,
+		value: Vec<u8>,
+		ttl: Option<u32>,
+	}
+
+	#[derive(Debug, PartialEq, Eq, Layout)]
+	enum Shape {
+		Empty,
+		Point(i32, i32),
+		Named { name: String },
+	}
+
+	#[derive(Debug, PartialEq, Eq, Layout)]
+	enum Flag {
+		On,
+		Off,
+	}
+
+	#[test]
+	fn struct_reads_borrowed() {
+		let value = PutMessage {
+			key: "key".into(),
+			value: vec![1, 2, 3],
+			ttl: Some(5),
+		};
+		let mut buf = Vec::new();
+		PutMessage::write(&value, &mut buf).unwrap();
+		assert_eq!(buf.len(), PutMessage::size(&value).unwrap());
+
+		let mut read = buf.as_slice();
+		let view = PutMessage::read(&mut read).unwrap();
+		assert!(read.is_empty());
+		assert_eq!(view.key, "key");
+		assert_eq!(view.value, &[1, 2, 3]);
+		assert_eq!(view.ttl, Some(5));
+		assert_eq!(PutMessage::into_owned(view), value);
+	}
+
+	#[test]
+	fn enum_roundtrip() {
+		for value in [
+			Shape::Empty,
+			Shape::Point(1, -1),
+			Shape::Named {
+				name: "name".into(),
+			},
+		] {
+			let mut buf = Vec::new();
+			Shape::write(&value, &mut buf).unwrap();
+			let view = Shape::read(&mut buf.as_slice()).unwrap();
+			assert_eq!(Shape::into_owned(view), value);
+		}
+
+		let mut buf = Vec::new();
+		Flag::write(&Flag::Off, &mut buf).unwrap();
+		assert_eq!(Flag::read(&mut buf.as_slice()).unwrap(), Flag::Off);
+		assert!(Flag::read(&mut 2u32.to_le_bytes().as_slice()).is_err());
+	}
+
+	#[test]
+	fn to_bytes_uses_layout() {
+		let value = PutMessage {
+			key: "key".into(),
+			value: vec![1],
+			ttl: None,
+		};
+		let bytes = value.to_bytes().unwrap();
+		assert_eq!(bytes.len(), value.bytes_len().unwrap());
+		assert_eq!(get_type_id(&bytes).unwrap(), PutMessage::TYPE_ID);
+		assert_eq!(PutMessage::from_bytes(&bytes).unwrap(), value);
+	}
+}
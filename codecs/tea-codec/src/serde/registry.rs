@@ -0,0 +1,677 @@
+//! Runtime descriptors of the types marked with `#[derive(TypeId)]`.
+//!
+//! The wire format only carries the type id string and a bincode body, so tools that live outside
+//! of rust (clients in other languages, packet inspectors) rely on the descriptors collected here
+//! to know the layout of each request and which response it expects.
+
+use std::{
+	borrow::Cow,
+	collections::BTreeMap,
+	sync::{RwLock, RwLockReadGuard},
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{evolvable::Evolvable, layout::LayoutBytes, TypeId};
+use crate::{bincode_options, Result};
+
+type Decoder = fn(&[u8]) -> Result<Value>;
+
+/// Implemented by `#[derive(TypeId)]` to describe the type at runtime.
+#[rustc_specialization_trait]
+pub trait Describe: TypeId {
+	fn descriptor() -> TypeDescriptor;
+
+	/// Adds the descriptors of the described types named in the fields, and of theirs in turn.
+	fn describe_nested(_registry: &mut TypeRegistry) {}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeDescriptor {
+	pub type_id: Cow<'static, str>,
+	pub name: Cow<'static, str>,
+	pub response_type_id: Option<Cow<'static, str>>,
+	pub schema: Schema,
+	/// Whether the body uses the tagged encoding of [`super::evolvable`] rather than plain bincode.
+	#[serde(default)]
+	pub evolvable: bool,
+	/// Whether the body uses the length prefixed encoding of [`super::layout`] rather than bincode.
+	#[serde(default)]
+	pub layout: bool,
+}
+
+/// The structure of a type in declaration order, which is also the order of its bincode encoding.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Schema {
+	Unit,
+	/// Fields named by their position.
+	Tuple(Vec<Field>),
+	Struct(Vec<Field>),
+	/// Variants are encoded by their index in this list, which keeps the variants skipped by serde.
+	Enum(Vec<Variant>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+	pub name: Cow<'static, str>,
+	/// The name used by serde if it renames the field, e.g. with `#[serde(rename_all = "camelCase")]`.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub json_name: Option<Cow<'static, str>>,
+	pub ty: Cow<'static, str>,
+	/// Whether the field may be missing in self-describing formats, as with
+	/// `#[serde(default)]` on it or its container, or `#[serde(skip_serializing_if = "...")]`.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub optional: bool,
+	/// The described types named in `ty`, whose descriptors are registered along with this one.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub refs: Vec<Reference>,
+}
+
+impl Field {
+	/// The name of the field in self-describing formats such as json.
+	pub fn json_name(&self) -> &str {
+		self.json_name.as_deref().unwrap_or(&self.name)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variant {
+	pub name: Cow<'static, str>,
+	/// The name used by serde if it renames the variant.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub json_name: Option<Cow<'static, str>>,
+	pub schema: Schema,
+	/// Whether serde skips the variant. It cannot be encoded then, but still takes up its index,
+	/// while serde decodes the variants after it by their index among the others.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub skipped: bool,
+}
+
+impl Variant {
+	/// The name of the variant in self-describing formats such as json.
+	pub fn json_name(&self) -> &str {
+		self.json_name.as_deref().unwrap_or(&self.name)
+	}
+}
+
+/// A described type named in a field, e.g. `Account` in `Option<Account>`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+	/// The type as written in the field.
+	pub name: Cow<'static, str>,
+	pub type_id: Cow<'static, str>,
+}
+
+/// A set of type descriptors keyed by type id.
+#[derive(Clone, Debug, Default)]
+pub struct TypeRegistry {
+	types: BTreeMap<Cow<'static, str>, TypeDescriptor>,
+	decoders: BTreeMap<Cow<'static, str>, Decoder>,
+}
+
+impl TypeRegistry {
+	pub const fn new() -> Self {
+		Self {
+			types: BTreeMap::new(),
+			decoders: BTreeMap::new(),
+		}
+	}
+
+	/// Describes the non-generic types deriving `TypeId` in the binary whose type ids start with
+	/// `prefix`, e.g. the name of their crate. Those serde can decode come with a decoder.
+	///
+	/// The types are collected before `main`, which wasm targets don't support, so the registry
+	/// is empty there.
+	pub fn derived(prefix: &str) -> Self {
+		let mut registry = Self::new();
+		for derived in inventory::iter::<Derived> {
+			if derived.type_id.starts_with(prefix) {
+				(derived.register)(&mut registry);
+			}
+		}
+		registry
+	}
+
+	pub fn register<T>(&mut self) -> &mut Self
+	where
+		T: Describe + Serialize + DeserializeOwned,
+	{
+		self.decoders
+			.insert(Cow::Borrowed(T::TYPE_ID), decode_json::<T>);
+		self.describe::<T>()
+	}
+
+	/// Adds the descriptor of a type and of the described types nested in it, without a decoder.
+	pub fn describe<T>(&mut self) -> &mut Self
+	where
+		T: Describe + ?Sized,
+	{
+		if !self.types.contains_key(T::TYPE_ID) {
+			self.insert(T::descriptor());
+			T::describe_nested(self);
+		}
+		self
+	}
+
+	/// Adds a descriptor without a decoder, e.g. one loaded from an exported catalog.
+	pub fn insert(&mut self, descriptor: TypeDescriptor) {
+		self.types.insert(descriptor.type_id.clone(), descriptor);
+	}
+
+	/// Decodes the bincode body of a registered type into json.
+	///
+	/// Returns `None` if the type is not registered with a decoder.
+	pub fn decode(&self, type_id: &str, payload: &[u8]) -> Option<Result<Value>> {
+		self.decoders.get(type_id).map(|decode| decode(payload))
+	}
+
+	pub fn get(&self, type_id: &str) -> Option<&TypeDescriptor> {
+		self.types.get(type_id)
+	}
+
+	pub fn len(&self) -> usize {
+		self.types.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.types.is_empty()
+	}
+
+	/// Descriptors ordered by type id.
+	pub fn iter(&self) -> impl Iterator<Item = &TypeDescriptor> {
+		self.types.values()
+	}
+
+	pub fn extend(&mut self, other: &TypeRegistry) {
+		for descriptor in other.iter() {
+			self.insert(descriptor.clone());
+		}
+		self.decoders.extend(
+			other
+				.decoders
+				.iter()
+				.map(|(type_id, decode)| (type_id.clone(), *decode)),
+		);
+	}
+
+	/// Exports every registered descriptor as a JSON array ordered by type id.
+	pub fn to_json(&self) -> Result<String> {
+		Ok(serde_json::to_string_pretty(
+			&self.iter().collect::<Vec<_>>(),
+		)?)
+	}
+}
+
+static GLOBAL: RwLock<TypeRegistry> = RwLock::new(TypeRegistry::new());
+
+fn decode_json<T>(payload: &[u8]) -> Result<Value>
+where
+	T: Serialize + DeserializeOwned,
+{
+	Ok(serde_json::to_value(T::decode_body(payload)?)?)
+}
+
+trait DecodeBody: Sized {
+	fn decode_body(payload: &[u8]) -> Result<Self>;
+}
+
+impl<T> DecodeBody for T
+where
+	T: DeserializeOwned,
+{
+	default fn decode_body(payload: &[u8]) -> Result<Self> {
+		T::decode_serde_body(payload)
+	}
+}
+
+impl<T> DecodeBody for T
+where
+	T: DeserializeOwned + LayoutBytes,
+{
+	fn decode_body(mut payload: &[u8]) -> Result<Self> {
+		Ok(T::into_owned(T::read(&mut payload)?))
+	}
+}
+
+trait DecodeSerdeBody: Sized {
+	fn decode_serde_body(payload: &[u8]) -> Result<Self>;
+}
+
+impl<T> DecodeSerdeBody for T
+where
+	T: DeserializeOwned,
+{
+	default fn decode_serde_body(payload: &[u8]) -> Result<Self> {
+		Ok(bincode_options().deserialize(payload)?)
+	}
+}
+
+impl<T> DecodeSerdeBody for T
+where
+	T: DeserializeOwned + Evolvable,
+{
+	fn decode_serde_body(payload: &[u8]) -> Result<Self> {
+		T::from_body(payload)
+	}
+}
+
+/// Registers a type to the process wide registry.
+pub fn register<T>()
+where
+	T: Describe + Serialize + DeserializeOwned,
+{
+	GLOBAL
+		.write()
+		.unwrap_or_else(|e| e.into_inner())
+		.register::<T>();
+}
+
+/// Merges a registry into the process wide registry.
+pub fn register_all(registry: &TypeRegistry) {
+	GLOBAL
+		.write()
+		.unwrap_or_else(|e| e.into_inner())
+		.extend(registry);
+}
+
+/// The process wide registry.
+pub fn global() -> RwLockReadGuard<'static, TypeRegistry> {
+	GLOBAL.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers the listed types into a registry, e.g. `register_types!(registry, ARequest, AResponse)`.
+#[macro_export]
+macro_rules! register_types {
+	($registry:expr, $($t:ty),* $(,)?) => {{
+		let registry: &mut $crate::serde::registry::TypeRegistry = $registry;
+		$(registry.register::<$t>();)*
+	}};
+}
+
+#[doc(hidden)]
+pub use inventory;
+
+/// Submitted by `#[derive(TypeId)]` for [`TypeRegistry::derived`].
+#[doc(hidden)]
+pub struct Derived {
+	pub type_id: &'static str,
+	pub register: fn(&mut TypeRegistry),
+}
+
+inventory::collect!(Derived);
+
+#[doc(hidden)]
+pub trait Register {
+	fn register_into(registry: &mut TypeRegistry);
+}
+
+impl<T> Register for T
+where
+	T: Describe,
+{
+	default fn register_into(registry: &mut TypeRegistry) {
+		registry.describe::<T>();
+	}
+}
+
+impl<T> Register for T
+where
+	T: Describe + super::Serialize + for<'a> super::Deserialize<'a>,
+{
+	fn register_into(registry: &mut TypeRegistry) {
+		registry.register::<T>();
+	}
+}
+
+#[doc(hidden)]
+pub trait MaybeDescribe {
+	fn reference(name: &'static str) -> Option<Reference>;
+	fn describe_into(registry: &mut TypeRegistry);
+}
+
+impl<T: ?Sized> MaybeDescribe for T {
+	default fn reference(_name: &'static str) -> Option<Reference> {
+		None
+	}
+
+	default fn describe_into(_registry: &mut TypeRegistry) {}
+}
+
+impl<T: ?Sized> MaybeDescribe for T
+where
+	T: Describe,
+{
+	fn reference(name: &'static str) -> Option<Reference> {
+		Some(Reference {
+			name: Cow::Borrowed(name),
+			type_id: Cow::Borrowed(T::TYPE_ID),
+		})
+	}
+
+	fn describe_into(registry: &mut TypeRegistry) {
+		registry.describe::<T>();
+	}
+}
+
+#[doc(hidden)]
+pub trait MaybeTypeId {
+	fn maybe_type_id() -> Option<&'static str>;
+}
+
+impl<T> MaybeTypeId for T {
+	default fn maybe_type_id() -> Option<&'static str> {
+		None
+	}
+}
+
+impl<T> MaybeTypeId for T
+where
+	T: TypeId,
+{
+	fn maybe_type_id() -> Option<&'static str> {
+		Some(T::TYPE_ID)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use std::mem::size_of;
+
+	use super::*;
+	use crate::serde::{layout::Layout, FromBytes, ToBytes, TypeId};
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct GreetRequest {
+		name: String,
+		times: Option<Vec<u8>>,
+		#[serde(skip)]
+		_cache: (),
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct GreetResponse(String, u32);
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[response(())]
+	enum Command {
+		Stop,
+		Move { x: i32, y: i32 },
+	}
+
+	#[test]
+	fn descriptor_of_struct() {
+		let descriptor = GreetRequest::descriptor();
+		assert_eq!(descriptor.type_id, GreetRequest::TYPE_ID);
+		assert_eq!(descriptor.name, "GreetRequest");
+		assert_eq!(
+			descriptor.response_type_id.as_deref(),
+			Some(GreetResponse::TYPE_ID)
+		);
+		assert_eq!(
+			descriptor.schema,
+			Schema::Struct(vec![
+				Field {
+					name: "name".into(),
+					json_name: None,
+					ty: "String".into(),
+					optional: false,
+					refs: vec![],
+				},
+				Field {
+					name: "times".into(),
+					json_name: None,
+					ty: "Option<Vec<u8>>".into(),
+					optional: false,
+					refs: vec![],
+				},
+			])
+		);
+
+		let descriptor = GreetResponse::descriptor();
+		assert_eq!(descriptor.response_type_id, None);
+		let Schema::Tuple(fields) = descriptor.schema else {
+			panic!("expected a tuple");
+		};
+		let fields = fields
+			.iter()
+			.map(|x| (&*x.name, &*x.ty))
+			.collect::<Vec<_>>();
+		assert_eq!(fields, vec![("0", "String"), ("1", "u32")]);
+	}
+
+	#[test]
+	fn descriptor_of_enum() {
+		let descriptor = Command::descriptor();
+		assert_eq!(descriptor.response_type_id.as_deref(), Some("()"));
+		assert_eq!(
+			descriptor.schema,
+			Schema::Enum(vec![
+				Variant {
+					name: "Stop".into(),
+					json_name: None,
+					schema: Schema::Unit,
+					skipped: false,
+				},
+				Variant {
+					name: "Move".into(),
+					json_name: None,
+					schema: Schema::Struct(vec![
+						Field {
+							name: "x".into(),
+							json_name: None,
+							ty: "i32".into(),
+							optional: false,
+							refs: vec![],
+						},
+						Field {
+							name: "y".into(),
+							json_name: None,
+							ty: "i32".into(),
+							optional: false,
+							refs: vec![],
+						},
+					]),
+					skipped: false,
+				},
+			])
+		);
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[serde(rename_all = "camelCase")]
+	#[response(())]
+	struct RenamedRequest {
+		tapp_id: u64,
+		#[serde(rename = "addr")]
+		address: String,
+		r#type: u8,
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[serde(rename_all = "snake_case")]
+	#[response(())]
+	enum RenamedCommand {
+		StopAll,
+		#[serde(rename_all = "camelCase")]
+		MoveTo {
+			to_x: i32,
+		},
+	}
+
+	#[test]
+	fn descriptor_follows_serde_renames() {
+		let Schema::Struct(fields) = RenamedRequest::descriptor().schema else {
+			panic!("expected a struct");
+		};
+		let names = fields.iter().map(|x| x.json_name()).collect::<Vec<_>>();
+		assert_eq!(names, vec!["tappId", "addr", "type"]);
+		assert_eq!(fields[0].name, "tapp_id");
+		assert_eq!(fields[1].name, "address");
+
+		let Schema::Enum(variants) = RenamedCommand::descriptor().schema else {
+			panic!("expected an enum");
+		};
+		assert_eq!(variants[0].json_name(), "stop_all");
+		assert_eq!(
+			variants[1].schema,
+			Schema::Struct(vec![Field {
+				name: "to_x".into(),
+				json_name: Some("toX".into()),
+				ty: "i32".into(),
+				optional: false,
+				refs: vec![],
+			}])
+		);
+	}
+
+	#[derive(Default, Serialize, Deserialize, TypeId)]
+	#[response(())]
+	struct FilterRequest {
+		key: String,
+		#[serde(default)]
+		types: Vec<String>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		limit: Option<u32>,
+	}
+
+	#[derive(Default, Serialize, Deserialize, TypeId)]
+	#[serde(default)]
+	#[response(())]
+	struct LenientRequest {
+		key: String,
+	}
+
+	#[test]
+	fn descriptor_marks_optional_fields() {
+		let Schema::Struct(fields) = FilterRequest::descriptor().schema else {
+			panic!("expected a struct");
+		};
+		let optional = fields.iter().map(|x| x.optional).collect::<Vec<_>>();
+		assert_eq!(optional, vec![false, true, true]);
+
+		let Schema::Struct(fields) = LenientRequest::descriptor().schema else {
+			panic!("expected a struct");
+		};
+		assert!(fields[0].optional);
+	}
+
+	#[test]
+	fn registry_exports_json() {
+		let mut registry = TypeRegistry::new();
+		crate::register_types!(&mut registry, GreetRequest, GreetResponse, Command);
+		assert_eq!(registry.len(), 3);
+		assert!(registry.get(Command::TYPE_ID).is_some());
+
+		let json = registry.to_json().unwrap();
+		let parsed: Vec<TypeDescriptor> = serde_json::from_str(&json).unwrap();
+		assert_eq!(parsed, registry.iter().cloned().collect::<Vec<_>>());
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct Point {
+		x: i32,
+		y: i32,
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[response(())]
+	struct RouteRequest {
+		stops: Vec<Point>,
+		via: Option<Box<RouteRequest>>,
+	}
+
+	#[test]
+	fn descriptor_refers_to_nested_types() {
+		let Schema::Struct(fields) = RouteRequest::descriptor().schema else {
+			panic!("expected a struct");
+		};
+		assert_eq!(
+			fields[0].refs,
+			vec![Reference {
+				name: "Point".into(),
+				type_id: Point::TYPE_ID.into(),
+			}]
+		);
+		assert_eq!(fields[1].refs[0].type_id, RouteRequest::TYPE_ID);
+
+		let mut registry = TypeRegistry::new();
+		registry.register::<RouteRequest>();
+		assert_eq!(registry.len(), 2);
+		assert!(registry.get(Point::TYPE_ID).is_some());
+		assert!(registry.decode(Point::TYPE_ID, &[]).is_none());
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[response(())]
+	enum Shape {
+		Dot,
+		#[serde(skip)]
+		#[allow(dead_code)]
+		Cached(Vec<u8>),
+		Line(Point, Point),
+	}
+
+	#[test]
+	fn descriptor_keeps_skipped_variants() {
+		let Schema::Enum(variants) = Shape::descriptor().schema else {
+			panic!("expected an enum");
+		};
+		let skipped = variants
+			.iter()
+			.map(|x| (&*x.name, x.skipped))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			skipped,
+			vec![("Dot", false), ("Cached", true), ("Line", false)]
+		);
+		let line = crate::bincode_options()
+			.serialize(&Shape::Line(Point { x: 1, y: 2 }, Point { x: 3, y: 4 }))
+			.unwrap();
+		assert_eq!(line[..4], 2u32.to_le_bytes());
+	}
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize, TypeId, Layout)]
+	#[layout(bytes)]
+	#[response(())]
+	struct PutRequest {
+		key: String,
+		value: Vec<u8>,
+		ttl: Option<u32>,
+	}
+
+	#[test]
+	fn layout_body_roundtrip() {
+		let request = PutRequest {
+			key: "key".into(),
+			value: vec![1, 2],
+			ttl: Some(3),
+		};
+		let bytes = request.to_bytes().unwrap();
+		assert_eq!(PutRequest::from_bytes(&bytes).unwrap(), request);
+
+		let mut registry = TypeRegistry::new();
+		registry.register::<PutRequest>();
+		assert!(registry.get(PutRequest::TYPE_ID).unwrap().layout);
+		let payload = &bytes[size_of::<u32>() + PutRequest::TYPE_ID.len()..];
+		assert_eq!(
+			registry
+				.decode(PutRequest::TYPE_ID, payload)
+				.unwrap()
+				.unwrap(),
+			serde_json::json!({ "key": "key", "value": [1, 2], "ttl": 3 })
+		);
+	}
+
+	#[test]
+	fn derived_types_are_collected() {
+		let registry = TypeRegistry::derived(module_path!());
+		for type_id in [GreetRequest::TYPE_ID, Command::TYPE_ID, Point::TYPE_ID] {
+			assert!(registry.decode(type_id, &[]).is_some(), "{type_id}");
+		}
+		assert!(TypeRegistry::derived("no_such_crate::").is_empty());
+	}
+}
@@ -0,0 +1,98 @@
+//! Human readable rendering of the buffers produced by `ToBytes`, for logs and error reports.
+
+use std::{
+	fmt::{Display, Formatter},
+	mem::size_of,
+};
+
+use super::{get_type_id, registry};
+
+/// Payloads longer than this are truncated in hex dumps.
+const MAX_HEX_BYTES: usize = 256;
+
+/// Renders a buffer produced by `ToBytes`.
+///
+/// Registered types are shown as their type id followed by the json of the body, others fall
+/// back to a hex dump. Nothing is decoded until the result is formatted.
+#[inline(always)]
+pub fn render(buf: &[u8]) -> Rendered<'_> {
+	Rendered(buf)
+}
+
+pub struct Rendered<'a>(&'a [u8]);
+
+impl Display for Rendered<'_> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let Some(type_id) = get_type_id(self.0).ok().filter(|x| !x.is_empty()) else {
+			return write_hex(f, self.0);
+		};
+		let payload = &self.0[size_of::<u32>() + type_id.len()..];
+		match registry::global().decode(type_id, payload) {
+			Some(Ok(value)) => write!(f, "{type_id} {value}"),
+			Some(Err(e)) => {
+				write!(f, "{type_id} (undecodable: {e}) ")?;
+				write_hex(f, payload)
+			}
+			None => {
+				write!(f, "{type_id} ")?;
+				write_hex(f, payload)
+			}
+		}
+	}
+}
+
+fn write_hex(f: &mut Formatter<'_>, buf: &[u8]) -> std::fmt::Result {
+	let shown = &buf[..buf.len().min(MAX_HEX_BYTES)];
+	write!(f, "0x{}", hex::encode(shown))?;
+	if shown.len() < buf.len() {
+		write!(f, "...({} bytes)", buf.len())?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use super::*;
+	use crate::serde::{registry, ToBytes, TypeId};
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct RenderedMessage {
+		key: String,
+		value: u32,
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct UnregisteredMessage(u8);
+
+	#[test]
+	fn render_registered_type() {
+		registry::register::<RenderedMessage>();
+		let bytes = RenderedMessage {
+			key: "a".to_string(),
+			value: 1,
+		}
+		.to_bytes()
+		.unwrap();
+		assert_eq!(
+			render(&bytes).to_string(),
+			format!(r#"{} {{"key":"a","value":1}}"#, RenderedMessage::TYPE_ID)
+		);
+	}
+
+	#[test]
+	fn render_falls_back_to_hex() {
+		let bytes = UnregisteredMessage(0xab).to_bytes().unwrap();
+		assert_eq!(
+			render(&bytes).to_string(),
+			format!("{} 0xab", UnregisteredMessage::TYPE_ID)
+		);
+
+		assert_eq!(render(&[1, 2]).to_string(), "0x0102");
+		assert_eq!(
+			render(&[0; MAX_HEX_BYTES + 1]).to_string(),
+			format!("0x{}...(257 bytes)", "00".repeat(MAX_HEX_BYTES))
+		);
+	}
+}
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
-use tea_codec::{pricing::Priced, serde::TypeId};
+use tea_codec::{
+	pricing::Priced,
+	serde::{layout::Layout, TypeId},
+};
+use tea_runtime_codec::runtime::http::{HttpRequest as RawHttpRequest, HttpResponse};
 
 pub const NAME: &[u8] = b"tea:adapter";
 
@@ -22,14 +26,36 @@ pub struct RegisterHttp(pub Vec<String>);
 pub struct RegisterSocketio(pub Vec<String>);
 
 #[doc(hidden)]
-#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced, Layout)]
 #[price(10000)]
 #[response(Vec<u8>)]
+#[layout(bytes)]
 pub struct HttpRequest {
 	pub action: String,
 	pub payload: Vec<u8>,
 }
 
+/// Registers path prefixes, e.g. `/fees`, whose http requests are forwarded whole as
+/// `HttpRouteRequest` instead of by action name.
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+#[response(())]
+pub struct RegisterHttpRoutes(pub Vec<String>);
+
+/// An http request under a prefix registered with `RegisterHttpRoutes`, with its method, uri,
+/// headers and body as received. The status, headers and body of the response are sent back
+/// as they are.
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+#[response(HttpResponse)]
+pub struct HttpRouteRequest {
+	pub request: RawHttpRequest,
+	/// The ip address of the connected peer as seen by the adapter, not taken from headers.
+	pub remote_addr: Option<String>,
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
 #[price(10000)]
@@ -37,6 +63,8 @@ pub struct HttpRequest {
 pub struct SocketioRequest {
 	pub action: String,
 	pub payload: Vec<u8>,
+	/// The id of the socket the request was received from, set by the adapter.
+	pub socket_id: String,
 }
 
 #[doc(hidden)]
@@ -45,6 +73,17 @@ pub struct SocketioRequest {
 #[response(Vec<u8>)]
 pub struct SocketioClientRequest(pub Vec<u8>);
 
+/// Emits the event to the sockets in the room, where every socket is in the room of its id.
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+#[response(())]
+pub struct SocketioEmitRequest {
+	pub room: String,
+	pub event: String,
+	pub payload: Vec<u8>,
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
 #[price(10000)]
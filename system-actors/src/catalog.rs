@@ -0,0 +1,59 @@
+//! The catalog of every message type defined by the system actors.
+
+use tea_codec::serde::registry::TypeRegistry;
+
+/// Collects the descriptors of all system actor requests and responses, and of the described
+/// types nested in them, which can then be exported with [`TypeRegistry::to_json`].
+///
+/// Hosts may pass it to `tea_codec::serde::registry::register_all` so that logs render
+/// system actor messages as json.
+pub fn catalog() -> TypeRegistry {
+	TypeRegistry::derived(concat!(env!("CARGO_CRATE_NAME"), "::"))
+}
+
+#[cfg(test)]
+mod tests {
+	use tea_codec::serde::{
+		registry::{Field, Schema},
+		TypeId,
+	};
+
+	use super::catalog;
+	use crate::crypto;
+
+	fn fields(schema: &Schema) -> Vec<&Field> {
+		match schema {
+			Schema::Unit => vec![],
+			Schema::Tuple(fields) | Schema::Struct(fields) => fields.iter().collect(),
+			Schema::Enum(variants) => variants.iter().flat_map(|x| fields(&x.schema)).collect(),
+		}
+	}
+
+	#[test]
+	fn catalog_describes_every_request() {
+		let catalog = catalog();
+		assert!(catalog.len() > 500);
+
+		let sign = catalog.get(crypto::SignRequest::TYPE_ID).unwrap();
+		assert_eq!(
+			sign.response_type_id.as_deref(),
+			Some(crypto::SignResponse::TYPE_ID)
+		);
+		assert!(catalog.decode(crypto::SignRequest::TYPE_ID, &[]).is_some());
+		assert!(catalog.get(crypto::SignResponse::TYPE_ID).is_some());
+
+		let refs = catalog
+			.iter()
+			.flat_map(|x| fields(&x.schema))
+			.flat_map(|x| &x.refs)
+			.collect::<Vec<_>>();
+		assert!(!refs.is_empty());
+		for reference in refs {
+			assert!(
+				catalog.get(&reference.type_id).is_some(),
+				"{} is not described",
+				reference.type_id
+			);
+		}
+	}
+}
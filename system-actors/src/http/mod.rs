@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use tea_codec::serde::TypeId;
+use tea_runtime_codec::actor_txns::pre_args::OracleHttpArg;
 
 pub const NAME: &[u8] = b"tea:http";
 
@@ -18,6 +19,19 @@ pub struct OracleHttpRequest {
 	pub payload: Option<String>,
 }
 
+/// Use the request as a pre-arg via `Arg::oracle_http`, so that every replica executes the
+/// txn against the same recorded response.
+impl From<OracleHttpRequest> for OracleHttpArg {
+	fn from(request: OracleHttpRequest) -> Self {
+		OracleHttpArg {
+			method: request.method,
+			url: request.url,
+			headers: request.headers.unwrap_or_default(),
+			payload: request.payload,
+		}
+	}
+}
+
 /// Base response from sending an oracle http request via tea system.
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
 pub struct OracleHttpResponse {
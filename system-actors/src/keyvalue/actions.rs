@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 use tea_actorx::ActorId;
 use tea_codec::pricing::Priced;
-use tea_codec::serde::TypeId;
-use tea_runtime_codec::tapp::TokenId;
+use tea_codec::serde::{layout::Layout, TypeId};
+use tea_runtime_codec::tapp::{Hash, TokenId};
 
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
@@ -63,6 +63,79 @@ pub struct CancelLockRequest {
 	pub key: String,
 }
 
+/// What a key is expected to hold for a conditional write to apply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TypeId)]
+pub enum CasCondition {
+	/// The key does not exist or has expired.
+	Absent,
+	/// The key has not been written since it had this version.
+	Version(u64),
+	/// The sha256 hash of the stored value.
+	ValueHash(Hash),
+}
+
+/// Returns the value of the key together with its version.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(0)]
+pub struct GetVersionedRequest {
+	pub key: String,
+}
+
+/// Every write of a key gives it a new version, absent keys have version 0.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct GetVersionedResponse {
+	pub value: Option<Vec<u8>>,
+	pub version: u64,
+}
+
+/// Sets the value of the key, or deletes it if `value` is `None`, only if `condition` holds.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+pub struct CasRequest {
+	pub key: String,
+	pub condition: CasCondition,
+	pub value: Option<Vec<u8>>,
+	pub expires_s: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct CasResponse {
+	pub success: bool,
+	/// The version after the write, or the current version if the condition did not hold.
+	pub version: u64,
+	/// The current value if the condition did not hold, so that callers can retry without reading again.
+	pub current: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub enum BatchOp {
+	Set {
+		key: String,
+		value: Vec<u8>,
+		expires_s: Option<i32>,
+	},
+	Del {
+		key: String,
+	},
+}
+
+/// Applies every op in order only if every condition holds, otherwise nothing is written.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(30000)]
+pub struct BatchRequest {
+	pub conditions: Vec<(String, CasCondition)>,
+	pub ops: Vec<BatchOp>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct BatchResponse {
+	pub success: bool,
+	/// Keys of which the condition did not hold.
+	pub failed: Vec<String>,
+	/// Versions of the keys written by each op, in the order of `ops`. Empty if not applied.
+	pub versions: Vec<u64>,
+}
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
 #[price(10000)]
@@ -229,8 +302,9 @@ pub struct KeyVecTailOffResponse {
 }
 
 #[doc(hidden)]
-#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced, Layout)]
 #[price(10000)]
+#[layout(bytes)]
 pub struct KeyVecGetRequest {
 	pub key: String,
 }
@@ -4,6 +4,7 @@ extern crate tea_codec as tea_sdk;
 
 pub mod adapter;
 pub mod billing;
+pub mod catalog;
 pub mod console;
 pub mod crypto;
 pub mod env;
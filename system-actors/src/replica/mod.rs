@@ -2,7 +2,9 @@ use crate::tokenstate_service::UpgradeVersion;
 use serde::{Deserialize, Serialize};
 use tea_codec::pricing::Priced;
 use tea_codec::serde::TypeId;
-use tea_runtime_codec::actor_txns::{pre_args::ArgSlots, tsid::Tsid, txn::FullTxn};
+use tea_runtime_codec::actor_txns::{
+	pre_args::ArgSlots, receipt::TxnReceipt, tsid::Tsid, txn::FullTxn,
+};
 use tea_runtime_codec::tapp::{Hash, ReplicaId};
 
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
@@ -15,6 +17,32 @@ pub struct TxnItem {
 pub struct HistoryItem {
 	pub txn_item: TxnItem,
 	pub err_msg: Option<String>,
+	/// Gas metered while executing the txn, at most the `gas_limit` of its `TxnSerial`.
+	///
+	/// Appended after 0.3.0-dev.7, items written before are read by [`HistoryItem::decode`].
+	pub gas_used: u64,
+}
+
+/// The layout of [`HistoryItem`] before `gas_used`.
+#[derive(Deserialize)]
+struct HistoryItemV0 {
+	txn_item: TxnItem,
+	err_msg: Option<String>,
+}
+
+impl HistoryItem {
+	/// Decodes a history item encoded with `serialize`, also one written before `gas_used` was
+	/// appended, which is read as having used no gas.
+	pub fn decode(buf: &[u8]) -> tea_codec::Result<Self> {
+		tea_codec::deserialize(buf).or_else(|e| {
+			let item: HistoryItemV0 = tea_codec::deserialize(buf).map_err(|_| e)?;
+			Ok(HistoryItem {
+				txn_item: item.txn_item,
+				err_msg: item.err_msg,
+				gas_used: 0,
+			})
+		})
+	}
 }
 
 pub const NAME: &[u8] = b"tea:replica";
@@ -205,6 +233,25 @@ pub struct AppendToHistoryRequest(pub HistoryItem);
 #[response(())]
 pub struct AppendCommitHashRequest(pub Hash, pub Vec<u8>);
 
+/// Stores the receipt of an executed txn by its hash, failed txns included.
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+#[response(())]
+pub struct AppendTxnReceiptRequest(pub TxnReceipt);
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
+#[price(10000)]
+pub struct QueryTxnReceiptRequest {
+	pub txn_hash: Hash,
+	/// Only events of these types are returned, all events are if empty.
+	pub event_types: Vec<String>,
+}
+
+/// `None` if the txn has not been executed yet.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct QueryTxnReceiptResponse(pub Option<TxnReceipt>);
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
 #[price(10000)]
@@ -217,6 +264,7 @@ pub struct IsTxnAlreadyExecutedResponse(pub bool);
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId, Priced)]
 #[price(10)]
+#[response(ExecTxnResponse)]
 pub struct ExecTxnRequest(
 	pub Tsid,
 	pub Vec<u8>,
@@ -279,3 +327,39 @@ pub struct IsBatchApplyingResponse(pub bool);
 #[price(10000)]
 #[response(())]
 pub struct SetBatchApplyingRequest(pub bool);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tea_runtime_codec::actor_txns::txn::FullTxn;
+
+	#[test]
+	fn history_items_written_before_gas_used_decode() {
+		#[derive(Serialize)]
+		struct LegacyHistoryItem {
+			txn_item: TxnItem,
+			err_msg: Option<String>,
+		}
+
+		let txn_item = TxnItem {
+			txn: FullTxn::new_no_args(vec![1, 2, 3]),
+			tsid: Tsid::default(),
+		};
+		let buf = tea_codec::serialize(&LegacyHistoryItem {
+			txn_item: txn_item.clone(),
+			err_msg: Some("failed".into()),
+		})
+		.unwrap();
+		let item = HistoryItem::decode(&buf).unwrap();
+		assert_eq!(item.txn_item.txn.txn_bytes, vec![1, 2, 3]);
+		assert_eq!(item.err_msg.as_deref(), Some("failed"));
+		assert_eq!(item.gas_used, 0);
+
+		let item = HistoryItem {
+			gas_used: 7,
+			..item
+		};
+		let decoded = HistoryItem::decode(&tea_codec::serialize(&item).unwrap()).unwrap();
+		assert_eq!(decoded.gas_used, 7);
+	}
+}
@@ -16,6 +16,7 @@ use tea_runtime_codec::tapp::{
 use tea_runtime_codec::{
 	actor_txns::{
 		pre_args::{Arg, ArgSlots},
+		receipt::TxnReceipt,
 		tsid::Tsid,
 	},
 	tapp::ra::VersionPcrs,
@@ -177,6 +178,16 @@ pub struct FindExecutedTxnFromAllRequest(pub Vec<u8>);
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
 pub struct FindExecutedTxnFromAllResponse(pub Vec<u8>);
 
+/// Queries the receipt of a txn from the replica, see `replica::QueryTxnReceiptRequest`.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct QueryTxnReceiptRequest {
+	pub txn_hash: Hash,
+	pub event_types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct QueryTxnReceiptResponse(pub Option<TxnReceipt>);
+
 #[doc(hidden)]
 #[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
 pub struct CheckUserSessionRequest {
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 #![allow(unused_imports)]
 
+use crate::client::help::ActionOk;
 use crate::client::{check_auth, help, request, Error, Result};
 use crate::enclave::actors::env::tapp_payment_channel_token_id;
 use crate::enclave::actors::statemachine;
@@ -9,6 +10,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tea_actorx::ActorId;
 use tea_runtime_codec::tapp::{Account, Balance, ChannelId, ChannelItem, ChannelItemStatus};
+use tea_sdk::serde::TypeId;
 use tea_sdk::IntoGlobal;
 use tea_system_actors::payment_channel::{
 	txns::PaymentChannelTxn, QueryChannelInfoRequest, QueryChannelInfoResponse, NAME,
@@ -17,8 +19,9 @@ use tea_system_actors::tokenstate;
 
 const TARGET_ACTOR: &[u8] = NAME;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct PayerOpenChannelRequest {
 	pub uuid: String,
 	pub tapp_id_b64: String,
@@ -34,8 +37,9 @@ pub struct PayerOpenChannelRequest {
 	pub expire_time: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct PayerEarlyTerminateRequest {
 	pub uuid: String,
 	pub tapp_id_b64: String,
@@ -45,8 +49,9 @@ pub struct PayerEarlyTerminateRequest {
 	pub channel_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct PayerTerminateRequest {
 	pub uuid: String,
 	pub tapp_id_b64: String,
@@ -56,8 +61,9 @@ pub struct PayerTerminateRequest {
 	pub channel_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct PayerRefillRequest {
 	pub uuid: String,
 	pub tapp_id_b64: String,
@@ -68,8 +74,9 @@ pub struct PayerRefillRequest {
 	pub refill_amount: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct PayeeUpdatePaymentRequest {
 	pub uuid: String,
 	pub tapp_id_b64: String,
@@ -81,8 +88,9 @@ pub struct PayeeUpdatePaymentRequest {
 	pub new_fund_remaining: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryChannelListWithAccountRequest {
 	pub uuid: String,
 	pub address: String,
@@ -91,8 +99,9 @@ pub struct QueryChannelListWithAccountRequest {
 	pub expire_time: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryChannelListWithChannelIdRequest {
 	pub uuid: String,
 	pub address: String,
@@ -101,8 +110,10 @@ pub struct QueryChannelListWithChannelIdRequest {
 	pub channel_id: Vec<String>,
 }
 
-pub async fn open_payment_channel(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: PayerOpenChannelRequest = serde_json::from_slice(&payload)?;
+pub async fn open_payment_channel(
+	req: PayerOpenChannelRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("open_payment_channel ...");
@@ -145,11 +156,13 @@ pub async fn open_payment_channel(payload: Vec<u8>, from_actor: String) -> Resul
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn early_terminate(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: PayerEarlyTerminateRequest = serde_json::from_slice(&payload)?;
+pub async fn early_terminate(
+	req: PayerEarlyTerminateRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("early_terminate ...");
@@ -170,11 +183,10 @@ pub async fn early_terminate(payload: Vec<u8>, from_actor: String) -> Result<Vec
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn terminate(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: PayerTerminateRequest = serde_json::from_slice(&payload)?;
+pub async fn terminate(req: PayerTerminateRequest, from_actor: String) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("terminate ...");
@@ -196,11 +208,10 @@ pub async fn terminate(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>>
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn refill_fund(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: PayerRefillRequest = serde_json::from_slice(&payload)?;
+pub async fn refill_fund(req: PayerRefillRequest, from_actor: String) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("refill_fund ...");
@@ -223,14 +234,13 @@ pub async fn refill_fund(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
 pub async fn query_channel_list_with_account(
-	payload: Vec<u8>,
+	req: QueryChannelListWithAccountRequest,
 	_from_actor: String,
-) -> Result<Vec<u8>> {
-	let req: QueryChannelListWithAccountRequest = serde_json::from_slice(&payload)?;
+) -> Result<ActionOk> {
 	// check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("query_channel_list_with_account from local_state ...");
@@ -269,14 +279,13 @@ pub async fn query_channel_list_with_account(
 
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
 pub async fn query_channel_list_with_channel_id(
-	payload: Vec<u8>,
+	req: QueryChannelListWithChannelIdRequest,
 	_from_actor: String,
-) -> Result<Vec<u8>> {
-	let req: QueryChannelListWithChannelIdRequest = serde_json::from_slice(&payload)?;
+) -> Result<ActionOk> {
 	// check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("query_channel_list_with_channel_id from local_state ...");
@@ -309,11 +318,13 @@ pub async fn query_channel_list_with_channel_id(
 
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn payee_update_payment(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: PayeeUpdatePaymentRequest = serde_json::from_slice(&payload)?;
+pub async fn payee_update_payment(
+	req: PayeeUpdatePaymentRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("payee_update_payment ...");
@@ -338,5 +349,5 @@ pub async fn payee_update_payment(payload: Vec<u8>, from_actor: String) -> Resul
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
@@ -1,5 +1,6 @@
 use crate::client::error::{Error, Result};
-use crate::client::help;
+use crate::client::help::{self, ActionOk, JsonResponse};
+use crate::client::push;
 use crate::client::request;
 use crate::client::txn_cache;
 use crate::client::types::txn_callback;
@@ -15,12 +16,13 @@ use serde_json::json;
 use std::str::FromStr;
 use tea_codec::OptionExt;
 use tea_codec::{deserialize, serialize};
-use tea_runtime_codec::actor_txns::Tsid;
+use tea_runtime_codec::actor_txns::{receipt::TxnStatus, Tsid};
 use tea_runtime_codec::tapp::{Account, Balance, TokenId};
 use tea_runtime_codec::vmh::message::{
 	encode_protobuf,
 	structs_proto::{replica, tappstore},
 };
+use tea_sdk::serde::TypeId;
 use tea_sdk::{IntoGlobal, ResultExt};
 use tea_system_actors::tappstore::txns::TappstoreTxn;
 use tea_system_actors::tappstore::CheckUserSessionRequest;
@@ -31,10 +33,12 @@ use tea_system_actors::tappstore::FindExecutedTxnFromAllRequest;
 use tea_system_actors::tappstore::FindExecutedTxnRequest;
 use tea_system_actors::tappstore::QueryTeaBalanceRequest;
 use tea_system_actors::tappstore::QueryTeaDepositRequest;
+use tea_system_actors::tappstore::QueryTxnReceiptRequest as TappstoreQueryTxnReceiptRequest;
 use tea_system_actors::tokenstate_service::TxnExistenceStatus;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct LoginRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
@@ -45,8 +49,7 @@ pub struct LoginRequest {
 	pub pk: String,
 	pub uuid: String,
 }
-pub async fn txn_login(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: LoginRequest = serde_json::from_slice(&payload)?;
+pub async fn txn_login(req: LoginRequest, from_actor: String) -> Result<ActionOk> {
 	info!("login request action... {:?}", req);
 	let _txn_uuid = req.uuid.to_string();
 
@@ -69,18 +72,21 @@ pub async fn txn_login(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>>
 	)
 	.await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QuerySessionKeyRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
 	pub uuid: String,
 }
-pub async fn query_session_key(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QuerySessionKeyRequest = serde_json::from_slice(&payload)?;
+pub async fn query_session_key(
+	req: QuerySessionKeyRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
 	let uuid = req.uuid;
 
 	let r = request::send_tappstore_query(
@@ -116,7 +122,7 @@ pub async fn query_session_key(payload: Vec<u8>, from_actor: String) -> Result<V
 	});
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
 pub async fn check_auth(tapp_id_hex: &str, address: &str, auth_b64: &str) -> Result<Vec<u8>> {
@@ -144,18 +150,21 @@ pub async fn extend_auth(tapp_id_hex: &str, address: &str, auth_b64: &str) -> Re
 	help::save_session_key(auth_b64.to_string(), tapp_id_hex, address).await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+/// Every field is optional, as logging out never required any.
+#[derive(Debug, Default, Serialize, Deserialize, TypeId)]
+#[serde(rename_all = "camelCase", default)]
+#[response(ActionOk)]
 pub struct LogoutRequest {
 	pub address: String,
 }
-pub fn txn_logout(_payload: Vec<u8>, _from_actor: String) -> Result<Vec<u8>> {
+pub async fn txn_logout(_req: LogoutRequest, _from_actor: String) -> Result<ActionOk> {
 	// TODO
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct HttpQueryBalanceRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
@@ -164,8 +173,9 @@ pub struct HttpQueryBalanceRequest {
 	pub target: Option<String>,
 	pub target_tapp_id_b64: Option<String>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct HttpQueryDepositRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
@@ -174,16 +184,18 @@ pub struct HttpQueryDepositRequest {
 	pub target: Option<String>,
 	pub target_tapp_id_b64: Option<String>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(JsonResponse)]
 pub struct HttpQueryCreditRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
 	pub uuid: String,
 	pub auth_b64: String,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryAssetRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
@@ -192,8 +204,7 @@ pub struct QueryAssetRequest {
 	pub target: Option<String>,
 }
 
-pub async fn query_balance(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: HttpQueryBalanceRequest = serde_json::from_slice(&payload)?;
+pub async fn query_balance(req: HttpQueryBalanceRequest, from_actor: String) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	let uuid = req.uuid;
@@ -224,14 +235,21 @@ pub async fn query_balance(payload: Vec<u8>, from_actor: String) -> Result<Vec<u
 		});
 
 		help::cache_json_with_uuid(&uuid, x).await?;
-
-		return help::result_ok();
+		push::notify_balance(
+			&query_account,
+			&query_token_id.to_hex(),
+			&balance.to_string(),
+			&ts.to_string(),
+		)
+		.await;
+
+		return Ok(ActionOk::default());
 	}
 
 	let auth_key = base64::decode(&req.auth_b64).into_g::<Error>()?;
 
 	let query_data = tappstore::TeaBalanceRequest {
-		account: query_account,
+		account: query_account.clone(),
 		token_id: serialize(&query_token_id)?,
 		auth_key,
 	};
@@ -243,20 +261,22 @@ pub async fn query_balance(payload: Vec<u8>, from_actor: String) -> Result<Vec<u
 	)
 	.await?;
 	let r = tappstore::TeaBalanceResponse::decode(res.0.as_slice()).into_g::<Error>()?;
+	let balance = deserialize::<Balance, _>(&r.balance)?.to_string();
+	let ts = help::u128_from_le_buffer(&r.ts)?.to_string();
 	let x = serde_json::json!({
-		"balance": deserialize::<Balance,_>(&r.balance)?.to_string(),
-		"ts": help::u128_from_le_buffer(&r.ts)?.to_string(),
+		"balance": balance,
+		"ts": ts,
 		"uuid": uuid
 	});
 	info!("query tea_balance from remotely => {:?}", x);
 
 	help::cache_json_with_uuid(&uuid, x).await?;
+	push::notify_balance(&query_account, &query_token_id.to_hex(), &balance, &ts).await;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn query_deposit(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: HttpQueryDepositRequest = serde_json::from_slice(&payload)?;
+pub async fn query_deposit(req: HttpQueryDepositRequest, from_actor: String) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("begin to query tea deposit");
@@ -289,7 +309,7 @@ pub async fn query_deposit(payload: Vec<u8>, from_actor: String) -> Result<Vec<u
 
 		help::cache_json_with_uuid(&uuid, x).await?;
 
-		return help::result_ok();
+		return Ok(ActionOk::default());
 	}
 
 	let query_data = tappstore::TeaBalanceRequest {
@@ -313,11 +333,10 @@ pub async fn query_deposit(payload: Vec<u8>, from_actor: String) -> Result<Vec<u
 
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-pub async fn query_credit(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: HttpQueryCreditRequest = serde_json::from_slice(&payload)?;
+pub async fn query_credit(req: HttpQueryCreditRequest, from_actor: String) -> Result<JsonResponse> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	info!("begin to query credit balance...");
@@ -338,14 +357,13 @@ pub async fn query_credit(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8
 		});
 
 		help::cache_json_with_uuid(&uuid, x).await?;
-		return help::result_ok();
+		return Ok(ActionOk::default().into());
 	}
 
-	help::result_error("Not permission to query credit".into())
+	Ok(JsonResponse::error("Not permission to query credit"))
 }
 
-pub async fn query_asset(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryAssetRequest = serde_json::from_slice(&payload)?;
+pub async fn query_asset(req: QueryAssetRequest, from_actor: String) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 
 	let query_account = match &req.target {
@@ -378,18 +396,18 @@ pub async fn query_asset(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>
 	});
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryAllowanceRequest {
 	pub address: String,
 	pub tapp_id_b64: String,
 	pub uuid: String,
 }
-pub async fn query_allowance(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryAllowanceRequest = serde_json::from_slice(&payload)?;
+pub async fn query_allowance(req: QueryAllowanceRequest, from_actor: String) -> Result<ActionOk> {
 	info!("query allowance... => {:?}", req);
 
 	let uuid = req.uuid;
@@ -411,7 +429,7 @@ pub async fn query_allowance(payload: Vec<u8>, from_actor: String) -> Result<Vec
 
 		help::cache_json_with_uuid(&uuid, x).await?;
 
-		return help::result_ok();
+		return Ok(ActionOk::default());
 	}
 
 	let query_data = tappstore::TokenAllowanceRequest {
@@ -432,23 +450,26 @@ pub async fn query_allowance(payload: Vec<u8>, from_actor: String) -> Result<Vec
 	});
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(JsonResponse)]
 pub struct QueryTappMetadataRequest {
 	pub uuid: String,
 	pub token_id: String,
 }
-pub async fn query_tapp_metadata(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryTappMetadataRequest = serde_json::from_slice(&payload)?;
+pub async fn query_tapp_metadata(
+	req: QueryTappMetadataRequest,
+	from_actor: String,
+) -> Result<JsonResponse> {
 	info!("query_tapp_metadata... => {:?}", req);
 
 	let cache_key = "query_tapp_metadata";
 	if let Ok(x) = help::get_query_cache(cache_key).await {
 		if let Some(val) = x {
-			return Ok(val);
+			return Ok(JsonResponse(serde_json::from_slice(&val)?));
 		}
 	}
 
@@ -487,35 +508,45 @@ pub async fn query_tapp_metadata(payload: Vec<u8>, from_actor: String) -> Result
 	};
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default().into())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryErrorLogRequest {
 	pub uuid: String,
 	pub query_type: String,
 	pub query_key: String,
 }
-pub async fn query_error_log(_payload: Vec<u8>, _from_actor: String) -> Result<Vec<u8>> {
+pub async fn query_error_log(_req: QueryErrorLogRequest, _from_actor: String) -> Result<ActionOk> {
 	todo!("do later");
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(QueryResultResponse)]
 pub struct HttpQueryResultWithUuid {
 	pub uuid: String,
 }
-pub async fn query_result(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: HttpQueryResultWithUuid = serde_json::from_slice(&payload)?;
+
+/// The json cached under the uuid by the query, or `{"error": ...}` if it failed.
+#[derive(Debug, Serialize, Deserialize, TypeId)]
+pub struct QueryResultResponse(pub serde_json::Value);
+
+pub async fn query_result(
+	req: HttpQueryResultWithUuid,
+	from_actor: String,
+) -> Result<QueryResultResponse> {
 	match crate::client::query_cb::query_callback(from_actor, &req.uuid).await {
-		Ok(res_val) => Ok(serde_json::to_vec(&res_val)?),
-		Err(e) => help::result_error(e.to_string()),
+		Ok(res_val) => Ok(QueryResultResponse(res_val)),
+		Err(e) => Ok(QueryResultResponse(json!({ "error": e.to_string() }))),
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryHashRequest {
 	pub uuid: String,
 	pub hash: String,
@@ -523,8 +554,7 @@ pub struct QueryHashRequest {
 }
 
 #[allow(unused_must_use)]
-pub async fn query_txn_hash_result(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryHashRequest = serde_json::from_slice(&payload)?;
+pub async fn query_txn_hash_result(req: QueryHashRequest, from_actor: String) -> Result<ActionOk> {
 	info!("begin to query hash result...");
 
 	let uuid = req.uuid;
@@ -588,11 +618,68 @@ pub async fn query_txn_hash_result(payload: Vec<u8>, from_actor: String) -> Resu
 		help::cache_json_with_uuid(&uuid, x).await?;
 	}
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
+pub struct QueryTxnReceiptRequest {
+	pub uuid: String,
+	pub hash: String,
+	/// Only events of these types are returned, all events are if empty.
+	#[serde(default)]
+	pub event_types: Vec<String>,
+}
+
+pub async fn query_txn_receipt(
+	req: QueryTxnReceiptRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
+	let txn_hash = hex::decode(&req.hash).into_g::<Error>()?;
+
+	let res = request::send_tappstore_query(
+		&from_actor,
+		TappstoreQueryTxnReceiptRequest {
+			txn_hash: txn_hash.as_slice().try_into().into_g::<Error>()?,
+			event_types: req.event_types,
+		},
+		None,
+	)
+	.await?;
+
+	let observed = res.0.is_some();
+	let x = match res.0 {
+		Some(receipt) => json!({
+			"status": true,
+			"ts": receipt.tsid.ts.to_string(),
+			"committed": receipt.is_committed(),
+			"error": match &receipt.status {
+				TxnStatus::Committed => None,
+				TxnStatus::Failed(e) => Some(e),
+			},
+			"statements": receipt.statements.len(),
+			"events": receipt.events.iter().map(|x| json!({
+				"eventType": x.event_type,
+				"data": hex::encode(&x.data),
+			})).collect::<Vec<_>>(),
+		}),
+		None => json!({
+			"status": false,
+			"error": "wait",
+		}),
+	};
+	if observed {
+		push::notify_receipt(&req.hash, &x).await;
+	}
+	help::cache_json_with_uuid(&req.uuid, x).await?;
+
+	Ok(ActionOk::default())
+}
+
+#[derive(Debug, Serialize, Deserialize, TypeId)]
+#[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryHashFromAllRequest {
 	pub tapp_id_b64: String,
 	pub address: String,
@@ -603,10 +690,9 @@ pub struct QueryHashFromAllRequest {
 }
 #[allow(unused_must_use)]
 pub async fn query_txn_hash_result_from_all(
-	payload: Vec<u8>,
+	req: QueryHashFromAllRequest,
 	from_actor: String,
-) -> Result<Vec<u8>> {
-	let req: QueryHashFromAllRequest = serde_json::from_slice(&payload)?;
+) -> Result<ActionOk> {
 	check_auth(&req.tapp_id_b64, &req.address, &req.auth_b64).await?;
 	info!("begin to query hash result from all...");
 
@@ -637,22 +723,25 @@ pub async fn query_txn_hash_result_from_all(
 	});
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(JsonResponse)]
 pub struct QuerySystemVersionRequest {
 	pub uuid: String,
 }
-pub async fn query_system_version(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QuerySystemVersionRequest = serde_json::from_slice(&payload)?;
+pub async fn query_system_version(
+	req: QuerySystemVersionRequest,
+	from_actor: String,
+) -> Result<JsonResponse> {
 	info!("query_system_version...");
 
 	let cache_key = "query_system_version";
 	if let Ok(x) = help::get_query_cache(cache_key).await {
 		if let Some(val) = x {
-			return Ok(val);
+			return Ok(JsonResponse(serde_json::from_slice(&val)?));
 		}
 	}
 
@@ -676,18 +765,21 @@ pub async fn query_system_version(payload: Vec<u8>, from_actor: String) -> Resul
 	help::set_query_cache(&cache_key, x.clone()).await?;
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default().into())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(JsonResponse)]
 pub struct QueryMultiTappAllowanceFromLocalStateRequest {
 	pub address: String,
 	pub tapp_id_b64_array: Vec<String>,
 	pub uuid: String,
 }
-pub async fn query_multi_tapp_allowance(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryMultiTappAllowanceFromLocalStateRequest = serde_json::from_slice(&payload)?;
+pub async fn query_multi_tapp_allowance(
+	req: QueryMultiTappAllowanceFromLocalStateRequest,
+	from_actor: String,
+) -> Result<JsonResponse> {
 	info!(
 		"query multi tapp allowance from local state... => {:?}",
 		req
@@ -696,9 +788,9 @@ pub async fn query_multi_tapp_allowance(payload: Vec<u8>, from_actor: String) ->
 	let uuid = req.uuid;
 
 	if !state::is_system_actor(&from_actor) {
-		return help::result_error(
-			"No permission to call query_multi_tapp_allowance method.".into(),
-		);
+		return Ok(JsonResponse::error(
+			"No permission to call query_multi_tapp_allowance method.",
+		));
 	}
 
 	let acct = req.address.parse()?;
@@ -718,16 +810,19 @@ pub async fn query_multi_tapp_allowance(payload: Vec<u8>, from_actor: String) ->
 	});
 	help::cache_json_with_uuid(&uuid, x).await?;
 
-	help::result_ok()
+	Ok(ActionOk::default().into())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryCreditSystemInfoRequest {
 	pub uuid: String,
 }
-pub async fn query_credit_system_info(payload: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryCreditSystemInfoRequest = serde_json::from_slice(&payload)?;
+pub async fn query_credit_system_info(
+	req: QueryCreditSystemInfoRequest,
+	from_actor: String,
+) -> Result<ActionOk> {
 	info!("query_credit_system_info...");
 
 	let uuid = req.uuid;
@@ -749,5 +844,5 @@ pub async fn query_credit_system_info(payload: Vec<u8>, from_actor: String) -> R
 		})
 	};
 	help::cache_json_with_uuid(&uuid, x).await?;
-	help::result_ok()
+	Ok(ActionOk::default())
 }
@@ -57,6 +57,15 @@ pub enum Errors {
 
 	#[error("Unclave utils error: {0}")]
 	EnclaveUtilsError(String),
+
+	#[error("txn status can not change from {0} to {1}")]
+	InvalidTxnStatusTransition(String, String),
+
+	#[error("invalid push topic: {0}")]
+	InvalidPushTopic(String),
+
+	#[error("push topic {0} is not readable by {1}")]
+	PushTopicForbidden(String, String),
 }
 
 impl From<RuntimeTappError> for Error {
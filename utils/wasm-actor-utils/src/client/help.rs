@@ -1,8 +1,9 @@
 use crate::client::{Errors, Result};
 use crate::enclave::actors::kvp;
-use serde::Serialize;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 use tea_codec::OptionExt;
 use tea_runtime_codec::tapp::Balance;
+use tea_sdk::serde::TypeId;
 
 /// Set a cache value for 1800 senond.
 pub async fn set_mem_cache(key: &str, val: Vec<u8>) -> Result<()> {
@@ -57,13 +58,68 @@ pub async fn get_aes_key(tapp_id_b64: &str) -> Result<Vec<u8>> {
 	Ok(aes_key)
 }
 
+/// The response of actions whose result is cached under the uuid of the request.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeId)]
+pub struct ActionOk {
+	pub data: String,
+	pub status: bool,
+}
+
+impl Default for ActionOk {
+	fn default() -> Self {
+		ActionOk {
+			data: "ok".to_string(),
+			status: true,
+		}
+	}
+}
+
+/// The response of actions answering with other json than `ActionOk`, e.g. with a cached result
+/// or with `{"error": ...}`.
+///
+/// Encoded as the json value itself in self-describing formats, and as its json text in bincode,
+/// which can't decode a `serde_json::Value`.
+#[derive(Debug, Clone, PartialEq, TypeId)]
+pub struct JsonResponse(pub serde_json::Value);
+
+impl Serialize for JsonResponse {
+	fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+		if serializer.is_human_readable() {
+			self.0.serialize(serializer)
+		} else {
+			serializer.serialize_str(&self.0.to_string())
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for JsonResponse {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+		if deserializer.is_human_readable() {
+			serde_json::Value::deserialize(deserializer).map(JsonResponse)
+		} else {
+			let text = String::deserialize(deserializer)?;
+			serde_json::from_str(&text)
+				.map(JsonResponse)
+				.map_err(D::Error::custom)
+		}
+	}
+}
+
+impl JsonResponse {
+	pub fn error(e: impl ToString) -> Self {
+		JsonResponse(serde_json::json!({ "error": e.to_string() }))
+	}
+}
+
+impl From<ActionOk> for JsonResponse {
+	fn from(value: ActionOk) -> Self {
+		JsonResponse(serde_json::json!(value))
+	}
+}
+
 /// Return a success json value.
 pub fn result_ok() -> Result<Vec<u8>> {
-	let json = serde_json::json!({
-		"data": "ok",
-		"status": true,
-	});
-	Ok(serde_json::to_vec(&json)?)
+	Ok(serde_json::to_vec(&ActionOk::default())?)
 }
 
 /// Return a custom error json value.
@@ -142,3 +198,22 @@ pub fn string_to_balance(balance_str: &str) -> Result<Balance> {
 		.map_err(|_| Errors::Unnamed(format!("Balance_string parse error.")))?;
 	Ok(balance)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tea_sdk::serde::{FromBytes, ToBytes};
+
+	#[test]
+	fn json_response_roundtrips() {
+		let response = JsonResponse(serde_json::json!({ "error": "denied", "code": [1, 2] }));
+		let bytes = response.to_bytes().unwrap();
+		assert_eq!(JsonResponse::from_bytes(&bytes).unwrap(), response);
+		assert_eq!(serde_json::to_value(&response).unwrap(), response.0);
+		let json = serde_json::to_vec(&response).unwrap();
+		assert_eq!(
+			serde_json::from_slice::<JsonResponse>(&json).unwrap(),
+			response
+		);
+	}
+}
@@ -0,0 +1,614 @@
+//! Routing of the http requests the adapter dispatches to an actor.
+//!
+//! Routes are matched by method and path pattern, where `:name` matches one segment and
+//! `*name` matches the rest of the path. Handlers take a `RouteRequest` to read the path
+//! parameters, the query and the json body from, and answer an `HttpResponse` or an
+//! `HttpError` with its status code. Middleware such as `SessionAuth`, `RateLimit` and `Cors`
+//! wraps either all routes of a router or only those merged from another router.
+//!
+//! ```ignore
+//! let public = HttpRouter::new().get("/fees/:id", |req: RouteRequest| async move {
+//!     let id: u64 = req.param("id")?;
+//!     ok(&query_fee(id).await?)
+//! });
+//! let private = HttpRouter::new()
+//!     .with(SessionAuth::default())
+//!     .post("/fees", add_fee);
+//! let router = public.merge(private).with(Cors::any());
+//! router.register().await?;
+//!
+//! // in the handler of `adapter::HttpRouteRequest`, answering the full response
+//! Ok(router.handle_adapter(req).await)
+//! ```
+//!
+//! The router registers the literal prefixes of its routes with the adapter, which then
+//! forwards the requests under them whole, so that middleware sees the method, the headers
+//! and the address of the peer.
+
+pub mod middleware;
+
+pub use middleware::{Cors, Middleware, Next, RateLimit, Session, SessionAuth};
+
+use crate::client::error::{Errors, Result};
+use crate::enclave::action::HttpRouteRequest;
+use crate::enclave::actors::adapter::register_adapter_http_routes;
+use futures::{future::BoxFuture, Future};
+use http::{header, Extensions, HeaderMap, HeaderValue, Method, StatusCode, Version};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tea_runtime_codec::runtime::http::{HttpRequest, HttpResponse};
+
+pub type HttpResult<T = HttpResponse> = std::result::Result<T, HttpError>;
+
+type Handler = Arc<dyn Fn(RouteRequest) -> BoxFuture<'static, HttpResult> + Send + Sync>;
+
+/// An error answered with its status code and `{"error": <message>}` as body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+	pub status: StatusCode,
+	pub message: String,
+}
+
+impl HttpError {
+	pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+		HttpError {
+			status,
+			message: message.into(),
+		}
+	}
+
+	pub fn bad_request(message: impl Into<String>) -> Self {
+		Self::new(StatusCode::BAD_REQUEST, message)
+	}
+
+	pub fn unauthorized(message: impl Into<String>) -> Self {
+		Self::new(StatusCode::UNAUTHORIZED, message)
+	}
+
+	pub fn forbidden(message: impl Into<String>) -> Self {
+		Self::new(StatusCode::FORBIDDEN, message)
+	}
+
+	pub fn not_found(message: impl Into<String>) -> Self {
+		Self::new(StatusCode::NOT_FOUND, message)
+	}
+
+	pub fn internal(message: impl Into<String>) -> Self {
+		Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+	}
+
+	pub fn into_response(self) -> HttpResponse {
+		json(self.status, &serde_json::json!({ "error": self.message }))
+	}
+}
+
+impl From<Errors> for HttpError {
+	fn from(e: Errors) -> Self {
+		Self::internal(e.to_string())
+	}
+}
+
+impl From<crate::enclave::error::Error> for HttpError {
+	fn from(e: crate::enclave::error::Error) -> Self {
+		Errors::from(e).into()
+	}
+}
+
+/// A response without body.
+pub fn empty(status: StatusCode) -> HttpResponse {
+	HttpResponse {
+		status,
+		version: Version::HTTP_11,
+		headers: HeaderMap::new(),
+		body: Vec::new(),
+	}
+}
+
+/// A response with the value as json body.
+pub fn json<T: Serialize + ?Sized>(status: StatusCode, value: &T) -> HttpResponse {
+	match serde_json::to_vec(value) {
+		Ok(body) => {
+			let mut rtn = empty(status);
+			rtn.headers.insert(
+				header::CONTENT_TYPE,
+				HeaderValue::from_static("application/json"),
+			);
+			rtn.body = body;
+			rtn
+		}
+		Err(e) => HttpError::internal(e.to_string()).into_response(),
+	}
+}
+
+/// `200 OK` with the value as json body.
+pub fn ok<T: Serialize + ?Sized>(value: &T) -> HttpResult {
+	Ok(json(StatusCode::OK, value))
+}
+
+/// `201 Created` with the value as json body.
+pub fn created<T: Serialize + ?Sized>(value: &T) -> HttpResult {
+	Ok(json(StatusCode::CREATED, value))
+}
+
+/// `204 No Content`.
+pub fn no_content() -> HttpResult {
+	Ok(empty(StatusCode::NO_CONTENT))
+}
+
+/// An http request with the parameters of the route it matched.
+pub struct RouteRequest {
+	pub method: Method,
+	/// The path without query, still percent-encoded.
+	pub path: String,
+	/// The decoded query pairs in order.
+	pub query: Vec<(String, String)>,
+	pub headers: HeaderMap<HeaderValue>,
+	pub body: Vec<u8>,
+	/// The ip address of the peer as seen by the adapter, `None` if not known.
+	pub remote_addr: Option<String>,
+	/// The decoded path parameters, set when the request is routed.
+	pub params: BTreeMap<String, String>,
+	/// Values attached by middleware, e.g. the `Session` of `SessionAuth`.
+	pub extensions: Extensions,
+}
+
+impl RouteRequest {
+	pub fn new(method: Method, path_and_query: &str, headers: HeaderMap, body: Vec<u8>) -> Self {
+		let (path, query) = path_and_query
+			.split_once('?')
+			.unwrap_or((path_and_query, ""));
+		RouteRequest {
+			method,
+			path: if path.starts_with('/') {
+				path.to_string()
+			} else {
+				format!("/{path}")
+			},
+			query: query
+				.split('&')
+				.filter(|x| !x.is_empty())
+				.map(|pair| {
+					let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+					(decode_query(name), decode_query(value))
+				})
+				.collect(),
+			headers,
+			body,
+			remote_addr: None,
+			params: BTreeMap::new(),
+			extensions: Extensions::new(),
+		}
+	}
+
+	pub fn from_http(req: HttpRequest) -> Self {
+		let path_and_query = req.uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+		Self::new(req.method, path_and_query, req.headers, req.body)
+	}
+
+	pub fn from_adapter(req: HttpRouteRequest) -> Self {
+		let mut rtn = Self::from_http(req.request);
+		rtn.remote_addr = req.remote_addr;
+		rtn
+	}
+
+	/// A path parameter of the route, answering `400 Bad Request` if it does not parse.
+	pub fn param<T: FromStr>(&self, name: &str) -> HttpResult<T> {
+		let value = self
+			.params
+			.get(name)
+			.ok_or_else(|| HttpError::internal(format!("route has no parameter {name}")))?;
+		value
+			.parse()
+			.map_err(|_| HttpError::bad_request(format!("invalid path parameter {name}: {value}")))
+	}
+
+	/// The first query parameter of the name, answering `400 Bad Request` if it does not parse.
+	pub fn query<T: FromStr>(&self, name: &str) -> HttpResult<Option<T>> {
+		self.query
+			.iter()
+			.find(|(key, _)| key == name)
+			.map(|(_, value)| {
+				value.parse().map_err(|_| {
+					HttpError::bad_request(format!("invalid query parameter {name}: {value}"))
+				})
+			})
+			.transpose()
+	}
+
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.headers.get(name).and_then(|x| x.to_str().ok())
+	}
+
+	/// The json body, answering `400 Bad Request` if it does not parse.
+	pub fn json<T: DeserializeOwned>(&self) -> HttpResult<T> {
+		serde_json::from_slice(&self.body)
+			.map_err(|e| HttpError::bad_request(format!("invalid json body: {e}")))
+	}
+
+	/// The session attached by `SessionAuth`.
+	pub fn session(&self) -> Option<&Session> {
+		self.extensions.get()
+	}
+}
+
+enum Segment {
+	Literal(String),
+	Param(String),
+	Rest(String),
+}
+
+/// A path pattern such as `/fees/:id` or `/files/*path`.
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+	/// Panics if a parameter has no name or `*` is not the last segment.
+	fn parse(pattern: &str) -> Self {
+		let segments = split_path(pattern)
+			.map(|x| match x.as_bytes().first() {
+				Some(b':') => Segment::Param(x[1..].to_string()),
+				Some(b'*') => Segment::Rest(x[1..].to_string()),
+				_ => Segment::Literal(x.to_string()),
+			})
+			.collect::<Vec<_>>();
+		for (i, segment) in segments.iter().enumerate() {
+			match segment {
+				Segment::Param(name) | Segment::Rest(name) if name.is_empty() => {
+					panic!("parameter without name in route {pattern}")
+				}
+				Segment::Rest(_) if i + 1 != segments.len() => {
+					panic!("* is not the last segment of route {pattern}")
+				}
+				_ => {}
+			}
+		}
+		Pattern(segments)
+	}
+
+	/// The decoded parameters if the path matches.
+	fn matches(&self, path: &str) -> Option<BTreeMap<String, String>> {
+		let mut params = BTreeMap::new();
+		let mut segments = split_path(path);
+		for pattern in &self.0 {
+			match pattern {
+				Segment::Literal(literal) => {
+					if decode_path(segments.next()?) != *literal {
+						return None;
+					}
+				}
+				Segment::Param(name) => {
+					params.insert(name.clone(), decode_path(segments.next()?));
+				}
+				Segment::Rest(name) => {
+					let rest = segments.by_ref().map(decode_path).collect::<Vec<_>>();
+					params.insert(name.clone(), rest.join("/"));
+				}
+			}
+		}
+		segments.next().is_none().then_some(params)
+	}
+
+	/// The literal segments before the first parameter.
+	fn prefix(&self) -> String {
+		self.0
+			.iter()
+			.map_while(|x| match x {
+				Segment::Literal(literal) => Some(literal.as_str()),
+				_ => None,
+			})
+			.collect::<Vec<_>>()
+			.join("/")
+	}
+}
+
+struct Route {
+	method: Method,
+	pattern: Pattern,
+	text: String,
+	middleware: Vec<Arc<dyn Middleware>>,
+	handler: Handler,
+}
+
+/// Routes http requests to handlers through a chain of middleware, see the module docs.
+#[derive(Default)]
+pub struct HttpRouter {
+	routes: Vec<Route>,
+	middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl HttpRouter {
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// Registers the handler of a route, panics if the route is registered already.
+	pub fn route<F, Fut>(mut self, method: Method, pattern: &str, handler: F) -> Self
+	where
+		F: Fn(RouteRequest) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = HttpResult> + Send + 'static,
+	{
+		self.add(Route {
+			method,
+			pattern: Pattern::parse(pattern),
+			text: pattern.to_string(),
+			middleware: Vec::new(),
+			handler: Arc::new(move |req| Box::pin(handler(req))),
+		});
+		self
+	}
+
+	pub fn get<F, Fut>(self, pattern: &str, handler: F) -> Self
+	where
+		F: Fn(RouteRequest) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = HttpResult> + Send + 'static,
+	{
+		self.route(Method::GET, pattern, handler)
+	}
+
+	pub fn post<F, Fut>(self, pattern: &str, handler: F) -> Self
+	where
+		F: Fn(RouteRequest) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = HttpResult> + Send + 'static,
+	{
+		self.route(Method::POST, pattern, handler)
+	}
+
+	pub fn put<F, Fut>(self, pattern: &str, handler: F) -> Self
+	where
+		F: Fn(RouteRequest) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = HttpResult> + Send + 'static,
+	{
+		self.route(Method::PUT, pattern, handler)
+	}
+
+	pub fn delete<F, Fut>(self, pattern: &str, handler: F) -> Self
+	where
+		F: Fn(RouteRequest) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = HttpResult> + Send + 'static,
+	{
+		self.route(Method::DELETE, pattern, handler)
+	}
+
+	/// Adds middleware around all requests of this router, including those not matching any
+	/// route. Middleware added first runs first.
+	pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+		self.middleware.push(Arc::new(middleware));
+		self
+	}
+
+	/// Adds the routes of another router, whose middleware only wraps its own routes.
+	pub fn merge(mut self, other: HttpRouter) -> Self {
+		for mut route in other.routes {
+			route
+				.middleware
+				.splice(0..0, other.middleware.iter().cloned());
+			self.add(route);
+		}
+		self
+	}
+
+	/// The path prefixes to register with the adapter, which are the literal segments of the
+	/// routes before their first parameter.
+	pub fn prefixes(&self) -> Vec<String> {
+		let mut prefixes = self
+			.routes
+			.iter()
+			.map(|x| format!("/{}", x.pattern.prefix()))
+			.collect::<Vec<_>>();
+		prefixes.sort();
+		prefixes.dedup();
+		prefixes
+	}
+
+	/// Registers the prefixes of the routes with the adapter.
+	pub async fn register(&self) -> Result<()> {
+		register_adapter_http_routes(self.prefixes()).await?;
+		Ok(())
+	}
+
+	pub async fn handle(&self, req: RouteRequest) -> HttpResponse {
+		let endpoint = |req| -> BoxFuture<'_, HttpResponse> { Box::pin(self.dispatch(req)) };
+		Next::new(&self.middleware, &endpoint).run(req).await
+	}
+
+	pub async fn handle_http(&self, req: HttpRequest) -> HttpResponse {
+		self.handle(RouteRequest::from_http(req)).await
+	}
+
+	pub async fn handle_adapter(&self, req: HttpRouteRequest) -> HttpResponse {
+		self.handle(RouteRequest::from_adapter(req)).await
+	}
+
+	fn add(&mut self, route: Route) {
+		if self
+			.routes
+			.iter()
+			.any(|x| x.method == route.method && x.text == route.text)
+		{
+			panic!("route {} {} is registered twice", route.method, route.text);
+		}
+		self.routes.push(route);
+	}
+
+	/// Answers `404 Not Found` if no route matches the path, or `405 Method Not Allowed` with
+	/// the allowed methods if no route of the path matches the method.
+	async fn dispatch(&self, mut req: RouteRequest) -> HttpResponse {
+		let mut allowed = Vec::new();
+		for route in &self.routes {
+			let Some(params) = route.pattern.matches(&req.path) else {
+				continue;
+			};
+			if route.method != req.method {
+				allowed.push(route.method.as_str());
+				continue;
+			}
+			req.params = params;
+			let endpoint = |req| -> BoxFuture<'_, HttpResponse> {
+				let rtn = (route.handler)(req);
+				Box::pin(async move { rtn.await.unwrap_or_else(HttpError::into_response) })
+			};
+			return Next::new(&route.middleware, &endpoint).run(req).await;
+		}
+
+		if allowed.is_empty() {
+			return HttpError::not_found(format!("no route for {}", req.path)).into_response();
+		}
+		let mut rtn = HttpError::new(
+			StatusCode::METHOD_NOT_ALLOWED,
+			format!("{} is not allowed for {}", req.method, req.path),
+		)
+		.into_response();
+		if let Ok(allowed) = HeaderValue::from_str(&allowed.join(", ")) {
+			rtn.headers.insert(header::ALLOW, allowed);
+		}
+		rtn
+	}
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+	path.split('/').filter(|x| !x.is_empty())
+}
+
+fn decode_path(s: &str) -> String {
+	percent_decode(s.as_bytes())
+}
+
+fn decode_query(s: &str) -> String {
+	percent_decode(s.replace('+', " ").as_bytes())
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+fn percent_decode(s: &[u8]) -> String {
+	let mut rtn = Vec::with_capacity(s.len());
+	let mut i = 0;
+	while i < s.len() {
+		let hex = s
+			.get(i + 1..i + 3)
+			.and_then(|x| std::str::from_utf8(x).ok());
+		match (s[i], hex.and_then(|x| u8::from_str_radix(x, 16).ok())) {
+			(b'%', Some(byte)) => {
+				rtn.push(byte);
+				i += 3;
+			}
+			(byte, _) => {
+				rtn.push(byte);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&rtn).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use serde::Deserialize;
+	use tea_sdk::{deserialize, serialize};
+
+	#[derive(Serialize, Deserialize)]
+	struct Fee {
+		amount: u64,
+	}
+
+	fn router() -> HttpRouter {
+		HttpRouter::new()
+			.get("/fees/:id", |req: RouteRequest| async move {
+				let id: u64 = req.param("id")?;
+				let page: u32 = req.query("page")?.unwrap_or(1);
+				ok(&(id, page))
+			})
+			.post("/fees", |req: RouteRequest| async move {
+				let fee: Fee = req.json()?;
+				created(&fee)
+			})
+			.get("/files/*path", |req: RouteRequest| async move {
+				ok(&req.param::<String>("path")?)
+			})
+	}
+
+	/// Sends the request through the adapter encoding and decodes the response it gets back.
+	pub(super) fn send(
+		router: &HttpRouter,
+		method: Method,
+		uri: &str,
+		headers: &[(&'static str, &str)],
+		body: &str,
+	) -> HttpResponse {
+		let mut request = http::Request::builder().method(method).uri(uri);
+		for (name, value) in headers {
+			request = request.header(*name, *value);
+		}
+		let request = request.body(body.as_bytes().to_vec()).unwrap();
+		let req: HttpRouteRequest = deserialize(
+			serialize(&HttpRouteRequest {
+				request: HttpRequest::try_from(request).unwrap(),
+				remote_addr: Some("10.0.0.1".into()),
+			})
+			.unwrap(),
+		)
+		.unwrap();
+		deserialize(serialize(&block_on(router.handle_adapter(req))).unwrap()).unwrap()
+	}
+
+	fn call(router: &HttpRouter, method: Method, uri: &str, body: &str) -> (StatusCode, String) {
+		let rtn = send(router, method, uri, &[], body);
+		(rtn.status, String::from_utf8(rtn.body).unwrap())
+	}
+
+	#[test]
+	fn route_requests() {
+		let router = router();
+		assert_eq!(
+			call(&router, Method::GET, "/fees/7?page=2", ""),
+			(StatusCode::OK, "[7,2]".into())
+		);
+		assert_eq!(
+			call(&router, Method::GET, "/fees/x", "").0,
+			StatusCode::BAD_REQUEST
+		);
+		assert_eq!(
+			call(&router, Method::POST, "/fees", r#"{"amount":3}"#),
+			(StatusCode::CREATED, r#"{"amount":3}"#.into())
+		);
+		assert_eq!(
+			call(&router, Method::POST, "/fees", "{").0,
+			StatusCode::BAD_REQUEST
+		);
+		assert_eq!(
+			call(&router, Method::GET, "/files/a%20b/c", ""),
+			(StatusCode::OK, r#""a b/c""#.into())
+		);
+		assert_eq!(
+			call(&router, Method::GET, "/nothing", "").0,
+			StatusCode::NOT_FOUND
+		);
+
+		let rtn = send(&router, Method::DELETE, "/fees", &[], "");
+		assert_eq!(rtn.status, StatusCode::METHOD_NOT_ALLOWED);
+		assert_eq!(rtn.headers[header::ALLOW], "POST");
+		assert_eq!(router.prefixes(), vec!["/fees", "/files"]);
+	}
+
+	#[test]
+	fn adapter_requests_keep_headers_and_peer() {
+		let router = HttpRouter::new().get("/whoami", |req: RouteRequest| async move {
+			let mut rtn = ok(&(req.header("x-address"), &req.remote_addr))?;
+			rtn.headers
+				.insert("x-request-id", HeaderValue::from_static("7"));
+			Ok(rtn)
+		});
+		let rtn = send(&router, Method::GET, "/whoami", &[("x-address", "0x1")], "");
+		assert_eq!(rtn.status, StatusCode::OK);
+		assert_eq!(rtn.headers["x-request-id"], "7");
+		assert_eq!(rtn.headers[header::CONTENT_TYPE], "application/json");
+		assert_eq!(rtn.body, br#"["0x1","10.0.0.1"]"#);
+	}
+
+	#[test]
+	#[should_panic(expected = "registered twice")]
+	fn duplicated_route() {
+		router().merge(HttpRouter::new().post("/fees", |_| async { no_content() }));
+	}
+}
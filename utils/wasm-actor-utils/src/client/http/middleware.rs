@@ -0,0 +1,452 @@
+use super::{empty, HttpError, RouteRequest};
+use crate::client::api::user::extend_auth;
+use crate::client::error::Result;
+use crate::client::help;
+use crate::enclave::actors::{env, kvp};
+use futures::future::BoxFuture;
+use http::{header, HeaderValue, Method, StatusCode};
+use std::sync::Arc;
+use tea_runtime_codec::runtime::http::HttpResponse;
+
+type Endpoint<'a> = dyn Fn(RouteRequest) -> BoxFuture<'a, HttpResponse> + Send + Sync + 'a;
+
+/// Wraps the handling of requests, e.g. to reject them or to add response headers.
+pub trait Middleware: Send + Sync {
+	/// Answers the request itself or passes it on with `next.run(req)`.
+	fn handle<'a>(&'a self, req: RouteRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse>;
+}
+
+/// The rest of the middleware chain and the handler at its end.
+pub struct Next<'a> {
+	middleware: &'a [Arc<dyn Middleware>],
+	endpoint: &'a Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+	pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>], endpoint: &'a Endpoint<'a>) -> Self {
+		Next {
+			middleware,
+			endpoint,
+		}
+	}
+
+	pub fn run(self, req: RouteRequest) -> BoxFuture<'a, HttpResponse> {
+		match self.middleware.split_first() {
+			Some((first, rest)) => first.handle(req, Next::new(rest, self.endpoint)),
+			None => (self.endpoint)(req),
+		}
+	}
+}
+
+/// The session of a request authenticated by `SessionAuth`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+	pub tapp_id_hex: String,
+	pub address: String,
+}
+
+/// Answers `401 Unauthorized` unless the request carries the session key of the address,
+/// which is extended on success at most once per `extend_interval_s`.
+///
+/// The credentials are read from the `x-tapp-id`, `x-address` and `authorization` headers,
+/// the latter optionally as `Bearer <key>`, or else from the `tappIdB64`, `address` and
+/// `authB64` fields of the json body as sent by the client actions.
+pub struct SessionAuth {
+	pub tapp_id_header: &'static str,
+	pub address_header: &'static str,
+	pub auth_header: &'static str,
+	pub extend_interval_s: i32,
+}
+
+impl Default for SessionAuth {
+	fn default() -> Self {
+		SessionAuth {
+			tapp_id_header: "x-tapp-id",
+			address_header: "x-address",
+			auth_header: header::AUTHORIZATION.as_str(),
+			extend_interval_s: 60 * 60,
+		}
+	}
+}
+
+impl SessionAuth {
+	fn credentials(&self, req: &RouteRequest) -> Option<(String, String, String)> {
+		let from_headers = || {
+			let auth = req.header(self.auth_header)?;
+			Some((
+				req.header(self.tapp_id_header)?.to_string(),
+				req.header(self.address_header)?.to_string(),
+				auth.strip_prefix("Bearer ").unwrap_or(auth).to_string(),
+			))
+		};
+		let from_body = || {
+			let body: serde_json::Value = serde_json::from_slice(&req.body).ok()?;
+			let field = |name| body.get(name)?.as_str().map(str::to_string);
+			Some((field("tappIdB64")?, field("address")?, field("authB64")?))
+		};
+		from_headers().or_else(from_body)
+	}
+
+	/// Extends the session key unless it was extended within `extend_interval_s`, so that a
+	/// client sending many requests does not rewrite its key with each of them.
+	async fn extend(&self, tapp_id_hex: &str, address: &str, auth: &str) -> Result<()> {
+		let key = format!("session_extended_{tapp_id_hex}_{address}");
+		if kvp::exists(&key).await? {
+			return Ok(());
+		}
+		extend_auth(tapp_id_hex, address, auth).await?;
+		kvp::set(&key, &true, self.extend_interval_s).await?;
+		Ok(())
+	}
+}
+
+/// Compares without returning at the first difference, so that the time taken does not tell
+/// how much of a guessed key is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Middleware for SessionAuth {
+	fn handle<'a>(&'a self, mut req: RouteRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+		Box::pin(async move {
+			let Some((tapp_id_hex, address, auth)) = self.credentials(&req) else {
+				return HttpError::unauthorized("not_login").into_response();
+			};
+			match help::get_session_key(&tapp_id_hex, &address).await {
+				Ok(key) if constant_time_eq(key.as_bytes(), auth.as_bytes()) => {}
+				_ => return HttpError::unauthorized("not_login").into_response(),
+			}
+			if let Err(e) = self.extend(&tapp_id_hex, &address, &auth).await {
+				return HttpError::from(e).into_response();
+			}
+			req.extensions.insert(Session {
+				tapp_id_hex,
+				address,
+			});
+			next.run(req).await
+		})
+	}
+}
+
+type KeyFn = Arc<dyn Fn(&RouteRequest) -> Option<String> + Send + Sync>;
+
+/// Answers `429 Too Many Requests` once a client sent more than `limit` requests within the
+/// current window of `window_s` seconds.
+///
+/// Clients are told apart by the address of their session, so `SessionAuth` should run first,
+/// or else by the address of the peer as seen by the adapter. Headers such as
+/// `x-forwarded-for` are set by the client and not trusted. Requests of clients that cannot be
+/// told apart are answered `400 Bad Request` rather than counted together. Counters are kept in
+/// the key-value actor and expire with their window.
+pub struct RateLimit {
+	name: String,
+	limit: u32,
+	window_s: u32,
+	key: KeyFn,
+}
+
+impl RateLimit {
+	/// Panics if the window is zero.
+	pub fn new(name: impl Into<String>, limit: u32, window_s: u32) -> Self {
+		assert!(window_s > 0, "rate limit window must not be zero");
+		RateLimit {
+			name: name.into(),
+			limit,
+			window_s,
+			key: Arc::new(|req| {
+				req.session()
+					.map(|x| format!("session_{}", x.address))
+					.or_else(|| req.remote_addr.as_ref().map(|x| format!("peer_{x}")))
+			}),
+		}
+	}
+
+	/// Tells clients apart by the given key instead, `None` if the client is not known.
+	pub fn key_by(
+		mut self,
+		key: impl Fn(&RouteRequest) -> Option<String> + Send + Sync + 'static,
+	) -> Self {
+		self.key = Arc::new(key);
+		self
+	}
+
+	async fn count(&self, client: &str) -> crate::enclave::error::Result<(u32, u64)> {
+		let now_s = (env::system_time_as_nanos().await? / 1_000_000_000) as u64;
+		let window = now_s / self.window_s as u64;
+		let key = format!("http_rate_{}_{client}_{window}", self.name);
+		let count = kvp::update(&key, Some(self.window_s as i32), |count: Option<u32>| {
+			let count = count.unwrap_or_default().saturating_add(1);
+			Ok((Some(count), count))
+		})
+		.await?;
+		Ok((count, (window + 1) * self.window_s as u64 - now_s))
+	}
+}
+
+impl Middleware for RateLimit {
+	fn handle<'a>(&'a self, req: RouteRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+		Box::pin(async move {
+			let Some(client) = (self.key)(&req) else {
+				return HttpError::bad_request("client cannot be told apart for rate limiting")
+					.into_response();
+			};
+			match self.count(&client).await {
+				Ok((count, _)) if count <= self.limit => next.run(req).await,
+				Ok((_, retry_after)) => {
+					let mut rtn =
+						HttpError::new(StatusCode::TOO_MANY_REQUESTS, "too many requests")
+							.into_response();
+					rtn.headers
+						.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+					rtn
+				}
+				Err(e) => HttpError::from(e).into_response(),
+			}
+		})
+	}
+}
+
+/// Answers CORS preflight requests and adds `Access-Control-Allow-Origin` to the responses
+/// of allowed origins.
+pub struct Cors {
+	origins: Option<Vec<String>>,
+	methods: Vec<Method>,
+	headers: Vec<String>,
+	max_age_s: Option<u32>,
+}
+
+impl Cors {
+	/// Allows all origins.
+	pub fn any() -> Self {
+		Cors {
+			origins: None,
+			methods: vec![Method::GET, Method::POST, Method::PUT, Method::DELETE],
+			headers: ["content-type", "authorization", "x-tapp-id", "x-address"]
+				.map(str::to_string)
+				.to_vec(),
+			max_age_s: None,
+		}
+	}
+
+	/// Allows the given origins only, e.g. `https://app.example.com`.
+	pub fn origins(origins: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		Cors {
+			origins: Some(origins.into_iter().map(Into::into).collect()),
+			..Self::any()
+		}
+	}
+
+	pub fn methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+		self.methods = methods.into_iter().collect();
+		self
+	}
+
+	pub fn headers(mut self, headers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+		self.headers = headers.into_iter().map(Into::into).collect();
+		self
+	}
+
+	pub fn max_age(mut self, max_age_s: u32) -> Self {
+		self.max_age_s = Some(max_age_s);
+		self
+	}
+
+	fn allows(&self, origin: &str) -> bool {
+		match &self.origins {
+			Some(origins) => origins.iter().any(|x| x == origin),
+			None => true,
+		}
+	}
+
+	fn preflight(&self) -> HttpResponse {
+		let mut rtn = empty(StatusCode::NO_CONTENT);
+		let methods = self
+			.methods
+			.iter()
+			.map(Method::as_str)
+			.collect::<Vec<_>>()
+			.join(", ");
+		for (name, value) in [
+			(header::ACCESS_CONTROL_ALLOW_METHODS, methods),
+			(
+				header::ACCESS_CONTROL_ALLOW_HEADERS,
+				self.headers.join(", "),
+			),
+		] {
+			if let Ok(value) = HeaderValue::from_str(&value) {
+				rtn.headers.insert(name, value);
+			}
+		}
+		if let Some(max_age_s) = self.max_age_s {
+			rtn.headers
+				.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age_s));
+		}
+		rtn
+	}
+}
+
+impl Middleware for Cors {
+	fn handle<'a>(&'a self, req: RouteRequest, next: Next<'a>) -> BoxFuture<'a, HttpResponse> {
+		Box::pin(async move {
+			let Some(origin) = req.header(header::ORIGIN.as_str()).map(str::to_string) else {
+				return next.run(req).await;
+			};
+			let preflight = req.method == Method::OPTIONS
+				&& req
+					.headers
+					.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+			if !self.allows(&origin) {
+				if preflight {
+					return HttpError::forbidden(format!("origin {origin} is not allowed"))
+						.into_response();
+				}
+				return next.run(req).await;
+			}
+
+			let mut rtn = if preflight {
+				self.preflight()
+			} else {
+				next.run(req).await
+			};
+			let allowed = match self.origins {
+				Some(_) => HeaderValue::from_str(&origin).ok(),
+				None => Some(HeaderValue::from_static("*")),
+			};
+			if let Some(allowed) = allowed {
+				rtn.headers
+					.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+			}
+			if self.origins.is_some() {
+				rtn.headers
+					.append(header::VARY, HeaderValue::from_static("Origin"));
+			}
+			rtn
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::client::http::{no_content, ok, tests::send, HttpRouter};
+	use http::HeaderMap;
+
+	#[test]
+	fn cors() {
+		let router = HttpRouter::new()
+			.post("/fees", |_| async { no_content() })
+			.with(Cors::origins(["https://a.com"]).max_age(60));
+		let preflight = |origin| {
+			send(
+				&router,
+				Method::OPTIONS,
+				"/fees",
+				&[
+					("origin", origin),
+					("access-control-request-method", "POST"),
+				],
+				"",
+			)
+		};
+		let post = |origin| send(&router, Method::POST, "/fees", &[("origin", origin)], "");
+
+		let rtn = preflight("https://a.com");
+		assert_eq!(rtn.status, StatusCode::NO_CONTENT);
+		assert_eq!(
+			rtn.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+			"https://a.com"
+		);
+		assert_eq!(rtn.headers[header::ACCESS_CONTROL_MAX_AGE], "60");
+
+		let rtn = post("https://a.com");
+		assert_eq!(rtn.status, StatusCode::NO_CONTENT);
+		assert_eq!(rtn.headers[header::VARY], "Origin");
+
+		assert_eq!(preflight("https://b.com").status, StatusCode::FORBIDDEN);
+		assert!(!post("https://b.com")
+			.headers
+			.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+	}
+
+	#[test]
+	fn session_from_headers() {
+		let router = HttpRouter::new().get("/me", |req: RouteRequest| async move {
+			ok(&SessionAuth::default().credentials(&req))
+		});
+		let rtn = send(
+			&router,
+			Method::GET,
+			"/me",
+			&[
+				("x-tapp-id", "t"),
+				("x-address", "0x1"),
+				("authorization", "Bearer k"),
+			],
+			"",
+		);
+		assert_eq!(rtn.body, br#"["t","0x1","k"]"#);
+	}
+
+	#[test]
+	fn rate_limit_keys() {
+		let limit = RateLimit::new("fees", 1, 60);
+		let mut req = RouteRequest::new(Method::GET, "/fees", HeaderMap::new(), Vec::new());
+		req.headers
+			.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.2"));
+		assert_eq!((limit.key)(&req), None);
+
+		req.remote_addr = Some("10.0.0.1".into());
+		assert_eq!((limit.key)(&req), Some("peer_10.0.0.1".into()));
+
+		req.extensions.insert(Session {
+			tapp_id_hex: "t".into(),
+			address: "0x1".into(),
+		});
+		assert_eq!((limit.key)(&req), Some("session_0x1".into()));
+	}
+
+	#[test]
+	fn keys_compare_whole() {
+		assert!(constant_time_eq(b"key", b"key"));
+		assert!(!constant_time_eq(b"key", b"kez"));
+		assert!(!constant_time_eq(b"key", b"ke"));
+		assert!(constant_time_eq(b"", b""));
+	}
+
+	#[cfg(feature = "native")]
+	#[tokio::test]
+	async fn sessions_are_extended_once_per_interval() {
+		use crate::enclave::native::keyvalue::KeyValueActor;
+		use tea_actorx::{ActorExt, WithActorHost};
+
+		async {
+			KeyValueActor::default().register().await?;
+			let auth = SessionAuth::default();
+			auth.extend("t", "0x1", "k").await?;
+			assert_eq!(help::get_session_key("t", "0x1").await?, "k");
+
+			kvp::del("session_key_t_0x1").await?;
+			auth.extend("t", "0x1", "k").await?;
+			assert!(help::get_session_key("t", "0x1").await.is_err());
+			Ok::<_, crate::client::error::Error>(())
+		}
+		.with_actor_host()
+		.await
+		.unwrap();
+	}
+
+	#[test]
+	fn session_from_body() {
+		let auth = SessionAuth::default();
+		let req = RouteRequest::new(
+			Method::POST,
+			"/fees",
+			HeaderMap::new(),
+			br#"{"tappIdB64":"t","address":"0x1","authB64":"k"}"#.to_vec(),
+		);
+		assert_eq!(
+			auth.credentials(&req),
+			Some(("t".into(), "0x1".into(), "k".into()))
+		);
+	}
+}
@@ -1,8 +1,11 @@
 pub mod api;
 mod error;
 pub mod help;
+pub mod http;
+pub mod push;
 mod query_cb;
 pub mod request;
+pub mod router;
 pub mod txn_cache;
 pub mod types;
 pub mod utility;
@@ -0,0 +1,680 @@
+//! Push of txn status transitions, balance changes and txn events to frontends over socket.io,
+//! so that they do not have to poll `queryHashResult` and `query_result`.
+//!
+//! A frontend sends the `subscribe` action with a topic over its socket, whose id the adapter
+//! passes along. Every message published to the topic is then emitted to the socket as a
+//! `push` event with the json of `PushMessage`. The messages of a topic are numbered by `seq`,
+//! and the latest ones are kept, so a frontend that reconnects subscribes again with the last
+//! `seq` it has seen to receive those it missed. A message published while subscribing may
+//! arrive both ways, and should be dropped by its `seq`.
+//!
+//! Subscriptions expire an hour after they were made or last published to, so a frontend
+//! waiting on a quiet topic for longer subscribes again.
+//!
+//! Balances are pushed whenever they are queried, and once a txn of the account is executed,
+//! in the tokens its balance was queried in before.
+
+use crate::client::api::{state::fetch_tea_balance, user::check_auth};
+use crate::client::error::{Errors, Result};
+use crate::client::help::ActionOk;
+use crate::client::txn_cache::{get_item_by_hash, TxnCacheItem, TxnTrackStatus};
+use crate::enclave::actors::adapter::{register_adapter_socketio_dispatcher, socketio_emit};
+use crate::enclave::actors::{env::system_time_as_nanos, kvp};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, VecDeque};
+use tea_runtime_codec::tapp::{Account, TokenId};
+use tea_sdk::serde::TypeId;
+
+/// The socket.io event the messages are emitted as.
+pub const PUSH_EVENT: &str = "push";
+/// The socket.io actions to register with the adapter, see `register_socketio`.
+pub const SOCKETIO_ACTIONS: [&str; 2] = ["subscribe", "unsubscribe"];
+
+const PUSH_KEY: &str = "push";
+const LOG_TTL_S: i32 = 60 * 60 * 24;
+const SUBSCRIPTION_TTL_S: i32 = 60 * 60;
+const MAX_LOG_LEN: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Topic {
+	/// The status transitions and the receipt of the txn with the hash in hex.
+	Txn(String),
+	/// The status transitions of the txns sent by the account and its balance changes.
+	Account(String),
+	/// The events of the type in the receipts of txns.
+	Event(String),
+}
+
+impl Topic {
+	pub fn txn(hash_hex: &str) -> Result<Self> {
+		let hash = hash_hex.trim_start_matches("0x").to_lowercase();
+		match hex::decode(&hash) {
+			Ok(bytes) if bytes.len() == 32 => Ok(Topic::Txn(hash)),
+			_ => Err(Errors::InvalidPushTopic(format!("txn hash {hash_hex}"))),
+		}
+	}
+
+	pub fn account(account: &Account) -> Self {
+		Topic::Account(format!("{account:?}"))
+	}
+
+	/// The topic in the form it is published to, e.g. with the txn hash in lower case.
+	pub fn normalize(self) -> Result<Self> {
+		match self {
+			Topic::Txn(hash) => Self::txn(&hash),
+			Topic::Account(address) => address
+				.parse::<Account>()
+				.map(|x| Self::account(&x))
+				.map_err(|_| Errors::InvalidPushTopic(format!("address {address}"))),
+			Topic::Event(event_type) if event_type.is_empty() => {
+				Err(Errors::InvalidPushTopic("empty event type".into()))
+			}
+			topic => Ok(topic),
+		}
+	}
+
+	fn key(&self) -> String {
+		match self {
+			Topic::Txn(hash) => format!("txn_{hash}"),
+			Topic::Account(address) => format!("account_{address}"),
+			Topic::Event(event_type) => format!("event_{event_type}"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushMessage {
+	pub topic: Topic,
+	/// Counts the messages of the topic from 1, unless its log expired in between.
+	pub seq: u64,
+	/// The time of publishing in nanoseconds.
+	pub ts: String,
+	pub data: Value,
+}
+
+/// The latest messages of a topic.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct TopicLog {
+	last_seq: u64,
+	messages: VecDeque<PushMessage>,
+}
+
+impl TopicLog {
+	/// The log is stored as json, since bincode can not decode the json data of its messages.
+	/// A log that can not be read is started anew.
+	fn decode(json: Option<String>) -> Self {
+		json.and_then(|x| serde_json::from_str(&x).ok())
+			.unwrap_or_default()
+	}
+
+	fn encode(&self) -> serde_json::Result<String> {
+		serde_json::to_string(self)
+	}
+
+	fn append(&mut self, topic: &Topic, ts: u128, data: Value) -> PushMessage {
+		self.last_seq += 1;
+		let message = PushMessage {
+			topic: topic.clone(),
+			seq: self.last_seq,
+			ts: ts.to_string(),
+			data,
+		};
+		self.messages.push_back(message.clone());
+		if self.messages.len() > MAX_LOG_LEN {
+			self.messages.pop_front();
+		}
+		message
+	}
+
+	/// The kept messages after `last_seq`, and whether any before them were dropped already.
+	/// All are returned if `last_seq` is ahead of the log, which then expired meanwhile.
+	fn since(&self, last_seq: u64) -> (Vec<PushMessage>, bool) {
+		if last_seq > self.last_seq {
+			return (self.messages.iter().cloned().collect(), true);
+		}
+		let first = self.messages.front().map_or(self.last_seq + 1, |x| x.seq);
+		let missed = self
+			.messages
+			.iter()
+			.filter(|x| x.seq > last_seq)
+			.cloned()
+			.collect();
+		(missed, first > last_seq + 1)
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, TypeId)]
+#[serde(rename_all = "camelCase")]
+#[response(SubscribeResponse)]
+pub struct SubscribeRequest {
+	pub topic: Topic,
+	/// The `seq` of the last message seen before reconnecting, to receive the missed ones.
+	pub last_seq: Option<u64>,
+	/// The session of the account of a `Topic::Account`, or of the sender of a `Topic::Txn`.
+	pub tapp_id_b64: Option<String>,
+	pub auth_b64: Option<String>,
+	/// The sender of the txn of a `Topic::Txn`.
+	pub address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, TypeId)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeResponse {
+	/// The `seq` of the latest message of the topic.
+	pub seq: u64,
+	/// The kept messages after `last_seq`.
+	pub missed: Vec<PushMessage>,
+	/// Whether messages after `last_seq` are no longer kept, so the state has to be queried.
+	pub gap: bool,
+}
+
+/// Subscribes the socket of the id to the topic.
+pub async fn subscribe(req: SubscribeRequest, socket_id: String) -> Result<SubscribeResponse> {
+	let topic = req.topic.clone().normalize()?;
+	authorize(&topic, &req).await?;
+
+	update_set(&subscribers_key(&topic), |x| x.insert(socket_id.clone())).await?;
+	update_set(&socket_key(&socket_id), |x| x.insert(topic.clone())).await?;
+
+	let log = TopicLog::decode(kvp::get(&log_key(&topic)).await?);
+	let (missed, gap) = match req.last_seq {
+		Some(last_seq) => log.since(last_seq),
+		None => (Vec::new(), false),
+	};
+	Ok(SubscribeResponse {
+		seq: log.last_seq,
+		missed,
+		gap,
+	})
+}
+
+/// Only the account itself may read a `Topic::Account`, and only the sender of the txn a
+/// `Topic::Txn`, whose status carries the arguments of the txn.
+async fn authorize(topic: &Topic, req: &SubscribeRequest) -> Result<()> {
+	let address = match topic {
+		Topic::Account(address) => address.clone(),
+		Topic::Txn(hash) => {
+			let address = req.address.clone().unwrap_or_default();
+			let sender = get_item_by_hash(hash)
+				.await?
+				.map(|x| Topic::account(&x.sender));
+			let account = address.parse::<Account>().ok().map(|x| Topic::account(&x));
+			if sender.is_none() || sender != account {
+				return Err(Errors::PushTopicForbidden(format!("txn {hash}"), address));
+			}
+			address
+		}
+		Topic::Event(_) => return Ok(()),
+	};
+	check_auth(
+		req.tapp_id_b64.as_deref().unwrap_or_default(),
+		&address,
+		req.auth_b64.as_deref().unwrap_or_default(),
+	)
+	.await?;
+	Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, TypeId)]
+#[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
+pub struct UnsubscribeRequest {
+	/// All topics of the socket are unsubscribed if not given, e.g. once it disconnected.
+	pub topic: Option<Topic>,
+}
+
+/// Unsubscribes the socket of the id from the topic, or from all of its topics.
+pub async fn unsubscribe(req: UnsubscribeRequest, socket_id: String) -> Result<ActionOk> {
+	let topics = match req.topic {
+		Some(topic) => vec![topic.normalize()?],
+		None => kvp::get::<BTreeSet<Topic>>(&socket_key(&socket_id))
+			.await?
+			.unwrap_or_default()
+			.into_iter()
+			.collect(),
+	};
+	for topic in topics {
+		remove_subscriber(&topic, &socket_id).await?;
+	}
+	Ok(ActionOk::default())
+}
+
+/// Handles the json request of one of the `SOCKETIO_ACTIONS` received from the socket of the
+/// id, which is taken from the adapter rather than from the request.
+pub async fn handle_socketio(action: &str, payload: &[u8], socket_id: String) -> Result<Vec<u8>> {
+	match action {
+		"subscribe" => {
+			let rtn = subscribe(serde_json::from_slice(payload)?, socket_id).await?;
+			Ok(serde_json::to_vec(&rtn)?)
+		}
+		"unsubscribe" => {
+			let rtn = unsubscribe(serde_json::from_slice(payload)?, socket_id).await?;
+			Ok(serde_json::to_vec(&rtn)?)
+		}
+		_ => Err(Errors::UnknownAction(action.to_string())),
+	}
+}
+
+/// Registers `SOCKETIO_ACTIONS` with the adapter, which client actors pass on to
+/// `types::map_socketio_handler`.
+pub async fn register_socketio() -> Result<()> {
+	register_adapter_socketio_dispatcher(SOCKETIO_ACTIONS.map(Into::into).to_vec()).await?;
+	Ok(())
+}
+
+/// Appends the data to the log of the topic and emits it to the subscribers, where sockets
+/// that can not be reached are unsubscribed and the others have their subscription extended.
+/// Failing to update the subscription of a socket is logged, and the others still get the data.
+pub async fn publish(topic: Topic, data: Value) -> Result<PushMessage> {
+	let ts = system_time_as_nanos().await?;
+	let message = kvp::update(&log_key(&topic), Some(LOG_TTL_S), |log| {
+		let mut log = TopicLog::decode(log);
+		let message = log.append(&topic, ts, data.clone());
+		let json = log
+			.encode()
+			.map_err(|e| crate::enclave::error::Error::Unnamed(e.to_string()))?;
+		Ok((Some(json), message))
+	})
+	.await?;
+
+	let payload = serde_json::to_vec(&message)?;
+	let subscribers: BTreeSet<String> = kvp::get(&subscribers_key(&topic))
+		.await?
+		.unwrap_or_default();
+	for socket_id in subscribers {
+		let updated = match socketio_emit(&socket_id, PUSH_EVENT, payload.clone()).await {
+			Err(e) => {
+				warn!("push to socket {socket_id} failed, unsubscribing it: {e}");
+				remove_subscriber(&topic, &socket_id).await
+			}
+			Ok(_) => update_set(&socket_key(&socket_id), |_: &mut BTreeSet<Topic>| false).await,
+		};
+		if let Err(e) = updated {
+			warn!("subscription of socket {socket_id} to {topic:?} is not updated: {e}");
+		}
+	}
+	update_set(&subscribers_key(&topic), |_: &mut BTreeSet<String>| false).await?;
+	Ok(message)
+}
+
+/// Publishes like `publish`, but only logs failures, since pushing is secondary to the
+/// actions observing the changes.
+pub async fn notify(topic: Topic, data: Value) {
+	if let Err(e) = publish(topic.clone(), data).await {
+		warn!("publish to push topic {topic:?} failed: {e}");
+	}
+}
+
+/// Pushes the status of the cached txn to its hash and its sender, and the balances of the
+/// sender once the txn is executed.
+pub async fn notify_txn_status(item: &TxnCacheItem) {
+	let data = json!({ "kind": "txnStatus", "txn": item.to_json() });
+	if let Some(hash) = item.hash_hex.as_deref().map(Topic::txn) {
+		match hash {
+			Ok(topic) => notify(topic, data.clone()).await,
+			Err(e) => warn!("txn cache item {} has {e}", item.time),
+		}
+	}
+	notify(Topic::account(&item.sender), data).await;
+	if matches!(
+		item.status,
+		TxnTrackStatus::Executed | TxnTrackStatus::Failed
+	) {
+		notify_balances(&item.sender).await;
+	}
+}
+
+/// Pushes the balances of the account in the tokens it was queried in, if anyone subscribed
+/// to the account. Balances are read from the local state, and not pushed where it is missing.
+pub async fn notify_balances(account: &Account) {
+	let topic = Topic::account(account);
+	let subscribed = kvp::exists(&subscribers_key(&topic)).await;
+	if !matches!(subscribed, Ok(true)) {
+		return;
+	}
+	let tokens: BTreeSet<String> = match kvp::get(&balance_tokens_key(&topic)).await {
+		Ok(tokens) => tokens.unwrap_or_default(),
+		Err(e) => {
+			warn!("balances of {account:?} are not pushed: {e}");
+			return;
+		}
+	};
+	for token_id_hex in tokens {
+		let balance = match TokenId::from_hex(&token_id_hex) {
+			Ok(token_id) => fetch_tea_balance(token_id, *account).await,
+			Err(e) => Err(e.into()),
+		};
+		match balance {
+			Ok((ts, balance)) => {
+				let address = format!("{account:?}");
+				notify_balance(
+					&address,
+					&token_id_hex,
+					&balance.to_string(),
+					&ts.to_string(),
+				)
+				.await
+			}
+			Err(e) => warn!("balance of {account:?} in {token_id_hex} is not pushed: {e}"),
+		}
+	}
+}
+
+/// Pushes the balance of the account to it if it changed since it was observed last.
+pub async fn notify_balance(address: &str, token_id_hex: &str, balance: &str, ts: &str) {
+	let topic = match Topic::Account(address.to_string()).normalize() {
+		Ok(topic) => topic,
+		Err(e) => {
+			warn!("balance of {address} is not pushed: {e}");
+			return;
+		}
+	};
+	let tokens = kvp::update(
+		&balance_tokens_key(&topic),
+		Some(LOG_TTL_S),
+		|tokens: Option<BTreeSet<String>>| {
+			let mut tokens = tokens.unwrap_or_default();
+			tokens.insert(token_id_hex.to_string());
+			Ok((Some(tokens), ()))
+		},
+	)
+	.await;
+	if let Err(e) = tokens {
+		warn!("token {token_id_hex} of {address} is not recorded for balance pushes: {e}");
+	}
+	let key = format!("_{PUSH_KEY}_balance_{}_{token_id_hex}", topic.key());
+	let changed = kvp::update(&key, Some(LOG_TTL_S), |last: Option<String>| {
+		Ok((Some(balance.to_string()), last.as_deref() != Some(balance)))
+	})
+	.await;
+	match changed {
+		Ok(true) => {
+			let data = json!({
+				"kind": "balance",
+				"tokenId": token_id_hex,
+				"balance": balance,
+				"ts": ts,
+			});
+			notify(topic, data).await
+		}
+		Ok(false) => {}
+		Err(e) => warn!("balance of {address} is not pushed: {e}"),
+	}
+}
+
+/// Pushes the receipt of the txn to its hash, and its events to their types. Each is pushed
+/// once only, however often the receipt is queried.
+pub async fn notify_receipt(hash_hex: &str, receipt: &Value) {
+	let topic = match Topic::txn(hash_hex) {
+		Ok(topic) => topic,
+		Err(e) => {
+			warn!("receipt of {hash_hex} is not pushed: {e}");
+			return;
+		}
+	};
+	let events = receipt["events"].as_array().cloned().unwrap_or_default();
+	let event_types = events
+		.iter()
+		.filter_map(|x| x["eventType"].as_str().map(str::to_string))
+		.collect::<BTreeSet<_>>();
+
+	// the receipt itself is marked as published by the empty event type
+	let key = format!("_{PUSH_KEY}_receipt_{}", topic.key());
+	let published = kvp::update(&key, Some(LOG_TTL_S), |published| {
+		let mut published: BTreeSet<String> = published.unwrap_or_default();
+		let new = event_types
+			.iter()
+			.chain([&String::new()])
+			.filter(|x| !published.contains(*x))
+			.cloned()
+			.collect::<BTreeSet<_>>();
+		published.extend(new.iter().cloned());
+		Ok((Some(published), new))
+	})
+	.await;
+	let new = match published {
+		Ok(new) => new,
+		Err(e) => {
+			warn!("receipt of {hash_hex} is not pushed: {e}");
+			return;
+		}
+	};
+
+	if new.contains("") {
+		notify(
+			topic.clone(),
+			json!({ "kind": "receipt", "receipt": receipt }),
+		)
+		.await;
+	}
+	for event in events {
+		let Some(event_type) = event["eventType"].as_str() else {
+			continue;
+		};
+		if new.contains(event_type) {
+			let data = json!({ "kind": "event", "txn": hash_hex, "event": event });
+			notify(Topic::Event(event_type.to_string()), data).await;
+		}
+	}
+}
+
+async fn remove_subscriber(topic: &Topic, socket_id: &str) -> Result<()> {
+	update_set(&subscribers_key(topic), |x: &mut BTreeSet<String>| {
+		x.remove(socket_id)
+	})
+	.await?;
+	update_set(&socket_key(socket_id), |x: &mut BTreeSet<Topic>| {
+		x.remove(topic)
+	})
+	.await?;
+	Ok(())
+}
+
+/// Changes the set of the key, which is removed once empty. Every update extends its ttl,
+/// even one that leaves the set as it is.
+async fn update_set<T>(key: &str, mut f: impl FnMut(&mut BTreeSet<T>) -> bool) -> Result<()>
+where
+	T: Ord + Serialize + serde::de::DeserializeOwned,
+{
+	kvp::update(key, Some(SUBSCRIPTION_TTL_S), |set| {
+		let mut set: BTreeSet<T> = set.unwrap_or_default();
+		f(&mut set);
+		Ok(((!set.is_empty()).then_some(set), ()))
+	})
+	.await?;
+	Ok(())
+}
+
+fn log_key(topic: &Topic) -> String {
+	format!("_{PUSH_KEY}_log_{}", topic.key())
+}
+
+fn subscribers_key(topic: &Topic) -> String {
+	format!("_{PUSH_KEY}_subscribers_{}", topic.key())
+}
+
+/// The tokens of which the balances of the account are pushed.
+fn balance_tokens_key(topic: &Topic) -> String {
+	format!("_{PUSH_KEY}_balance_tokens_{}", topic.key())
+}
+
+fn socket_key(socket_id: &str) -> String {
+	format!("_{PUSH_KEY}_socket_{socket_id}")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalize_topics() {
+		let hash = "AB".repeat(32);
+		assert_eq!(
+			Topic::Txn(format!("0x{hash}")).normalize().unwrap(),
+			Topic::Txn("ab".repeat(32))
+		);
+		assert!(Topic::txn("abcd").is_err());
+		assert!(Topic::Account("alice".into()).normalize().is_err());
+		assert!(Topic::Event(String::new()).normalize().is_err());
+
+		let topic: Topic = serde_json::from_str(r#"{"event":"Transfer"}"#).unwrap();
+		assert_eq!(topic, Topic::Event("Transfer".into()));
+	}
+
+	#[test]
+	fn resume_from_log() {
+		let topic = Topic::Event("Transfer".into());
+		let mut log = TopicLog::default();
+		for i in 0..MAX_LOG_LEN + 5 {
+			log.append(&topic, i as u128, json!(i));
+		}
+		assert_eq!(log.messages.len(), MAX_LOG_LEN);
+		assert_eq!(log.last_seq, MAX_LOG_LEN as u64 + 5);
+
+		let (missed, gap) = log.since(log.last_seq - 2);
+		assert_eq!(missed.iter().map(|x| x.seq).collect::<Vec<_>>(), [104, 105]);
+		assert!(!gap);
+
+		let (missed, gap) = log.since(log.last_seq);
+		assert!(missed.is_empty() && !gap);
+
+		let (missed, gap) = log.since(5);
+		assert_eq!(missed.len(), MAX_LOG_LEN);
+		assert!(!gap);
+		let (_, gap) = log.since(4);
+		assert!(gap);
+
+		let (missed, gap) = log.since(1000);
+		assert_eq!(missed.len(), MAX_LOG_LEN);
+		assert!(gap);
+	}
+
+	#[cfg(feature = "native")]
+	mod sockets {
+		use super::*;
+		use crate::client::help::save_session_key;
+		use crate::client::txn_cache::{add_to_txn_cache, set_item_tsid};
+		use crate::enclave::native::keyvalue::KeyValueActor;
+		use std::sync::{Arc, Mutex};
+		use std::time::{Duration, SystemTime};
+		use tea_actorx::{ActorExt, ActorId, HandlerActor, WithActorHost};
+		use tea_runtime_codec::actor_txns::tsid::Tsid;
+		use tea_sdk::serde::handle::handles;
+		use tea_system_actors::adapter::{self, SocketioEmitRequest};
+		use tea_system_actors::env::{self, GetSystemTimeRequest, GetSystemTimeResponse};
+
+		struct Clock;
+
+		impl HandlerActor for Clock {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(env::NAME))
+			}
+		}
+
+		#[handles]
+		impl Clock {
+			async fn handle(&self, _: GetSystemTimeRequest) -> tea_sdk::Result<_> {
+				Ok(GetSystemTimeResponse(
+					SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+				))
+			}
+		}
+
+		/// A `tea:adapter` actor recording the rooms emitted to.
+		#[derive(Clone, Default)]
+		struct Adapter(Arc<Mutex<Vec<String>>>);
+
+		impl HandlerActor for Adapter {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(adapter::NAME))
+			}
+		}
+
+		#[handles]
+		impl Adapter {
+			async fn handle(&self, req: SocketioEmitRequest) -> tea_sdk::Result<_> {
+				self.0.lock().unwrap().push(req.room);
+				Ok(())
+			}
+		}
+
+		async fn run(test: impl std::future::Future<Output = Result<Adapter>>) -> Vec<String> {
+			async {
+				KeyValueActor::default().register().await?;
+				Clock.register().await?;
+				let adapter = test.await?;
+				let rooms = adapter.0.lock().unwrap().clone();
+				Ok::<_, Errors>(rooms)
+			}
+			.with_actor_host()
+			.await
+			.unwrap()
+		}
+
+		async fn send(action: &str, payload: Value, socket_id: &str) -> Result<Value> {
+			let payload = serde_json::to_vec(&payload)?;
+			let rtn = handle_socketio(action, &payload, socket_id.to_string()).await?;
+			Ok(serde_json::from_slice(&rtn)?)
+		}
+
+		#[tokio::test]
+		async fn sockets_come_from_the_adapter() {
+			let rooms = run(async {
+				let adapter = Adapter::default();
+				adapter.clone().register().await?;
+				let topic = json!({ "event": "Transfer" });
+				send(
+					"subscribe",
+					json!({ "topic": topic, "socketId": "s2" }),
+					"s1",
+				)
+				.await?;
+				publish(Topic::Event("Transfer".into()), json!(1)).await?;
+				send("unsubscribe", json!({}), "s2").await?;
+				publish(Topic::Event("Transfer".into()), json!(2)).await?;
+				send("unsubscribe", json!({}), "s1").await?;
+				publish(Topic::Event("Transfer".into()), json!(3)).await?;
+				Ok(adapter)
+			})
+			.await;
+			assert_eq!(rooms, ["s1", "s1"]);
+		}
+
+		#[tokio::test]
+		async fn txn_topics_are_read_by_their_sender_only() {
+			let rooms = run(async {
+				let adapter = Adapter::default();
+				adapter.clone().register().await?;
+				let sender = format!("{:?}", Account::repeat_byte(1));
+				let item = add_to_txn_cache("a", vec![], &sender, "actor").await?;
+				let mut tsid = Tsid::default();
+				tsid.hash = [7; 32];
+				set_item_tsid(&item, tsid).await?;
+				save_session_key("key".into(), "tapp", &sender).await?;
+
+				let subscribe = |address: &str, auth: &str| {
+					let req = json!({
+						"topic": { "txn": hex::encode([7; 32]) },
+						"address": address,
+						"tappIdB64": "tapp",
+						"authB64": auth,
+					});
+					async move { send("subscribe", req, "s1").await }
+				};
+				let other = format!("{:?}", Account::repeat_byte(2));
+				assert!(matches!(
+					subscribe(&other, "key").await,
+					Err(Errors::PushTopicForbidden(..))
+				));
+				assert!(subscribe(&sender, "wrong").await.is_err());
+				subscribe(&sender, "key").await?;
+				Ok(adapter)
+			})
+			.await;
+			assert!(rooms.is_empty());
+		}
+	}
+}
@@ -0,0 +1,311 @@
+//! Dispatch of client actions to handlers with typed requests and responses.
+//!
+//! Every action is registered with its request type, whose `Request::Response` is the
+//! response type. Requests are decoded from json before the handler is called, and the
+//! response is encoded to json after it returns. The router also exports a catalog of its
+//! actions with the json schema of each request and response, generated from the
+//! descriptors of `#[derive(TypeId)]`. Described types nested in them are defined once
+//! under `$defs` and referred to with `$ref`.
+
+use crate::client::error::{Errors, Result};
+use futures::Future;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use tea_sdk::serde::{
+	handle::Request,
+	registry::{Describe, Reference, Schema, TypeDescriptor, TypeRegistry},
+};
+
+type ActionFuture = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send>>;
+type ActionHandler = Box<dyn Fn(Vec<u8>, String) -> ActionFuture + Send + Sync>;
+
+/// An action as listed in the catalog of an `ActionRouter`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionDescriptor {
+	pub name: String,
+	pub request: TypeDescriptor,
+	pub response: TypeDescriptor,
+	pub request_schema: Value,
+	pub response_schema: Value,
+}
+
+#[derive(Default)]
+pub struct ActionRouter {
+	actions: BTreeMap<&'static str, (ActionDescriptor, ActionHandler)>,
+}
+
+impl ActionRouter {
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// Registers the handler of an action, panics if the action is registered already.
+	pub fn action<Req, F, Fut>(mut self, name: &'static str, handler: F) -> Self
+	where
+		Req: Request + Describe + DeserializeOwned,
+		Req::Response: Describe + Serialize,
+		F: Fn(Req, String) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = Result<Req::Response>> + Send + 'static,
+	{
+		let (request, response) = (Req::descriptor(), Req::Response::descriptor());
+		let mut registry = TypeRegistry::new();
+		registry.describe::<Req>().describe::<Req::Response>();
+		let descriptor = ActionDescriptor {
+			name: name.to_string(),
+			request_schema: json_schema(&request, &registry),
+			response_schema: json_schema(&response, &registry),
+			request,
+			response,
+		};
+		let handler: ActionHandler = Box::new(move |arg, from_actor| {
+			let rtn = serde_json::from_slice(&arg).map(|req| handler(req, from_actor));
+			Box::pin(async move { Ok(serde_json::to_vec(&rtn?.await?)?) })
+		});
+		if self.actions.insert(name, (descriptor, handler)).is_some() {
+			panic!("action {name} is registered twice");
+		}
+		self
+	}
+
+	/// Decodes the json request of the action, calls its handler and encodes the json response.
+	pub async fn dispatch(
+		&self,
+		action: &str,
+		arg: Vec<u8>,
+		from_actor: String,
+	) -> Result<Vec<u8>> {
+		let (_, handler) = self
+			.actions
+			.get(action)
+			.ok_or_else(|| Errors::UnknownAction(action.to_string()))?;
+		handler(arg, from_actor).await
+	}
+
+	/// Names of all actions in alphabetical order.
+	pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+		self.actions.keys().copied()
+	}
+
+	/// Descriptors of all actions in alphabetical order.
+	pub fn catalog(&self) -> impl Iterator<Item = &ActionDescriptor> {
+		self.actions.values().map(|(descriptor, _)| descriptor)
+	}
+
+	/// Exports the catalog as a JSON array, e.g. to generate client SDKs from it.
+	pub fn catalog_json(&self) -> Result<String> {
+		Ok(serde_json::to_string_pretty(
+			&self.catalog().collect::<Vec<_>>(),
+		)?)
+	}
+}
+
+/// The json schema of a described type as encoded by `serde_json`. The described types nested
+/// in it are taken from `registry` and defined under `$defs` by their type id.
+pub fn json_schema(descriptor: &TypeDescriptor, registry: &TypeRegistry) -> Value {
+	let mut schema = schema_of(&descriptor.schema);
+	let mut defs = Map::new();
+	let mut pending = type_ids_of(&descriptor.schema);
+	while let Some(type_id) = pending.pop() {
+		if defs.contains_key(type_id) {
+			continue;
+		}
+		let Some(nested) = registry.get(type_id) else {
+			continue;
+		};
+		defs.insert(type_id.to_string(), schema_of(&nested.schema));
+		pending.extend(type_ids_of(&nested.schema));
+	}
+	if !defs.is_empty() {
+		schema["$defs"] = Value::Object(defs);
+	}
+	schema
+}
+
+fn schema_of(schema: &Schema) -> Value {
+	match schema {
+		Schema::Unit => json!({ "type": "null" }),
+		// newtypes are encoded as their content
+		Schema::Tuple(fields) if fields.len() == 1 => type_schema(&fields[0].ty, &fields[0].refs),
+		Schema::Tuple(fields) => json!({
+			"type": "array",
+			"prefixItems": fields.iter().map(|x| type_schema(&x.ty, &x.refs)).collect::<Vec<_>>(),
+			"items": false,
+		}),
+		Schema::Struct(fields) => {
+			let mut properties = Map::new();
+			let mut required = Vec::new();
+			for field in fields {
+				if !field.optional && option_content(&field.ty).is_none() {
+					required.push(field.json_name().to_string());
+				}
+				properties.insert(
+					field.json_name().to_string(),
+					type_schema(&field.ty, &field.refs),
+				);
+			}
+			json!({
+				"type": "object",
+				"properties": properties,
+				"required": required,
+			})
+		}
+		Schema::Enum(variants) => {
+			let variants = variants
+				.iter()
+				.filter(|x| !x.skipped)
+				.map(|x| match &x.schema {
+					Schema::Unit => json!({ "const": x.json_name() }),
+					schema => json!({
+						"type": "object",
+						"properties": { x.json_name(): schema_of(schema) },
+						"required": [x.json_name()],
+					}),
+				})
+				.collect::<Vec<_>>();
+			json!({ "oneOf": variants })
+		}
+	}
+}
+
+/// Type ids of the described types named in the fields of the schema.
+fn type_ids_of(schema: &Schema) -> Vec<&str> {
+	match schema {
+		Schema::Unit => vec![],
+		Schema::Tuple(fields) | Schema::Struct(fields) => fields
+			.iter()
+			.flat_map(|x| &x.refs)
+			.map(|x| &*x.type_id)
+			.collect(),
+		Schema::Enum(variants) => variants
+			.iter()
+			.flat_map(|x| type_ids_of(&x.schema))
+			.collect(),
+	}
+}
+
+/// The json schema of a type named as in `Field::ty`, with the described types in `refs`
+/// referring to their definition under `$defs`. Other types than primitives, options, vectors,
+/// boxes and json values are left open with their rust name in `x-rust-type`.
+fn type_schema(ty: &str, refs: &[Reference]) -> Value {
+	if let Some(reference) = refs.iter().find(|x| x.name == ty) {
+		return json!({ "$ref": format!("#/$defs/{}", reference.type_id) });
+	}
+	if let Some(content) = option_content(ty) {
+		return json!({ "anyOf": [type_schema(content, refs), { "type": "null" }] });
+	}
+	if let Some(content) = generic_content(ty, "Vec") {
+		return json!({ "type": "array", "items": type_schema(content, refs) });
+	}
+	if let Some(content) = generic_content(ty, "Box") {
+		return type_schema(content, refs);
+	}
+	match ty.rsplit("::").next().unwrap_or(ty) {
+		"String" | "str" | "&str" | "char" => json!({ "type": "string" }),
+		"bool" => json!({ "type": "boolean" }),
+		"u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128"
+		| "isize" => json!({ "type": "integer" }),
+		"f32" | "f64" => json!({ "type": "number" }),
+		"Value" => json!({}),
+		_ => json!({ "x-rust-type": ty }),
+	}
+}
+
+fn option_content(ty: &str) -> Option<&str> {
+	generic_content(ty, "Option")
+}
+
+/// `T` of `Generic<T>`, also if the generic is written with its path.
+fn generic_content<'a>(ty: &'a str, generic: &str) -> Option<&'a str> {
+	let content = ty.strip_suffix('>')?;
+	let (path, content) = content.split_once('<')?;
+	(path.rsplit("::").next() == Some(generic)).then_some(content)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tea_sdk::serde::TypeId;
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[serde(rename_all = "camelCase")]
+	#[response(())]
+	struct TransferRequest {
+		tapp_id_b64: String,
+		amount: u64,
+		memo: Option<String>,
+		targets: Vec<String>,
+		#[serde(default)]
+		tags: Vec<String>,
+	}
+
+	#[test]
+	fn schema_of_request() {
+		assert_eq!(
+			json_schema(&TransferRequest::descriptor(), &TypeRegistry::new()),
+			json!({
+				"type": "object",
+				"properties": {
+					"tappIdB64": { "type": "string" },
+					"amount": { "type": "integer" },
+					"memo": { "anyOf": [{ "type": "string" }, { "type": "null" }] },
+					"targets": { "type": "array", "items": { "type": "string" } },
+					"tags": { "type": "array", "items": { "type": "string" } },
+				},
+				"required": ["tappIdB64", "amount", "targets"],
+			})
+		);
+		assert_eq!(
+			type_schema("std::option::Option<tea_runtime_codec::tapp::Account>", &[]),
+			json!({ "anyOf": [{ "x-rust-type": "tea_runtime_codec::tapp::Account" }, { "type": "null" }] })
+		);
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	struct Stop {
+		name: String,
+	}
+
+	#[derive(Serialize, Deserialize, TypeId)]
+	#[response(())]
+	struct RouteRequest {
+		stops: Vec<Stop>,
+		next: Option<Box<RouteRequest>>,
+	}
+
+	#[test]
+	fn schema_refers_to_nested_types() {
+		let mut registry = TypeRegistry::new();
+		registry.describe::<RouteRequest>();
+		let schema = json_schema(&RouteRequest::descriptor(), &registry);
+		let stop = format!("#/$defs/{}", Stop::TYPE_ID);
+		let route = format!("#/$defs/{}", RouteRequest::TYPE_ID);
+		assert_eq!(
+			schema["properties"],
+			json!({
+				"stops": { "type": "array", "items": { "$ref": stop } },
+				"next": { "anyOf": [{ "$ref": route }, { "type": "null" }] },
+			})
+		);
+		assert_eq!(
+			schema["$defs"][Stop::TYPE_ID]["properties"],
+			json!({ "name": { "type": "string" } })
+		);
+		assert_eq!(
+			schema["$defs"][RouteRequest::TYPE_ID]["required"],
+			json!(["stops"])
+		);
+	}
+
+	#[tokio::test]
+	async fn logout_accepts_a_body_without_address() {
+		let router = crate::client::types::client_router();
+		router
+			.dispatch("logout", b"{}".to_vec(), String::new())
+			.await
+			.unwrap();
+		let logout = router.catalog().find(|x| x.name == "logout").unwrap();
+		assert_eq!(logout.request_schema["required"], json!([]));
+	}
+}
@@ -1,15 +1,82 @@
-use crate::client::error::{Error, Result};
-use crate::client::help;
-use crate::enclave::actors::kvp;
-// use prost::Message;
+//! Tracks the txns sent by this client until they are executed.
+//!
+//! Every item is stored under its own key and expires after `CACHE_TTL_S`. Item ids are
+//! the nanosecond time the txn was added, so the indexes by sender and status are plain
+//! sorted lists of ids, and ids older than the TTL are dropped whenever an index is written.
+//! An item and the indexes it is in are always written together in one keyvalue batch.
+//!
+//! Items used to be kept in one list under `_cache_txn_key_`, which is moved into the store
+//! by `migrate_legacy_cache` the first time this actor reads the store.
+
+use crate::client::error::{Error, Errors, Result};
+use crate::client::help::{self, ActionOk};
+use crate::client::push;
+use crate::enclave::actors::{env::system_time_as_nanos, kvp};
+use crate::enclave::error::Errors as EnclaveErrors;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::BTreeSet;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tea_runtime_codec::actor_txns::tsid::Tsid;
 use tea_runtime_codec::tapp::Account;
+use tea_sdk::serde::TypeId;
 use tea_sdk::IntoGlobal;
+use tea_system_actors::keyvalue::actions::CasCondition;
 
 pub const CACHE_TXN_KEY: &str = "cache_txn_key";
+const CACHE_TTL_S: i32 = 60 * 60 * 24 * 7;
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxnTrackStatus {
+	/// Added before sending, the txn has no hash yet
+	Pending,
+	/// Accepted by a validator, the tsid is known
+	Sequenced,
+	Executed,
+	Failed,
+}
+
+impl TxnTrackStatus {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			TxnTrackStatus::Pending => "Pending",
+			TxnTrackStatus::Sequenced => "Sequenced",
+			TxnTrackStatus::Executed => "Executed",
+			TxnTrackStatus::Failed => "Failed",
+		}
+	}
+
+	/// The status as reported before the tracked statuses were added, empty until the txn is
+	/// executed.
+	pub fn legacy_str(&self) -> &'static str {
+		match self {
+			TxnTrackStatus::Pending | TxnTrackStatus::Sequenced => "",
+			TxnTrackStatus::Executed => "Success",
+			TxnTrackStatus::Failed => "Fail",
+		}
+	}
+
+	pub fn can_transit_to(&self, next: TxnTrackStatus) -> bool {
+		use TxnTrackStatus::*;
+		matches!(
+			(self, next),
+			(Pending, Sequenced) | (Pending, Failed) | (Sequenced, Executed) | (Sequenced, Failed)
+		)
+	}
+
+	fn transit_to(&mut self, next: TxnTrackStatus) -> Result<()> {
+		if !self.can_transit_to(next) {
+			return Err(Errors::InvalidTxnStatusTransition(
+				self.as_str().to_string(),
+				next.as_str().to_string(),
+			));
+		}
+		*self = next;
+		Ok(())
+	}
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TxnCacheItem {
@@ -23,7 +90,7 @@ pub struct TxnCacheItem {
 	pub txn_bytes: Vec<u8>,
 	pub txn_status: String,
 	pub error: Option<String>,
-	pub status: Option<String>,
+	pub status: TxnTrackStatus,
 }
 
 impl TxnCacheItem {
@@ -49,10 +116,8 @@ impl TxnCacheItem {
 				Some(t) => t.to_string(),
 				None => "".to_string(),
 			},
-			"status": match &self.status {
-				Some(s) => s.clone(),
-				None => "".to_string(),
-			},
+			"status": self.status.legacy_str(),
+			"track_status": self.status.as_str(),
 			"error": match &self.error {
 				Some(s) => s.clone(),
 				None => "".to_string(),
@@ -60,47 +125,124 @@ impl TxnCacheItem {
 		});
 		r
 	}
+
+	/// The status as kept before `TxnTrackStatus`, see `TxnTrackStatus::legacy_str`.
+	#[deprecated(note = "use `status` instead")]
+	pub fn legacy_status(&self) -> Option<String> {
+		match self.status.legacy_str() {
+			"" => None,
+			status => Some(status.to_string()),
+		}
+	}
+
+	fn index_keys(&self) -> BTreeSet<String> {
+		BTreeSet::from([
+			all_index_key(),
+			sender_index_key(&self.sender),
+			status_index_key(self.status),
+		])
+	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, TypeId)]
 #[serde(rename_all = "camelCase")]
+#[response(ActionOk)]
 pub struct QueryTxnCacheListRequest {
 	pub uuid: String,
 	pub address: String,
 	pub sender: Option<String>,
+	pub status: Option<TxnTrackStatus>,
+	/// The `cursor` of the previous page, the newest items are returned if absent
+	pub cursor: Option<String>,
+	pub limit: Option<usize>,
 }
 
-pub async fn query_txn_cache_list(payload: Vec<u8>, _from_actor: String) -> Result<Vec<u8>> {
-	let req: QueryTxnCacheListRequest = serde_json::from_slice(&payload)?;
+pub async fn query_txn_cache_list(
+	req: QueryTxnCacheListRequest,
+	_from_actor: String,
+) -> Result<ActionOk> {
 	info!("start query txn cache list...");
 
-	let uuid = req.uuid;
-	let cache_list = get_cache_instance().await?;
-	let list: Vec<serde_json::Value> = if let Some(acct) = req.sender {
-		cache_list
-			.iter()
-			.filter(|x| x.sender == acct.parse().unwrap())
-			.map(|x| x.to_json())
-			.collect()
-	} else {
-		cache_list.iter().map(|x| x.to_json()).collect()
+	let query = TxnCacheQuery {
+		sender: req.sender.map(|x| x.parse()).transpose()?,
+		status: req.status,
+		cursor: req
+			.cursor
+			.map(|x| u128::from_str(&x))
+			.transpose()
+			.into_g::<Error>()?,
+		limit: req.limit.unwrap_or(DEFAULT_PAGE_SIZE),
 	};
+	let page = query_items(&query).await?;
+	let list: Vec<serde_json::Value> = page.items.iter().map(|x| x.to_json()).collect();
 	let list_json = json!({
 	  "list": &list,
+	  "cursor": page.cursor.map(|x| x.to_string()),
 	});
-	help::cache_json_with_uuid(&uuid, list_json).await?;
-	help::result_ok()
+	help::cache_json_with_uuid(&req.uuid, list_json).await?;
+	Ok(ActionOk::default())
+}
+
+/// Filters of a page of tracked txns, newest first.
+#[derive(Debug, Clone, Default)]
+pub struct TxnCacheQuery {
+	pub sender: Option<Account>,
+	pub status: Option<TxnTrackStatus>,
+	/// Only items older than this id are returned
+	pub cursor: Option<u128>,
+	pub limit: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxnCachePage {
+	pub items: Vec<TxnCacheItem>,
+	/// Pass as `TxnCacheQuery::cursor` to get the next page, `None` if this is the last one
+	pub cursor: Option<u128>,
+}
+
+pub async fn query_items(query: &TxnCacheQuery) -> Result<TxnCachePage> {
+	migrate_legacy_cache_once().await?;
+	let key = match (&query.sender, query.status) {
+		(Some(sender), _) => sender_index_key(sender),
+		(None, Some(status)) => status_index_key(status),
+		(None, None) => all_index_key(),
+	};
+	let ids = kvp::get::<Vec<u128>>(&key).await?.unwrap_or_default();
+
+	let mut items = Vec::new();
+	let mut candidates = ids
+		.into_iter()
+		.rev()
+		.skip_while(|id| query.cursor.is_some_and(|cursor| *id >= cursor))
+		.peekable();
+	while items.len() < query.limit {
+		let Some(id) = candidates.next() else {
+			break;
+		};
+		// items expire on their own, the index may still have their ids
+		let Some(item) = get_item(id).await? else {
+			continue;
+		};
+		if query.status.is_some_and(|status| item.status != status) {
+			continue;
+		}
+		items.push(item);
+	}
+	let cursor = match (candidates.peek(), items.last()) {
+		(Some(_), Some(last)) => Some(last.time),
+		_ => None,
+	};
+	Ok(TxnCachePage { items, cursor })
 }
 
-#[allow(clippy::too_many_arguments)]
 pub async fn add_to_txn_cache(
 	txn_name: &str,
 	payload: Vec<u8>,
 	sender: &str,
 	from_actor: &str,
 ) -> Result<TxnCacheItem> {
-	let cache_item = TxnCacheItem {
-		time: crate::enclave::actors::env::system_time_as_nanos().await?,
+	let mut cache_item = TxnCacheItem {
+		time: system_time_as_nanos().await?,
 		from_actor: from_actor.to_string(),
 		hash_hex: None,
 		ts: None,
@@ -109,115 +251,525 @@ pub async fn add_to_txn_cache(
 		txn_bytes: payload,
 		txn_status: "Normal".into(),
 		sender: sender.to_string().parse()?,
-		status: None,
+		status: TxnTrackStatus::Pending,
 		error: None,
 	};
 
-	let mut cache_list = get_cache_instance().await?;
-	cache_list.push(cache_item.clone());
+	for _ in 0..kvp::UPDATE_RETRIES {
+		let added = update_item(cache_item.time, |current| {
+			Ok(current.is_none().then(|| cache_item.clone()))
+		})
+		.await?;
+		if added.is_some() {
+			return Ok(cache_item);
+		}
+		// another txn was added in the same nanosecond, ids must stay unique
+		cache_item.time += 1;
+	}
+	let e = EnclaveErrors::KeyValueUpdateContended(item_key(cache_item.time), kvp::UPDATE_RETRIES);
+	Err(crate::enclave::error::Error::from(e).into())
+}
+
+pub async fn get_item(id: u128) -> Result<Option<TxnCacheItem>> {
+	Ok(kvp::get(&item_key(id)).await?)
+}
 
-	if cache_list.len() > 500 {
-		cache_list.pop();
+pub async fn get_item_by_hash(hash: &str) -> Result<Option<TxnCacheItem>> {
+	migrate_legacy_cache_once().await?;
+	match kvp::get::<u128>(&hash_index_key(hash)).await? {
+		Some(id) => get_item(id).await,
+		None => Ok(None),
 	}
-	set_cache_instance(cache_list).await?;
+}
+
+/// Marks the item as sequenced with the tsid given by the validator.
+pub async fn set_item_tsid(item: &TxnCacheItem, tsid: Tsid) -> Result<()> {
+	let updated = update_item(item.time, |current| {
+		let Some(mut item) = current else {
+			return Ok(None);
+		};
+		if item.status == TxnTrackStatus::Sequenced {
+			return Ok(None);
+		}
+		item.status.transit_to(TxnTrackStatus::Sequenced)?;
+		item.hash_hex = Some(hex::encode(tsid.hash));
+		item.ts = Some(tsid.ts);
+		item.nonce = tsid.nonce;
+		Ok(Some(item))
+	})
+	.await?;
 
-	Ok(cache_item)
+	if let Some(item) = updated {
+		info!("After set_item_tsid => {:?}", item);
+		push::notify_txn_status(&item).await;
+	}
+	Ok(())
 }
 
-fn cache_key() -> String {
-	format!("_{CACHE_TXN_KEY}_")
+/// Marks the item of the txn hash as executed, or as failed if `error` is given.
+pub async fn set_item_status(hash: &str, error: Option<&str>) -> Result<()> {
+	migrate_legacy_cache_once().await?;
+	let Some(id) = kvp::get::<u128>(&hash_index_key(hash)).await? else {
+		return Ok(());
+	};
+	let next = match error {
+		Some(_) => TxnTrackStatus::Failed,
+		None => TxnTrackStatus::Executed,
+	};
+	let updated = update_item(id, |current| {
+		let Some(mut item) = current else {
+			return Ok(None);
+		};
+		if item.status == next {
+			return Ok(None);
+		}
+		item.status.transit_to(next)?;
+		item.error = error.map(Into::into);
+		Ok(Some(item))
+	})
+	.await?;
+
+	if let Some(item) = updated {
+		info!("After set_item_status => {:?}", item);
+		push::notify_txn_status(&item).await;
+	}
+	Ok(())
 }
 
-pub async fn get_cache_instance() -> Result<Vec<TxnCacheItem>> {
-	let key = cache_key();
+/// `TxnCacheItem` as kept in the legacy list, `status` was set to "Success" or "Fail" once the
+/// txn was executed.
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyTxnCacheItem {
+	time: u128,
+	from_actor: String,
+	sender: Account,
+	hash_hex: Option<String>,
+	ts: Option<u128>,
+	nonce: Option<u64>,
+	txn_name: String,
+	txn_bytes: Vec<u8>,
+	txn_status: String,
+	error: Option<String>,
+	status: Option<String>,
+}
 
-	let wrap_bytes = kvp::get(&key).await;
-	if wrap_bytes.is_err() {
-		return Ok(Vec::new());
+impl From<LegacyTxnCacheItem> for TxnCacheItem {
+	fn from(item: LegacyTxnCacheItem) -> Self {
+		let status = match (item.status.as_deref(), &item.hash_hex) {
+			(Some("Success"), _) => TxnTrackStatus::Executed,
+			(Some("Fail"), _) => TxnTrackStatus::Failed,
+			(_, Some(_)) => TxnTrackStatus::Sequenced,
+			(_, None) => TxnTrackStatus::Pending,
+		};
+		TxnCacheItem {
+			time: item.time,
+			from_actor: item.from_actor,
+			sender: item.sender,
+			hash_hex: item.hash_hex,
+			ts: item.ts,
+			nonce: item.nonce,
+			txn_name: item.txn_name,
+			txn_bytes: item.txn_bytes,
+			txn_status: item.txn_status,
+			error: item.error,
+			status,
+		}
 	}
-	let wrap_bytes = wrap_bytes.unwrap();
-	if wrap_bytes.is_none() {
-		return Ok(Vec::new());
+}
+
+/// All tracked txns, oldest first.
+#[deprecated(note = "use `query_items` instead")]
+pub async fn get_cache_instance() -> Result<Vec<TxnCacheItem>> {
+	migrate_legacy_cache_once().await?;
+	let ids = kvp::get::<Vec<u128>>(&all_index_key())
+		.await?
+		.unwrap_or_default();
+	let mut items = Vec::with_capacity(ids.len());
+	for id in ids {
+		if let Some(item) = get_item(id).await? {
+			items.push(item);
+		}
 	}
-	let bytes: Vec<u8> = wrap_bytes.unwrap();
-	let list: Vec<TxnCacheItem> = tea_codec::deserialize(bytes)?;
-	Ok(list)
+	Ok(items)
 }
 
+/// Writes every item of the list into the store. Unlike the legacy list, items missing from
+/// `list` are not removed, they expire after `CACHE_TTL_S`.
+#[deprecated(
+	note = "items are written by `add_to_txn_cache`, `set_item_tsid` and `set_item_status`"
+)]
 pub async fn set_cache_instance(list: Vec<TxnCacheItem>) -> Result<()> {
-	let key = cache_key();
-	let bytes = tea_codec::serialize(&list)?;
-	kvp::set(&key, &bytes, 60 * 60 * 24 * 365).await?;
+	for item in list {
+		update_item(item.time, |_| Ok(Some(item.clone()))).await?;
+	}
 	Ok(())
 }
 
+/// All tracked txns and the index of the one added at `ts_str`.
+#[deprecated(note = "use `get_item` instead")]
+#[allow(deprecated)]
 pub async fn get_cache_item_by_ts(ts_str: &str) -> Result<Option<(Vec<TxnCacheItem>, usize)>> {
-	let list = get_cache_instance().await?;
 	let ts = u128::from_str(ts_str).into_g::<Error>()?;
-	let wrap_search = list.binary_search_by(|x| x.time.cmp(&ts));
-
-	if wrap_search.is_err() {
-		return Ok(None);
-	}
-
-	let index = wrap_search.unwrap();
-
-	Ok(Some((list, index)))
+	let list = get_cache_instance().await?;
+	Ok(list
+		.binary_search_by(|x| x.time.cmp(&ts))
+		.ok()
+		.map(|index| (list, index)))
 }
 
+/// All tracked txns and the index of the one with the txn hash.
+#[deprecated(note = "use `get_item_by_hash` instead")]
+#[allow(deprecated)]
 pub async fn get_cache_item_by_hash(hash: &str) -> Result<Option<(Vec<TxnCacheItem>, usize)>> {
 	let list = get_cache_instance().await?;
+	Ok(list
+		.iter()
+		.rposition(|x| {
+			x.hash_hex
+				.as_ref()
+				.is_some_and(|x| x.eq_ignore_ascii_case(hash))
+		})
+		.map(|index| (list, index)))
+}
 
-	let mut index: Option<usize> = None;
-	for (i, x) in list.iter().enumerate() {
-		if let Some(v_hash) = &x.hash_hex {
-			if v_hash.eq_ignore_ascii_case(hash) {
-				index = Some(i);
+/// Runs `migrate_legacy_cache` until it succeeds once in this actor, the legacy list is
+/// never written again.
+async fn migrate_legacy_cache_once() -> Result<()> {
+	static MIGRATED: AtomicBool = AtomicBool::new(false);
+	if !MIGRATED.load(Ordering::Acquire) {
+		migrate_legacy_cache().await?;
+		MIGRATED.store(true, Ordering::Release);
+	}
+	Ok(())
+}
+
+/// Moves the items of the legacy list into the store and removes the list. Items older than
+/// `CACHE_TTL_S` are dropped, and items already in the store are kept, so concurrent
+/// migrations don't conflict. An unreadable list is dropped too.
+pub async fn migrate_legacy_cache() -> Result<()> {
+	let key = legacy_cache_key();
+	let Some(bytes) = kvp::get::<Vec<u8>>(&key).await? else {
+		return Ok(());
+	};
+	match tea_codec::deserialize::<Vec<LegacyTxnCacheItem>, _>(bytes) {
+		Ok(items) => {
+			let oldest = system_time_as_nanos()
+				.await?
+				.saturating_sub(CACHE_TTL_S as u128 * 1_000_000_000);
+			for item in items.into_iter().filter(|x| x.time >= oldest) {
+				let item = TxnCacheItem::from(item);
+				update_item(item.time, |current| {
+					Ok(current.is_none().then(|| item.clone()))
+				})
+				.await?;
 			}
 		}
+		Err(e) => warn!("legacy txn cache is dropped, it can't be read: {e}"),
 	}
-	if index.is_none() {
-		return Ok(None);
-	}
-	let index = index.unwrap();
+	kvp::del(&key).await?;
+	Ok(())
+}
+
+/// Writes the item returned by `f` together with the indexes it left or joined.
+/// Nothing is written if `f` returns `None`. `f` is called again on the latest item
+/// if another actor changed the item or one of its indexes meanwhile.
+async fn update_item(
+	id: u128,
+	mut f: impl FnMut(Option<TxnCacheItem>) -> Result<Option<TxnCacheItem>>,
+) -> Result<Option<TxnCacheItem>> {
+	let key = item_key(id);
+	let oldest = system_time_as_nanos()
+		.await?
+		.saturating_sub(CACHE_TTL_S as u128 * 1_000_000_000);
+	for _ in 0..kvp::UPDATE_RETRIES {
+		let (current, version) = kvp::get_versioned::<TxnCacheItem>(&key).await?;
+		let left = current
+			.as_ref()
+			.map(TxnCacheItem::index_keys)
+			.unwrap_or_default();
+		let Some(item) = f(current)? else {
+			return Ok(None);
+		};
+		let joined = item.index_keys();
 
-	Ok(Some((list, index)))
+		let mut batch = kvp::Batch::new()
+			.condition(&key, CasCondition::Version(version))
+			.set(&key, &item, Some(CACHE_TTL_S))?;
+		for index_key in left.union(&joined) {
+			let (ids, version) = kvp::get_versioned::<Vec<u128>>(index_key).await?;
+			let ids = reindex(
+				ids.unwrap_or_default(),
+				id,
+				joined.contains(index_key),
+				oldest,
+			);
+			batch = batch
+				.condition(index_key, CasCondition::Version(version))
+				.set(index_key, &ids, Some(CACHE_TTL_S))?;
+		}
+		if let Some(hash) = &item.hash_hex {
+			batch = batch.set(&hash_index_key(hash), &id, Some(CACHE_TTL_S))?;
+		}
+
+		if batch.commit().await?.is_ok() {
+			return Ok(Some(item));
+		}
+	}
+	let e = EnclaveErrors::KeyValueUpdateContended(key, kvp::UPDATE_RETRIES);
+	Err(crate::enclave::error::Error::from(e).into())
 }
 
-pub async fn set_item_tsid(item: &TxnCacheItem, tsid: Tsid) -> Result<()> {
-	let ts_str = item.time.to_string();
-	let wrap = get_cache_item_by_ts(&ts_str).await?;
-	if wrap.is_none() {
-		return Ok(());
+/// Removes `id` from the sorted ids, or inserts it if `keep`, dropping those older than
+/// `oldest`. Ids are only dropped once their items expired, so the indexes hold every live item.
+fn reindex(mut ids: Vec<u128>, id: u128, keep: bool, oldest: u128) -> Vec<u128> {
+	ids.retain(|x| *x >= oldest);
+	match (ids.binary_search(&id), keep) {
+		(Ok(index), false) => {
+			ids.remove(index);
+		}
+		(Err(index), true) => ids.insert(index, id),
+		_ => {}
 	}
-	let (mut list, index) = wrap.unwrap();
-	let item = list.get_mut(index).unwrap();
-	item.hash_hex = Some(hex::encode(tsid.hash));
-	item.ts = Some(tsid.ts);
-	item.nonce = tsid.nonce;
+	ids
+}
 
-	info!("After set_item_tsid => {:?}", item);
-	set_cache_instance(list).await?;
+fn legacy_cache_key() -> String {
+	format!("_{CACHE_TXN_KEY}_")
+}
 
-	Ok(())
+fn item_key(id: u128) -> String {
+	format!("_{CACHE_TXN_KEY}_item_{id}")
 }
 
-pub async fn set_item_status(hash: &str, error: Option<&str>) -> Result<()> {
-	let wrap = get_cache_item_by_hash(hash).await?;
-	if wrap.is_none() {
-		return Ok(());
+fn hash_index_key(hash: &str) -> String {
+	format!("_{CACHE_TXN_KEY}_hash_{}", hash.to_lowercase())
+}
+
+fn all_index_key() -> String {
+	format!("_{CACHE_TXN_KEY}_all")
+}
+
+fn sender_index_key(sender: &Account) -> String {
+	format!("_{CACHE_TXN_KEY}_sender_{sender:?}")
+}
+
+fn status_index_key(status: TxnTrackStatus) -> String {
+	format!("_{CACHE_TXN_KEY}_status_{}", status.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn status_transitions() {
+		use TxnTrackStatus::*;
+		let mut status = Pending;
+		assert!(status.transit_to(Executed).is_err());
+		status.transit_to(Sequenced).unwrap();
+		status.transit_to(Failed).unwrap();
+		assert!(status.transit_to(Executed).is_err());
+		assert_eq!(status, Failed);
 	}
-	let (mut list, index) = wrap.unwrap();
-	let item = list.get_mut(index).unwrap();
 
-	if let Some(err) = error {
-		item.status = Some("Fail".into());
-		item.error = Some(err.into());
-	} else {
-		item.status = Some("Success".into());
+	#[test]
+	fn reindex_keeps_ids_sorted_and_fresh() {
+		let ids = reindex(vec![1, 3, 5], 4, true, 2);
+		assert_eq!(ids, vec![3, 4, 5]);
+		assert_eq!(reindex(ids.clone(), 4, true, 0), ids);
+		assert_eq!(reindex(ids, 4, false, 0), vec![3, 5]);
+
+		let ids = reindex((0..1000).collect(), 1000, true, 0);
+		assert_eq!(ids.len(), 1001);
+		assert_eq!(ids.first(), Some(&0));
 	}
-	info!("After set_item_status => {:?}", item);
-	set_cache_instance(list).await?;
 
-	Ok(())
+	#[cfg(feature = "native")]
+	mod store {
+		use super::*;
+		use crate::enclave::native::keyvalue::KeyValueActor;
+		use std::time::{Duration, SystemTime};
+		use tea_actorx::{ActorExt, ActorId, HandlerActor, WithActorHost};
+		use tea_sdk::serde::handle::handles;
+		use tea_system_actors::env::{GetSystemTimeRequest, GetSystemTimeResponse, NAME};
+
+		/// A `tea:env` actor whose clock never moves, so every txn is added in the same
+		/// nanosecond.
+		struct StoppedClock;
+
+		impl HandlerActor for StoppedClock {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(NAME))
+			}
+		}
+
+		#[handles]
+		impl StoppedClock {
+			async fn handle(&self, _: GetSystemTimeRequest) -> tea_sdk::Result<_> {
+				Ok(GetSystemTimeResponse(
+					SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+				))
+			}
+		}
+
+		async fn run(test: impl std::future::Future<Output = Result<()>>) {
+			async {
+				KeyValueActor::default().register().await?;
+				StoppedClock.register().await?;
+				test.await
+			}
+			.with_actor_host()
+			.await
+			.unwrap();
+		}
+
+		fn sender(byte: u8) -> String {
+			format!("{:?}", Account::repeat_byte(byte))
+		}
+
+		fn tsid(byte: u8) -> Tsid {
+			let mut tsid = Tsid::default();
+			tsid.hash = [byte; 32];
+			tsid
+		}
+
+		fn query(sender: Option<u8>, status: Option<TxnTrackStatus>) -> TxnCacheQuery {
+			TxnCacheQuery {
+				sender: sender.map(Account::repeat_byte),
+				status,
+				cursor: None,
+				limit: 2,
+			}
+		}
+
+		fn names(page: &TxnCachePage) -> Vec<&str> {
+			page.items.iter().map(|x| x.txn_name.as_str()).collect()
+		}
+
+		#[tokio::test]
+		async fn ids_stay_unique_within_a_nanosecond() {
+			run(async {
+				let a = add_to_txn_cache("a", vec![], &sender(1), "actor").await?;
+				let b = add_to_txn_cache("b", vec![], &sender(1), "actor").await?;
+				assert_eq!(b.time, a.time + 1);
+				assert_eq!(get_item(a.time).await?.unwrap().txn_name, "a");
+				assert_eq!(get_item(b.time).await?.unwrap().txn_name, "b");
+				Ok(())
+			})
+			.await;
+		}
+
+		#[tokio::test]
+		async fn queries_page_and_filter() {
+			run(async {
+				let mut items = vec![];
+				for (name, byte) in [("a", 1), ("b", 2), ("c", 1), ("d", 1), ("e", 2)] {
+					items.push(add_to_txn_cache(name, vec![], &sender(byte), "actor").await?);
+				}
+				for (i, item) in items.iter().enumerate().take(3) {
+					set_item_tsid(item, tsid(i as u8)).await?;
+				}
+				set_item_status(&hex::encode([0; 32]), None).await?;
+				set_item_status(&hex::encode([1; 32]), Some("failed")).await?;
+
+				let page = query_items(&query(None, None)).await?;
+				assert_eq!(names(&page), vec!["e", "d"]);
+				let mut next = query(None, None);
+				next.cursor = page.cursor;
+				let page = query_items(&next).await?;
+				assert_eq!(names(&page), vec!["c", "b"]);
+				next.cursor = page.cursor;
+				let page = query_items(&next).await?;
+				assert_eq!(names(&page), vec!["a"]);
+				assert_eq!(page.cursor, None);
+
+				let page = query_items(&query(Some(1), None)).await?;
+				assert_eq!(names(&page), vec!["d", "c"]);
+				let page = query_items(&query(Some(2), Some(TxnTrackStatus::Failed))).await?;
+				assert_eq!(names(&page), vec!["b"]);
+				let page = query_items(&query(None, Some(TxnTrackStatus::Pending))).await?;
+				assert_eq!(names(&page), vec!["e", "d"]);
+				let page = query_items(&query(None, Some(TxnTrackStatus::Executed))).await?;
+				assert_eq!(names(&page), vec!["a"]);
+				assert_eq!(page.items[0].to_json()["status"], "Success");
+				Ok(())
+			})
+			.await;
+		}
+
+		#[tokio::test]
+		#[allow(deprecated)]
+		async fn deprecated_lookups_read_the_store() {
+			run(async {
+				add_to_txn_cache("a", vec![], &sender(1), "actor").await?;
+				let b = add_to_txn_cache("b", vec![], &sender(2), "actor").await?;
+				set_item_tsid(&b, tsid(7)).await?;
+				set_item_status(&hex::encode([7; 32]), None).await?;
+
+				let (list, index) = get_cache_item_by_hash(&hex::encode([7; 32]))
+					.await?
+					.unwrap();
+				assert_eq!(list.len(), 2);
+				assert_eq!(list[index].txn_name, "b");
+				assert_eq!(list[index].legacy_status(), Some("Success".to_string()));
+				let (_, by_ts) = get_cache_item_by_ts(&b.time.to_string()).await?.unwrap();
+				assert_eq!(by_ts, index);
+				assert_eq!(list[0].legacy_status(), None);
+				Ok(())
+			})
+			.await;
+		}
+
+		#[tokio::test]
+		async fn legacy_list_is_migrated() {
+			run(async {
+				let now = system_time_as_nanos().await?;
+				let legacy =
+					|time, hash_hex: Option<&str>, status: Option<&str>| LegacyTxnCacheItem {
+						time,
+						from_actor: "actor".into(),
+						sender: Account::repeat_byte(1),
+						hash_hex: hash_hex.map(Into::into),
+						ts: None,
+						nonce: None,
+						txn_name: format!("{time}"),
+						txn_bytes: vec![],
+						txn_status: "Normal".into(),
+						error: None,
+						status: status.map(Into::into),
+					};
+				let list = vec![
+					legacy(1, Some("aa"), Some("Success")),
+					legacy(now - 2, None, None),
+					legacy(now - 1, Some("bb"), Some("Fail")),
+					legacy(now, Some("cc"), None),
+				];
+				kvp::set(&legacy_cache_key(), &tea_codec::serialize(&list)?, 60).await?;
+				// other tests of this process may have run the migration already
+				migrate_legacy_cache().await?;
+
+				let mut query = query(None, None);
+				query.limit = 10;
+				let page = query_items(&query).await?;
+				let statuses = page
+					.items
+					.iter()
+					.map(|x| (x.time, x.status))
+					.collect::<Vec<_>>();
+				assert_eq!(
+					statuses,
+					vec![
+						(now, TxnTrackStatus::Sequenced),
+						(now - 1, TxnTrackStatus::Failed),
+						(now - 2, TxnTrackStatus::Pending),
+					]
+				);
+				assert_eq!(page.items[1].to_json()["status"], "Fail");
+				assert!(get_item_by_hash("CC").await?.is_some());
+				assert!(!kvp::exists(&legacy_cache_key()).await?);
+				Ok(())
+			})
+			.await;
+		}
+	}
 }
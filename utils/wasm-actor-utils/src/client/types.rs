@@ -1,53 +1,85 @@
 use crate::client::api;
 use crate::client::error::Result;
+use crate::client::push;
+use crate::client::router::ActionRouter;
 use crate::client::txn_cache;
-pub use crate::enclave::action::HttpRequest;
+pub use crate::enclave::action::{HttpRequest, SocketioRequest};
 use futures::Future;
 use std::pin::Pin;
+use std::sync::OnceLock;
 
 type CBD = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send>>;
 pub type CallbackCB = dyn Fn(Vec<u8>, String) -> CBD + Sync + Send + 'static;
 
+/// The router of the actions every client actor handles.
+///
+/// Client actors with actions of their own add them to this router and pass it to
+/// `init_router`.
+pub fn client_router() -> ActionRouter {
+	ActionRouter::new()
+		.action("login", api::user::txn_login)
+		.action("query_session_key", api::user::query_session_key)
+		.action("query_result", api::user::query_result)
+		.action("queryHashResult", api::user::query_txn_hash_result)
+		.action(
+			"queryHashResultFromAll",
+			api::user::query_txn_hash_result_from_all,
+		)
+		.action("queryTxnReceipt", api::user::query_txn_receipt)
+		.action("logout", api::user::txn_logout)
+		.action("query_balance", api::user::query_balance)
+		.action("query_deposit", api::user::query_deposit)
+		.action("query_credit", api::user::query_credit)
+		.action("query_asset", api::user::query_asset)
+		.action("query_allowance", api::user::query_allowance)
+		.action("query_tapp_metadata", api::user::query_tapp_metadata)
+		.action("query_error_log", api::user::query_error_log)
+		.action("query_system_version", api::user::query_system_version)
+		.action(
+			"query_multi_tapp_allowance_from_local_state",
+			api::user::query_multi_tapp_allowance,
+		)
+		.action("query_txn_cache_list", txn_cache::query_txn_cache_list)
+		.action(
+			"query_credit_system_info",
+			api::user::query_credit_system_info,
+		)
+		.action("open_payment_channel", api::channel::open_payment_channel)
+		.action("payer_early_terminate", api::channel::early_terminate)
+		.action("terminate", api::channel::terminate)
+		.action("payer_refill_fund", api::channel::refill_fund)
+		.action(
+			"query_channel_list_with_account",
+			api::channel::query_channel_list_with_account,
+		)
+		.action(
+			"query_channel_list_with_channel_id",
+			api::channel::query_channel_list_with_channel_id,
+		)
+		.action("payee_update_payment", api::channel::payee_update_payment)
+}
+
+static ROUTER: OnceLock<ActionRouter> = OnceLock::new();
+
+/// Sets the router of `map_handler`, which is `client_router()` unless set before the first
+/// action is handled. Returns the router back if one is set already.
+pub fn init_router(router: ActionRouter) -> std::result::Result<(), ActionRouter> {
+	ROUTER.set(router)
+}
+
+fn router() -> &'static ActionRouter {
+	ROUTER.get_or_init(client_router)
+}
+
 #[doc(hidden)]
 pub async fn map_handler(action: &str, arg: Vec<u8>, from_actor: String) -> Result<Vec<u8>> {
-	let res = match action {
-		"login" => api::user::txn_login(arg, from_actor).await?,
-		"query_session_key" => api::user::query_session_key(arg, from_actor).await?,
-		"query_result" => api::user::query_result(arg, from_actor).await?,
-		"queryHashResult" => api::user::query_txn_hash_result(arg, from_actor).await?,
-		"queryHashResultFromAll" => {
-			api::user::query_txn_hash_result_from_all(arg, from_actor).await?
-		}
-		"logout" => api::user::txn_logout(arg, from_actor)?,
-		"query_balance" => api::user::query_balance(arg, from_actor).await?,
-		"query_deposit" => api::user::query_deposit(arg, from_actor).await?,
-		"query_credit" => api::user::query_credit(arg, from_actor).await?,
-		"query_asset" => api::user::query_asset(arg, from_actor).await?,
-		"query_allowance" => api::user::query_allowance(arg, from_actor).await?,
-		"query_tapp_metadata" => api::user::query_tapp_metadata(arg, from_actor).await?,
-		"query_error_log" => api::user::query_error_log(arg, from_actor).await?,
-		"query_system_version" => api::user::query_system_version(arg, from_actor).await?,
-		"query_multi_tapp_allowance_from_local_state" => {
-			api::user::query_multi_tapp_allowance(arg, from_actor).await?
-		}
-		"query_txn_cache_list" => txn_cache::query_txn_cache_list(arg, from_actor).await?,
-		"query_credit_system_info" => api::user::query_credit_system_info(arg, from_actor).await?,
-
-		"open_payment_channel" => api::channel::open_payment_channel(arg, from_actor).await?,
-		"payer_early_terminate" => api::channel::early_terminate(arg, from_actor).await?,
-		"terminate" => api::channel::terminate(arg, from_actor).await?,
-		"payer_refill_fund" => api::channel::refill_fund(arg, from_actor).await?,
-		"query_channel_list_with_account" => {
-			api::channel::query_channel_list_with_account(arg, from_actor).await?
-		}
-		"query_channel_list_with_channel_id" => {
-			api::channel::query_channel_list_with_channel_id(arg, from_actor).await?
-		}
-		"payee_update_payment" => api::channel::payee_update_payment(arg, from_actor).await?,
+	router().dispatch(action, arg, from_actor).await
+}
 
-		_ => vec![],
-	};
-	Ok(res)
+/// Handles the socket.io actions registered by `push::register_socketio`.
+#[doc(hidden)]
+pub async fn map_socketio_handler(req: SocketioRequest, _from_actor: String) -> Result<Vec<u8>> {
+	push::handle_socketio(&req.action, &req.payload, req.socket_id).await
 }
 
 #[doc(hidden)]
@@ -83,30 +115,10 @@ pub async fn txn_callback(_uuid: &str, _from_actor: String) -> Result<Vec<u8>> {
 
 #[doc(hidden)]
 pub fn map_fn_list() -> Vec<&'static str> {
-	vec![
-		"login",
-		"query_session_key",
-		"query_result",
-		"queryHashResult",
-		"queryHashResultFromAll",
-		"logout",
-		"query_balance",
-		"query_deposit",
-		"query_credit",
-		"query_asset",
-		"query_allowance",
-		"query_tapp_metadata",
-		"query_error_log",
-		"query_system_version",
-		"query_multi_tapp_allowance_from_local_state",
-		"query_txn_cache_list",
-		"query_credit_system_info",
-		"open_payment_channel",
-		"payer_early_terminate",
-		"terminate",
-		"payer_refill_fund",
-		"query_channel_list_with_account",
-		"query_channel_list_with_channel_id",
-		"payee_update_payment",
-	]
+	router().names().collect()
+}
+
+/// The catalog of the client actions as JSON, see `ActionRouter::catalog_json`.
+pub fn action_catalog_json() -> Result<String> {
+	router().catalog_json()
 }
@@ -1,20 +1,20 @@
 use crate::enclave::{
 	actors::{
 		env::tappstore_id,
-		replica::report_txn_error,
+		replica::{append_txn_receipt, report_txn_error},
 		tokenstate::{cancel_sql_transaction, is_in_sql_transaction},
 	},
 	error::{Error, ProcessTransactionErrorFailed, Result},
 };
-use tea_runtime_codec::actor_txns::tsid::Tsid;
+use tea_runtime_codec::actor_txns::{receipt::TxnReceipt, tsid::Tsid};
 use tea_runtime_codec::tapp::TokenId;
 use tea_sdk::IntoGlobal;
-pub use tea_system_actors::adapter::HttpRequest;
+pub use tea_system_actors::adapter::{HttpRequest, HttpRouteRequest, SocketioRequest};
 
 /// Popup the txn error outside of the txn wrapper
 pub async fn process_txn_error(tsid: Tsid, inner: Error) -> Result<()> {
 	let token_id = tappstore_id().await?;
-	return if let Err(e) = process_txn_error_inner(token_id, tsid.hash.to_vec(), &inner).await {
+	return if let Err(e) = process_txn_error_inner(token_id, tsid, &inner).await {
 		Err(ProcessTransactionErrorFailed(e.to_string()).into())
 	} else {
 		Err(inner)
@@ -22,11 +22,20 @@ pub async fn process_txn_error(tsid: Tsid, inner: Error) -> Result<()> {
 }
 
 #[doc(hidden)]
-async fn process_txn_error_inner(token_id: TokenId, txn_hash: Vec<u8>, e: &Error) -> Result<()> {
-	report_txn_error(txn_hash, serde_json::to_string(&e).into_g::<Error>()?).await?;
+async fn process_txn_error_inner(token_id: TokenId, tsid: Tsid, e: &Error) -> Result<()> {
+	let error_msg = serde_json::to_string(&e).into_g::<Error>()?;
+	report_txn_error(tsid.hash.to_vec(), error_msg.clone()).await?;
 
 	if is_in_sql_transaction(token_id).await? {
 		cancel_sql_transaction(token_id).await?;
 	}
+
+	// the txn is failed already, a receipt that could not be stored must not fail the cleanup
+	if let Err(e) = append_txn_receipt(TxnReceipt::failed(tsid, error_msg)).await {
+		warn!(
+			"append receipt of failed txn with tsid {:?} failed: {}",
+			tsid, e
+		);
+	}
 	Ok(())
 }
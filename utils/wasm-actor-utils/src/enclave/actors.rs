@@ -8,6 +8,7 @@ pub mod layer1;
 pub mod libp2p;
 pub mod persist;
 pub mod replica;
+pub mod sql;
 pub mod statemachine;
 pub mod tappstore;
 pub mod tokenstate;
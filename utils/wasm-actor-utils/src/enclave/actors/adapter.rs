@@ -8,6 +8,14 @@ pub async fn register_adapter_http_dispatcher(actions: Vec<String>) -> Result<()
 	Ok(())
 }
 
+/// Registers path prefixes whose requests the adapter forwards whole as `HttpRouteRequest`.
+pub async fn register_adapter_http_routes(prefixes: Vec<String>) -> Result<()> {
+	ActorId::Static(NAME)
+		.call(RegisterHttpRoutes(prefixes))
+		.await?;
+	Ok(())
+}
+
 #[doc(hidden)]
 pub async fn register_adapter_socketio_dispatcher(actions: Vec<String>) -> Result<()> {
 	ActorId::Static(NAME)
@@ -15,3 +23,15 @@ pub async fn register_adapter_socketio_dispatcher(actions: Vec<String>) -> Resul
 		.await?;
 	Ok(())
 }
+
+/// Emits the event to the socket.io room, e.g. the id of a socket to reach it alone.
+pub async fn socketio_emit(room: &str, event: &str, payload: Vec<u8>) -> Result<()> {
+	ActorId::Static(NAME)
+		.call(SocketioEmitRequest {
+			room: room.to_string(),
+			event: event.to_string(),
+			payload,
+		})
+		.await?;
+	Ok(())
+}
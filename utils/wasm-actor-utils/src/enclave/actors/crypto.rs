@@ -1,7 +1,8 @@
 use crate::enclave::error::{Error, Result};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
 use prost::Message;
 use tea_actorx::ActorId;
-use tea_runtime_codec::tapp::Account;
+use tea_runtime_codec::tapp::{Account, Hash, ReplicaId};
 use tea_runtime_codec::vmh::message::{encode_protobuf, structs_proto::crypto};
 use tea_sdk::IntoGlobal;
 use tea_system_actors::crypto::*;
@@ -31,6 +32,20 @@ pub async fn ether_verify(account: Account, data: String, signature_hex: String)
 	Ok(res.0)
 }
 
+/// Verify the ed25519 signature of a node over `hash` with its tea id as the public key.
+///
+/// Pass it to `ArgSlots::oracle_record` to check that an oracle record was signed by the node
+/// that fetched it.
+pub fn verify_node_signature(tea_id: &ReplicaId, hash: &Hash, signature: &[u8]) -> bool {
+	let (Ok(public_key), Ok(signature)) = (
+		PublicKey::from_bytes(tea_id),
+		Signature::try_from(signature),
+	) else {
+		return false;
+	};
+	public_key.verify(hash, &signature).is_ok()
+}
+
 pub async fn aes_encrypt(key: Vec<u8>, data: Vec<u8>) -> Result<Vec<u8>> {
 	let res = ActorId::Static(NAME)
 		.call(AesEncryptRequest(encode_protobuf(
@@ -53,3 +68,54 @@ pub async fn aes_decrypt(key: Vec<u8>, encrypted_data: Vec<u8>) -> Result<Vec<u8
 	let res_data = crypto::AesDecryptResponse::decode(res.0.as_slice()).into_g::<Error>()?;
 	Ok(res_data.data)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ed25519_dalek::{Keypair, SecretKey, Signer};
+	use tea_runtime_codec::actor_txns::pre_args::{
+		ArgResult, ArgSlots, OracleHttpArg, OracleRecord,
+	};
+
+	#[test]
+	fn oracle_records_are_verified() {
+		let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+		let keypair = Keypair {
+			public: PublicKey::from(&secret),
+			secret,
+		};
+		let mut record = OracleRecord {
+			request: OracleHttpArg {
+				method: "GET".into(),
+				url: "https://example.com/price".into(),
+				..Default::default()
+			},
+			status: 200,
+			body: b"42".to_vec(),
+			fetched_at: 100,
+			node: keypair.public.to_bytes(),
+			signature: vec![],
+		};
+		record.signature = keypair
+			.sign(&record.signed_hash().unwrap())
+			.to_bytes()
+			.to_vec();
+		let slots = |record: &OracleRecord| ArgSlots {
+			args: vec![ArgResult::from_oracle_record(record).unwrap()],
+		};
+		assert_eq!(
+			slots(&record)
+				.oracle_record(&record.request, verify_node_signature)
+				.unwrap(),
+			Some(record.clone())
+		);
+
+		let mut forged = record.clone();
+		forged.body = b"43".to_vec();
+		assert!(slots(&forged)
+			.oracle_record(&forged.request, verify_node_signature)
+			.is_err());
+		forged.signature = vec![1, 2, 3];
+		assert!(forged.verify(verify_node_signature).is_err());
+	}
+}
@@ -1,7 +1,9 @@
-use crate::enclave::error::Result;
+use crate::enclave::error::{Errors, Result};
 use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
 use tea_actorx::ActorId;
 use tea_codec::{deserialize, serialize, IntoGlobal};
+use tea_runtime_codec::tapp::Hash;
 use tea_system_actors::keyvalue::actions::*;
 
 const KVP_ACTOR: ActorId = ActorId::Static(tea_system_actors::keyvalue::NAME);
@@ -77,6 +79,128 @@ pub async fn cancel_lock(key: &str) -> Result<()> {
 	Ok(())
 }
 
+/// How many times `update` retries before giving up when other actors keep writing the key.
+pub const UPDATE_RETRIES: usize = 16;
+
+/// Return cache value from key-value actor with its version, the version of absent keys is 0
+pub async fn get_versioned<T: DeserializeOwned>(key: &str) -> Result<(Option<T>, u64)> {
+	let req = GetVersionedRequest {
+		key: key.to_owned(),
+	};
+	let r = KVP_ACTOR.call(req).await?;
+	Ok((r.value.map(deserialize).transpose()?, r.version))
+}
+
+/// The hash of a value as compared by `CasCondition::ValueHash`
+pub fn value_hash<T: Serialize>(value: &T) -> Result<Hash> {
+	Ok(Sha256::digest(&serialize(value)?).into())
+}
+
+/// Set cache value, or remove it if `value` is `None`, only if `condition` holds.
+/// Return the new version, or `None` if the condition did not hold
+pub async fn compare_and_set<T: Serialize>(
+	key: &str,
+	condition: CasCondition,
+	value: Option<&T>,
+	expires_s: Option<i32>,
+) -> Result<Option<u64>> {
+	let req = CasRequest {
+		key: key.to_owned(),
+		condition,
+		value: value.map(serialize).transpose()?,
+		expires_s,
+	};
+	let r = KVP_ACTOR.call(req).await?;
+	Ok(r.success.then_some(r.version))
+}
+
+/// Read-modify-write of a cache value without locks.
+///
+/// `f` gets the current value and returns the new one (`None` removes the key) together with
+/// its result. If another actor wrote the key in between, `f` is called again with that value,
+/// so it must not have side effects. Nothing is held between the calls, so an actor crashing
+/// half way never blocks the others.
+pub async fn update<T, R>(
+	key: &str,
+	expires_s: Option<i32>,
+	mut f: impl FnMut(Option<T>) -> Result<(Option<T>, R)>,
+) -> Result<R>
+where
+	T: Serialize + DeserializeOwned,
+{
+	let (mut current, mut version) = get_versioned::<T>(key).await?;
+	for _ in 0..UPDATE_RETRIES {
+		let (value, result) = f(current)?;
+		let req = CasRequest {
+			key: key.to_owned(),
+			condition: CasCondition::Version(version),
+			value: value.as_ref().map(serialize).transpose()?,
+			expires_s,
+		};
+		let r = KVP_ACTOR.call(req).await?;
+		if r.success {
+			return Ok(result);
+		}
+		current = r.current.map(deserialize).transpose()?;
+		version = r.version;
+	}
+	Err(Errors::KeyValueUpdateContended(key.to_owned(), UPDATE_RETRIES).into())
+}
+
+/// Sets and removes of several keys applied together, only if every condition holds
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+	conditions: Vec<(String, CasCondition)>,
+	ops: Vec<BatchOp>,
+}
+
+impl Batch {
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	pub fn condition(mut self, key: &str, condition: CasCondition) -> Self {
+		self.conditions.push((key.to_owned(), condition));
+		self
+	}
+
+	pub fn set<T: Serialize>(
+		mut self,
+		key: &str,
+		value: &T,
+		expires_s: Option<i32>,
+	) -> Result<Self> {
+		self.ops.push(BatchOp::Set {
+			key: key.to_owned(),
+			value: serialize(value)?,
+			expires_s,
+		});
+		Ok(self)
+	}
+
+	pub fn del(mut self, key: &str) -> Self {
+		self.ops.push(BatchOp::Del {
+			key: key.to_owned(),
+		});
+		self
+	}
+
+	/// Applies the batch. Return the versions written by each op in order, or the keys of
+	/// which the condition did not hold if nothing was written
+	pub async fn commit(self) -> Result<std::result::Result<Vec<u64>, Vec<String>>> {
+		let req = BatchRequest {
+			conditions: self.conditions,
+			ops: self.ops,
+		};
+		let r = KVP_ACTOR.call(req).await?;
+		Ok(if r.success {
+			Ok(r.versions)
+		} else {
+			Err(r.failed)
+		})
+	}
+}
+
 #[doc(hidden)]
 pub async fn add(key: &str, value: i32) -> Result<i32> {
 	let req = AddRequest {
@@ -2,7 +2,8 @@ use super::{
 	enclave::generate_uuid,
 	replica::{random_select_validators_locally, IntelliSendMode},
 };
-use crate::enclave::error::{Error, Errors, Result};
+use crate::enclave::error::{Error, Errors, InvalidQuorum, QuorumNotReached, Result};
+use futures::future::join_all;
 #[cfg(feature = "__test")]
 use mocktopus::macros::mockable;
 use prost::Message;
@@ -29,6 +30,43 @@ use tea_system_actors::libp2p::{
 
 const INTELLI_CANDIDATES_COUNT: usize = 2;
 
+/// How many validators a quorum query is sent to, and how many of them must give the
+/// same response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+	required: usize,
+	total: usize,
+}
+
+impl Quorum {
+	/// Fails if `required` is not a majority of `total`, as two responses could reach the
+	/// quorum then.
+	pub fn new(required: usize, total: usize) -> std::result::Result<Self, InvalidQuorum> {
+		if total / 2 < required && required <= total {
+			Ok(Quorum { required, total })
+		} else {
+			Err(InvalidQuorum { required, total })
+		}
+	}
+
+	pub fn required(&self) -> usize {
+		self.required
+	}
+
+	pub fn total(&self) -> usize {
+		self.total
+	}
+}
+
+/// The response of a quorum query with the validators that gave it.
+#[derive(Debug, Clone)]
+pub struct QuorumResponse<T> {
+	pub value: T,
+	pub agreed: Vec<String>,
+	/// Validators that gave another response or failed to respond
+	pub dissenters: Vec<String>,
+}
+
 #[cfg(not(feature = "__test"))]
 /// Return current node's connection id
 pub async fn my_conn_id() -> Result<String> {
@@ -185,7 +223,137 @@ where
 			Ok(rtn)
 		}
 		IntelliSendMode::BothOk => compatible_query_ex(target, arg, timeout_ms).await,
+		IntelliSendMode::Quorum(quorum) => {
+			let rtn = quorum_actor_query_ex(target, arg, quorum, timeout_ms).await?;
+			if !rtn.dissenters.is_empty() {
+				warn!(
+					"validators {:?} dissent from the quorum response of {}",
+					rtn.dissenters,
+					ActorId::Static(target)
+				);
+			}
+			Ok(rtn.value)
+		}
+	}
+}
+
+/// Sends the query to `quorum.total()` random validators at once, and returns the response
+/// at least `quorum.required()` of them gave byte for byte. Fails without sending if fewer than
+/// `quorum.required()` validators are known.
+pub async fn quorum_actor_query_ex<C>(
+	target: &[u8],
+	arg: C,
+	quorum: Quorum,
+	timeout_ms: Option<u64>,
+) -> Result<QuorumResponse<C::Response>>
+where
+	C: Request + ToBytes,
+	C::Response: for<'a> FromBytes<'a> + Send,
+{
+	let conn_ids = select_quorum_validators(quorum).await?;
+	quorum_actor_query_on(target, arg, quorum, &conn_ids, timeout_ms).await
+}
+
+/// Picks `quorum.total()` random validators to ask, so that several queries can go to the same
+/// ones. Fails if fewer than `quorum.required()` validators are known.
+pub async fn select_quorum_validators(quorum: Quorum) -> Result<Vec<String>> {
+	let conn_ids = random_select_validators_locally(quorum.total)
+		.await?
+		.into_iter()
+		.map(|(_, conn_id)| conn_id)
+		.collect::<Vec<_>>();
+	if conn_ids.len() < quorum.required {
+		return Err(QuorumNotReached {
+			required: quorum.required,
+			asked: 0,
+			agreed: 0,
+			groups: vec![],
+			failed: vec![],
+		}
+		.into());
 	}
+	Ok(conn_ids)
+}
+
+/// Like [`quorum_actor_query_ex`], but asks the given validators, usually picked by
+/// [`select_quorum_validators`].
+pub async fn quorum_actor_query_on<C>(
+	target: &[u8],
+	arg: C,
+	quorum: Quorum,
+	conn_ids: &[String],
+	timeout_ms: Option<u64>,
+) -> Result<QuorumResponse<C::Response>>
+where
+	C: Request + ToBytes,
+	C::Response: for<'a> FromBytes<'a> + Send,
+{
+	let msg = generate_query_message(target, &arg.to_bytes()?).await?;
+	let responses = join_all(
+		conn_ids
+			.iter()
+			.map(|conn_id| send_to_state_receiver(conn_id.clone(), msg.clone(), timeout_ms)),
+	)
+	.await;
+
+	let (bytes, agreed, dissenters) = tally_quorum(
+		quorum,
+		conn_ids
+			.iter()
+			.cloned()
+			.zip(responses.into_iter().map(|x| x.ok())),
+	)?;
+	Ok(QuorumResponse {
+		value: C::Response::from_bytes(&bytes)?,
+		agreed,
+		dissenters,
+	})
+}
+
+/// The response with the validators agreeing and the others.
+type Tally = (Vec<u8>, Vec<String>, Vec<String>);
+
+/// Groups the validators by their responses and picks the largest group if it has at least
+/// `quorum.required()` validators. Returns the response, the validators agreeing and the others.
+fn tally_quorum(
+	quorum: Quorum,
+	responses: impl IntoIterator<Item = (String, Option<Vec<u8>>)>,
+) -> Result<Tally, QuorumNotReached> {
+	let required = quorum.required;
+	let mut groups: Vec<(Vec<u8>, Vec<String>)> = Vec::new();
+	let mut failed = Vec::new();
+	let mut asked = 0;
+	for (conn_id, response) in responses {
+		asked += 1;
+		let Some(bytes) = response else {
+			failed.push(conn_id);
+			continue;
+		};
+		match groups.iter_mut().find(|(x, _)| *x == bytes) {
+			Some((_, peers)) => peers.push(conn_id),
+			None => groups.push((bytes, vec![conn_id])),
+		}
+	}
+	groups.sort_by(|(_, a), (_, b)| b.len().cmp(&a.len()));
+
+	let agreed = groups.first().map_or(0, |(_, peers)| peers.len());
+	if agreed < required {
+		return Err(QuorumNotReached {
+			required,
+			asked,
+			agreed,
+			groups: groups.into_iter().map(|(_, peers)| peers).collect(),
+			failed,
+		});
+	}
+
+	let (bytes, agreed) = groups.remove(0);
+	let dissenters = groups
+		.into_iter()
+		.flat_map(|(_, peers)| peers)
+		.chain(failed)
+		.collect();
+	Ok((bytes, agreed, dissenters))
 }
 
 #[doc(hidden)]
@@ -385,3 +553,79 @@ pub async fn generate_query_message(
 		from_token,
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn responses(items: Vec<(&str, Option<Vec<u8>>)>) -> Vec<(String, Option<Vec<u8>>)> {
+		items
+			.into_iter()
+			.map(|(peer, bytes)| (peer.to_string(), bytes))
+			.collect()
+	}
+
+	#[test]
+	fn quorum_picks_largest_agreeing_group() {
+		let (bytes, agreed, dissenters) = tally_quorum(
+			Quorum::new(3, 5).unwrap(),
+			responses(vec![
+				("a", Some(vec![1])),
+				("b", Some(vec![2])),
+				("c", Some(vec![1])),
+				("d", None),
+				("e", Some(vec![1])),
+			]),
+		)
+		.unwrap();
+		assert_eq!(bytes, vec![1]);
+		assert_eq!(agreed, vec!["a", "c", "e"]);
+		assert_eq!(dissenters, vec!["b", "d"]);
+	}
+
+	#[test]
+	fn quorum_not_reached() {
+		let err = tally_quorum(
+			Quorum::new(2, 3).unwrap(),
+			responses(vec![
+				("a", Some(vec![1])),
+				("b", Some(vec![2])),
+				("c", None),
+			]),
+		)
+		.unwrap_err();
+		assert_eq!(
+			err,
+			QuorumNotReached {
+				required: 2,
+				asked: 3,
+				agreed: 1,
+				groups: vec![vec!["a".to_string()], vec!["b".to_string()]],
+				failed: vec!["c".to_string()],
+			}
+		);
+	}
+
+	#[test]
+	fn quorum_requires_a_majority() {
+		assert_eq!(
+			Quorum::new(2, 4),
+			Err(InvalidQuorum {
+				required: 2,
+				total: 4
+			})
+		);
+		assert!(Quorum::new(1, 1).is_ok());
+	}
+
+	#[test]
+	fn quorum_requires_no_more_than_asked() {
+		assert_eq!(
+			Quorum::new(4, 3),
+			Err(InvalidQuorum {
+				required: 4,
+				total: 3
+			})
+		);
+	}
+}
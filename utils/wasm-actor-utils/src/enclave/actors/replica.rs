@@ -7,7 +7,7 @@ use crate::enclave::{
 		crypto::sha256,
 		enclave::get_my_tea_id,
 		env::{self, system_time_as_nanos},
-		libp2p::try_send_remotely,
+		libp2p::{try_send_remotely, Quorum},
 		tappstore::process_pre_args,
 	},
 	error::{Error, Errors, ProviderOperationRejected, Result},
@@ -26,6 +26,7 @@ use tea_runtime_codec::vmh::message::{
 use tea_runtime_codec::{
 	actor_txns::{
 		pre_args::{Arg, ArgSlots},
+		receipt::TxnReceipt,
 		tsid::Tsid,
 		Followup, TxnSerial,
 	},
@@ -33,8 +34,8 @@ use tea_runtime_codec::{
 };
 use tea_sdk::{IntoGlobal, ResultExt};
 use tea_system_actors::replica::{
-	GetExecCursorRequest, ReceiveFollowupRequest, ReceiveTxnRequest, ReportTxnExecErrorRequest,
-	NAME,
+	AppendTxnReceiptRequest, GetExecCursorRequest, QueryTxnReceiptRequest, ReceiveFollowupRequest,
+	ReceiveTxnRequest, ReportTxnExecErrorRequest, NAME,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -42,6 +43,9 @@ pub enum IntelliSendMode {
 	LocalOnly,
 	RemoteOnly,
 	BothOk,
+	/// Queries are sent to `Quorum::total` validators and their responses must agree,
+	/// txns are sent as in `RemoteOnly`.
+	Quorum(Quorum),
 }
 
 impl Default for IntelliSendMode {
@@ -61,7 +65,10 @@ pub async fn intelli_send_txn(
 ) -> Result<Option<Tsid>> {
 	let txn_serial = new_txn_serial(target_actor, txn_bytes.to_vec(), 0b01, gas_limit).await?;
 
-	if mode == IntelliSendMode::RemoteOnly {
+	if matches!(
+		mode,
+		IntelliSendMode::RemoteOnly | IntelliSendMode::Quorum(_)
+	) {
 		return try_send_transaction_remotely(&txn_serial, pre_args, &None, timeout_ms).await;
 	}
 
@@ -243,6 +250,28 @@ pub async fn report_txn_error(txn_hash: Vec<u8>, error_msg: String) -> Result<()
 	Ok(())
 }
 
+/// Stores the receipt of an executed txn, see `TxnReceipt::committed` and `TxnReceipt::failed`.
+pub async fn append_txn_receipt(receipt: TxnReceipt) -> Result<()> {
+	ActorId::Static(NAME)
+		.call(AppendTxnReceiptRequest(receipt))
+		.await?;
+	Ok(())
+}
+
+/// The receipt of an executed txn, with only the events of `event_types` (all if empty).
+pub async fn query_txn_receipt(
+	txn_hash: Hash,
+	event_types: Vec<String>,
+) -> Result<Option<TxnReceipt>> {
+	let res = ActorId::Static(NAME)
+		.call(QueryTxnReceiptRequest {
+			txn_hash,
+			event_types,
+		})
+		.await?;
+	Ok(res.0)
+}
+
 #[doc(hidden)]
 pub async fn import_round_table(round_table_serial: Vec<u8>) -> Result<()> {
 	ActorId::Static(tea_system_actors::replica_service::NAME)
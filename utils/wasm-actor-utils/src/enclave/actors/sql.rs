@@ -0,0 +1,589 @@
+//! Typed access to the GlueSQL database of a tApp.
+//!
+//! Values are written into sql through `ToSql`, which quotes and escapes them, either by
+//! binding them to the `?` placeholders of a statement with `Sql` or through the query
+//! builders `Select`, `Insert`, `Update` and `Delete`. Selected rows are read back through
+//! `FromRow`, which is implemented for tuples by column position and can be derived for
+//! structs by column name. These fail with `GlueSqlErrors`, which `?` turns into `Error`.
+//!
+//! Balances and other `U256` values are stored as decimal strings, so columns of them sort
+//! as text in `ORDER BY`, e.g. "9" after "10". Order by a numeric column instead, or sort
+//! the rows after reading them.
+//!
+//! ```ignore
+//! #[derive(FromRow)]
+//! struct Fee {
+//!     account: Account,
+//!     amount: Balance,
+//!     timestamp: u64,
+//! }
+//!
+//! let sql = Select::from("TxnGasFeeTable")
+//!     .filter(col("account").eq(account))
+//!     .order_by("timestamp", Order::Desc)
+//!     .limit(10)
+//!     .build()?;
+//! let fees: Vec<Fee> = query_as(tappstore_id().await?, sql).await?;
+//! ```
+
+pub mod builder;
+pub mod migration;
+
+pub use builder::{col, Column, Delete, Filter, Insert, Order, Select, Update};
+pub use tea_codec_macros::FromRow;
+
+use crate::enclave::actors::tokenstate::sql_query_first;
+use crate::enclave::error::{GlueSqlErrors, Result};
+use gluesql_core::prelude::{Payload, Row, Value};
+use primitive_types::{H160, H256, U256};
+use tea_runtime_codec::tapp::TokenId;
+
+#[doc(hidden)]
+pub mod __private {
+	pub use gluesql_core::prelude::Row;
+
+	pub type Result<T> = std::result::Result<T, crate::enclave::error::GlueSqlErrors>;
+}
+
+/// A value that can be written into sql as a literal.
+pub trait ToSql {
+	/// The literal of the value, strings are quoted and escaped.
+	fn to_sql(&self) -> String;
+}
+
+/// A value that can be read from a GlueSQL value.
+pub trait FromSqlValue: Sized {
+	/// Returns `None` if the value is not of this type or out of its range.
+	fn from_sql_value(value: &Value) -> Option<Self>;
+
+	/// The name of the type in errors.
+	fn type_name() -> &'static str {
+		std::any::type_name::<Self>()
+	}
+
+	/// The value to read if the column is not selected at all.
+	fn missing() -> Option<Self> {
+		None
+	}
+}
+
+/// A type that can be read from a row of a `Payload::Select`.
+pub trait FromRow: Sized {
+	fn from_row(labels: &[String], row: &Row) -> Result<Self, GlueSqlErrors>;
+}
+
+/// Quotes a string literal, doubling the single quotes in it.
+pub fn quote_str(s: &str) -> String {
+	format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Checks that a table or column name is a plain identifier, so that it can be written into
+/// sql as it is.
+pub fn check_ident(ident: &str) -> Result<&str, GlueSqlErrors> {
+	let mut chars = ident.chars();
+	let valid = chars
+		.next()
+		.map(|x| x.is_ascii_alphabetic() || x == '_')
+		.unwrap_or(false)
+		&& chars.all(|x| x.is_ascii_alphanumeric() || x == '_');
+	if !valid {
+		return Err(GlueSqlErrors::InvalidIdentifier(ident.to_string()));
+	}
+	Ok(ident)
+}
+
+/// A statement with `?` placeholders and the values bound to them in order.
+///
+/// Placeholders inside quoted strings and identifiers, and inside `--` and `/* */` comments,
+/// are left as they are.
+#[derive(Default)]
+pub struct Sql {
+	sql: String,
+	params: Vec<String>,
+}
+
+/// The part of a statement `Sql::build` is in.
+#[derive(Clone, Copy)]
+enum Context {
+	Code,
+	Quoted(char),
+	LineComment,
+	BlockComment,
+}
+
+impl Sql {
+	pub fn new(sql: impl Into<String>) -> Self {
+		Sql {
+			sql: sql.into(),
+			params: Vec::new(),
+		}
+	}
+
+	pub fn bind(mut self, value: impl ToSql) -> Self {
+		self.params.push(value.to_sql());
+		self
+	}
+
+	/// The statement with every placeholder replaced by its bound value.
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		let mut rtn = String::with_capacity(self.sql.len());
+		let mut params = self.params.iter();
+		let mut placeholders = 0;
+		let mut context = Context::Code;
+		let mut chars = self.sql.chars().peekable();
+		while let Some(c) = chars.next() {
+			let next = chars.peek().copied();
+			match (context, c, next) {
+				(Context::Code, '?', _) => {
+					placeholders += 1;
+					if let Some(param) = params.next() {
+						rtn.push_str(param);
+					}
+					continue;
+				}
+				(Context::Code, '\'' | '"', _) => context = Context::Quoted(c),
+				(Context::Code, '-', Some('-')) => context = Context::LineComment,
+				// the opening and closing pairs are taken whole, so that `/*/` is not closed
+				(Context::Code, '/', Some('*')) | (Context::BlockComment, '*', Some('/')) => {
+					context = match context {
+						Context::Code => Context::BlockComment,
+						_ => Context::Code,
+					};
+					rtn.push(c);
+					rtn.extend(chars.next());
+					continue;
+				}
+				// a doubled quote closes and reopens the string, which leaves it open
+				(Context::Quoted(q), c, _) if q == c => context = Context::Code,
+				(Context::LineComment, '\n', _) => context = Context::Code,
+				_ => {}
+			}
+			rtn.push(c);
+		}
+		if placeholders != self.params.len() {
+			return Err(GlueSqlErrors::BindCountMismatch(
+				placeholders,
+				self.params.len(),
+			));
+		}
+		Ok(rtn)
+	}
+}
+
+/// Reads the columns of a row by name, see `FromRow`.
+pub struct RowReader<'a> {
+	labels: &'a [String],
+	row: &'a Row,
+}
+
+impl<'a> RowReader<'a> {
+	pub fn new(labels: &'a [String], row: &'a Row) -> Result<Self, GlueSqlErrors> {
+		if labels.len() != row.0.len() {
+			return Err(GlueSqlErrors::RowLengthMismatch(labels.len(), row.0.len()));
+		}
+		Ok(RowReader { labels, row })
+	}
+
+	/// Reads a column, failing if it is not selected unless `T` has a value for missing columns.
+	pub fn get<T: FromSqlValue>(&self, column: &str) -> Result<T, GlueSqlErrors> {
+		match self.row.get_value(self.labels, column) {
+			Some(value) => read_value(column, value),
+			None => T::missing().ok_or_else(|| {
+				GlueSqlErrors::ColumnNotFound(column.to_string(), self.labels.to_vec())
+			}),
+		}
+	}
+
+	/// Reads a column, or the default value if the column is not selected.
+	pub fn get_or_default<T: FromSqlValue + Default>(
+		&self,
+		column: &str,
+	) -> Result<T, GlueSqlErrors> {
+		match self.row.get_value(self.labels, column) {
+			Some(value) => read_value(column, value),
+			None => Ok(T::default()),
+		}
+	}
+}
+
+fn read_value<T: FromSqlValue>(column: &str, value: &Value) -> Result<T, GlueSqlErrors> {
+	T::from_sql_value(value).ok_or_else(|| {
+		GlueSqlErrors::ColumnTypeMismatch(
+			column.to_string(),
+			T::type_name().to_string(),
+			format!("{value:?}"),
+		)
+	})
+}
+
+/// Reads all rows of a select payload.
+pub fn query_rows<T: FromRow>(payload: &Payload) -> Result<Vec<T>, GlueSqlErrors> {
+	let Payload::Select { labels, rows } = payload else {
+		return Err(GlueSqlErrors::InvalidSelectResult);
+	};
+	rows.iter().map(|row| T::from_row(labels, row)).collect()
+}
+
+/// Runs a select statement and reads all of its rows.
+pub async fn query_as<T: FromRow>(token_id: TokenId, sql: String) -> Result<Vec<T>> {
+	Ok(query_rows(&sql_query_first(token_id, sql).await?)?)
+}
+
+/// Runs a select statement and reads its first row, if any.
+pub async fn query_one<T: FromRow>(token_id: TokenId, sql: String) -> Result<Option<T>> {
+	Ok(query_as(token_id, sql).await?.into_iter().next())
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+	fn to_sql(&self) -> String {
+		(**self).to_sql()
+	}
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+	fn to_sql(&self) -> String {
+		match self {
+			Some(value) => value.to_sql(),
+			None => "NULL".to_string(),
+		}
+	}
+}
+
+impl ToSql for str {
+	fn to_sql(&self) -> String {
+		quote_str(self)
+	}
+}
+
+impl ToSql for String {
+	fn to_sql(&self) -> String {
+		quote_str(self)
+	}
+}
+
+impl ToSql for bool {
+	fn to_sql(&self) -> String {
+		if *self { "TRUE" } else { "FALSE" }.to_string()
+	}
+}
+
+impl ToSql for f64 {
+	/// Non-finite floats have no literal, they are written as strings and fail at execution.
+	fn to_sql(&self) -> String {
+		if self.is_finite() {
+			format!("{self:?}")
+		} else {
+			quote_str(&self.to_string())
+		}
+	}
+}
+
+impl ToSql for [u8] {
+	fn to_sql(&self) -> String {
+		format!("X'{}'", hex::encode(self))
+	}
+}
+
+impl ToSql for Vec<u8> {
+	fn to_sql(&self) -> String {
+		self.as_slice().to_sql()
+	}
+}
+
+/// Hashes and accounts are written as `0x` prefixed hex strings.
+impl ToSql for H160 {
+	fn to_sql(&self) -> String {
+		quote_str(&format!("{self:?}"))
+	}
+}
+
+impl ToSql for H256 {
+	fn to_sql(&self) -> String {
+		quote_str(&format!("{self:?}"))
+	}
+}
+
+impl ToSql for TokenId {
+	fn to_sql(&self) -> String {
+		self.0.to_sql()
+	}
+}
+
+/// Balances do not fit into integer columns, they are written as decimal strings.
+impl ToSql for U256 {
+	fn to_sql(&self) -> String {
+		quote_str(&self.to_string())
+	}
+}
+
+macro_rules! impl_integer {
+	($($t:ty),*) => {
+		$(
+			impl ToSql for $t {
+				fn to_sql(&self) -> String {
+					self.to_string()
+				}
+			}
+
+			impl FromSqlValue for $t {
+				fn from_sql_value(value: &Value) -> Option<Self> {
+					match value {
+						Value::I8(x) => (*x).try_into().ok(),
+						Value::I16(x) => (*x).try_into().ok(),
+						Value::I32(x) => (*x).try_into().ok(),
+						Value::I64(x) => (*x).try_into().ok(),
+						Value::I128(x) => (*x).try_into().ok(),
+						Value::U8(x) => (*x).try_into().ok(),
+						// e.g. sums of integer columns, fractions are not read as integers
+						Value::Decimal(x) => x.normalize().to_string().parse().ok(),
+						_ => None,
+					}
+				}
+			}
+		)*
+	};
+}
+
+impl_integer!(i8, i16, i32, i64, i128, u8, u16, u32, u64, u128, usize);
+
+impl<T: FromSqlValue> FromSqlValue for Option<T> {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Null => Some(None),
+			value => T::from_sql_value(value).map(Some),
+		}
+	}
+
+	fn missing() -> Option<Self> {
+		Some(None)
+	}
+}
+
+impl FromSqlValue for Value {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		Some(value.clone())
+	}
+}
+
+impl FromSqlValue for String {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Str(x) => Some(x.clone()),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for bool {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Bool(x) => Some(*x),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for f64 {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::F64(x) => Some(*x),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for Vec<u8> {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Bytea(x) => Some(x.clone()),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for H160 {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Str(x) => x.parse().ok(),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for H256 {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Str(x) => x.parse().ok(),
+			_ => None,
+		}
+	}
+}
+
+impl FromSqlValue for TokenId {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		H160::from_sql_value(value).map(TokenId)
+	}
+}
+
+impl FromSqlValue for U256 {
+	fn from_sql_value(value: &Value) -> Option<Self> {
+		match value {
+			Value::Str(x) => U256::from_dec_str(x).ok(),
+			_ => None,
+		}
+	}
+}
+
+macro_rules! impl_from_row_for_tuple {
+	($($t:ident),*) => {
+		/// Tuples are read by column position, the row must have exactly one column per element.
+		impl<$($t: FromSqlValue),*> FromRow for ($($t,)*) {
+			fn from_row(labels: &[String], row: &Row) -> Result<Self, GlueSqlErrors> {
+				let len = impl_from_row_for_tuple!(@count $($t)*);
+				if labels.len() != len || row.0.len() != len {
+					return Err(GlueSqlErrors::RowLengthMismatch(len, row.0.len()));
+				}
+				let mut columns = labels.iter().zip(row.0.iter());
+				Ok(($({
+					let (column, value) = columns.next().expect("length checked above");
+					read_value::<$t>(column, value)?
+				},)*))
+			}
+		}
+	};
+	(@count $($t:ident)*) => { 0 $(+ impl_from_row_for_tuple!(@one $t))* };
+	(@one $t:ident) => { 1 };
+}
+
+impl_from_row_for_tuple!(A);
+impl_from_row_for_tuple!(A, B);
+impl_from_row_for_tuple!(A, B, C);
+impl_from_row_for_tuple!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(FromRow, Debug, PartialEq)]
+	#[from_row(path = "crate::enclave::actors::sql")]
+	struct Fee {
+		#[from_row(rename = "TxnHash")]
+		txn_hash: String,
+		amount: U256,
+		memo: Option<String>,
+		#[from_row(default)]
+		retries: u32,
+	}
+
+	fn labels(labels: &[&str]) -> Vec<String> {
+		labels.iter().map(|x| x.to_string()).collect()
+	}
+
+	#[test]
+	fn bind_escapes_values() {
+		let sql = Sql::new("SELECT * FROM T WHERE a = ? AND b = '?' AND c IN (?, ?)")
+			.bind("x' OR '1' = '1")
+			.bind(Some(-5i64))
+			.bind(None::<u64>)
+			.build()
+			.unwrap();
+		assert_eq!(
+			sql,
+			"SELECT * FROM T WHERE a = 'x'' OR ''1'' = ''1' AND b = '?' AND c IN (-5, NULL)"
+		);
+		assert_eq!(
+			Sql::new("SELECT ?").build(),
+			Err(GlueSqlErrors::BindCountMismatch(1, 0))
+		);
+		assert_eq!(
+			Sql::new("SELECT 'it''s ?'").bind(1).build(),
+			Err(GlueSqlErrors::BindCountMismatch(0, 1))
+		);
+	}
+
+	#[test]
+	fn bind_skips_comments() {
+		let sql = Sql::new("SELECT ? -- why?\nFROM T /* a?b */ WHERE a = ?")
+			.bind(1)
+			.bind("x")
+			.build()
+			.unwrap();
+		assert_eq!(sql, "SELECT 1 -- why?\nFROM T /* a?b */ WHERE a = 'x'");
+		assert_eq!(
+			Sql::new("SELECT 1 /*/ ? */, ?").bind(2).build().unwrap(),
+			"SELECT 1 /*/ ? */, 2"
+		);
+		assert_eq!(
+			Sql::new("SELECT '--', ? - ?")
+				.bind(3)
+				.bind(1)
+				.build()
+				.unwrap(),
+			"SELECT '--', 3 - 1"
+		);
+	}
+
+	#[test]
+	fn derive_from_row() {
+		let row = Row(vec![
+			Value::I64(3),
+			Value::Str("0xab".into()),
+			Value::Str("100".into()),
+		]);
+		let fee = Fee::from_row(&labels(&["ignored", "TxnHash", "amount"]), &row).unwrap();
+		assert_eq!(
+			fee,
+			Fee {
+				txn_hash: "0xab".into(),
+				amount: 100.into(),
+				memo: None,
+				retries: 0,
+			}
+		);
+
+		let row = Row(vec![Value::Str("0xab".into()), Value::I64(100)]);
+		assert_eq!(
+			Fee::from_row(&labels(&["TxnHash", "amount"]), &row),
+			Err(GlueSqlErrors::ColumnTypeMismatch(
+				"amount".into(),
+				U256::type_name().into(),
+				"I64(100)".into()
+			))
+		);
+		assert_eq!(
+			Fee::from_row(&labels(&["amount"]), &Row(vec![Value::Str("1".into())])),
+			Err(GlueSqlErrors::ColumnNotFound(
+				"TxnHash".into(),
+				labels(&["amount"])
+			))
+		);
+	}
+
+	#[test]
+	fn tuple_from_row() {
+		let row = Row(vec![Value::I64(7), Value::Null]);
+		let (count, name) = <(u64, Option<String>)>::from_row(&labels(&["a", "b"]), &row).unwrap();
+		assert_eq!((count, name), (7, None));
+		assert_eq!(
+			<(u8,)>::from_row(&labels(&["a"]), &Row(vec![Value::I64(300)])),
+			Err(GlueSqlErrors::ColumnTypeMismatch(
+				"a".into(),
+				"u8".into(),
+				"I64(300)".into()
+			))
+		);
+	}
+
+	#[test]
+	fn integers_from_decimals() {
+		let read = |x: &str| u64::from_sql_value(&Value::Decimal(x.parse().unwrap()));
+		assert_eq!(read("12"), Some(12));
+		assert_eq!(read("12.00"), Some(12));
+		assert_eq!(read("1.5"), None);
+		assert_eq!(read("-1"), None);
+		assert_eq!(
+			i8::from_sql_value(&Value::Decimal("-3.0".parse().unwrap())),
+			Some(-3)
+		);
+	}
+}
@@ -0,0 +1,449 @@
+use super::{check_ident, ToSql};
+use crate::enclave::error::{GlueSqlErrors, Result};
+
+/// A column in a filter, see `col`.
+pub struct Column(String);
+
+/// Starts a filter on the column, e.g. `col("amount").gt(10).and(col("memo").is_null())`.
+pub fn col(name: &str) -> Column {
+	Column(name.to_string())
+}
+
+impl Column {
+	pub fn eq(self, value: impl ToSql) -> Filter {
+		self.cmp("=", value)
+	}
+
+	pub fn ne(self, value: impl ToSql) -> Filter {
+		self.cmp("<>", value)
+	}
+
+	pub fn lt(self, value: impl ToSql) -> Filter {
+		self.cmp("<", value)
+	}
+
+	pub fn le(self, value: impl ToSql) -> Filter {
+		self.cmp("<=", value)
+	}
+
+	pub fn gt(self, value: impl ToSql) -> Filter {
+		self.cmp(">", value)
+	}
+
+	pub fn ge(self, value: impl ToSql) -> Filter {
+		self.cmp(">=", value)
+	}
+
+	pub fn is_null(self) -> Filter {
+		Filter::IsNull(self.0, true)
+	}
+
+	pub fn is_not_null(self) -> Filter {
+		Filter::IsNull(self.0, false)
+	}
+
+	/// Matches none of the rows if `values` is empty.
+	pub fn in_list<T: ToSql>(self, values: impl IntoIterator<Item = T>) -> Filter {
+		Filter::In(self.0, values.into_iter().map(|x| x.to_sql()).collect())
+	}
+
+	fn cmp(self, op: &'static str, value: impl ToSql) -> Filter {
+		Filter::Cmp(self.0, op, value.to_sql())
+	}
+}
+
+/// A `WHERE` condition, the values in it are written as sql literals already.
+pub enum Filter {
+	Cmp(String, &'static str, String),
+	IsNull(String, bool),
+	In(String, Vec<String>),
+	And(Vec<Filter>),
+	Or(Vec<Filter>),
+	Not(Box<Filter>),
+}
+
+impl Filter {
+	pub fn and(self, other: Filter) -> Filter {
+		match self {
+			Filter::And(mut filters) => {
+				filters.push(other);
+				Filter::And(filters)
+			}
+			filter => Filter::And(vec![filter, other]),
+		}
+	}
+
+	pub fn or(self, other: Filter) -> Filter {
+		match self {
+			Filter::Or(mut filters) => {
+				filters.push(other);
+				Filter::Or(filters)
+			}
+			filter => Filter::Or(vec![filter, other]),
+		}
+	}
+
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		Ok(match self {
+			Filter::Cmp(column, op, value) => format!("{} {op} {value}", check_ident(column)?),
+			Filter::IsNull(column, true) => format!("{} IS NULL", check_ident(column)?),
+			Filter::IsNull(column, false) => format!("{} IS NOT NULL", check_ident(column)?),
+			Filter::In(column, values) if values.is_empty() => {
+				check_ident(column)?;
+				"FALSE".to_string()
+			}
+			Filter::In(column, values) => {
+				format!("{} IN ({})", check_ident(column)?, values.join(", "))
+			}
+			Filter::And(filters) => join_filters(filters, " AND ", "TRUE")?,
+			Filter::Or(filters) => join_filters(filters, " OR ", "FALSE")?,
+			Filter::Not(filter) => format!("NOT ({})", filter.build()?),
+		})
+	}
+}
+
+impl std::ops::Not for Filter {
+	type Output = Filter;
+
+	fn not(self) -> Filter {
+		Filter::Not(Box::new(self))
+	}
+}
+
+fn join_filters(filters: &[Filter], sep: &str, empty: &str) -> Result<String, GlueSqlErrors> {
+	if filters.is_empty() {
+		return Ok(empty.to_string());
+	}
+	Ok(filters
+		.iter()
+		.map(|x| Ok(format!("({})", x.build()?)))
+		.collect::<Result<Vec<_>, GlueSqlErrors>>()?
+		.join(sep))
+}
+
+fn where_clause(filter: &Option<Filter>) -> Result<String, GlueSqlErrors> {
+	Ok(match filter {
+		Some(filter) => format!(" WHERE {}", filter.build()?),
+		None => String::new(),
+	})
+}
+
+fn add_filter(current: Option<Filter>, filter: Filter) -> Option<Filter> {
+	Some(match current {
+		Some(current) => current.and(filter),
+		None => filter,
+	})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+	Asc,
+	Desc,
+}
+
+/// `SELECT <columns> FROM <table> [WHERE ..] [ORDER BY ..] [LIMIT ..] [OFFSET ..]`,
+/// selecting all columns unless `columns` is called.
+pub struct Select {
+	table: String,
+	columns: Vec<String>,
+	filter: Option<Filter>,
+	order_by: Vec<(String, Order)>,
+	limit: Option<u64>,
+	offset: Option<u64>,
+}
+
+impl Select {
+	pub fn from(table: &str) -> Self {
+		Select {
+			table: table.to_string(),
+			columns: Vec::new(),
+			filter: None,
+			order_by: Vec::new(),
+			limit: None,
+			offset: None,
+		}
+	}
+
+	pub fn columns(mut self, columns: &[&str]) -> Self {
+		self.columns.extend(columns.iter().map(|x| x.to_string()));
+		self
+	}
+
+	/// Adds a filter, which is joined with the previous ones by `AND`.
+	pub fn filter(mut self, filter: Filter) -> Self {
+		self.filter = add_filter(self.filter, filter);
+		self
+	}
+
+	pub fn order_by(mut self, column: &str, order: Order) -> Self {
+		self.order_by.push((column.to_string(), order));
+		self
+	}
+
+	pub fn limit(mut self, limit: u64) -> Self {
+		self.limit = Some(limit);
+		self
+	}
+
+	pub fn offset(mut self, offset: u64) -> Self {
+		self.offset = Some(offset);
+		self
+	}
+
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		let columns = if self.columns.is_empty() {
+			"*".to_string()
+		} else {
+			self.columns
+				.iter()
+				.map(|x| check_ident(x))
+				.collect::<Result<Vec<_>, GlueSqlErrors>>()?
+				.join(", ")
+		};
+		let mut sql = format!(
+			"SELECT {columns} FROM {}{}",
+			check_ident(&self.table)?,
+			where_clause(&self.filter)?
+		);
+		if !self.order_by.is_empty() {
+			let order_by = self
+				.order_by
+				.iter()
+				.map(|(column, order)| {
+					let order = match order {
+						Order::Asc => "ASC",
+						Order::Desc => "DESC",
+					};
+					Ok(format!("{} {order}", check_ident(column)?))
+				})
+				.collect::<Result<Vec<_>, GlueSqlErrors>>()?;
+			sql += &format!(" ORDER BY {}", order_by.join(", "));
+		}
+		if let Some(limit) = self.limit {
+			sql += &format!(" LIMIT {limit}");
+		}
+		if let Some(offset) = self.offset {
+			sql += &format!(" OFFSET {offset}");
+		}
+		Ok(sql)
+	}
+}
+
+/// `INSERT INTO <table> (<columns>) VALUES (<values>)` of a single row.
+pub struct Insert {
+	table: String,
+	values: Vec<(String, String)>,
+}
+
+impl Insert {
+	pub fn into(table: &str) -> Self {
+		Insert {
+			table: table.to_string(),
+			values: Vec::new(),
+		}
+	}
+
+	pub fn value(mut self, column: &str, value: impl ToSql) -> Self {
+		self.values.push((column.to_string(), value.to_sql()));
+		self
+	}
+
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		if self.values.is_empty() {
+			return Err(GlueSqlErrors::EmptyStatement(
+				"INSERT".into(),
+				self.table.clone(),
+			));
+		}
+		let columns = self
+			.values
+			.iter()
+			.map(|(column, _)| check_ident(column))
+			.collect::<Result<Vec<_>, GlueSqlErrors>>()?;
+		let values = self.values.iter().map(|(_, value)| value.as_str());
+		Ok(format!(
+			"INSERT INTO {} ({}) VALUES ({})",
+			check_ident(&self.table)?,
+			columns.join(", "),
+			values.collect::<Vec<_>>().join(", ")
+		))
+	}
+}
+
+/// `UPDATE <table> SET <column> = <value>, .. WHERE ..`.
+///
+/// A filter is required unless `all_rows` is called, so that a forgotten filter does not
+/// update the whole table.
+pub struct Update {
+	table: String,
+	values: Vec<(String, String)>,
+	filter: Option<Filter>,
+	all_rows: bool,
+}
+
+impl Update {
+	pub fn table(table: &str) -> Self {
+		Update {
+			table: table.to_string(),
+			values: Vec::new(),
+			filter: None,
+			all_rows: false,
+		}
+	}
+
+	pub fn set(mut self, column: &str, value: impl ToSql) -> Self {
+		self.values.push((column.to_string(), value.to_sql()));
+		self
+	}
+
+	/// Adds a filter, which is joined with the previous ones by `AND`.
+	pub fn filter(mut self, filter: Filter) -> Self {
+		self.filter = add_filter(self.filter, filter);
+		self
+	}
+
+	pub fn all_rows(mut self) -> Self {
+		self.all_rows = true;
+		self
+	}
+
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		if self.values.is_empty() {
+			return Err(GlueSqlErrors::EmptyStatement(
+				"UPDATE".into(),
+				self.table.clone(),
+			));
+		}
+		if self.filter.is_none() && !self.all_rows {
+			return Err(GlueSqlErrors::MissingFilter(
+				"UPDATE".into(),
+				self.table.clone(),
+			));
+		}
+		let values = self
+			.values
+			.iter()
+			.map(|(column, value)| Ok(format!("{} = {value}", check_ident(column)?)))
+			.collect::<Result<Vec<_>, GlueSqlErrors>>()?;
+		Ok(format!(
+			"UPDATE {} SET {}{}",
+			check_ident(&self.table)?,
+			values.join(", "),
+			where_clause(&self.filter)?
+		))
+	}
+}
+
+/// `DELETE FROM <table> WHERE ..`.
+///
+/// A filter is required unless `all_rows` is called, as in `Update`.
+pub struct Delete {
+	table: String,
+	filter: Option<Filter>,
+	all_rows: bool,
+}
+
+impl Delete {
+	pub fn from(table: &str) -> Self {
+		Delete {
+			table: table.to_string(),
+			filter: None,
+			all_rows: false,
+		}
+	}
+
+	/// Adds a filter, which is joined with the previous ones by `AND`.
+	pub fn filter(mut self, filter: Filter) -> Self {
+		self.filter = add_filter(self.filter, filter);
+		self
+	}
+
+	pub fn all_rows(mut self) -> Self {
+		self.all_rows = true;
+		self
+	}
+
+	pub fn build(&self) -> Result<String, GlueSqlErrors> {
+		if self.filter.is_none() && !self.all_rows {
+			return Err(GlueSqlErrors::MissingFilter(
+				"DELETE".into(),
+				self.table.clone(),
+			));
+		}
+		Ok(format!(
+			"DELETE FROM {}{}",
+			check_ident(&self.table)?,
+			where_clause(&self.filter)?
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn build_statements() {
+		assert_eq!(
+			Select::from("Fees")
+				.columns(&["account", "amount"])
+				.filter(col("account").eq("0x'1"))
+				.filter(col("amount").gt(5u64).or(col("memo").is_null()))
+				.order_by("amount", Order::Desc)
+				.limit(10)
+				.offset(20)
+				.build()
+				.unwrap(),
+			"SELECT account, amount FROM Fees WHERE (account = '0x''1') AND ((amount > 5) OR (memo IS NULL)) ORDER BY amount DESC LIMIT 10 OFFSET 20"
+		);
+		assert_eq!(
+			Insert::into("Fees")
+				.value("account", "a")
+				.value("amount", 1u64)
+				.value("memo", None::<String>)
+				.build()
+				.unwrap(),
+			"INSERT INTO Fees (account, amount, memo) VALUES ('a', 1, NULL)"
+		);
+		assert_eq!(
+			Update::table("Fees")
+				.set("amount", 2u64)
+				.filter(col("account").in_list(["a", "b"]))
+				.build()
+				.unwrap(),
+			"UPDATE Fees SET amount = 2 WHERE account IN ('a', 'b')"
+		);
+		assert_eq!(
+			Delete::from("Fees")
+				.filter(!col("account").in_list(Vec::<String>::new()))
+				.build()
+				.unwrap(),
+			"DELETE FROM Fees WHERE NOT (FALSE)"
+		);
+	}
+
+	#[test]
+	fn rejects_unsafe_statements() {
+		assert_eq!(
+			Select::from("Fees; DROP TABLE Fees").build(),
+			Err(GlueSqlErrors::InvalidIdentifier(
+				"Fees; DROP TABLE Fees".into()
+			))
+		);
+		assert_eq!(
+			Delete::from("Fees").build(),
+			Err(GlueSqlErrors::MissingFilter("DELETE".into(), "Fees".into()))
+		);
+		assert_eq!(
+			Delete::from("Fees").all_rows().build().unwrap(),
+			"DELETE FROM Fees"
+		);
+		assert_eq!(
+			Insert::into("Fees").build(),
+			Err(GlueSqlErrors::EmptyStatement(
+				"INSERT".into(),
+				"Fees".into()
+			))
+		);
+	}
+}
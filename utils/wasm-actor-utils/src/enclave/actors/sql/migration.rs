@@ -0,0 +1,434 @@
+//! Versioned schema migrations of the glue db of a tApp.
+//!
+//! A tApp declares its migrations in order, numbered from 1. Each migration applied is
+//! recorded in the `_schema_migrations` table together with a checksum of its statements,
+//! so that a node knows which version its db is at and that the applied migrations are the
+//! ones still declared.
+//!
+//! Migrations are applied by `migrate` in the txn that upgrades the db, so that every
+//! replica applies them at the same tsid. At startup `check_schema` verifies the local db
+//! against the declared migrations and, given a quorum, against the dbs of other validators,
+//! and txns call `require_schema` before touching the tables.
+//!
+//! ```ignore
+//! const MIGRATIONS: &[Migration] = &[
+//!     Migration::new(1, "create fees", &["CREATE TABLE Fees (account TEXT, amount TEXT)"]),
+//!     Migration::new(2, "add memo", &["ALTER TABLE Fees ADD COLUMN memo TEXT NULL"]),
+//! ];
+//!
+//! // in the txn upgrading the db
+//! let gluedb_ctx = migrate(token_id, tsid, MIGRATIONS).await?;
+//! ```
+
+use super::{query_rows, FromRow, Insert, Order, Select};
+use crate::enclave::actors::libp2p::{quorum_actor_query_on, select_quorum_validators, Quorum};
+use crate::enclave::actors::tokenstate::{
+	begin_sql_transaction, cancel_sql_transaction, decode_sql_payloads, exec_glue_cmd,
+	has_glue_db_init, init_glue_db, sql_query,
+};
+use crate::enclave::error::{GlueSqlErrors, Result};
+use gluesql_core::executor::{Payload, PayloadVariable};
+use sha2::Digest;
+use std::collections::HashSet;
+use tea_codec::serialize;
+use tea_runtime_codec::actor_txns::tsid::Tsid;
+use tea_runtime_codec::tapp::TokenId;
+use tea_runtime_codec::vmh::message::{
+	encode_protobuf,
+	structs_proto::tokenstate::{self, GluedbTransactionContext},
+};
+use tea_system_actors::tokenstate::{ExecGlueQueryRequest, NAME};
+
+/// The table recording the applied migrations.
+pub const MIGRATIONS_TABLE: &str = "_schema_migrations";
+
+/// `applied_ts` is the time of the txn applying the migration in milliseconds, the nanoseconds
+/// of `Tsid::ts` don't fit into an `INTEGER`.
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS _schema_migrations (version INTEGER NOT NULL, name TEXT NOT NULL, checksum TEXT NOT NULL, applied_ts INTEGER NOT NULL)";
+
+/// A schema change declared by a tApp.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+	pub version: u32,
+	pub name: &'static str,
+	/// The sql commands of the migration, executed in order.
+	pub statements: &'static [&'static str],
+}
+
+impl Migration {
+	pub const fn new(
+		version: u32,
+		name: &'static str,
+		statements: &'static [&'static str],
+	) -> Self {
+		Migration {
+			version,
+			name,
+			statements,
+		}
+	}
+
+	/// The hex sha256 of the version, name and statements.
+	pub fn checksum(&self) -> String {
+		let mut hasher = sha2::Sha256::new();
+		hasher.update(self.version.to_le_bytes());
+		hasher.update(self.name.as_bytes());
+		for statement in self.statements {
+			hasher.update([0]);
+			hasher.update(statement.as_bytes());
+		}
+		hex::encode(hasher.finalize())
+	}
+}
+
+/// A migration as recorded in `_schema_migrations`.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+#[from_row(path = "crate::enclave::actors::sql")]
+pub struct AppliedMigration {
+	pub version: u32,
+	pub name: String,
+	pub checksum: String,
+}
+
+/// The schema of the local db as checked by `check_schema`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaState {
+	/// The version of the last migration applied, 0 if none is.
+	pub version: u32,
+	/// Versions of the declared migrations not applied yet.
+	pub pending: Vec<u32>,
+	/// Validators whose schema differs from the quorum, or which failed to respond.
+	pub dissenters: Vec<String>,
+}
+
+impl SchemaState {
+	pub fn is_current(&self) -> bool {
+		self.pending.is_empty()
+	}
+}
+
+/// Checks that the migrations are numbered from 1 without gaps and have unique names.
+pub fn validate(migrations: &[Migration]) -> Result<(), GlueSqlErrors> {
+	let mut names = HashSet::new();
+	for (i, migration) in migrations.iter().enumerate() {
+		let reason = if migration.version as usize != i + 1 {
+			format!(
+				"migration {} is declared at position {}",
+				migration.version,
+				i + 1
+			)
+		} else if migration.name.is_empty() || !names.insert(migration.name) {
+			format!(
+				"migration {} has an empty or duplicated name",
+				migration.version
+			)
+		} else if migration.statements.is_empty() {
+			format!("migration {} has no statements", migration.version)
+		} else {
+			continue;
+		};
+		return Err(GlueSqlErrors::InvalidMigrations(reason));
+	}
+	Ok(())
+}
+
+/// Checks the applied migrations against the declared ones and returns the version of the db.
+pub fn verify_applied(
+	applied: &[AppliedMigration],
+	migrations: &[Migration],
+) -> Result<u32, GlueSqlErrors> {
+	if applied.len() > migrations.len() {
+		return Err(GlueSqlErrors::SchemaAhead(
+			applied.len() as u32,
+			migrations.len() as u32,
+		));
+	}
+	for (applied, migration) in applied.iter().zip(migrations) {
+		if applied.version != migration.version
+			|| applied.name != migration.name
+			|| applied.checksum != migration.checksum()
+		{
+			return Err(GlueSqlErrors::MigrationMismatch(
+				applied.version,
+				applied.name.clone(),
+			));
+		}
+	}
+	Ok(applied.len() as u32)
+}
+
+/// The migrations recorded in the local db in the order of their versions.
+pub async fn applied_migrations(token_id: TokenId) -> Result<Vec<AppliedMigration>> {
+	if !has_glue_db_init(token_id).await? {
+		return Ok(Vec::new());
+	}
+	let payloads = sql_query(token_id, "SHOW TABLES".to_string()).await?;
+	if !has_migrations_table(&payloads) {
+		return Ok(Vec::new());
+	}
+	let payloads = sql_query(token_id, select_applied()?).await?;
+	Ok(read_applied(&payloads)?)
+}
+
+/// Verifies the local db against the declared migrations, and against the dbs of other
+/// validators if a quorum is given. Fails if the local db differs from the quorum.
+pub async fn check_schema(
+	token_id: TokenId,
+	migrations: &[Migration],
+	quorum: Option<Quorum>,
+) -> Result<SchemaState> {
+	validate(migrations)?;
+	let applied = applied_migrations(token_id).await?;
+	let version = verify_applied(&applied, migrations)?;
+
+	let mut dissenters = Vec::new();
+	if let Some(quorum) = quorum {
+		let (remote, agreed, others) = remote_applied_migrations(token_id, quorum).await?;
+		if remote != applied {
+			return Err(GlueSqlErrors::SchemaDiverged(version, remote.len() as u32, agreed).into());
+		}
+		if !others.is_empty() {
+			warn!("validators {others:?} differ from the schema of token {token_id:?} at version {version}");
+		}
+		dissenters = others;
+	}
+
+	Ok(SchemaState {
+		version,
+		pending: migrations
+			.iter()
+			.map(|x| x.version)
+			.filter(|x| *x > version)
+			.collect(),
+		dissenters,
+	})
+}
+
+/// Fails unless the local db has applied all declared migrations, call it before a txn
+/// touches the tables.
+pub async fn require_schema(token_id: TokenId, migrations: &[Migration]) -> Result<()> {
+	let state = check_schema(token_id, migrations, None).await?;
+	if !state.is_current() {
+		return Err(GlueSqlErrors::SchemaOutdated(state.version, migrations.len() as u32).into());
+	}
+	Ok(())
+}
+
+/// Applies the pending migrations within an sql transaction in the txn of `tsid`.
+///
+/// Returns the context of the sql transaction to commit with `CommitContext::gluedb_ctx`,
+/// or `None` if all migrations are applied already. The sql transaction is cancelled if any
+/// statement fails, and the error of the statement is returned even if cancelling fails.
+pub async fn migrate(
+	token_id: TokenId,
+	tsid: Tsid,
+	migrations: &[Migration],
+) -> Result<Option<GluedbTransactionContext>> {
+	validate(migrations)?;
+	if !has_glue_db_init(token_id).await? {
+		init_glue_db(token_id, tsid).await?;
+	}
+	let applied = applied_migrations(token_id).await?;
+	let version = verify_applied(&applied, migrations)?;
+	let pending = &migrations[version as usize..];
+	if pending.is_empty() {
+		return Ok(None);
+	}
+
+	let ctx = begin_sql_transaction(token_id).await?;
+	if let Err(e) = apply(token_id, tsid, pending).await {
+		if let Err(cancel) = cancel_sql_transaction(token_id).await {
+			warn!("failed to cancel the sql transaction of token {token_id:?} after migration failed: {cancel}");
+		}
+		return Err(e);
+	}
+	info!(
+		"migrated schema of token {token_id:?} from version {version} to {}",
+		migrations.len()
+	);
+	Ok(Some(ctx))
+}
+
+async fn apply(token_id: TokenId, tsid: Tsid, pending: &[Migration]) -> Result<()> {
+	exec_glue_cmd(token_id, CREATE_MIGRATIONS_TABLE.to_string(), tsid).await?;
+	for migration in pending {
+		for statement in migration.statements {
+			exec_glue_cmd(token_id, statement.to_string(), tsid).await?;
+		}
+		let record = Insert::into(MIGRATIONS_TABLE)
+			.value("version", migration.version)
+			.value("name", migration.name)
+			.value("checksum", migration.checksum())
+			.value("applied_ts", (tsid.ts / 1_000_000) as u64)
+			.build()?;
+		exec_glue_cmd(token_id, record, tsid).await?;
+	}
+	Ok(())
+}
+
+/// The migrations recorded by `quorum.required()` of `quorum.total()` validators, with the
+/// validators agreeing and the others. Both the table lookup and the select go to the same
+/// validators, so the select is only sent where the quorum saw the table.
+async fn remote_applied_migrations(
+	token_id: TokenId,
+	quorum: Quorum,
+) -> Result<(Vec<AppliedMigration>, Vec<String>, Vec<String>)> {
+	let validators = select_quorum_validators(quorum).await?;
+	let tables = quorum_sql_query(token_id, "SHOW TABLES".to_string(), quorum, &validators).await?;
+	if !has_migrations_table(&tables.0) {
+		return Ok((Vec::new(), tables.1, tables.2));
+	}
+	let (payloads, agreed, dissenters) =
+		quorum_sql_query(token_id, select_applied()?, quorum, &validators).await?;
+	Ok((read_applied(&payloads)?, agreed, dissenters))
+}
+
+async fn quorum_sql_query(
+	token_id: TokenId,
+	sql: String,
+	quorum: Quorum,
+	validators: &[String],
+) -> Result<(Vec<Payload>, Vec<String>, Vec<String>)> {
+	let req = tokenstate::ExecGlueQueryRequest {
+		token_id: serialize(&token_id)?,
+		sql,
+	};
+	let rtn = quorum_actor_query_on(
+		NAME,
+		ExecGlueQueryRequest(encode_protobuf(req)?),
+		quorum,
+		validators,
+		None,
+	)
+	.await?;
+	Ok((
+		decode_sql_payloads(&rtn.value.0)?,
+		rtn.agreed,
+		rtn.dissenters,
+	))
+}
+
+fn select_applied() -> Result<String, GlueSqlErrors> {
+	Select::from(MIGRATIONS_TABLE)
+		.columns(&["version", "name", "checksum"])
+		.order_by("version", Order::Asc)
+		.build()
+}
+
+fn has_migrations_table(payloads: &[Payload]) -> bool {
+	payloads.iter().any(|x| match x {
+		Payload::ShowVariable(PayloadVariable::Tables(tables)) => {
+			tables.iter().any(|x| x == MIGRATIONS_TABLE)
+		}
+		_ => false,
+	})
+}
+
+fn read_applied(payloads: &[Payload]) -> Result<Vec<AppliedMigration>, GlueSqlErrors> {
+	let payload = payloads.first().ok_or(GlueSqlErrors::InvalidSelectResult)?;
+	let applied: Vec<AppliedMigration> = query_rows(payload)?;
+	if let Some((i, x)) = applied
+		.iter()
+		.enumerate()
+		.find(|(i, x)| x.version as usize != i + 1)
+	{
+		return Err(GlueSqlErrors::InvalidMigrations(format!(
+			"migration {} is recorded at position {}",
+			x.version,
+			i + 1
+		)));
+	}
+	Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use gluesql_core::prelude::{Row, Value};
+
+	const MIGRATIONS: &[Migration] = &[
+		Migration::new(1, "create fees", &["CREATE TABLE Fees (account TEXT)"]),
+		Migration::new(
+			2,
+			"add amount",
+			&["ALTER TABLE Fees ADD COLUMN amount TEXT"],
+		),
+	];
+
+	fn applied(migration: &Migration) -> AppliedMigration {
+		AppliedMigration {
+			version: migration.version,
+			name: migration.name.to_string(),
+			checksum: migration.checksum(),
+		}
+	}
+
+	#[test]
+	fn validate_declared_migrations() {
+		validate(MIGRATIONS).unwrap();
+		validate(&[]).unwrap();
+		assert!(validate(&MIGRATIONS[1..]).is_err());
+		assert!(validate(&[
+			MIGRATIONS[0],
+			Migration::new(2, "create fees", &["SELECT 1"])
+		])
+		.is_err());
+		assert!(validate(&[Migration::new(1, "empty", &[])]).is_err());
+	}
+
+	#[test]
+	fn verify_applied_migrations() {
+		assert_eq!(verify_applied(&[], MIGRATIONS), Ok(0));
+		assert_eq!(
+			verify_applied(&[applied(&MIGRATIONS[0])], MIGRATIONS),
+			Ok(1)
+		);
+		assert_eq!(
+			verify_applied(&[applied(&MIGRATIONS[0])], &[]),
+			Err(GlueSqlErrors::SchemaAhead(1, 0))
+		);
+
+		let changed = Migration::new(
+			1,
+			"create fees",
+			&["CREATE TABLE Fees (account TEXT, memo TEXT)"],
+		);
+		assert_eq!(
+			verify_applied(&[applied(&changed)], MIGRATIONS),
+			Err(GlueSqlErrors::MigrationMismatch(1, "create fees".into()))
+		);
+	}
+
+	#[test]
+	fn read_applied_rows() {
+		let labels = vec![
+			"version".to_string(),
+			"name".to_string(),
+			"checksum".to_string(),
+		];
+		let row = |version: i64| {
+			Row(vec![
+				Value::I64(version),
+				Value::Str(MIGRATIONS[version as usize - 1].name.to_string()),
+				Value::Str(MIGRATIONS[version as usize - 1].checksum()),
+			])
+		};
+		let payload = Payload::Select {
+			labels: labels.clone(),
+			rows: vec![row(1), row(2)],
+		};
+		assert_eq!(
+			read_applied(&[payload]),
+			Ok(MIGRATIONS.iter().map(applied).collect())
+		);
+
+		let payload = Payload::Select {
+			labels,
+			rows: vec![row(2)],
+		};
+		assert!(read_applied(&[payload]).is_err());
+		assert!(has_migrations_table(&[Payload::ShowVariable(
+			PayloadVariable::Tables(vec!["Fees".into(), MIGRATIONS_TABLE.into()])
+		)]));
+	}
+}
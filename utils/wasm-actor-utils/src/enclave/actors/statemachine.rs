@@ -3,7 +3,7 @@ use crate::enclave::{
 	actors::{
 		env::tappstore_id,
 		persist::{async_persist_request, async_persist_request_silently},
-		replica::send_transaction_locally,
+		replica::{append_txn_receipt, send_transaction_locally},
 	},
 	error::{Error, Errors, Result},
 };
@@ -20,13 +20,15 @@ use tea_runtime_codec::vmh::message::{
 };
 use tea_runtime_codec::{
 	actor_txns::{
-		auth::TokenAuthOp,
-		context::{ReadConflictMode, TokenContext},
+		auth::{deserialize_auth_grants, policy::AuthPolicy, TokenAuthOp},
+		context::{bundle::ContextBundle, ReadConflictMode, TokenContext},
+		receipt::{TxnEvent, TxnReceipt},
 		tsid::Tsid,
 		TxnSerial,
 	},
 	tapp::RECEIPTING_AUTH_KEY,
 };
+use tea_system_actors::replica::HistoryItem;
 use tea_system_actors::tappstore::txns::TappstoreTxn;
 use tea_system_actors::tokenstate::{self as codec};
 
@@ -38,6 +40,9 @@ pub struct CommitContext {
 	pub payee_app_ctx: Option<Vec<u8>>,
 	pub auth_key: AuthKey,
 	pub memo: String,
+	/// Serialized `ContextBundle` committing the contexts of several tokens atomically,
+	/// `ctx` is empty if it is set.
+	pub ctx_bundle: Option<Vec<u8>>,
 }
 
 impl CommitContext {
@@ -56,9 +61,24 @@ impl CommitContext {
 			payee_app_ctx,
 			auth_key,
 			memo,
+			ctx_bundle: None,
 		}
 	}
 
+	/// Commit all contexts of the bundle in one txn, either all of them or none are committed.
+	pub fn bundle(
+		bundle: &ContextBundle,
+		auth_key: AuthKey,
+		memo: String,
+	) -> Result<CommitContext> {
+		Ok(CommitContext {
+			ctx_bundle: Some(serialize(bundle)?),
+			auth_key,
+			memo,
+			..Default::default()
+		})
+	}
+
 	#[doc(hidden)]
 	pub fn ctx_god_mode(ctx: Vec<u8>) -> CommitContext {
 		CommitContext {
@@ -82,6 +102,20 @@ impl CommitContext {
 	#[doc(hidden)]
 	pub fn log_from_bytes(&self) -> Result<String> {
 		let mut str = String::new();
+		if let Some(ctx_bundle) = &self.ctx_bundle {
+			let bundle: ContextBundle = deserialize(ctx_bundle)?;
+			for ctx in bundle.contexts() {
+				let ctx = serialize(ctx)?;
+				str.push_str(&format!(
+					"\nBundleContext:\n\t{}\n\t{}\n\t{}\n\t{}",
+					TokenContext::log_tea_from_bytes(&ctx)?,
+					TokenContext::log_deposit_from_bytes(&ctx)?,
+					TokenContext::log_bonding_from_bytes(&ctx)?,
+					TokenContext::log_allowance_from_bytes(&ctx)?,
+				));
+			}
+			return Ok(str);
+		}
 		str.push_str(&format!(
 			"\nTAppStoreContext:\n\t{}\n\t{}\n\t{}\n\t{}",
 			TokenContext::log_tea_from_bytes(&self.ctx)?,
@@ -141,10 +175,13 @@ impl CommitContextList {
 				commit(CommitContext::ctx_god_mode(placeholder_ctx)).await?;
 			assert_eq!(credit, Balance::zero());
 			assert_eq!(debit, Balance::zero());
+			Self::append_receipt(TxnReceipt::committed(tsid, vec![], vec![])).await;
 			return Ok(vec![]);
 		}
 
 		let mut global_statements = vec![];
+		let mut receipt_statements = vec![];
+		let mut events = vec![];
 		let mut actual_neutral_balance: (Balance, Balance) = (Balance::zero(), Balance::zero());
 		let mut hasher = sha2::Sha256::new();
 		for ctx in self.ctx_list.iter() {
@@ -155,9 +192,11 @@ impl CommitContextList {
 				actual_neutral_balance.1 + hidden_acct_debit,
 			);
 			hasher.update(hash);
+			events.extend(self.try_get_ctx_events(ctx)?);
 
 			if !statements.is_empty() {
-				let timestamp = self.try_get_ctx_timestamp(&ctx.ctx)?;
+				receipt_statements.extend(statements.iter().cloned());
+				let timestamp = self.try_get_ctx_timestamp(ctx)?;
 				global_statements.push((statements, timestamp, ctx.memo.clone()));
 			}
 		}
@@ -200,13 +239,35 @@ impl CommitContextList {
 			}
 		}
 
+		Self::append_receipt(TxnReceipt::committed(tsid, receipt_statements, events)).await;
 		Ok(hasher.finalize().to_vec())
 	}
 
-	fn try_get_ctx_timestamp(&self, ctx: &[u8]) -> Result<u128> {
-		let ctx: TokenContext = deserialize(ctx)?;
+	/// The txn is committed already, so a receipt that could not be stored is only logged.
+	async fn append_receipt(receipt: TxnReceipt) {
+		let tsid = receipt.tsid;
+		if let Err(e) = append_txn_receipt(receipt).await {
+			warn!("append receipt of txn with tsid {:?} failed: {}", tsid, e);
+		}
+	}
+
+	fn try_get_ctx_timestamp(&self, ctx: &CommitContext) -> Result<u128> {
+		if let Some(ctx_bundle) = &ctx.ctx_bundle {
+			let bundle: ContextBundle = deserialize(ctx_bundle)?;
+			return Ok(bundle.get_tsid().ts);
+		}
+		let ctx = TokenContext::decode_bytes(&ctx.ctx)?;
 		Ok(ctx.tsid.ts)
 	}
+
+	fn try_get_ctx_events(&self, ctx: &CommitContext) -> Result<Vec<TxnEvent>> {
+		if let Some(ctx_bundle) = &ctx.ctx_bundle {
+			let bundle: ContextBundle = deserialize(ctx_bundle)?;
+			return Ok(bundle.events().cloned().collect());
+		}
+		let ctx = TokenContext::decode_bytes(&ctx.ctx)?;
+		Ok(ctx.get_events().to_vec())
+	}
 	pub fn log_from_bytes(&self) -> Result<String> {
 		let mut str = String::new();
 		for c in self.ctx_list.iter() {
@@ -226,6 +287,7 @@ impl TryInto<CommitRequest> for CommitContext {
 			auth_key: serialize(&self.auth_key)?,
 			payee_miner_ctx: self.payee_miner_ctx,
 			payee_app_ctx: self.payee_app_ctx,
+			ctx_bundle: self.ctx_bundle,
 		})
 	}
 }
@@ -242,6 +304,7 @@ pub async fn query_state_tsid() -> Result<Tsid> {
 
 /// Checking for state-machine.
 pub async fn check(ctx: CommitContext) -> Result<()> {
+	verify_auth(&ctx).await?;
 	let buf = encode_protobuf::<CommitRequest>(ctx.try_into()?)?;
 
 	ActorId::Static(codec::NAME)
@@ -257,6 +320,7 @@ pub async fn check(ctx: CommitContext) -> Result<()> {
 pub async fn commit(
 	ctx: CommitContext,
 ) -> Result<(Balance, Balance, Vec<TypedStatement>, Vec<u8>)> {
+	verify_auth(&ctx).await?;
 	let buf = encode_protobuf::<CommitRequest>(ctx.try_into()?)?;
 	let res_buf = ActorId::Static(codec::NAME)
 		.call(codec::CommitTxnRequest(buf))
@@ -290,11 +354,41 @@ pub async fn new_txn_serial(
 	))
 }
 
-/// Return the auth_key buffer from state-machine
-pub async fn query_auth_ops_bytes(auth: AuthKey, gas_limit: u64) -> Result<Vec<u8>> {
-	if auth == GOD_MODE_AUTH_KEY {
-		error!("If authkey is GOD MODE, use generate_god_mode_ops_bytes instead");
+/// Verify the context against the auth ops read again from its auth key, because the
+/// stored procedure actor may have changed the auth ops carried by the context.
+async fn verify_auth(ctx: &CommitContext) -> Result<()> {
+	if ctx.auth_key == GOD_MODE_AUTH_KEY || ctx.auth_key == RECEIPTING_AUTH_KEY {
+		return Ok(());
 	}
+	if let Some(ctx_bundle) = &ctx.ctx_bundle {
+		let bundle: ContextBundle = deserialize(ctx_bundle)?;
+		if bundle.contexts().all(|x| x.required_auth_ops().is_empty()) {
+			return Ok(());
+		}
+		let (auth_ops, policies, _) = query_auth_grants(ctx.auth_key).await?;
+		bundle.verify_auth(&auth_ops, tappstore_id().await?)?;
+		bundle.verify_policies(&policies)?;
+		return Ok(());
+	}
+	let token_ctx = TokenContext::decode_bytes(&ctx.ctx)?;
+	if token_ctx.required_auth_ops().is_empty() {
+		return Ok(());
+	}
+	let (auth_ops, policies, _) = query_auth_grants(ctx.auth_key).await?;
+	token_ctx.verify_auth(&auth_ops, tappstore_id().await?)?;
+	token_ctx.verify_policies(&policies, &mut Default::default())?;
+	Ok(())
+}
+
+/// Return the auth ops and the renewed expiration of the auth_key from state-machine
+async fn query_auth_ops(auth: AuthKey) -> Result<(Vec<TokenAuthOp>, u128)> {
+	let (auth_ops, _, expire) = query_auth_grants(auth).await?;
+	Ok((auth_ops, expire))
+}
+
+/// Return the auth ops, the policies they were granted by if any, and the renewed
+/// expiration of the auth_key from state-machine
+async fn query_auth_grants(auth: AuthKey) -> Result<(Vec<TokenAuthOp>, Vec<AuthPolicy>, u128)> {
 	let auth_bytes = serialize(&auth)?;
 	let req = QueryAuthOpsRequest {
 		auth_key: auth_bytes,
@@ -303,7 +397,15 @@ pub async fn query_auth_ops_bytes(auth: AuthKey, gas_limit: u64) -> Result<Vec<u
 	let res_bytes = ActorId::Static(codec::NAME)
 		.call(codec::QueryAuthOpsBytesRequest(buf))
 		.await?;
-	let (auth_ops, new_expire): (Vec<TokenAuthOp>, u128) = deserialize(res_bytes.0)?;
+	Ok(deserialize_auth_grants(&res_bytes.0)?)
+}
+
+/// Return the auth_key buffer from state-machine
+pub async fn query_auth_ops_bytes(auth: AuthKey, gas_limit: u64) -> Result<Vec<u8>> {
+	if auth == GOD_MODE_AUTH_KEY {
+		error!("If authkey is GOD MODE, use generate_god_mode_ops_bytes instead");
+	}
+	let (auth_ops, new_expire) = query_auth_ops(auth).await?;
 	send_tx_new_auth_key_expired(&auth, new_expire, gas_limit).await?;
 	let auth_ops_bytes = serialize(&auth_ops)?;
 	Ok(auth_ops_bytes)
@@ -586,15 +688,57 @@ pub async fn pay_miner_gas(
 	amount: &Balance,
 	tappstore_ctx: Vec<u8>,
 	payee_ctx: Vec<u8>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+	send_pay_miner_gas(
+		miner_token_id,
+		from_account,
+		Some(amount),
+		None,
+		tappstore_ctx,
+		payee_ctx,
+	)
+	.await
+}
+
+/// Pays the miner for the gas metered while executing the txn, as recorded in its
+/// `HistoryItem` by `exec_txn`, rather than for an estimated amount like `pay_miner_gas`.
+#[doc(hidden)]
+pub async fn pay_miner_metered_gas(
+	miner_token_id: &TokenId, // miner cml entity id
+	from_account: &Account,
+	history: &HistoryItem,
+	tappstore_ctx: Vec<u8>,
+	payee_ctx: Vec<u8>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+	send_pay_miner_gas(
+		miner_token_id,
+		from_account,
+		None,
+		Some(history.gas_used),
+		tappstore_ctx,
+		payee_ctx,
+	)
+	.await
+}
+
+/// Exactly one of `amount` and `gas_used` is set, see `PayMinerGasRequest`.
+async fn send_pay_miner_gas(
+	miner_token_id: &TokenId,
+	from_account: &Account,
+	amount: Option<&Balance>,
+	gas_used: Option<u64>,
+	tappstore_ctx: Vec<u8>,
+	payee_ctx: Vec<u8>,
 ) -> Result<(Vec<u8>, Vec<u8>)> {
 	warn!("need some kind of auth, make sure bad actor cannot call this function from unauth use case");
 
 	let req = PayMinerGasRequest {
 		token_id: serialize(miner_token_id)?,
 		address: serialize(from_account)?,
-		amount: serialize(amount)?,
+		amount: amount.map(serialize).transpose()?.unwrap_or_default(),
 		tappstore_ctx,
 		payee_ctx,
+		gas_used,
 	};
 	let buf = encode_protobuf(req)?;
 	let res_buf = ActorId::Static(codec::NAME)
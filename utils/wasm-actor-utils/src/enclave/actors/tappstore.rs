@@ -1,6 +1,7 @@
 use super::{
 	libp2p::{connected_peers, intelli_actor_query_ex},
 	replica::IntelliSendMode,
+	tokenstate::has_glue_db_init,
 	util::random_select,
 };
 use crate::enclave::{
@@ -21,11 +22,10 @@ use tea_runtime_codec::tapp::{
 };
 use tea_runtime_codec::vmh::message::{
 	encode_protobuf,
-	structs_proto::{persist, tappstore, tokenstate},
+	structs_proto::{persist, tappstore},
 };
 use tea_sdk::IntoGlobal;
 use tea_system_actors::tappstore::*;
-use tea_system_actors::tokenstate::HasDbInitRequest;
 
 /// Simple system date formatter
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -60,15 +60,7 @@ pub async fn node_profiles_by_conn_ids(
 
 #[doc(hidden)]
 pub async fn has_tappstore_init() -> Result<bool> {
-	let buf = ActorId::Static(tea_system_actors::tokenstate::NAME)
-		.call(HasDbInitRequest(encode_protobuf(
-			tokenstate::HasGlueDbInitRequest {
-				token_id: serialize(&tappstore_id().await?)?,
-			},
-		)?))
-		.await?;
-	let res = tokenstate::HasGlueDbInitResponse::decode(buf.0.as_slice()).into_g::<Error>()?;
-	Ok(res.has_init)
+	has_glue_db_init(tappstore_id().await?).await
 }
 
 #[doc(hidden)]
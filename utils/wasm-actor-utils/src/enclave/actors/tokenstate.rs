@@ -5,6 +5,7 @@ use prost::Message;
 use tea_actorx::ActorId;
 use tea_codec::{deserialize, serialize, IntoGlobal};
 use tea_runtime_codec::actor_txns::context::TokenContext;
+use tea_runtime_codec::actor_txns::tsid::Tsid;
 use tea_runtime_codec::tapp::{Account, Balance, TokenId};
 use tea_runtime_codec::vmh::message::{encode_protobuf, structs_proto::tokenstate};
 use tea_system_actors::tokenstate::*;
@@ -153,7 +154,12 @@ pub async fn sql_query(token_id: TokenId, sql: String) -> Result<Vec<Payload>> {
 	let res = ActorId::Static(NAME)
 		.call(ExecGlueQueryRequest(encode_protobuf(req)?))
 		.await?;
-	let res = tokenstate::ExecGlueQueryResponse::decode(res.0.as_slice()).into_g::<Error>()?;
+	decode_sql_payloads(&res.0)
+}
+
+/// Decode the payloads of an `ExecGlueQueryResponse`, also those returned by remote queries.
+pub fn decode_sql_payloads(buf: &[u8]) -> Result<Vec<Payload>> {
+	let res = tokenstate::ExecGlueQueryResponse::decode(buf).into_g::<Error>()?;
 	Ok(res
 		.payloads
 		.iter()
@@ -174,6 +180,62 @@ pub async fn sql_query_first(token_id: TokenId, sql: String) -> Result<Payload>
 	Ok(payloads.remove(0))
 }
 
+/// Whether the glue db of the token has been initialized.
+pub async fn has_glue_db_init(token_id: TokenId) -> Result<bool> {
+	let buf = ActorId::Static(NAME)
+		.call(HasDbInitRequest(encode_protobuf(
+			tokenstate::HasGlueDbInitRequest {
+				token_id: serialize(&token_id)?,
+			},
+		)?))
+		.await?;
+	let res = tokenstate::HasGlueDbInitResponse::decode(buf.0.as_slice()).into_g::<Error>()?;
+	Ok(res.has_init)
+}
+
+/// Initialize the glue db of the token in the txn of `tsid`.
+pub async fn init_glue_db(token_id: TokenId, tsid: Tsid) -> Result<()> {
+	ActorId::Static(NAME)
+		.call(InitGlueSqlRequest(encode_protobuf(
+			tokenstate::InitGlueSqlRequest {
+				token_id: serialize(&token_id)?,
+				tsid: serialize(&tsid)?,
+			},
+		)?))
+		.await?;
+	Ok(())
+}
+
+/// Begin an sql transaction of the token, the returned context is committed with
+/// `CommitContext::gluedb_ctx`.
+pub async fn begin_sql_transaction(
+	token_id: TokenId,
+) -> Result<tokenstate::GluedbTransactionContext> {
+	let buf = ActorId::Static(NAME)
+		.call(SqlBeginTransactionRequest(encode_protobuf(
+			tokenstate::BeginTransactionRequest {
+				token_id: serialize(&token_id)?,
+			},
+		)?))
+		.await?;
+	let res = tokenstate::BeginTransactionResponse::decode(buf.0.as_slice()).into_g::<Error>()?;
+	Ok(res.context.unwrap_or_default())
+}
+
+/// Execute an sql command that writes the glue db, within the sql transaction of the token.
+pub async fn exec_glue_cmd(token_id: TokenId, sql: String, tsid: Tsid) -> Result<()> {
+	ActorId::Static(NAME)
+		.call(ExecGlueCmdRequest(encode_protobuf(
+			tokenstate::ExecGlueSqlRequest {
+				token_id: serialize(&token_id)?,
+				sql,
+				tsid: serialize(&tsid)?,
+			},
+		)?))
+		.await?;
+	Ok(())
+}
+
 /// Move token from user to another user.
 /// It's usually used to move TEA under GLOBAL token_id, which is TAppStore in the system.
 pub async fn mov(from: Account, to: Account, amt: Balance, ctx: Vec<u8>) -> Result<Vec<u8>> {
@@ -232,8 +294,8 @@ pub async fn cross_move(
 		return Ok((from_ctx_bytes, to_ctx_bytes));
 	}
 
-	let from_ctx: TokenContext = deserialize(&from_ctx_bytes)?;
-	let to_ctx: TokenContext = deserialize(&to_ctx_bytes)?;
+	let from_ctx = TokenContext::decode_bytes(&from_ctx_bytes)?;
+	let to_ctx = TokenContext::decode_bytes(&to_ctx_bytes)?;
 
 	if from_ctx.tid == to_ctx.tid {
 		// same token id, move.
@@ -277,8 +339,8 @@ pub async fn api_cross_move(
 		return Ok((from_ctx_bytes, to_ctx_bytes));
 	}
 
-	let from_ctx: TokenContext = deserialize(&from_ctx_bytes)?;
-	let to_ctx: TokenContext = deserialize(&to_ctx_bytes)?;
+	let from_ctx = TokenContext::decode_bytes(&from_ctx_bytes)?;
+	let to_ctx = TokenContext::decode_bytes(&to_ctx_bytes)?;
 
 	if from_ctx.tid == to_ctx.tid {
 		// same token id, move.
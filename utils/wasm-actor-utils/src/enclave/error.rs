@@ -43,6 +43,12 @@ pub enum Error {
 	#[error(transparent)]
 	AsyncNotFinished(#[from] AsyncNotFinished),
 
+	#[error(transparent)]
+	QuorumNotReached(#[from] QuorumNotReached),
+
+	#[error(transparent)]
+	InvalidQuorum(#[from] InvalidQuorum),
+
 	#[error("Http request error: {0}")]
 	HttpRequest(String),
 
@@ -150,12 +156,54 @@ pub enum Errors {
 
 	#[error("all libp2p response error, last is: {0}")]
 	Libp2pAllResponseError(String),
+
+	#[error("key {0} is locked")]
+	KeyLocked(String),
+
+	#[error("key {0} holds a value of another type")]
+	KeyValueWrongType(String),
+
+	#[error("adding to the value of key {0} overflows")]
+	KeyValueAddOverflow(String),
+
+	#[error("gave up updating key {0} after {1} conflicting writes")]
+	KeyValueUpdateContended(String, usize),
+
+	#[error("libp2p message to {0} timed out")]
+	Libp2pTimeout(String),
+
+	#[error("node {0} has no handler of action {2} of actor {1}")]
+	Libp2pUnhandled(String, String, String),
+
+	#[error("simulated node {0} is down")]
+	SimNodeDown(String),
 }
 
 #[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[error("async not finished")]
 pub struct AsyncNotFinished;
 
+/// No response of a quorum query was given by enough validators.
+#[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[error("only {agreed} of {asked} validators agreed on the response, {required} required")]
+pub struct QuorumNotReached {
+	pub required: usize,
+	pub asked: usize,
+	pub agreed: usize,
+	/// Conn ids of the validators grouped by the response they gave, largest group first
+	pub groups: Vec<Vec<String>>,
+	/// Conn ids of the validators that failed to respond
+	pub failed: Vec<String>,
+}
+
+/// A quorum of validators that is not a majority of the validators asked.
+#[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[error("a quorum of {required} out of {total} validators is not a majority")]
+pub struct InvalidQuorum {
+	pub required: usize,
+	pub total: usize,
+}
+
 #[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[error("process transaction error failed: {0}")]
 pub struct ProcessTransactionErrorFailed(pub String);
@@ -194,6 +242,42 @@ pub enum GlueSqlErrors {
 
 	#[error("failed to get first payload with sql '{0}' about token {1:?}")]
 	InvalidFirstPayload(String, TokenId),
+
+	#[error("{0:?} is not a valid table or column name")]
+	InvalidIdentifier(String),
+
+	#[error("sql has {0} placeholders but {1} values are bound")]
+	BindCountMismatch(usize, usize),
+
+	#[error("row has {1} values but {0} columns are expected")]
+	RowLengthMismatch(usize, usize),
+
+	#[error("column {0} is not selected, selected columns are {1:?}")]
+	ColumnNotFound(String, Vec<String>),
+
+	#[error("column {0} expected {1} but got {2}")]
+	ColumnTypeMismatch(String, String, String),
+
+	#[error("{0} of table {1} has no values to write")]
+	EmptyStatement(String, String),
+
+	#[error("{0} of table {1} has no filter, use `all_rows` to affect every row")]
+	MissingFilter(String, String),
+
+	#[error("invalid schema migrations: {0}")]
+	InvalidMigrations(String),
+
+	#[error("applied migration {0} {1} differs from the declared one")]
+	MigrationMismatch(u32, String),
+
+	#[error("schema is at version {0} but only {1} migrations are declared")]
+	SchemaAhead(u32, u32),
+
+	#[error("schema is at version {0} but the latest migration is {1}")]
+	SchemaOutdated(u32, u32),
+
+	#[error("local schema at version {0} differs from the schema at version {1} agreed by {2:?}")]
+	SchemaDiverged(u32, u32, Vec<String>),
 }
 
 #[derive(Error, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
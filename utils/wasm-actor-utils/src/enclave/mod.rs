@@ -7,3 +7,5 @@ pub mod action;
 pub mod actors;
 pub mod error;
 pub mod logging;
+#[cfg(feature = "native")]
+pub mod native;
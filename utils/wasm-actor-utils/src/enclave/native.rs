@@ -0,0 +1,6 @@
+//! Native implementations of system actors, registrable on a local actor host to run tApp code
+//! end-to-end without a node.
+
+pub mod keyvalue;
+pub mod libp2p;
+pub mod txn;
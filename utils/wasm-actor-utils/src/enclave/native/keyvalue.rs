@@ -0,0 +1,992 @@
+use crate::enclave::error::Errors;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::mem::discriminant;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tea_actorx::{caller, ActorId, HandlerActor};
+use tea_sdk::errorx::Global;
+use tea_sdk::serde::handle::handles;
+use tea_sdk::{deserialize, serialize};
+use tea_system_actors::keyvalue::{actions::*, NAME};
+
+/// Boxed since `Errors` is much larger than the values returned by the store.
+type StoreResult<T> = Result<T, Box<Errors>>;
+
+/// The memory reported as total by `GlobalMemoryUsageRequest` if not given by `KeyValueActor::new`.
+pub const DEFAULT_CAPACITY: u64 = 1 << 30;
+
+/// A `tea:keyvalue` actor keeping everything in memory, so that code using
+/// `enclave::actors::kvp` runs on a local actor host without a node:
+///
+/// ```ignore
+/// async {
+///     KeyValueActor::default().register().await?;
+///     kvp::set("key", &1u32, 60).await?;
+/// }
+/// .with_actor_host()
+/// .await
+/// ```
+pub struct KeyValueActor {
+	capacity: u64,
+	store: Mutex<KeyValueStore>,
+}
+
+impl Default for KeyValueActor {
+	fn default() -> Self {
+		Self::new(DEFAULT_CAPACITY)
+	}
+}
+
+impl HandlerActor for KeyValueActor {
+	fn id(&self) -> Option<ActorId> {
+		Some(ActorId::Static(NAME))
+	}
+}
+
+impl KeyValueActor {
+	pub fn new(capacity: u64) -> Self {
+		KeyValueActor {
+			capacity,
+			store: Default::default(),
+		}
+	}
+
+	fn with<T>(
+		&self,
+		f: impl FnOnce(&mut KeyValueStore, Instant) -> StoreResult<T>,
+	) -> tea_sdk::Result<T> {
+		let mut store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+		f(&mut store, Instant::now()).map_err(|e| Global::Unnamed(e.to_string()))
+	}
+}
+
+#[handles]
+impl KeyValueActor {
+	async fn handle(&self, GetRequest { key }: _) -> tea_sdk::Result<_> {
+		let value = self.with(|store, now| store.get(&key, now))?;
+		Ok(GetResponse {
+			exists: value.is_some(),
+			value,
+		})
+	}
+
+	async fn handle(&self, req: SetRequest) -> tea_sdk::Result<_> {
+		let owner = owner();
+		self.with(|store, now| {
+			let expires_at = expires_at(req.expires_s, now);
+			store.insert(req.key, Value::Bytes(req.value.clone()), expires_at, owner);
+			Ok(())
+		})?;
+		Ok(SetResponse { value: req.value })
+	}
+
+	async fn handle(&self, GetVersionedRequest { key }: _) -> tea_sdk::Result<_> {
+		let (value, version) = self.with(|store, now| store.get_versioned(&key, now))?;
+		Ok(GetVersionedResponse { value, version })
+	}
+
+	async fn handle(&self, req: CasRequest) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let (success, version, current) = self.with(|store, now| {
+			let expires_at = expires_at(req.expires_s, now);
+			store.compare_and_set(req.key, &req.condition, req.value, expires_at, owner, now)
+		})?;
+		Ok(CasResponse {
+			success,
+			version,
+			current,
+		})
+	}
+
+	async fn handle(&self, BatchRequest { conditions, ops }: _) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let result = self.with(|store, now| store.batch(&conditions, ops, owner, now))?;
+		Ok(match result {
+			Ok(versions) => BatchResponse {
+				success: true,
+				failed: Vec::new(),
+				versions,
+			},
+			Err(failed) => BatchResponse {
+				success: false,
+				failed,
+				versions: Vec::new(),
+			},
+		})
+	}
+
+	async fn handle(&self, DelRequest { key }: _) -> tea_sdk::Result<_> {
+		self.with(|store, _| {
+			store.entries.remove(&key);
+			Ok(())
+		})?;
+		Ok(DelResponse { key })
+	}
+
+	async fn handle(&self, LockRequest { key, expires_s }: _) -> tea_sdk::Result<_> {
+		self.with(|store, now| store.lock(key, expires_at(expires_s, now), now))
+	}
+
+	async fn handle(&self, CancelLockRequest { key }: _) -> tea_sdk::Result<_> {
+		self.with(|store, _| {
+			store.locks.remove(&key);
+			Ok(())
+		})
+	}
+
+	async fn handle(&self, AddRequest { key, value }: _) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let value = self.with(|store, now| store.add(key, value, owner, now))?;
+		Ok(AddResponse { value })
+	}
+
+	async fn handle(&self, ListPushRequest { key, value }: _) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let new_count = self.with(|store, now| {
+			let list = store.list_mut(key, owner, now)?;
+			list.push_back(value);
+			Ok(list.len() as i32)
+		})?;
+		Ok(ListResponse { new_count })
+	}
+
+	async fn handle(&self, ListDelItemRequest { key, value }: _) -> tea_sdk::Result<_> {
+		let new_count = self.with(|store, now| {
+			let (_, len) = store.remove_items(&key, now, |x| match x {
+				Value::List(list) => {
+					let len = list.len();
+					list.retain(|x| *x != value);
+					Some((list.len() < len, list.len()))
+				}
+				_ => None,
+			})?;
+			Ok(len as i32)
+		})?;
+		Ok(ListResponse { new_count })
+	}
+
+	async fn handle(&self, ListClearRequest { key }: _) -> tea_sdk::Result<_> {
+		self.with(|store, _| {
+			store.entries.remove(&key);
+			Ok(())
+		})?;
+		Ok(DelResponse { key })
+	}
+
+	async fn handle(&self, req: ListRangeRequest) -> tea_sdk::Result<_> {
+		let values =
+			self.with(|store, now| store.list_range(&req.key, req.start, req.stop, now))?;
+		Ok(ListRangeResponse { values })
+	}
+
+	async fn handle(&self, SetAddRequest { key, value }: _) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let new_count = self.with(|store, now| {
+			let set = store.set_mut(key, owner, now)?;
+			set.insert(value);
+			Ok(set.len() as i32)
+		})?;
+		Ok(SetOperationResponse { new_count })
+	}
+
+	async fn handle(&self, SetRemoveRequest { key, value }: _) -> tea_sdk::Result<_> {
+		let new_count = self.with(|store, now| {
+			let (_, len) = store.remove_items(&key, now, |x| match x {
+				Value::Set(set) => Some((set.remove(&value), set.len())),
+				_ => None,
+			})?;
+			Ok(len as i32)
+		})?;
+		Ok(SetOperationResponse { new_count })
+	}
+
+	async fn handle(&self, SetQueryRequest { key }: _) -> tea_sdk::Result<_> {
+		let values = self.with(|store, now| {
+			Ok(store
+				.set(&key, now)?
+				.into_iter()
+				.flatten()
+				.cloned()
+				.collect())
+		})?;
+		Ok(SetQueryResponse { values })
+	}
+
+	async fn handle(&self, SetIntersectionRequest { keys }: _) -> tea_sdk::Result<_> {
+		let values = self.with(|store, now| {
+			let mut result: Option<BTreeSet<Vec<u8>>> = None;
+			for key in keys.iter() {
+				let set = store.set(key, now)?.cloned().unwrap_or_default();
+				result = Some(match result {
+					Some(result) => result.intersection(&set).cloned().collect(),
+					None => set,
+				});
+			}
+			Ok(result.unwrap_or_default().into_iter().collect())
+		})?;
+		Ok(SetQueryResponse { values })
+	}
+
+	async fn handle(&self, SetUnionRequest { keys }: _) -> tea_sdk::Result<_> {
+		let values = self.with(|store, now| {
+			let mut result = BTreeSet::new();
+			for key in keys.iter() {
+				result.extend(store.set(key, now)?.into_iter().flatten().cloned());
+			}
+			Ok(result.into_iter().collect())
+		})?;
+		Ok(SetQueryResponse { values })
+	}
+
+	async fn handle(&self, KeyExistsQueryRequest { key }: _) -> tea_sdk::Result<_> {
+		let (exists, value) = self.with(|store, now| {
+			Ok(match store.entry(&key, now) {
+				Some(Value::Bytes(value)) => (true, Some(value.clone())),
+				Some(_) => (true, None),
+				None => (false, None),
+			})
+		})?;
+		Ok(KeyExistsQueryResponse { exists, value })
+	}
+
+	async fn handle(&self, req: KeyVecInsertRequest) -> tea_sdk::Result<_> {
+		let owner = owner();
+		let success = self.with(|store, now| {
+			let keyvec = store.keyvec_mut(req.key, owner, now)?;
+			Ok(match req.value {
+				Some(TupleKeyValue { k, v }) if req.overwrite || !keyvec.contains_key(&k) => {
+					keyvec.insert(k, v);
+					true
+				}
+				_ => false,
+			})
+		})?;
+		Ok(KeyVecInsertResponse { success })
+	}
+
+	async fn handle(&self, KeyVecGetRequest { key }: _) -> tea_sdk::Result<_> {
+		let values = self.with(|store, now| {
+			Ok(store
+				.keyvec(&key, now)?
+				.into_iter()
+				.flatten()
+				.map(|(k, v)| TupleKeyValue {
+					k: *k,
+					v: v.clone(),
+				})
+				.collect())
+		})?;
+		Ok(KeyVecGetResponse { values })
+	}
+
+	async fn handle(&self, KeyVecRemoveItemRequest { key, value_idx }: _) -> tea_sdk::Result<_> {
+		let success = self.with(|store, now| {
+			let (removed, _) = store.remove_items(&key, now, |x| match x {
+				Value::KeyVec(keyvec) => Some((keyvec.remove(&value_idx).is_some(), keyvec.len())),
+				_ => None,
+			})?;
+			Ok(removed)
+		})?;
+		Ok(KeyVecRemoveItemResponse { success })
+	}
+
+	async fn handle(&self, KeyVecTailOffRequest { key, remain }: _) -> tea_sdk::Result<_> {
+		let len = self.with(|store, now| {
+			let (_, len) = store.remove_items(&key, now, |x| match x {
+				Value::KeyVec(keyvec) => {
+					let len = keyvec.len();
+					// drop the items of the smallest keys, keeping the latest `remain` ones
+					while keyvec.len() > remain as usize {
+						keyvec.pop_first();
+					}
+					Some((keyvec.len() < len, keyvec.len()))
+				}
+				_ => None,
+			})?;
+			Ok(len as u32)
+		})?;
+		Ok(KeyVecTailOffResponse { len })
+	}
+
+	async fn handle(&self, TaskMemorySizeRequest { uuid }: _) -> tea_sdk::Result<_> {
+		let size =
+			self.with(|store, now| Ok(store.size_of(now, |key, _| key.starts_with(&uuid))))?;
+		Ok(TaskMemorySizeResponse { size })
+	}
+
+	async fn handle(&self, ActorMemorySizeRequest { actor_id }: _) -> tea_sdk::Result<_> {
+		let size =
+			self.with(|store, now| Ok(store.size_of(now, |_, owner| owner == Some(&actor_id))))?;
+		Ok(ActorMemorySizeResponse { size })
+	}
+
+	async fn handle(&self, _: GlobalMemoryUsageRequest) -> tea_sdk::Result<_> {
+		let used = self.with(|store, now| Ok(store.size_of(now, |_, _| true)))?;
+		Ok(GlobalMemoryUsageResponse {
+			total: self.capacity,
+			free: self.capacity.saturating_sub(used),
+			token_memories: Vec::new(),
+		})
+	}
+}
+
+/// The actor calling the key-value actor, to which the memory of the keys it writes is accounted.
+fn owner() -> Option<ActorId> {
+	caller().ok().flatten()
+}
+
+/// Non-positive `expires_s` never expire, as `None` does.
+fn expires_at(expires_s: Option<i32>, now: Instant) -> Option<Instant> {
+	match expires_s {
+		Some(s) if s > 0 => Some(now + Duration::from_secs(s as u64)),
+		_ => None,
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+	Bytes(Vec<u8>),
+	List(VecDeque<Vec<u8>>),
+	Set(BTreeSet<Vec<u8>>),
+	KeyVec(BTreeMap<i32, Vec<u8>>),
+}
+
+impl Value {
+	fn size(&self) -> u64 {
+		let size = match self {
+			Value::Bytes(value) => value.len(),
+			Value::List(list) => list.iter().map(Vec::len).sum(),
+			Value::Set(set) => set.iter().map(Vec::len).sum(),
+			Value::KeyVec(keyvec) => keyvec
+				.values()
+				.map(|x| x.len() + std::mem::size_of::<i32>())
+				.sum(),
+		};
+		size as u64
+	}
+}
+
+struct Entry {
+	value: Value,
+	expires_at: Option<Instant>,
+	owner: Option<ActorId>,
+	version: u64,
+}
+
+impl Entry {
+	fn is_expired(&self, now: Instant) -> bool {
+		self.expires_at.map_or(false, |x| x <= now)
+	}
+}
+
+/// Entries are dropped lazily when an expired key is touched, and skipped by the size queries.
+#[derive(Default)]
+struct KeyValueStore {
+	entries: HashMap<String, Entry>,
+	/// Locked keys and when the lock expires, independent of the value of the key.
+	locks: HashMap<String, Option<Instant>>,
+	/// The version given to the last write, shared by all keys so a deleted and recreated key
+	/// never gets a version it had before.
+	version: u64,
+}
+
+impl KeyValueStore {
+	fn entry(&mut self, key: &str, now: Instant) -> Option<&Value> {
+		if self.entries.get(key)?.is_expired(now) {
+			self.entries.remove(key);
+			return None;
+		}
+		self.entries.get(key).map(|x| &x.value)
+	}
+
+	/// The value of the key, set to `empty` if absent or expired. The key keeps its expiry, and
+	/// fails without a new version if it holds another type than `empty`.
+	fn entry_mut(
+		&mut self,
+		key: String,
+		owner: Option<ActorId>,
+		now: Instant,
+		empty: Value,
+	) -> StoreResult<&mut Value> {
+		match self.entries.get(&key) {
+			Some(entry) if entry.is_expired(now) => {
+				self.entries.remove(&key);
+			}
+			Some(entry) if discriminant(&entry.value) != discriminant(&empty) => {
+				return Err(Errors::KeyValueWrongType(key).into());
+			}
+			_ => {}
+		}
+		let version = self.next_version();
+		let entry = self.entries.entry(key).or_insert_with(|| Entry {
+			value: empty,
+			expires_at: None,
+			owner: None,
+			version,
+		});
+		entry.version = version;
+		if owner.is_some() {
+			entry.owner = owner;
+		}
+		Ok(&mut entry.value)
+	}
+
+	/// Removes items from the collection of the key without creating it. `remove` returns
+	/// whether it removed anything and the number of items left, or `None` if the key holds
+	/// another type. The key gets a new version only if items were removed, and is dropped
+	/// once its collection is empty.
+	fn remove_items(
+		&mut self,
+		key: &str,
+		now: Instant,
+		remove: impl FnOnce(&mut Value) -> Option<(bool, usize)>,
+	) -> StoreResult<(bool, usize)> {
+		if self.entries.get(key).is_some_and(|x| x.is_expired(now)) {
+			self.entries.remove(key);
+		}
+		let Some(entry) = self.entries.get_mut(key) else {
+			return Ok((false, 0));
+		};
+		let (removed, len) =
+			remove(&mut entry.value).ok_or_else(|| Errors::KeyValueWrongType(key.to_string()))?;
+		if len == 0 {
+			self.entries.remove(key);
+		} else if removed {
+			self.version += 1;
+			entry.version = self.version;
+		}
+		Ok((removed, len))
+	}
+
+	fn next_version(&mut self) -> u64 {
+		self.version += 1;
+		self.version
+	}
+
+	/// Returns the version given to the new value.
+	fn insert(
+		&mut self,
+		key: String,
+		value: Value,
+		expires_at: Option<Instant>,
+		owner: Option<ActorId>,
+	) -> u64 {
+		let version = self.next_version();
+		self.entries.insert(
+			key,
+			Entry {
+				value,
+				expires_at,
+				owner,
+				version,
+			},
+		);
+		version
+	}
+
+	fn get_versioned(&mut self, key: &str, now: Instant) -> StoreResult<(Option<Vec<u8>>, u64)> {
+		let value = self.get(key, now)?;
+		let version = self.entries.get(key).map_or(0, |x| x.version);
+		Ok((value, version))
+	}
+
+	fn holds(&mut self, key: &str, condition: &CasCondition, now: Instant) -> StoreResult<bool> {
+		let (value, version) = self.get_versioned(key, now)?;
+		Ok(match condition {
+			CasCondition::Absent => value.is_none(),
+			CasCondition::Version(expected) => version == *expected,
+			CasCondition::ValueHash(hash) => {
+				value.is_some_and(|x| Sha256::digest(&x).as_slice() == hash)
+			}
+		})
+	}
+
+	/// Returns whether the condition held, the version after the request and the current value
+	/// if it did not hold.
+	fn compare_and_set(
+		&mut self,
+		key: String,
+		condition: &CasCondition,
+		value: Option<Vec<u8>>,
+		expires_at: Option<Instant>,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<(bool, u64, Option<Vec<u8>>)> {
+		if !self.holds(&key, condition, now)? {
+			let (current, version) = self.get_versioned(&key, now)?;
+			return Ok((false, version, current));
+		}
+		let version = match value {
+			Some(value) => self.insert(key, Value::Bytes(value), expires_at, owner),
+			None => {
+				self.entries.remove(&key);
+				0
+			}
+		};
+		Ok((true, version, None))
+	}
+
+	/// Returns the keys of which the condition did not hold, or the versions written by `ops`.
+	fn batch(
+		&mut self,
+		conditions: &[(String, CasCondition)],
+		ops: Vec<BatchOp>,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<Result<Vec<u64>, Vec<String>>> {
+		let mut failed = Vec::new();
+		for (key, condition) in conditions {
+			if !self.holds(key, condition, now)? {
+				failed.push(key.clone());
+			}
+		}
+		if !failed.is_empty() {
+			return Ok(Err(failed));
+		}
+		// Like `entry_mut`, a set must not replace a collection, checked before any op applies.
+		let mut deleted = BTreeSet::new();
+		for op in &ops {
+			match op {
+				BatchOp::Set { key, .. } => {
+					let collection = !deleted.contains(key)
+						&& self
+							.entry(key, now)
+							.is_some_and(|x| !matches!(x, Value::Bytes(_)));
+					if collection {
+						return Err(Errors::KeyValueWrongType(key.clone()).into());
+					}
+					deleted.remove(key);
+				}
+				BatchOp::Del { key } => {
+					deleted.insert(key);
+				}
+			}
+		}
+		Ok(Ok(ops
+			.into_iter()
+			.map(|op| match op {
+				BatchOp::Set {
+					key,
+					value,
+					expires_s,
+				} => self.insert(
+					key,
+					Value::Bytes(value),
+					expires_at(expires_s, now),
+					owner.clone(),
+				),
+				BatchOp::Del { key } => {
+					self.entries.remove(&key);
+					0
+				}
+			})
+			.collect()))
+	}
+
+	fn get(&mut self, key: &str, now: Instant) -> StoreResult<Option<Vec<u8>>> {
+		match self.entry(key, now) {
+			Some(Value::Bytes(value)) => Ok(Some(value.clone())),
+			Some(_) => Err(Errors::KeyValueWrongType(key.to_string()).into()),
+			None => Ok(None),
+		}
+	}
+
+	fn lock(&mut self, key: String, expires_at: Option<Instant>, now: Instant) -> StoreResult<()> {
+		if let Some(lock) = self.locks.get(&key) {
+			if lock.map_or(true, |x| x > now) {
+				return Err(Errors::KeyLocked(key).into());
+			}
+		}
+		self.locks.insert(key, expires_at);
+		Ok(())
+	}
+
+	/// Counters are stored as serialized `i32`s, so they can also be read with `GetRequest`.
+	fn add(
+		&mut self,
+		key: String,
+		delta: i32,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<i32> {
+		let current = match self.get(&key, now)? {
+			Some(value) => {
+				deserialize::<i32, _>(value).map_err(|_| Errors::KeyValueWrongType(key.clone()))?
+			}
+			None => 0,
+		};
+		let value = current
+			.checked_add(delta)
+			.ok_or_else(|| Errors::KeyValueAddOverflow(key.clone()))?;
+		let buf =
+			serialize(&value).map_err(|e| Errors::FailedToParse(key.clone(), e.to_string()))?;
+		match self.entry_mut(key, owner, now, Value::Bytes(Vec::new()))? {
+			Value::Bytes(x) => *x = buf,
+			_ => unreachable!("checked by get"),
+		}
+		Ok(value)
+	}
+
+	fn list_mut(
+		&mut self,
+		key: String,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<&mut VecDeque<Vec<u8>>> {
+		match self.entry_mut(key, owner, now, Value::List(Default::default()))? {
+			Value::List(list) => Ok(list),
+			_ => unreachable!("checked by entry_mut"),
+		}
+	}
+
+	/// Items from `start` to `stop` inclusive, negative indexes count from the end of the list.
+	fn list_range(
+		&mut self,
+		key: &str,
+		start: i32,
+		stop: i32,
+		now: Instant,
+	) -> StoreResult<Vec<Vec<u8>>> {
+		let list = match self.entry(key, now) {
+			Some(Value::List(list)) => list,
+			Some(_) => return Err(Errors::KeyValueWrongType(key.to_string()).into()),
+			None => return Ok(Vec::new()),
+		};
+		let len = list.len() as i64;
+		let index = |i: i32| if i < 0 { len + i as i64 } else { i as i64 };
+		let start = index(start).max(0);
+		let stop = index(stop).min(len - 1);
+		if start > stop {
+			return Ok(Vec::new());
+		}
+		Ok(list
+			.range(start as usize..=stop as usize)
+			.cloned()
+			.collect())
+	}
+
+	fn set(&mut self, key: &str, now: Instant) -> StoreResult<Option<&BTreeSet<Vec<u8>>>> {
+		match self.entry(key, now) {
+			Some(Value::Set(set)) => Ok(Some(set)),
+			Some(_) => Err(Errors::KeyValueWrongType(key.to_string()).into()),
+			None => Ok(None),
+		}
+	}
+
+	fn set_mut(
+		&mut self,
+		key: String,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<&mut BTreeSet<Vec<u8>>> {
+		match self.entry_mut(key, owner, now, Value::Set(Default::default()))? {
+			Value::Set(set) => Ok(set),
+			_ => unreachable!("checked by entry_mut"),
+		}
+	}
+
+	fn keyvec(&mut self, key: &str, now: Instant) -> StoreResult<Option<&BTreeMap<i32, Vec<u8>>>> {
+		match self.entry(key, now) {
+			Some(Value::KeyVec(keyvec)) => Ok(Some(keyvec)),
+			Some(_) => Err(Errors::KeyValueWrongType(key.to_string()).into()),
+			None => Ok(None),
+		}
+	}
+
+	fn keyvec_mut(
+		&mut self,
+		key: String,
+		owner: Option<ActorId>,
+		now: Instant,
+	) -> StoreResult<&mut BTreeMap<i32, Vec<u8>>> {
+		match self.entry_mut(key, owner, now, Value::KeyVec(Default::default()))? {
+			Value::KeyVec(keyvec) => Ok(keyvec),
+			_ => unreachable!("checked by entry_mut"),
+		}
+	}
+
+	/// Bytes of the keys and values of the live entries matching `filter`.
+	fn size_of(&self, now: Instant, filter: impl Fn(&str, Option<&ActorId>) -> bool) -> u64 {
+		self.entries
+			.iter()
+			.filter(|(key, entry)| !entry.is_expired(now) && filter(key, entry.owner.as_ref()))
+			.map(|(key, entry)| key.len() as u64 + entry.value.size())
+			.sum()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn entries_expire() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		store.insert(
+			"a".to_string(),
+			Value::Bytes(vec![1]),
+			expires_at(Some(10), now),
+			None,
+		);
+		store.insert("b".to_string(), Value::Bytes(vec![2]), None, None);
+		assert_eq!(store.get("a", now).unwrap(), Some(vec![1]));
+		assert_eq!(store.size_of(now, |_, _| true), 4);
+
+		let later = now + Duration::from_secs(10);
+		assert_eq!(store.size_of(later, |_, _| true), 2);
+		assert_eq!(store.get("a", later).unwrap(), None);
+		assert_eq!(store.get("b", later).unwrap(), Some(vec![2]));
+	}
+
+	#[test]
+	fn locks_block_until_canceled_or_expired() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		store
+			.lock("a".to_string(), expires_at(Some(5), now), now)
+			.unwrap();
+		assert_eq!(
+			store.lock("a".to_string(), None, now),
+			Err(Box::new(Errors::KeyLocked("a".to_string())))
+		);
+		let later = now + Duration::from_secs(5);
+		store.lock("a".to_string(), None, later).unwrap();
+		assert!(store.lock("a".to_string(), None, later).is_err());
+		store.locks.remove("a");
+		store.lock("a".to_string(), None, later).unwrap();
+	}
+
+	#[test]
+	fn compare_and_set_by_version_and_hash() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		let (success, v1, _) = store
+			.compare_and_set(
+				"a".into(),
+				&CasCondition::Absent,
+				Some(vec![1]),
+				None,
+				None,
+				now,
+			)
+			.unwrap();
+		assert!(success);
+		let (success, version, current) = store
+			.compare_and_set(
+				"a".into(),
+				&CasCondition::Absent,
+				Some(vec![2]),
+				None,
+				None,
+				now,
+			)
+			.unwrap();
+		assert_eq!((success, version, current), (false, v1, Some(vec![1])));
+
+		let hash = Sha256::digest(&[1u8]).into();
+		let (success, v2, _) = store
+			.compare_and_set(
+				"a".into(),
+				&CasCondition::ValueHash(hash),
+				Some(vec![2]),
+				None,
+				None,
+				now,
+			)
+			.unwrap();
+		assert!(success && v2 > v1);
+		let cas = |store: &mut KeyValueStore, version| {
+			store
+				.compare_and_set(
+					"a".into(),
+					&CasCondition::Version(version),
+					None,
+					None,
+					None,
+					now,
+				)
+				.unwrap()
+				.0
+		};
+		assert!(!cas(&mut store, v1));
+		assert!(cas(&mut store, v2));
+		assert_eq!(store.get_versioned("a", now).unwrap(), (None, 0));
+	}
+
+	#[test]
+	fn batch_applies_all_or_nothing() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		let version = store.insert("a".into(), Value::Bytes(vec![1]), None, None);
+		let ops = || {
+			vec![
+				BatchOp::Set {
+					key: "b".into(),
+					value: vec![2],
+					expires_s: None,
+				},
+				BatchOp::Del { key: "a".into() },
+			]
+		};
+		let conditions = vec![
+			("a".to_string(), CasCondition::Version(version + 1)),
+			("b".to_string(), CasCondition::Absent),
+		];
+		assert_eq!(
+			store.batch(&conditions, ops(), None, now).unwrap(),
+			Err(vec!["a".to_string()])
+		);
+		assert_eq!(store.get("b", now).unwrap(), None);
+
+		let conditions = vec![
+			("a".to_string(), CasCondition::Version(version)),
+			("b".to_string(), CasCondition::Absent),
+		];
+		let versions = store.batch(&conditions, ops(), None, now).unwrap().unwrap();
+		assert_eq!(versions, vec![version + 1, 0]);
+		assert_eq!(store.get("a", now).unwrap(), None);
+		assert_eq!(store.get("b", now).unwrap(), Some(vec![2]));
+	}
+
+	#[test]
+	fn batch_sets_do_not_replace_collections() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		store
+			.list_mut("l".to_string(), None, now)
+			.unwrap()
+			.push_back(vec![1]);
+		let set = |key: &str| BatchOp::Set {
+			key: key.to_string(),
+			value: vec![2],
+			expires_s: None,
+		};
+		assert_eq!(
+			store.batch(&[], vec![set("a"), set("l")], None, now),
+			Err(Box::new(Errors::KeyValueWrongType("l".to_string())))
+		);
+		assert!(store.entry("a", now).is_none());
+		assert_eq!(store.list_range("l", 0, -1, now).unwrap(), vec![vec![1]]);
+
+		let del = BatchOp::Del {
+			key: "l".to_string(),
+		};
+		assert!(store
+			.batch(&[], vec![del, set("l")], None, now)
+			.unwrap()
+			.is_ok());
+		assert_eq!(store.get("l", now).unwrap(), Some(vec![2]));
+	}
+
+	#[test]
+	fn counters_and_lists() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		assert_eq!(store.add("n".to_string(), 3, None, now).unwrap(), 3);
+		assert_eq!(store.add("n".to_string(), -1, None, now).unwrap(), 2);
+		assert_eq!(
+			deserialize::<i32, _>(store.get("n", now).unwrap().unwrap()).unwrap(),
+			2
+		);
+		assert_eq!(
+			store.list_mut("n".to_string(), None, now),
+			Err(Box::new(Errors::KeyValueWrongType("n".to_string())))
+		);
+
+		let list = store.list_mut("l".to_string(), None, now).unwrap();
+		list.extend([vec![1], vec![2], vec![3]]);
+		assert_eq!(
+			store.list_range("l", 1, -1, now).unwrap(),
+			vec![vec![2], vec![3]]
+		);
+		assert_eq!(
+			store.list_range("l", -2, 0, now).unwrap(),
+			Vec::<Vec<u8>>::new()
+		);
+		assert_eq!(store.list_range("l", 0, 10, now).unwrap().len(), 3);
+		assert_eq!(store.list_range("none", 0, -1, now).unwrap().len(), 0);
+	}
+
+	#[test]
+	fn removals_neither_create_nor_keep_empty_collections() {
+		let mut store = KeyValueStore::default();
+		let now = Instant::now();
+		let remove = |item: u8| {
+			move |x: &mut Value| match x {
+				Value::Set(set) => Some((set.remove(&vec![item]), set.len())),
+				_ => None,
+			}
+		};
+		assert_eq!(store.remove_items("s", now, remove(1)).unwrap(), (false, 0));
+		assert!(store.entry("s", now).is_none());
+
+		store
+			.set_mut("s".to_string(), None, now)
+			.unwrap()
+			.extend([vec![1], vec![2]]);
+		let version = store.entries["s"].version;
+		assert_eq!(store.remove_items("s", now, remove(3)).unwrap(), (false, 2));
+		assert_eq!(store.entries["s"].version, version);
+		assert_eq!(store.remove_items("s", now, remove(1)).unwrap(), (true, 1));
+		assert!(store.entries["s"].version > version);
+		assert_eq!(store.remove_items("s", now, remove(2)).unwrap(), (true, 0));
+		assert!(store.entry("s", now).is_none());
+
+		let version = store.insert("b".to_string(), Value::Bytes(vec![1]), None, None);
+		assert_eq!(
+			store.remove_items("b", now, remove(1)),
+			Err(Box::new(Errors::KeyValueWrongType("b".to_string())))
+		);
+		assert!(store.set_mut("b".to_string(), None, now).is_err());
+		assert_eq!(
+			store.get_versioned("b", now).unwrap(),
+			(Some(vec![1]), version)
+		);
+	}
+
+	#[tokio::test]
+	async fn serves_kvp_on_an_actor_host() {
+		use crate::enclave::actors::kvp;
+		use tea_actorx::{ActorExt, WithActorHost};
+
+		async {
+			KeyValueActor::default().register().await?;
+
+			kvp::set("key", &7u32, 60).await?;
+			assert_eq!(kvp::get::<u32>("key").await?, Some(7));
+			assert_eq!(kvp::add("counter", 3).await?, 3);
+			assert!(kvp::list_push("key", &1u32).await.is_err());
+
+			assert_eq!(kvp::set_remove("set", &1u32).await?, 0);
+			assert!(!kvp::exists("set").await?);
+			kvp::set_add("set", &1u32).await?;
+			assert_eq!(kvp::set_add("set", &2u32).await?, 2);
+			assert_eq!(kvp::set_query::<u32>("set").await?, vec![1, 2]);
+			assert_eq!(kvp::set_remove("set", &1u32).await?, 1);
+			assert_eq!(kvp::set_remove("set", &2u32).await?, 0);
+			assert!(!kvp::exists("set").await?);
+
+			kvp::list_push("list", &1u32).await?;
+			assert_eq!(kvp::list_push("list", &2u32).await?, 2);
+			assert_eq!(kvp::list_del_item("list", &1u32).await?, 1);
+			assert_eq!(kvp::list_range::<u32>("list", 0, -1).await?, vec![2]);
+			assert_eq!(kvp::list_del_item("list", &2u32).await?, 0);
+			assert!(!kvp::exists("list").await?);
+
+			for i in 0..3 {
+				kvp::keyvec_insert("keyvec", (i, &i), false).await?;
+			}
+			assert_eq!(kvp::keyvec_tail_off("keyvec", 2).await?, 2);
+			kvp::keyvec_remove_item("keyvec", 1).await?;
+			assert_eq!(kvp::keyvec_get::<i32>("keyvec").await?, vec![(2, 2)]);
+			assert_eq!(kvp::keyvec_tail_off("keyvec", 0).await?, 0);
+			assert!(!kvp::exists("keyvec").await?);
+			Ok::<_, crate::enclave::error::Error>(())
+		}
+		.with_actor_host()
+		.await
+		.unwrap();
+	}
+}
@@ -0,0 +1,953 @@
+use crate::enclave::error::Errors;
+use futures::future::{select_ok, BoxFuture};
+use prost::Message;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tea_actorx::{spawn, ActorExt, ActorId, HandlerActor, WithActorHost};
+use tea_runtime_codec::actor_txns::context::simulator::SplitMix64;
+use tea_runtime_codec::vmh::message::{encode_protobuf, structs_proto::libp2p};
+use tea_sdk::errorx::Global;
+use tea_sdk::serde::handle::handles;
+use tea_system_actors::libp2p::*;
+use tokio::sync::{mpsc, oneshot};
+
+/// How long a reply is waited for if the request gives no timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Handler = Arc<dyn Fn(Inbound) -> BoxFuture<'static, tea_sdk::Result<Vec<u8>>> + Send + Sync>;
+type Job = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+/// An in-process network of simulated nodes, each running its own actor host with a
+/// `tea:libp2p` actor that sends messages to the other nodes, so that code using
+/// `enclave::actors::libp2p` runs without real peers:
+///
+/// ```ignore
+/// let network = SimNetwork::new(7);
+/// let a = network.add_node("a").await?;
+/// let b = network.add_node("b").await?;
+/// b.handle(state_receiver::NAME, "libp2p.state-receiver", |msg| async move {
+///     Ok(reply_to(msg.content))
+/// });
+/// network.set_link(LinkConfig { latency: Duration::from_millis(50), ..Default::default() });
+/// a.run(|| send_to_state_receiver("b".into(), msg, None)).await??;
+/// ```
+///
+/// Messages to an actor and action are answered by the handler a test registered for them on
+/// the target node, which runs on the actor host of that node. Links delay messages by their
+/// latency each way and lose them at random, and nodes in different groups of a partition
+/// neither list nor reach each other. Lost messages are waited for until the timeout of the
+/// request. The randomness is drawn from the seed of the network, so runs on a current-thread
+/// runtime, e.g. `#[tokio::test(start_paused = true)]`, are deterministic.
+#[derive(Clone)]
+pub struct SimNetwork(Arc<Mutex<NetworkState>>);
+
+/// A node of a `SimNetwork`.
+#[derive(Clone)]
+pub struct SimNode {
+	conn_id: String,
+	network: SimNetwork,
+}
+
+/// A message delivered to a handler of a simulated node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inbound {
+	pub source_conn_id: String,
+	pub source_action: String,
+	/// The gossip topic if the message was published.
+	pub topic: Option<String>,
+	pub content: Vec<u8>,
+}
+
+/// The behavior of a link between two nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LinkConfig {
+	/// The delay of messages on the link, each way.
+	pub latency: Duration,
+	/// Up to this much is added to `latency` at random.
+	pub jitter: Duration,
+	/// The probability of a message to be lost, from 0 to 1.
+	pub loss: f64,
+}
+
+/// A message sent on the network, as recorded by `SimNetwork::deliveries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+	pub from: String,
+	pub to: String,
+	pub topic: Option<String>,
+	pub target_action: String,
+	pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+	Delivered,
+	Lost,
+	Partitioned,
+	/// The target node does not exist or has been removed.
+	Unknown,
+	/// The target node has no handler for the actor and action.
+	Unhandled,
+}
+
+#[derive(Default)]
+struct NetworkState {
+	nodes: BTreeMap<String, NodeState>,
+	link: LinkConfig,
+	links: HashMap<(String, String), LinkConfig>,
+	/// The group of the nodes in a partition, where unlisted nodes are in group 0.
+	groups: HashMap<String, usize>,
+	rng: SplitMix64,
+	deliveries: Vec<Delivery>,
+}
+
+struct NodeState {
+	jobs: mpsc::UnboundedSender<Job>,
+	handlers: HashMap<(Vec<u8>, String), Handler>,
+	topics: BTreeSet<String>,
+}
+
+enum Route {
+	Deliver {
+		delay: Duration,
+		jobs: mpsc::UnboundedSender<Job>,
+		handler: Handler,
+	},
+	Dropped,
+	Failed(Global),
+}
+
+impl SimNetwork {
+	pub fn new(seed: u64) -> Self {
+		SimNetwork(Arc::new(Mutex::new(NetworkState {
+			rng: SplitMix64(seed),
+			..Default::default()
+		})))
+	}
+
+	/// Starts a node with its own actor host, on which the `tea:libp2p` actor of the network
+	/// is registered. Panics if the conn id is taken.
+	pub async fn add_node(&self, conn_id: impl Into<String>) -> tea_sdk::Result<SimNode> {
+		let conn_id = conn_id.into();
+		let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+		{
+			let mut state = self.state();
+			if state.nodes.contains_key(&conn_id) {
+				panic!("node {conn_id} is added twice");
+			}
+			state.nodes.insert(
+				conn_id.clone(),
+				NodeState {
+					jobs,
+					handlers: HashMap::new(),
+					topics: BTreeSet::new(),
+				},
+			);
+		}
+
+		tokio::spawn(
+			async move {
+				while let Some(job) = rx.recv().await {
+					spawn(job());
+				}
+			}
+			.with_actor_host(),
+		);
+		let node = SimNode {
+			conn_id: conn_id.clone(),
+			network: self.clone(),
+		};
+		let actor = Libp2pActor {
+			conn_id,
+			network: self.clone(),
+		};
+		node.run(move || actor.register()).await??;
+		Ok(node)
+	}
+
+	/// Stops the node, dropping its actor host, as if it crashed.
+	pub fn remove_node(&self, conn_id: &str) {
+		self.state().nodes.remove(conn_id);
+	}
+
+	pub fn node(&self, conn_id: &str) -> Option<SimNode> {
+		self.state().nodes.contains_key(conn_id).then(|| SimNode {
+			conn_id: conn_id.to_string(),
+			network: self.clone(),
+		})
+	}
+
+	/// Sets the behavior of all links without one of their own.
+	pub fn set_link(&self, config: LinkConfig) {
+		self.state().link = config;
+	}
+
+	pub fn set_link_between(&self, a: &str, b: &str, config: LinkConfig) {
+		self.state().links.insert(link_key(a, b), config);
+	}
+
+	/// Splits the network into the groups, which can not reach each other. Nodes in none of
+	/// the groups form another one.
+	pub fn partition<G, S>(&self, groups: impl IntoIterator<Item = G>)
+	where
+		G: IntoIterator<Item = S>,
+		S: Into<String>,
+	{
+		let mut state = self.state();
+		state.groups.clear();
+		for (i, group) in groups.into_iter().enumerate() {
+			for conn_id in group {
+				state.groups.insert(conn_id.into(), i + 1);
+			}
+		}
+	}
+
+	/// Ends the partition.
+	pub fn heal(&self) {
+		self.state().groups.clear();
+	}
+
+	/// The messages sent so far in order.
+	pub fn deliveries(&self) -> Vec<Delivery> {
+		self.state().deliveries.clone()
+	}
+
+	fn state(&self) -> MutexGuard<NetworkState> {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Sends the message and waits for the reply of the handler on the target node.
+	async fn send(
+		&self,
+		from: &str,
+		to: &str,
+		topic: Option<String>,
+		message: libp2p::RuntimeMessage,
+		timeout: Duration,
+	) -> tea_sdk::Result<Vec<u8>> {
+		let target = message.target_address.unwrap_or_default();
+		let route = self.state().route(from, to, topic.clone(), &target);
+		let (delay, jobs, handler) = match route {
+			Route::Deliver {
+				delay,
+				jobs,
+				handler,
+			} => (delay, jobs, handler),
+			Route::Dropped => {
+				tokio::time::sleep(timeout).await;
+				return Err(error(Errors::Libp2pTimeout(to.to_string())));
+			}
+			Route::Failed(e) => return Err(e),
+		};
+
+		let inbound = Inbound {
+			source_conn_id: from.to_string(),
+			source_action: message
+				.source_address
+				.map(|x| x.target_action)
+				.unwrap_or_default(),
+			topic,
+			content: message.content,
+		};
+		let reply = async move {
+			tokio::time::sleep(delay).await;
+			let reply = run_on(to, &jobs, move || handler(inbound)).await?;
+			tokio::time::sleep(delay).await;
+			reply
+		};
+		tokio::time::timeout(timeout, reply)
+			.await
+			.map_err(|_| error(Errors::Libp2pTimeout(to.to_string())))?
+	}
+}
+
+impl NetworkState {
+	/// Decides the fate of a message and records it.
+	fn route(
+		&mut self,
+		from: &str,
+		to: &str,
+		topic: Option<String>,
+		target: &libp2p::RuntimeAddress,
+	) -> Route {
+		let key = (target.target_key.clone(), target.target_action.clone());
+		let link = self
+			.links
+			.get(&link_key(from, to))
+			.copied()
+			.unwrap_or(self.link);
+		let (outcome, route) = match self.nodes.get(to) {
+			None => (
+				Outcome::Unknown,
+				Route::Failed(error(Errors::ConnIdNotExist(to.to_string()))),
+			),
+			Some(_) if !self.reachable(from, to) => (Outcome::Partitioned, Route::Dropped),
+			Some(_) if self.rng.next_f64() < link.loss => (Outcome::Lost, Route::Dropped),
+			Some(node) => match node.handlers.get(&key) {
+				Some(handler) => (
+					Outcome::Delivered,
+					Route::Deliver {
+						delay: link.latency + link.jitter.mul_f64(self.rng.next_f64()),
+						jobs: node.jobs.clone(),
+						handler: handler.clone(),
+					},
+				),
+				None => (
+					Outcome::Unhandled,
+					Route::Failed(error(Errors::Libp2pUnhandled(
+						to.to_string(),
+						String::from_utf8_lossy(&key.0).into_owned(),
+						key.1.clone(),
+					))),
+				),
+			},
+		};
+		self.deliveries.push(Delivery {
+			from: from.to_string(),
+			to: to.to_string(),
+			topic,
+			target_action: key.1,
+			outcome,
+		});
+		route
+	}
+
+	fn reachable(&self, a: &str, b: &str) -> bool {
+		self.groups.get(a).unwrap_or(&0) == self.groups.get(b).unwrap_or(&0)
+	}
+
+	/// The other nodes reachable from the node, sorted by conn id.
+	fn peers(&self, conn_id: &str) -> Vec<String> {
+		self.nodes
+			.keys()
+			.filter(|x| *x != conn_id && self.reachable(conn_id, x))
+			.cloned()
+			.collect()
+	}
+}
+
+impl SimNode {
+	pub fn conn_id(&self) -> &str {
+		&self.conn_id
+	}
+
+	/// Runs the future on the actor host of the node, e.g. to register actors or to call them.
+	pub async fn run<F, Fut>(&self, f: F) -> tea_sdk::Result<Fut::Output>
+	where
+		F: FnOnce() -> Fut + Send + 'static,
+		Fut: Future + Send + 'static,
+		Fut::Output: Send + 'static,
+	{
+		let jobs = self
+			.network
+			.state()
+			.nodes
+			.get(&self.conn_id)
+			.map(|x| x.jobs.clone())
+			.ok_or_else(|| error(Errors::SimNodeDown(self.conn_id.clone())))?;
+		run_on(&self.conn_id, &jobs, f).await
+	}
+
+	/// Answers the messages to the actor and action on this node with the handler, which runs
+	/// on the actor host of the node.
+	pub fn handle<F, Fut>(&self, target_key: &[u8], target_action: &str, handler: F)
+	where
+		F: Fn(Inbound) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = tea_sdk::Result<Vec<u8>>> + Send + 'static,
+	{
+		let handler: Handler = Arc::new(move |msg| Box::pin(handler(msg)));
+		if let Some(node) = self.network.state().nodes.get_mut(&self.conn_id) {
+			node.handlers
+				.insert((target_key.to_vec(), target_action.to_string()), handler);
+		}
+	}
+}
+
+async fn run_on<F, Fut>(
+	conn_id: &str,
+	jobs: &mpsc::UnboundedSender<Job>,
+	f: F,
+) -> tea_sdk::Result<Fut::Output>
+where
+	F: FnOnce() -> Fut + Send + 'static,
+	Fut: Future + Send + 'static,
+	Fut::Output: Send + 'static,
+{
+	let (tx, rx) = oneshot::channel();
+	let job: Job = Box::new(move || {
+		Box::pin(async move {
+			let _ = tx.send(f().await);
+		})
+	});
+	let down = || error(Errors::SimNodeDown(conn_id.to_string()));
+	jobs.send(job).map_err(|_| down())?;
+	rx.await.map_err(|_| down())
+}
+
+/// The `tea:libp2p` actor of a simulated node.
+struct Libp2pActor {
+	conn_id: String,
+	network: SimNetwork,
+}
+
+impl HandlerActor for Libp2pActor {
+	fn id(&self) -> Option<ActorId> {
+		Some(ActorId::Static(NAME))
+	}
+}
+
+#[handles]
+impl Libp2pActor {
+	async fn handle(&self, _: MyConnIdRequest) -> tea_sdk::Result<_> {
+		Ok(MyConnIdResponse(self.conn_id.clone()))
+	}
+
+	async fn handle(&self, _: HasCooldownRequest) -> tea_sdk::Result<_> {
+		Ok(HasCooldownResponse(true))
+	}
+
+	async fn handle(&self, _: ListPeersRequest) -> tea_sdk::Result<_> {
+		let peers = self.network.state().peers(&self.conn_id);
+		Ok(ListPeersResponse(encode_protobuf(
+			libp2p::ListPeersResponse { peers },
+		)?))
+	}
+
+	async fn handle(&self, RandomPeersRequest(req): _) -> tea_sdk::Result<_> {
+		let count = decode::<libp2p::RandomPeersRequest>(&req)?.count as usize;
+		let mut state = self.network.state();
+		let mut peers = state.peers(&self.conn_id);
+		let insufficient_peers = peers.len() < count;
+		// a partial Fisher-Yates shuffle picking the first `count`
+		for i in 0..count.min(peers.len()) {
+			let j = i + state.rng.below((peers.len() - i) as u64) as usize;
+			peers.swap(i, j);
+		}
+		peers.truncate(count);
+		Ok(RandomPeersResponse(encode_protobuf(
+			libp2p::RandomoPeersResponse {
+				peers,
+				insufficient_peers,
+			},
+		)?))
+	}
+
+	async fn handle(&self, SubscribeGossipTopicRequest(req): _) -> tea_sdk::Result<_> {
+		let topic = decode::<libp2p::SubscribeGossipTopicRequest>(&req)?.topic_name;
+		if let Some(node) = self.network.state().nodes.get_mut(&self.conn_id) {
+			node.topics.insert(topic);
+		}
+		Ok(())
+	}
+
+	async fn handle(&self, UnsubscribeGossipTopicRequest(req): _) -> tea_sdk::Result<_> {
+		let topic = decode::<libp2p::UnsubscribeGossipTopicRequest>(&req)?.topic_name;
+		if let Some(node) = self.network.state().nodes.get_mut(&self.conn_id) {
+			node.topics.remove(&topic);
+		}
+		Ok(())
+	}
+
+	/// Publishes to the subscribers of the topic, or to all peers without topic.
+	async fn handle(&self, PubMessageRequest(req): _) -> tea_sdk::Result<_> {
+		let req = decode::<libp2p::PubMessage>(&req)?;
+		let topic = req.topic.map(|x| x.topic_name);
+		let message = req.runtime_message.unwrap_or_default();
+		let targets = {
+			let state = self.network.state();
+			state
+				.nodes
+				.iter()
+				.filter(|(conn_id, node)| {
+					**conn_id != self.conn_id
+						&& match &topic {
+							Some(topic) => node.topics.contains(topic),
+							None => true,
+						}
+				})
+				.map(|(conn_id, _)| conn_id.clone())
+				.collect::<Vec<_>>()
+		};
+		for to in targets {
+			let (network, from) = (self.network.clone(), self.conn_id.clone());
+			let (topic, message) = (topic.clone(), message.clone());
+			tokio::spawn(async move {
+				let _ = network
+					.send(&from, &to, topic, message, DEFAULT_TIMEOUT)
+					.await;
+			});
+		}
+		Ok(())
+	}
+
+	async fn handle(&self, req: SendMessageRequest) -> tea_sdk::Result<_> {
+		let msg = decode::<libp2p::GeneralRequest>(&req.msg)?;
+		let timeout = timeout(req.timeout_ms);
+		let message = msg.runtime_message.unwrap_or_default();
+		if !req.with_reply {
+			let (network, from) = (self.network.clone(), self.conn_id.clone());
+			tokio::spawn(async move {
+				let _ = network
+					.send(&from, &msg.target_conn_id, None, message, timeout)
+					.await;
+			});
+			return Ok(SendMessageResponse(None));
+		}
+		let reply = self
+			.network
+			.send(&self.conn_id, &msg.target_conn_id, None, message, timeout)
+			.await?;
+		Ok(SendMessageResponse(Some(reply)))
+	}
+
+	/// Sends to all targets at once and returns the first reply.
+	async fn handle(&self, req: SendMessageExRequest) -> tea_sdk::Result<_> {
+		let msg = decode::<libp2p::GeneralRequest>(&req.msg)?;
+		let timeout = timeout(req.timeout_ms);
+		let message = msg.runtime_message.unwrap_or_default();
+		let sends = req.targets.iter().map(|to| {
+			let network = self.network.clone();
+			let message = message.clone();
+			Box::pin(async move {
+				network
+					.send(&self.conn_id, to, None, message, timeout)
+					.await
+			})
+		});
+		let reply = match select_ok(sends).await {
+			Ok((reply, _)) => Ok(reply),
+			Err(e) => Err(error(Errors::Libp2pAllResponseError(e.to_string()))),
+		};
+		Ok(SendMessageExResponse(reply))
+	}
+}
+
+fn timeout(timeout_ms: Option<u64>) -> Duration {
+	timeout_ms.map_or(DEFAULT_TIMEOUT, Duration::from_millis)
+}
+
+fn link_key(a: &str, b: &str) -> (String, String) {
+	if a <= b {
+		(a.to_string(), b.to_string())
+	} else {
+		(b.to_string(), a.to_string())
+	}
+}
+
+fn decode<T: Message + Default>(buf: &[u8]) -> tea_sdk::Result<T> {
+	T::decode(buf).map_err(|e| Global::Unnamed(format!("decode protobuf error: {e:?}")))
+}
+
+fn error(e: Errors) -> Global {
+	Global::Unnamed(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn address(action: &str) -> libp2p::RuntimeAddress {
+		libp2p::RuntimeAddress {
+			target_key: b"someone".to_vec(),
+			target_action: action.to_string(),
+		}
+	}
+
+	fn state(seed: u64, nodes: &[&str]) -> NetworkState {
+		let mut state = NetworkState {
+			rng: SplitMix64(seed),
+			..Default::default()
+		};
+		for conn_id in nodes {
+			let handler: Handler = Arc::new(|msg| Box::pin(async move { Ok(msg.content) }));
+			state.nodes.insert(
+				conn_id.to_string(),
+				NodeState {
+					jobs: mpsc::unbounded_channel().0,
+					handlers: [((b"someone".to_vec(), "echo".to_string()), handler)].into(),
+					topics: BTreeSet::new(),
+				},
+			);
+		}
+		state
+	}
+
+	fn outcomes(state: &mut NetworkState, to: &str, action: &str, n: usize) -> Vec<Outcome> {
+		(0..n)
+			.map(|_| {
+				state.route("a", to, None, &address(action));
+				state.deliveries.last().unwrap().outcome
+			})
+			.collect()
+	}
+
+	#[test]
+	fn partitions_and_routes() {
+		let mut state = state(1, &["a", "b", "c"]);
+		assert_eq!(state.peers("a"), vec!["b", "c"]);
+		state.groups = [("c".to_string(), 1)].into();
+		assert_eq!(state.peers("a"), vec!["b"]);
+		assert_eq!(state.peers("c"), Vec::<String>::new());
+
+		assert_eq!(outcomes(&mut state, "b", "echo", 1), [Outcome::Delivered]);
+		assert_eq!(outcomes(&mut state, "c", "echo", 1), [Outcome::Partitioned]);
+		assert_eq!(outcomes(&mut state, "b", "other", 1), [Outcome::Unhandled]);
+		assert_eq!(outcomes(&mut state, "d", "echo", 1), [Outcome::Unknown]);
+	}
+
+	#[test]
+	fn loss_is_deterministic() {
+		let lossy = |seed| {
+			let mut state = state(seed, &["a", "b"]);
+			state.link.loss = 0.5;
+			outcomes(&mut state, "b", "echo", 32)
+		};
+		let outcomes = lossy(7);
+		assert_eq!(outcomes, lossy(7));
+		assert_ne!(outcomes, lossy(8));
+		assert!(outcomes.contains(&Outcome::Lost));
+		assert!(outcomes.contains(&Outcome::Delivered));
+	}
+
+	mod network {
+		use super::*;
+		use crate::enclave::actors::libp2p::{
+			connected_peers, my_conn_id, pub_message, send_all_state_receiver,
+		};
+		use crate::enclave::actors::replica::{intelli_send_txn, IntelliSendMode};
+		use sha2::{Digest, Sha256};
+		use std::time::SystemTime;
+		use tea_runtime_codec::actor_txns::tsid::Tsid;
+		use tea_runtime_codec::vmh::message::structs_proto::{crypto, replica, tokenstate};
+		use tea_sdk::serialize;
+		use tea_system_actors::{env, nitro, replica_service, state_receiver};
+		use tokio::time::Instant;
+
+		const LATENCY: Duration = Duration::from_millis(50);
+		const STATE_RECEIVER: &str = "libp2p.state-receiver";
+
+		async fn network(conn_ids: &[&str]) -> (SimNetwork, Vec<SimNode>) {
+			let network = SimNetwork::new(7);
+			network.set_link(LinkConfig {
+				latency: LATENCY,
+				..Default::default()
+			});
+			let mut nodes = Vec::new();
+			for conn_id in conn_ids {
+				nodes.push(network.add_node(*conn_id).await.unwrap());
+			}
+			(network, nodes)
+		}
+
+		/// Answers state receiver messages with the serialized value.
+		fn answer<T: serde::Serialize + Send + Sync + 'static>(node: &SimNode, value: T) {
+			let value = Arc::new(value);
+			node.handle(state_receiver::NAME, STATE_RECEIVER, move |_| {
+				let value = value.clone();
+				async move { serialize(&*value) }
+			});
+		}
+
+		fn tsid(byte: u8) -> Tsid {
+			let mut tsid = Tsid::default();
+			tsid.hash = [byte; 32];
+			tsid
+		}
+
+		/// The outcomes of the messages sent so far, sorted by the node they were sent to.
+		fn outcomes(network: &SimNetwork) -> Vec<(String, Outcome)> {
+			let mut outcomes = network
+				.deliveries()
+				.into_iter()
+				.map(|x| (x.to, x.outcome))
+				.collect::<Vec<_>>();
+			outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+			outcomes
+		}
+
+		#[tokio::test(start_paused = true)]
+		async fn add_node() {
+			let (network, nodes) = network(&["a", "b", "c"]).await;
+			let a = &nodes[0];
+			assert_eq!(a.run(my_conn_id).await.unwrap().unwrap(), "a");
+			assert_eq!(a.run(connected_peers).await.unwrap().unwrap(), ["b", "c"]);
+
+			network.partition([["c"]]);
+			assert_eq!(a.run(connected_peers).await.unwrap().unwrap(), ["b"]);
+			network.heal();
+
+			network.remove_node("b");
+			assert_eq!(a.run(connected_peers).await.unwrap().unwrap(), ["c"]);
+			assert!(nodes[1].run(|| async {}).await.is_err());
+			assert!(network.node("b").is_none());
+		}
+
+		#[tokio::test(start_paused = true)]
+		#[should_panic(expected = "added twice")]
+		async fn add_node_twice() {
+			network(&["a", "a"]).await;
+		}
+
+		#[tokio::test(start_paused = true)]
+		async fn send_all_state_receiver_takes_the_first_reply() {
+			let (network, nodes) = network(&["a", "b", "c"]).await;
+			answer(&nodes[1], Some(tsid(2)));
+			answer(&nodes[2], Some(tsid(3)));
+			network.set_link_between(
+				"a",
+				"b",
+				LinkConfig {
+					latency: LATENCY * 3,
+					..Default::default()
+				},
+			);
+
+			let send = |targets: &[&str]| {
+				let validators = targets
+					.iter()
+					.map(|x| (Vec::new(), x.to_string()))
+					.collect::<Vec<_>>();
+				nodes[0].run(move || {
+					send_all_state_receiver::<Option<Tsid>>(
+						validators,
+						tokenstate::StateReceiverMessage::default(),
+						None,
+					)
+				})
+			};
+			let start = Instant::now();
+			assert_eq!(send(&["b", "c"]).await.unwrap().unwrap(), Some(tsid(3)));
+			assert_eq!(start.elapsed(), LATENCY * 2);
+			assert_eq!(
+				outcomes(&network),
+				[
+					("b".to_string(), Outcome::Delivered),
+					("c".to_string(), Outcome::Delivered)
+				]
+			);
+
+			assert!(send(&["c", "d"]).await.unwrap().is_err());
+		}
+
+		#[tokio::test(start_paused = true)]
+		async fn gossip_reaches_the_subscribers() {
+			let (network, nodes) = network(&["a", "b", "c"]).await;
+			let received = Arc::new(Mutex::new(Vec::new()));
+			for node in &nodes[1..] {
+				let (received, conn_id) = (received.clone(), node.conn_id().to_string());
+				node.handle(b"blocks", "new-block", move |msg| {
+					received
+						.lock()
+						.unwrap()
+						.push((conn_id.clone(), msg.topic, msg.content));
+					async { Ok(Vec::new()) }
+				});
+			}
+			nodes[1]
+				.run(|| {
+					ActorId::Static(NAME).call(SubscribeGossipTopicRequest(
+						encode_protobuf(libp2p::SubscribeGossipTopicRequest {
+							topic_name: "blocks".into(),
+						})
+						.unwrap(),
+					))
+				})
+				.await
+				.unwrap()
+				.unwrap();
+
+			let publish = |topic: Option<&str>, content: &[u8]| {
+				let (topic, content) = (topic.map(str::to_string), content.to_vec());
+				nodes[0].run(move || {
+					let address = libp2p::RuntimeAddress {
+						target_key: b"blocks".to_vec(),
+						target_action: "new-block".into(),
+					};
+					pub_message(address, None, content, topic)
+				})
+			};
+			publish(Some("blocks"), b"1").await.unwrap().unwrap();
+			tokio::time::sleep(LATENCY * 3).await;
+			assert_eq!(
+				*received.lock().unwrap(),
+				[("b".to_string(), Some("blocks".to_string()), b"1".to_vec())]
+			);
+
+			received.lock().unwrap().clear();
+			publish(None, b"2").await.unwrap().unwrap();
+			tokio::time::sleep(LATENCY * 3).await;
+			let mut received = received.lock().unwrap().clone();
+			received.sort();
+			assert_eq!(
+				received,
+				[
+					("b".to_string(), None, b"2".to_vec()),
+					("c".to_string(), None, b"2".to_vec())
+				]
+			);
+			assert!(network
+				.deliveries()
+				.iter()
+				.all(|x| x.outcome == Outcome::Delivered));
+		}
+
+		/// The system actors `intelli_send_txn` calls on a node without replica of its own.
+		struct Nitro;
+
+		impl HandlerActor for Nitro {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(nitro::NAME))
+			}
+		}
+
+		#[handles]
+		impl Nitro {
+			async fn handle(&self, nitro::GenerateRandomRequest(len): _) -> tea_sdk::Result<_> {
+				Ok(vec![7; len as usize])
+			}
+
+			async fn handle(&self, _: nitro::GenerateUuidRequest) -> tea_sdk::Result<_> {
+				Ok(nitro::GenerateUuidResponse("uuid".into()))
+			}
+
+			async fn handle(&self, _: nitro::GetTeaIdRequest) -> tea_sdk::Result<_> {
+				Ok(nitro::GetTeaIdResponse(vec![1; 32]))
+			}
+		}
+
+		struct Crypto;
+
+		impl HandlerActor for Crypto {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(tea_system_actors::crypto::NAME))
+			}
+		}
+
+		#[handles]
+		impl Crypto {
+			async fn handle(
+				&self,
+				tea_system_actors::crypto::Sha256Request(req): _,
+			) -> tea_sdk::Result<_> {
+				let content = decode::<crypto::ShaRequest>(&req)?.content;
+				Ok(tea_system_actors::crypto::Sha256Response(encode_protobuf(
+					crypto::ShaResponse {
+						hash: Sha256::digest(&content).to_vec(),
+					},
+				)?))
+			}
+		}
+
+		struct Env;
+
+		impl HandlerActor for Env {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(env::NAME))
+			}
+		}
+
+		#[handles]
+		impl Env {
+			async fn handle(&self, _: env::GetWasmActorTokenIdRequest) -> tea_sdk::Result<_> {
+				Ok(env::GetWasmActorTokenIdResponse(None))
+			}
+
+			async fn handle(&self, _: env::GetSystemTimeRequest) -> tea_sdk::Result<_> {
+				Ok(env::GetSystemTimeResponse(SystemTime::UNIX_EPOCH))
+			}
+		}
+
+		struct ReplicaService(Vec<String>);
+
+		impl HandlerActor for ReplicaService {
+			fn id(&self) -> Option<ActorId> {
+				Some(ActorId::Static(replica_service::NAME))
+			}
+		}
+
+		#[handles]
+		impl ReplicaService {
+			async fn handle(
+				&self,
+				_: replica_service::ValidatorsMembersRequest,
+			) -> tea_sdk::Result<_> {
+				Ok(replica_service::ValidatorsMembersResponse(encode_protobuf(
+					replica::ValidatorMembersResponse {
+						validator_members: Some(replica::ValidatorMembers {
+							members: self.0.iter().map(|x| x.as_bytes().to_vec()).collect(),
+							conn_ids: self.0.clone(),
+						}),
+					},
+				)?))
+			}
+		}
+
+		#[tokio::test(start_paused = true)]
+		async fn intelli_send_txn_fails_over() {
+			let (network, nodes) = network(&["a", "b", "c"]).await;
+			answer(&nodes[1], Some(tsid(2)));
+			answer(&nodes[2], Some(tsid(3)));
+			nodes[0]
+				.run(|| async {
+					Nitro.register().await?;
+					Crypto.register().await?;
+					Env.register().await?;
+					ReplicaService(vec!["b".into(), "c".into()])
+						.register()
+						.await
+				})
+				.await
+				.unwrap()
+				.unwrap();
+			let send = || {
+				nodes[0].run(|| {
+					intelli_send_txn(
+						b"someone",
+						b"txn",
+						vec![],
+						IntelliSendMode::RemoteOnly,
+						1000,
+						None,
+					)
+				})
+			};
+
+			// b drops every message, so the reply of c is taken
+			network.set_link_between(
+				"a",
+				"b",
+				LinkConfig {
+					latency: LATENCY,
+					loss: 1.0,
+					..Default::default()
+				},
+			);
+			let start = Instant::now();
+			assert_eq!(send().await.unwrap().unwrap(), Some(tsid(3)));
+			assert_eq!(start.elapsed(), LATENCY * 2);
+			assert_eq!(
+				outcomes(&network),
+				[
+					("b".to_string(), Outcome::Lost),
+					("c".to_string(), Outcome::Delivered)
+				]
+			);
+
+			// c crashed, so the txn goes to b only
+			network.set_link(LinkConfig::default());
+			network.set_link_between("a", "b", LinkConfig::default());
+			network.remove_node("c");
+			let sent = network.deliveries().len();
+			assert_eq!(send().await.unwrap().unwrap(), Some(tsid(2)));
+			assert_eq!(
+				network.deliveries()[sent..]
+					.iter()
+					.map(|x| (x.to.as_str(), x.outcome))
+					.collect::<Vec<_>>(),
+				[("b", Outcome::Delivered)]
+			);
+		}
+	}
+}
@@ -0,0 +1,95 @@
+use tea_actorx::{ActorId, WithGasLimit};
+use tea_runtime_codec::actor_txns::TxnSerial;
+use tea_sdk::deserialize;
+use tea_system_actors::replica::{ExecTxnRequest, ExecTxnResponse, HistoryItem, TxnItem};
+
+/// Executes the txn of `item` on the actor it names the way a replica does, metered under the
+/// `gas_limit` of the txn.
+///
+/// Returns the response of the actor together with the `HistoryItem` of the txn, which records
+/// the gas it used and is what `pay_miner_metered_gas` pays the miner for. The gas is recorded
+/// for failed txns too; a txn running out of gas fails with `GasFeeExhausted` having used the
+/// whole limit.
+pub async fn exec_txn(item: TxnItem) -> (tea_sdk::Result<ExecTxnResponse>, HistoryItem) {
+	let (result, gas_used) = match deserialize::<TxnSerial, _>(&item.txn.txn_bytes) {
+		Ok(txn) => {
+			let req = ExecTxnRequest(
+				item.tsid,
+				txn.bytes().to_vec(),
+				txn.nonce(),
+				txn.extra(),
+				item.txn.args.clone(),
+			);
+			ActorId::Shared(txn.actor_name().into())
+				.call(req)
+				.with_gas_limit(txn.gas_limit())
+				.await
+		}
+		Err(e) => (Err(e), 0),
+	};
+	let history = HistoryItem {
+		err_msg: result.as_ref().err().map(ToString::to_string),
+		txn_item: item,
+		gas_used,
+	};
+	(result, history)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tea_actorx::{cost, ActorExt, HandlerActor, WithActorHost};
+	use tea_runtime_codec::actor_txns::{tsid::Tsid, txn::FullTxn};
+	use tea_sdk::errorx::Global;
+	use tea_sdk::serde::handle::handles;
+
+	const NAME: &[u8] = b"test:metered";
+
+	/// Costs as much gas as the txn bytes say.
+	struct MeteredActor;
+
+	impl HandlerActor for MeteredActor {
+		fn id(&self) -> Option<ActorId> {
+			Some(ActorId::Static(NAME))
+		}
+	}
+
+	#[handles]
+	impl MeteredActor {
+		async fn handle(&self, ExecTxnRequest(_, bytes, ..): _) -> tea_sdk::Result<_> {
+			for _ in bytes {
+				cost(10)?;
+			}
+			Ok(ExecTxnResponse(vec![], None))
+		}
+	}
+
+	fn txn(steps: usize, gas_limit: u64) -> TxnItem {
+		let txn = TxnSerial::new(NAME.to_vec(), vec![0; steps], 1, 0, gas_limit);
+		TxnItem {
+			txn: FullTxn::new_no_args(tea_sdk::serialize(&txn).unwrap()),
+			tsid: Tsid::default(),
+		}
+	}
+
+	#[tokio::test]
+	async fn txns_are_metered_under_their_gas_limit() {
+		async {
+			MeteredActor.register().await?;
+
+			let (result, history) = exec_txn(txn(3, 100)).await;
+			assert!(result.is_ok());
+			assert_eq!(history.gas_used, 30);
+			assert_eq!(history.err_msg, None);
+
+			let (result, history) = exec_txn(txn(11, 100)).await;
+			assert!(matches!(result, Err(Global::GasFeeExhausted(_))));
+			assert_eq!(history.gas_used, 100);
+			assert!(history.err_msg.is_some());
+			Ok::<_, Global>(())
+		}
+		.with_actor_host()
+		.await
+		.unwrap();
+	}
+}